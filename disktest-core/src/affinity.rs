@@ -0,0 +1,84 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use std::io;
+
+/// Check whether pinning threads to CPUs is supported on this operating system.
+pub fn affinity_supported() -> bool {
+    cfg!(any(target_os="linux", target_os="windows"))
+}
+
+#[cfg(target_os="linux")]
+fn os_pin_current_thread(cpu: usize) -> io::Result<()> {
+    if cpu >= libc::CPU_SETSIZE as usize {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "CPU number is too big"));
+    }
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_ZERO(&mut set);
+        libc::CPU_SET(cpu, &mut set);
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(target_os="windows")]
+fn os_pin_current_thread(cpu: usize) -> io::Result<()> {
+    use winapi::um::processthreadsapi::GetCurrentThread;
+    use winapi::um::winbase::SetThreadAffinityMask;
+
+    if cpu >= usize::BITS as usize {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "CPU number is too big"));
+    }
+    if unsafe { SetThreadAffinityMask(GetCurrentThread(), 1 << cpu) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os="linux", target_os="windows")))]
+fn os_pin_current_thread(_cpu: usize) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "CPU affinity is not supported"))
+}
+
+/// Pin the calling thread to the CPU.
+pub fn pin_current_thread(cpu: usize) -> io::Result<()> {
+    os_pin_current_thread(cpu)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pin_current_thread() {
+        if affinity_supported() {
+            std::thread::spawn(|| {
+                pin_current_thread(0).unwrap();
+                assert!(pin_current_thread(1 << 20).is_err());
+            }).join().unwrap();
+        }
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,79 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use std::ops::{Deref, DerefMut};
+
+/// Zero initialized byte buffer with an aligned start address.
+/// Direct (unbuffered) I/O requires the memory buffers to be aligned.
+pub struct AlignedBuffer {
+    buf:    Vec<u8>,
+    offset: usize,
+    len:    usize,
+}
+
+impl AlignedBuffer {
+    /// Allocate a new buffer of len bytes.
+    /// The start address will be aligned to align bytes.
+    pub fn new(len: usize, align: usize) -> AlignedBuffer {
+        assert!(align.is_power_of_two());
+        let buf = vec![0; len + align];
+        let offset = buf.as_ptr().align_offset(align);
+        assert!(offset < align);
+        AlignedBuffer {
+            buf,
+            offset,
+            len,
+        }
+    }
+}
+
+impl Deref for AlignedBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf[self.offset..self.offset+self.len]
+    }
+}
+
+impl DerefMut for AlignedBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buf[self.offset..self.offset+self.len]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aligned_buffer() {
+        for align in &[1, 512, 4096] {
+            let mut a = AlignedBuffer::new(10000, *align);
+            assert_eq!(a.len(), 10000);
+            assert_eq!(a.as_ptr() as usize % align, 0);
+            assert!(a.iter().all(|x| *x == 0));
+            a[9999] = 42;
+            assert_eq!(a[9999], 42);
+        }
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
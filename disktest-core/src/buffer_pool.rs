@@ -0,0 +1,87 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+
+use std::sync::{Arc, Mutex};
+
+/// Pool of recycled chunk buffers.
+/// The consumer returns the buffers of used chunks to the pool
+/// and the generator threads take them from there instead of allocating new ones.
+#[derive(Clone)]
+pub struct BufferPool {
+    buffers:        Arc<Mutex<Vec<Vec<u8>>>>,
+    capacity:       usize,
+}
+
+impl BufferPool {
+    /// Create a new pool that holds up to capacity buffers.
+    pub fn new(capacity: usize) -> BufferPool {
+        BufferPool {
+            buffers:    Arc::new(Mutex::new(Vec::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    /// Get a buffer of size bytes.
+    /// The content of a recycled buffer is undefined.
+    pub fn get(&self, size: usize) -> Vec<u8> {
+        let buf = self.buffers.lock().unwrap().pop();
+        match buf {
+            Some(buf) if buf.len() == size => buf,
+            _ => vec![0; size],
+        }
+    }
+
+    /// Return a buffer to the pool.
+    /// The buffer is dropped, if the pool is full.
+    pub fn put(&self, buf: Vec<u8>) {
+        let mut buffers = self.buffers.lock().unwrap();
+        if buffers.len() < self.capacity {
+            buffers.push(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recycle() {
+        let pool = BufferPool::new(1);
+        let a = pool.get(16);
+        assert_eq!(a.len(), 16);
+        let a_ptr = a.as_ptr();
+        let b = pool.get(16);
+        pool.put(a);
+        pool.put(b);
+        assert_eq!(pool.buffers.lock().unwrap().len(), 1);
+        let c = pool.get(16);
+        assert_eq!(c.as_ptr(), a_ptr);
+        assert_eq!(pool.buffers.lock().unwrap().len(), 0);
+
+        // Buffers with a different size are not reused.
+        pool.put(c);
+        assert_eq!(pool.get(32).len(), 32);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,165 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+/// Size of one address marker, in bytes.
+/// This is a multiple of all common sector sizes and of the direct I/O alignment.
+pub const MARKER_SIZE: usize = 4096;
+/// Default number of address markers that are spread over the device.
+pub const DEFAULT_NR_MARKERS: u64 = 1024;
+
+/// Magic value at the start of each marker record.
+const MARKER_MAGIC: &[u8; 8] = b"DTCAPMRK";
+/// Size of one record within the marker.
+const RECORD_SIZE: usize = 32;
+
+/// Result of a capacity check.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DtCapacity {
+    /// The capacity that the device claims to have, in bytes.
+    pub claimed:    u64,
+    /// The capacity that is actually usable, in bytes.
+    pub usable:     u64,
+    /// The number of markers that have been written.
+    pub markers:    usize,
+    /// The number of markers that could not be read back correctly.
+    pub bad:        usize,
+    /// The distance between two markers, in bytes.
+    pub resolution: u64,
+}
+
+impl DtCapacity {
+    /// Check if the device is a fake device with less usable capacity than claimed.
+    pub fn is_fake(&self) -> bool {
+        self.usable < self.claimed
+    }
+}
+
+/// Calculate the marker positions for a device of the given size.
+/// The positions are relative to the start of the test area.
+/// The last marker is always placed at the very end of the area.
+pub fn marker_positions(size: u64, nr_markers: u64) -> Vec<u64> {
+    let marker_size = MARKER_SIZE as u64;
+    if size < marker_size {
+        return vec![];
+    }
+    let last = size - (size % marker_size) - marker_size;
+    let step = (size / nr_markers.max(1)).max(marker_size);
+    let step = step - (step % marker_size);
+    let mut positions: Vec<u64> = (0..).map(|i| i * step)
+                                       .take_while(|&p| p < last)
+                                       .collect();
+    positions.push(last);
+    positions
+}
+
+/// Build the marker for the absolute device offset.
+/// The token identifies the run, so that markers of previous runs are not accepted.
+pub fn make_marker(buffer: &mut [u8], token: u64, offset: u64) {
+    let mut record = [0u8; RECORD_SIZE];
+    record[0..8].copy_from_slice(MARKER_MAGIC);
+    record[8..16].copy_from_slice(&token.to_le_bytes());
+    record[16..24].copy_from_slice(&offset.to_le_bytes());
+    record[24..32].copy_from_slice(&(!offset).to_le_bytes());
+    for chunk in buffer[0..MARKER_SIZE].chunks_mut(RECORD_SIZE) {
+        chunk.copy_from_slice(&record);
+    }
+}
+
+/// Parse a marker that has been read back from the device.
+/// Returns the offset stored in the marker, or None if the marker is invalid.
+pub fn parse_marker(buffer: &[u8], token: u64) -> Option<u64> {
+    let record = &buffer[0..RECORD_SIZE];
+    if &record[0..8] != MARKER_MAGIC ||
+       record[8..16] != token.to_le_bytes() {
+        return None;
+    }
+    let mut offset = [0u8; 8];
+    offset.copy_from_slice(&record[16..24]);
+    let offset = u64::from_le_bytes(offset);
+    if record[24..32] != (!offset).to_le_bytes() ||
+       !buffer[0..MARKER_SIZE].chunks(RECORD_SIZE).all(|c| c == record) {
+        return None;
+    }
+    Some(offset)
+}
+
+/// Evaluate the markers that have been read back.
+/// positions are the absolute marker offsets in ascending order
+/// and found are the offsets read from the markers.
+/// A marker that contains the offset of another marker is stored at a real location,
+/// but the other marker's address wraps around to it. The other address is not usable.
+/// Returns the index of the first unusable marker, if any.
+pub fn first_bad_marker(positions: &[u64], found: &[Option<u64>]) -> Option<usize> {
+    let mut bad = vec![false; positions.len()];
+    for (i, (&pos, &found)) in positions.iter().zip(found).enumerate() {
+        match found {
+            Some(offset) if offset == pos => (),
+            Some(offset) => {
+                match positions.binary_search(&offset) {
+                    Ok(j) => bad[j.max(i)] = true,
+                    Err(_) => bad[i] = true,
+                }
+            },
+            None => bad[i] = true,
+        }
+    }
+    bad.iter().position(|&b| b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_marker_positions() {
+        assert_eq!(marker_positions(4095, 4), Vec::<u64>::new());
+        assert_eq!(marker_positions(4096, 4), vec![0]);
+        assert_eq!(marker_positions(4096 * 8 + 100, 4), vec![0, 8192, 16384, 24576, 28672]);
+        assert_eq!(marker_positions(4096 * 3, 100), vec![0, 4096, 8192]);
+        let pos = marker_positions(1 << 30, DEFAULT_NR_MARKERS);
+        assert_eq!(pos.len(), DEFAULT_NR_MARKERS as usize + 1);
+        assert_eq!(*pos.last().unwrap(), (1 << 30) - 4096);
+    }
+
+    #[test]
+    fn test_marker() {
+        let mut buffer = vec![0u8; MARKER_SIZE];
+        make_marker(&mut buffer, 42, 0x1234_5678_9000);
+        assert_eq!(parse_marker(&buffer, 42), Some(0x1234_5678_9000));
+        assert_eq!(parse_marker(&buffer, 43), None);
+        buffer[MARKER_SIZE - 1] ^= 1;
+        assert_eq!(parse_marker(&buffer, 42), None);
+        assert_eq!(parse_marker(&[0u8; MARKER_SIZE], 0), None);
+    }
+
+    #[test]
+    fn test_first_bad_marker() {
+        let pos = vec![0, 100, 200, 300];
+        assert_eq!(first_bad_marker(&pos, &[Some(0), Some(100), Some(200), Some(300)]), None);
+        // Addresses above 200 wrap around to 0.
+        assert_eq!(first_bad_marker(&pos, &[Some(200), Some(300), Some(200), Some(300)]), Some(2));
+        // Writes above 200 are dropped.
+        assert_eq!(first_bad_marker(&pos, &[Some(0), Some(100), None, None]), Some(2));
+        assert_eq!(first_bad_marker(&pos, &[Some(0), Some(123), Some(200), Some(300)]), Some(1));
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,301 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+use std::fs::File;
+use std::path::Path;
+
+#[cfg(target_os="linux")]
+fn os_disk_size(file: &File) -> ah::Result<Option<u64>> {
+    use std::mem::size_of;
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::io::AsRawFd;
+
+    if !file.metadata()?.file_type().is_block_device() {
+        return Ok(None);
+    }
+
+    // BLKGETSIZE64 = _IOR(0x12, 114, size_t)
+    const IOC_READ: libc::c_ulong = 2;
+    const BLKGETSIZE64: libc::c_ulong = (IOC_READ << 30) |
+                                        ((size_of::<usize>() as libc::c_ulong) << 16) |
+                                        (0x12 << 8) |
+                                        114;
+
+    let mut size: u64 = 0;
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(),
+                                   BLKGETSIZE64 as _,
+                                   &mut size as *mut u64) };
+    if ret < 0 {
+        Err(ah::format_err!("BLKGETSIZE64 failed: {}",
+                            std::io::Error::last_os_error()))
+    } else {
+        Ok(Some(size))
+    }
+}
+
+#[cfg(target_os="windows")]
+fn os_disk_size(file: &File) -> ah::Result<Option<u64>> {
+    use std::mem::{size_of, zeroed};
+    use std::os::windows::io::AsRawHandle;
+    use std::ptr::null_mut;
+    use winapi::shared::minwindef::{DWORD, LPVOID};
+    use winapi::um::ioapiset::DeviceIoControl;
+    use winapi::um::winioctl::{GET_LENGTH_INFORMATION, IOCTL_DISK_GET_LENGTH_INFO};
+
+    let mut info: GET_LENGTH_INFORMATION = unsafe { zeroed() };
+    let mut count: DWORD = 0;
+    let ok = unsafe { DeviceIoControl(file.as_raw_handle() as _,
+                                      IOCTL_DISK_GET_LENGTH_INFO,
+                                      null_mut(),
+                                      0,
+                                      &mut info as *mut _ as LPVOID,
+                                      size_of::<GET_LENGTH_INFORMATION>() as DWORD,
+                                      &mut count,
+                                      null_mut()) };
+    if ok == 0 {
+        // Not a disk device.
+        Ok(None)
+    } else {
+        Ok(Some(unsafe { *info.Length.QuadPart() } as u64))
+    }
+}
+
+/// Check if the file is a macOS disk device (/dev/diskN or /dev/rdiskN).
+#[cfg(target_os="macos")]
+fn is_macos_disk(file: &File) -> ah::Result<bool> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let file_type = file.metadata()?.file_type();
+    Ok(file_type.is_block_device() || file_type.is_char_device())
+}
+
+// DKIOCGETBLOCKSIZE = _IOR('d', 24, uint32_t)
+#[cfg(target_os="macos")]
+const DKIOCGETBLOCKSIZE: libc::c_ulong = 0x40046418;
+// DKIOCGETBLOCKCOUNT = _IOR('d', 25, uint64_t)
+#[cfg(target_os="macos")]
+const DKIOCGETBLOCKCOUNT: libc::c_ulong = 0x40086419;
+
+#[cfg(target_os="macos")]
+fn os_disk_size(file: &File) -> ah::Result<Option<u64>> {
+    use std::os::unix::io::AsRawFd;
+
+    if !is_macos_disk(file)? {
+        return Ok(None);
+    }
+
+    let block_size = match os_disk_sector_size(file)? {
+        Some(block_size) => block_size,
+        None => return Ok(None),
+    };
+    let mut block_count: u64 = 0;
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(),
+                                   DKIOCGETBLOCKCOUNT,
+                                   &mut block_count as *mut u64) };
+    if ret < 0 {
+        Err(ah::format_err!("DKIOCGETBLOCKCOUNT failed: {}",
+                            std::io::Error::last_os_error()))
+    } else {
+        Ok(Some(block_count * block_size as u64))
+    }
+}
+
+#[cfg(not(any(target_os="linux", target_os="windows", target_os="macos")))]
+fn os_disk_size(_file: &File) -> ah::Result<Option<u64>> {
+    Ok(None)
+}
+
+#[cfg(target_os="linux")]
+fn os_disk_sector_size(file: &File) -> ah::Result<Option<u32>> {
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::io::AsRawFd;
+
+    if !file.metadata()?.file_type().is_block_device() {
+        return Ok(None);
+    }
+
+    // BLKSSZGET = _IO(0x12, 104)
+    const BLKSSZGET: libc::c_ulong = (0x12 << 8) | 104;
+
+    let mut size: libc::c_int = 0;
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(),
+                                   BLKSSZGET as _,
+                                   &mut size as *mut libc::c_int) };
+    if ret < 0 || size <= 0 {
+        Err(ah::format_err!("BLKSSZGET failed: {}",
+                            std::io::Error::last_os_error()))
+    } else {
+        Ok(Some(size as u32))
+    }
+}
+
+#[cfg(target_os="windows")]
+fn os_disk_sector_size(file: &File) -> ah::Result<Option<u32>> {
+    use std::mem::{size_of, zeroed};
+    use std::os::windows::io::AsRawHandle;
+    use std::ptr::null_mut;
+    use winapi::shared::minwindef::{DWORD, LPVOID};
+    use winapi::um::ioapiset::DeviceIoControl;
+    use winapi::um::winioctl::{DISK_GEOMETRY_EX, IOCTL_DISK_GET_DRIVE_GEOMETRY_EX};
+
+    let mut geometry: DISK_GEOMETRY_EX = unsafe { zeroed() };
+    let mut count: DWORD = 0;
+    let ok = unsafe { DeviceIoControl(file.as_raw_handle() as _,
+                                      IOCTL_DISK_GET_DRIVE_GEOMETRY_EX,
+                                      null_mut(),
+                                      0,
+                                      &mut geometry as *mut _ as LPVOID,
+                                      size_of::<DISK_GEOMETRY_EX>() as DWORD,
+                                      &mut count,
+                                      null_mut()) };
+    if ok == 0 || geometry.Geometry.BytesPerSector == 0 {
+        // Not a disk device.
+        Ok(None)
+    } else {
+        Ok(Some(geometry.Geometry.BytesPerSector))
+    }
+}
+
+#[cfg(target_os="macos")]
+fn os_disk_sector_size(file: &File) -> ah::Result<Option<u32>> {
+    use std::os::unix::io::AsRawFd;
+
+    if !is_macos_disk(file)? {
+        return Ok(None);
+    }
+
+    let mut block_size: u32 = 0;
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(),
+                                   DKIOCGETBLOCKSIZE,
+                                   &mut block_size as *mut u32) };
+    if ret < 0 || block_size == 0 {
+        Err(ah::format_err!("DKIOCGETBLOCKSIZE failed: {}",
+                            std::io::Error::last_os_error()))
+    } else {
+        Ok(Some(block_size))
+    }
+}
+
+#[cfg(not(any(target_os="linux", target_os="windows", target_os="macos")))]
+fn os_disk_sector_size(_file: &File) -> ah::Result<Option<u32>> {
+    Ok(None)
+}
+
+#[cfg(unix)]
+fn os_free_space(path: &Path) -> ah::Result<Option<u64>> {
+    use std::ffi::CString;
+    use std::mem::zeroed;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat: libc::statvfs = unsafe { zeroed() };
+    let ret = unsafe { libc::statvfs(path.as_ptr(), &mut stat) };
+    if ret < 0 {
+        Err(ah::format_err!("statvfs failed: {}",
+                            std::io::Error::last_os_error()))
+    } else {
+        #[allow(clippy::unnecessary_cast)]
+        Ok(Some(stat.f_bavail as u64 * stat.f_frsize as u64))
+    }
+}
+
+#[cfg(target_os="windows")]
+fn os_free_space(path: &Path) -> ah::Result<Option<u64>> {
+    use std::mem::zeroed;
+    use std::os::windows::ffi::OsStrExt;
+    use std::ptr::null_mut;
+    use winapi::shared::ntdef::ULARGE_INTEGER;
+    use winapi::um::fileapi::GetDiskFreeSpaceExW;
+
+    let path: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut free: ULARGE_INTEGER = unsafe { zeroed() };
+    let ok = unsafe { GetDiskFreeSpaceExW(path.as_ptr(),
+                                          &mut free,
+                                          null_mut(),
+                                          null_mut()) };
+    if ok == 0 {
+        Err(ah::format_err!("GetDiskFreeSpaceExW failed: {}",
+                            std::io::Error::last_os_error()))
+    } else {
+        Ok(Some(unsafe { *free.QuadPart() }))
+    }
+}
+
+#[cfg(not(any(unix, target_os="windows")))]
+fn os_free_space(_path: &Path) -> ah::Result<Option<u64>> {
+    Ok(None)
+}
+
+/// Get the size of a disk device, in bytes.
+/// Returns None, if the file is not a disk device (e.g. a regular file).
+pub fn disk_size(file: &File) -> ah::Result<Option<u64>> {
+    os_disk_size(file)
+}
+
+/// Get the logical sector size of a disk device, in bytes.
+/// All accesses to the raw device must be aligned to this size.
+/// Returns None, if the file is not a disk device (e.g. a regular file).
+pub fn disk_sector_size(file: &File) -> ah::Result<Option<u32>> {
+    os_disk_sector_size(file)
+}
+
+/// Get the free space of the file system that contains the path, in bytes.
+/// This is the space that is available to the current user.
+/// Returns None, if the free space can't be determined on this operating system.
+pub fn free_space(path: &Path) -> ah::Result<Option<u64>> {
+    os_free_space(path)
+}
+
+/// Check if the path is a Windows raw device path (e.g. \\.\PhysicalDrive1).
+pub fn is_windows_device_path(path: &str) -> bool {
+    path.starts_with("\\\\.\\") || path.starts_with("//./")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_disk_size() {
+        let tfile = NamedTempFile::new().unwrap();
+        assert_eq!(disk_size(tfile.as_file()).unwrap(), None);
+        assert_eq!(disk_sector_size(tfile.as_file()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_free_space() {
+        let tdir = tempfile::tempdir().unwrap();
+        assert!(free_space(tdir.path()).unwrap().unwrap() > 0);
+        assert!(free_space(&tdir.path().join("nonexistent")).is_err());
+    }
+
+    #[test]
+    fn test_windows_device_path() {
+        assert!(is_windows_device_path("\\\\.\\PhysicalDrive1"));
+        assert!(is_windows_device_path("//./PhysicalDrive1"));
+        assert!(!is_windows_device_path("D:\\testfile.img"));
+        assert!(!is_windows_device_path("/dev/sdc"));
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,3724 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+use crate::affinity::{affinity_supported, pin_current_thread};
+use crate::aligned_buffer::AlignedBuffer;
+use crate::capacity::{DEFAULT_NR_MARKERS, DtCapacity, MARKER_SIZE, first_bad_marker, make_marker, marker_positions, parse_marker};
+use crate::disk_size::{disk_size, disk_sector_size, is_windows_device_path};
+use crate::drop_caches::{drop_file_caches, drop_range_caches};
+use crate::error::{DtError, DtIoOp};
+use crate::header::{DtHeader, HEADER_SIZE, HEADER_VERSION, seed_fingerprint};
+use crate::kdf::DtKdf;
+use crate::latency::DtLatency;
+use crate::mismatch::describe_mismatch;
+use crate::mmap::{DtMmap, MMAP_WINDOW};
+use crate::preallocate::preallocate;
+use crate::progress::{ConsoleProgress, DtPhase, JsonProgress, JsonSink, Progress, ProgressObserver};
+use crate::random_io::{DtRandomIo, RANDOM_IO_SIZE, random_offset};
+use crate::reader::DtStreamReader;
+use rand::Rng;
+use crate::stream::{DtStreamChunk, stream_key};
+use crate::stream_aggregator::DtStreamAgg;
+use crate::throttle::Throttle;
+use crate::trail::DtTrail;
+use crate::zones::DtZones;
+use std::cmp::{max, min};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write, Seek, SeekFrom};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, SyncSender, sync_channel};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(not(target_os="windows"))]
+use libc::ENOSPC;
+#[cfg(target_os="windows")]
+use winapi::shared::winerror::ERROR_DISK_FULL as ENOSPC;
+
+pub use crate::stream_aggregator::DtStreamType;
+
+const LOG_BYTE_THRES: u64   = 1024 * 1024;
+/// Number of bytes that are compared to find the cause of a mismatch in the first chunk.
+const DIAGNOSE_LEN: usize   = 64 * 1024;
+const CHECKPOINT_SEC_THRES: u64 = 60;
+/// Maximum time of one sleep of the rate limit.
+const THROTTLE_STEP: Duration = Duration::from_millis(100);
+/// Interval of checking the pause flag while paused.
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Size of the written regions, whose operating system caches are dropped while writing.
+const CACHE_DROP_REGION: u64 = 64 * 1024 * 1024;
+
+/// Alignment of memory buffers, file offsets and access sizes in direct I/O mode.
+const DIRECT_IO_ALIGN: usize = 4096;
+/// Number of chunks that are read ahead from the other device of a comparison.
+const COMPARE_QUEUE_LEN: usize = 2;
+/// Interval of checking the position of a followed write.
+const TRAIL_POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// Delay before the first retry of a failed read. It doubles with each further retry.
+const READ_RETRY_DELAY: Duration = Duration::from_millis(100);
+/// Maximum delay between two retries of a failed read.
+const READ_RETRY_MAX_DELAY: Duration = Duration::from_secs(10);
+
+/// Open flag that makes every write durable, when it is acknowledged.
+#[cfg(any(target_os="linux", target_os="android", target_os="macos"))]
+const SYNC_FLAG: libc::c_int = libc::O_DSYNC;
+/// Open flag that makes every write durable, when it is acknowledged.
+#[cfg(all(unix, not(any(target_os="linux", target_os="android", target_os="macos"))))]
+const SYNC_FLAG: libc::c_int = libc::O_SYNC;
+/// Open flag that makes every write durable, when it is acknowledged.
+#[cfg(target_os="windows")]
+const SYNC_FLAG: u32 = winapi::um::winbase::FILE_FLAG_WRITE_THROUGH;
+
+/// Open a file with synchronous writes, if sync is true.
+#[cfg(any(unix, target_os="windows"))]
+fn os_open(options: &mut OpenOptions, path: &Path, sync: bool) -> io::Result<File> {
+    #[cfg(unix)]
+    use std::os::unix::fs::OpenOptionsExt;
+    #[cfg(target_os="windows")]
+    use std::os::windows::fs::OpenOptionsExt;
+
+    if sync {
+        options.custom_flags(SYNC_FLAG);
+    }
+    options.open(path)
+}
+
+/// Open a file with synchronous writes, if sync is true.
+#[cfg(not(any(unix, target_os="windows")))]
+fn os_open(options: &mut OpenOptions, path: &Path, sync: bool) -> io::Result<File> {
+    if sync {
+        return Err(io::Error::other("Synchronous writes are not supported on this operating system."));
+    }
+    options.open(path)
+}
+
+/// Open a file with disabled operating system caches.
+#[cfg(target_os="linux")]
+fn os_open_direct(options: &mut OpenOptions, path: &Path, sync: bool) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let flags = if sync { libc::O_DIRECT | SYNC_FLAG } else { libc::O_DIRECT };
+    options.custom_flags(flags).open(path)
+}
+
+/// Open a file with disabled operating system caches.
+#[cfg(target_os="windows")]
+fn os_open_direct(options: &mut OpenOptions, path: &Path, sync: bool) -> io::Result<File> {
+    use std::os::windows::fs::OpenOptionsExt;
+    use winapi::um::winbase::FILE_FLAG_NO_BUFFERING;
+
+    let flags = if sync { FILE_FLAG_NO_BUFFERING | SYNC_FLAG } else { FILE_FLAG_NO_BUFFERING };
+    options.custom_flags(flags).open(path)
+}
+
+/// Open a file with disabled operating system caches.
+#[cfg(target_os="macos")]
+fn os_open_direct(options: &mut OpenOptions, path: &Path, sync: bool) -> io::Result<File> {
+    use std::os::unix::io::AsRawFd;
+
+    let file = os_open(options, path, sync)?;
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_NOCACHE, 1) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(file)
+}
+
+/// Open a file with disabled operating system caches.
+#[cfg(not(any(target_os="linux", target_os="windows", target_os="macos")))]
+fn os_open_direct(_options: &mut OpenOptions, _path: &Path, _sync: bool) -> io::Result<File> {
+    Err(io::Error::other("Direct I/O is not supported on this operating system."))
+}
+
+/// Sort the regions (offset, length) and merge the overlapping and adjacent regions.
+/// Empty regions are removed.
+fn merge_regions(regions: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    let mut regions = regions;
+    regions.retain(|&(_, length)| length > 0);
+    regions.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(regions.len());
+    for (offset, length) in regions {
+        match merged.last_mut() {
+            Some(last) if offset <= last.0 + last.1 => {
+                last.1 = max(last.1, offset + length - last.0);
+            },
+            _ => merged.push((offset, length)),
+        }
+    }
+    merged
+}
+
+/// Path that selects the standard input (for reading)
+/// or the standard output (for writing) instead of a file.
+pub const STDIO_PATH: &str = "-";
+
+/// The opened file or standard stream.
+enum DtFileHandle {
+    File(File),
+    /// The standard input. It can only be read sequentially.
+    Stdin(io::Stdin),
+    /// The standard output. It can only be written sequentially.
+    Stdout(io::Stdout),
+}
+
+/// A file or disk device that is used by the Disktest core.
+pub struct DisktestFile {
+    file:           Option<DtFileHandle>,
+    path:           PathBuf,
+    disk_size:      Option<u64>,
+    sector_size:    Option<u32>,
+    direct:         bool,
+    seek_offset:    u64,
+    write_count:    u64,
+    quiet_level:    u8,
+    /// Absolute position of a standard stream, once it has been set by the first seek.
+    stream_pos:     Option<u64>,
+}
+
+impl DisktestFile {
+    /// Open a file for use by the Disktest core.
+    /// The path STDIO_PATH opens the standard input for reading
+    /// or the standard output for writing.
+    /// With sync, every write is durable on the device, when it returns.
+    pub fn open(path:           &str,
+                read:           bool,
+                write:          bool,
+                direct:         bool,
+                sync:           bool,
+                quiet_level:    u8) -> ah::Result<DisktestFile> {
+        if path == STDIO_PATH {
+            return DisktestFile::open_stdio(read, write, direct, sync, quiet_level);
+        }
+
+        // Raw Windows devices (e.g. \\.\PhysicalDrive1) can't be created.
+        let create = write && !is_windows_device_path(path);
+
+        let path = Path::new(path);
+        let mut options = OpenOptions::new();
+        options.read(read)
+               .write(write)
+               .create(create);
+        let file = if direct {
+            os_open_direct(&mut options, path, sync)
+        } else {
+            os_open(&mut options, path, sync)
+        };
+        let file = match file {
+            Ok(f) => f,
+            Err(e) => {
+                return Err(ah::format_err!("Failed to open file {:?}: {}", path, e));
+            },
+        };
+
+        let disk_size = match disk_size(&file) {
+            Ok(size) => size,
+            Err(e) => {
+                eprintln!("WARNING: Failed to get the disk size: {}", e);
+                None
+            },
+        };
+        let sector_size = match disk_sector_size(&file) {
+            Ok(size) => size,
+            Err(e) => {
+                eprintln!("WARNING: Failed to get the disk sector size: {}", e);
+                None
+            },
+        };
+
+        Ok(DisktestFile {
+            file:           Some(DtFileHandle::File(file)),
+            path:           path.to_path_buf(),
+            disk_size,
+            sector_size,
+            direct,
+            seek_offset:    0,
+            write_count:    0,
+            quiet_level,
+            stream_pos:     None,
+        })
+    }
+
+    /// Open the standard input for reading or the standard output for writing.
+    fn open_stdio(read:         bool,
+                  write:        bool,
+                  direct:       bool,
+                  sync:         bool,
+                  quiet_level:  u8) -> ah::Result<DisktestFile> {
+        if read == write {
+            return Err(DtError::InvalidParameter(
+                "The standard streams can either be read or written.".to_string()).into());
+        }
+        if direct {
+            return Err(DtError::InvalidParameter(
+                "Direct I/O is not possible on the standard streams.".to_string()).into());
+        }
+        if sync {
+            return Err(DtError::InvalidParameter(
+                "Synchronous writes are not possible on the standard streams.".to_string()).into());
+        }
+        let (file, name) = if write {
+            (DtFileHandle::Stdout(io::stdout()), "<stdout>")
+        } else {
+            (DtFileHandle::Stdin(io::stdin()), "<stdin>")
+        };
+        Ok(DisktestFile {
+            file:           Some(file),
+            path:           PathBuf::from(name),
+            disk_size:      None,
+            sector_size:    None,
+            direct,
+            seek_offset:    0,
+            write_count:    0,
+            quiet_level,
+            stream_pos:     None,
+        })
+    }
+
+    /// Seek to a position in the file.
+    /// A standard stream starts at the position of the first seek.
+    /// Afterwards it can't be moved anymore.
+    fn seek(&mut self, offset: u64) -> io::Result<u64> {
+        let result = match self.file.as_mut() {
+            Some(DtFileHandle::File(f)) => f.seek(SeekFrom::Start(offset)),
+            Some(_) => match self.stream_pos {
+                Some(pos) if pos != offset => {
+                    Err(io::Error::other("Seeking is not possible on the standard streams."))
+                },
+                _ => {
+                    self.stream_pos = Some(offset);
+                    Ok(offset)
+                },
+            },
+            None => Err(io::Error::other("File already closed.")),
+        };
+        if result.is_ok() {
+            self.seek_offset = offset;
+        }
+        result
+    }
+
+    /// Get the current position in the file.
+    fn position(&mut self) -> io::Result<u64> {
+        match self.file.as_mut() {
+            Some(DtFileHandle::File(f)) => f.stream_position(),
+            Some(_) => self.stream_pos.ok_or_else(|| io::Error::other("The stream has not been started.")),
+            None => Err(io::Error::other("File already closed.")),
+        }
+    }
+
+    /// Sync all written data to disk.
+    fn sync(&mut self) -> io::Result<()> {
+        match self.file.as_mut() {
+            Some(DtFileHandle::File(f)) => f.sync_all(),
+            Some(DtFileHandle::Stdout(f)) => f.flush(),
+            Some(DtFileHandle::Stdin(_)) => Ok(()),
+            None => Err(io::Error::other("File already closed.")),
+        }
+    }
+
+    /// Sync the written data to disk without the metadata that is not needed to read it.
+    fn sync_data(&mut self) -> io::Result<()> {
+        match self.file.as_mut() {
+            Some(DtFileHandle::File(f)) => f.sync_data(),
+            Some(DtFileHandle::Stdout(f)) => f.flush(),
+            Some(DtFileHandle::Stdin(_)) => Ok(()),
+            None => Err(io::Error::other("File already closed.")),
+        }
+    }
+
+    /// Read data from the file.
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let count = match self.file.as_mut() {
+            Some(DtFileHandle::File(f)) => f.read(buffer)?,
+            Some(DtFileHandle::Stdin(f)) => f.read(buffer)?,
+            Some(DtFileHandle::Stdout(_)) => {
+                return Err(io::Error::other("The standard output can't be read."));
+            },
+            None => return Err(io::Error::other("File already closed.")),
+        };
+        if let Some(pos) = self.stream_pos.as_mut() {
+            *pos += count as u64;
+        }
+        Ok(count)
+    }
+
+    /// Write data to the file.
+    fn write(&mut self, buffer: &[u8]) -> io::Result<()> {
+        match self.file.as_mut() {
+            Some(DtFileHandle::File(f)) => f.write_all(buffer)?,
+            Some(DtFileHandle::Stdout(f)) => f.write_all(buffer)?,
+            Some(DtFileHandle::Stdin(_)) => {
+                return Err(io::Error::other("The standard input can't be written."));
+            },
+            None => return Err(io::Error::other("File already closed.")),
+        }
+        self.write_count += buffer.len() as u64;
+        if let Some(pos) = self.stream_pos.as_mut() {
+            *pos += buffer.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// Close the file and try to drop all write caches.
+    fn close(&mut self) {
+        // Take and destruct the File object.
+        match self.file.take() {
+            Some(DtFileHandle::File(file)) => {
+                // If bytes have been written, try to drop the operating system caches.
+                if self.write_count > 0 {
+                    // Pass the File object to the dropper.
+                    // It will destruct the File object.
+                    if let Err(e) = drop_file_caches(file,
+                                                     self.path.as_path(),
+                                                     self.seek_offset,
+                                                     self.write_count) {
+                        eprintln!("WARNING: Failed to drop operating system caches: {}", e);
+                    } else if self.quiet_level < 1 {
+                        println!("Write done and successfully dropped file caches.");
+                    }
+                    self.write_count = 0;
+                }
+            },
+            // The standard streams have no caches to drop.
+            Some(DtFileHandle::Stdout(mut f)) => {
+                let _ = f.flush();
+            },
+            Some(DtFileHandle::Stdin(_)) | None => (),
+        }
+    }
+
+    /// Try to drop the operating system caches of the whole file.
+    fn drop_caches(&self) -> ah::Result<()> {
+        match self.file.as_ref() {
+            Some(DtFileHandle::File(f)) => drop_file_caches(f.try_clone()?, self.path.as_path(), 0, 0),
+            Some(_) => Ok(()),
+            None => Err(ah::format_err!("File already closed.")),
+        }
+    }
+
+    /// Try to drop the operating system caches of the written region
+    /// at the absolute offset, so that a long write does not evict the caches
+    /// of everything else on the system.
+    fn drop_range_caches(&self, offset: u64, len: u64) -> ah::Result<()> {
+        match self.file.as_ref() {
+            Some(DtFileHandle::File(f)) if !self.direct => drop_range_caches(f, offset, len),
+            _ => Ok(()),
+        }
+    }
+
+    /// Preallocate len bytes at the absolute offset, if the file is a regular file.
+    /// Unsupported preallocation is ignored.
+    fn preallocate(&self, offset: u64, len: u64) -> io::Result<()> {
+        match self.file.as_ref() {
+            Some(DtFileHandle::File(f)) if f.metadata()?.is_file() => {
+                match preallocate(f, offset, len) {
+                    Err(e) if e.kind() == io::ErrorKind::Unsupported => Ok(()),
+                    result => result,
+                }
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Get the size of the file, if it is a regular file that can be memory mapped.
+    fn mappable_size(&self) -> Option<u64> {
+        match self.file.as_ref() {
+            Some(DtFileHandle::File(f)) if DtMmap::supported() && !self.direct => {
+                match f.metadata() {
+                    Ok(meta) if meta.is_file() => Some(meta.len()),
+                    _ => None,
+                }
+            },
+            _ => None,
+        }
+    }
+
+    /// Map len bytes of the file at the absolute offset read-only.
+    fn map(&self, offset: u64, len: usize) -> io::Result<DtMmap> {
+        match self.file.as_ref() {
+            Some(DtFileHandle::File(f)) => DtMmap::map(f, offset, len),
+            Some(_) => Err(io::Error::other("The standard streams can't be memory mapped.")),
+            None => Err(io::Error::other("File already closed.")),
+        }
+    }
+
+    /// Get a reference to the PathBuf in use.
+    fn get_path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// Get the size of the disk device, if the file is a disk device.
+    pub fn get_disk_size(&self) -> Option<u64> {
+        self.disk_size
+    }
+
+    /// Get the logical sector size of the disk device, if the file is a disk device.
+    pub fn get_sector_size(&self) -> Option<u32> {
+        self.sector_size
+    }
+
+    /// Check if the file has been opened in direct I/O mode.
+    pub fn is_direct(&self) -> bool {
+        self.direct
+    }
+
+    /// Check if the file is the standard input or output.
+    pub fn is_stdio(&self) -> bool {
+        matches!(self.file, Some(DtFileHandle::Stdin(_)) | Some(DtFileHandle::Stdout(_)))
+    }
+
+}
+
+impl Drop for DisktestFile {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Reader of the other device of a comparison.
+/// The device is read chunk by chunk in a separate thread,
+/// in parallel to the compared device.
+struct CompareReader {
+    receiver:       Receiver<ah::Result<Vec<u8>>>,
+}
+
+impl CompareReader {
+    /// Seek to the absolute byte offset and start reading.
+    fn start(file: DisktestFile,
+             seek: u64,
+             chunk_size: usize) -> ah::Result<CompareReader> {
+        let mut file = file;
+        if let Err(e) = file.seek(seek) {
+            return Err(DtError::Io { op: DtIoOp::Seek, offset: seek, source: e }.into());
+        }
+        let (sender, receiver) = sync_channel(COMPARE_QUEUE_LEN);
+        thread::spawn(move || CompareReader::thread(file, seek, chunk_size, sender));
+        Ok(CompareReader {
+            receiver,
+        })
+    }
+
+    /// Read chunks until the end of the device, a read error,
+    /// or until the receiver is dropped.
+    fn thread(file: DisktestFile,
+              seek: u64,
+              chunk_size: usize,
+              sender: SyncSender<ah::Result<Vec<u8>>>) {
+        let mut file = file;
+        let mut offset = seek;
+        let mut buffer = AlignedBuffer::new(chunk_size, DIRECT_IO_ALIGN);
+        loop {
+            let mut count = 0;
+            while count < chunk_size {
+                match file.read(&mut buffer[count..]) {
+                    Ok(0) => break,
+                    Ok(n) => count += n,
+                    Err(e) => {
+                        let e = DtError::Io { op: DtIoOp::Read, offset: offset + count as u64, source: e };
+                        let _ = sender.send(Err(e.into()));
+                        return;
+                    },
+                }
+            }
+            if sender.send(Ok(buffer[..count].to_vec())).is_err() || count < chunk_size {
+                return;
+            }
+            offset += count as u64;
+        }
+    }
+
+    /// Get the next chunk.
+    /// It is shorter than the chunk size at the end of the device.
+    fn next_chunk(&mut self) -> ah::Result<Vec<u8>> {
+        match self.receiver.recv() {
+            Ok(result) => result,
+            // The thread stopped at the end of the device.
+            Err(_) => Ok(Vec::new()),
+        }
+    }
+}
+
+/// Default granularity of bad regions, if the sector size is unknown.
+const DEFAULT_ERROR_BLOCK_SIZE: u64 = 512;
+
+/// Kind of an error that occurred during write or verify.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DtErrorKind {
+    Write,
+    Read,
+    Mismatch,
+    /// The device ended before all data could be read.
+    ShortRead,
+    /// The device was full before all data could be written.
+    ShortWrite,
+}
+
+impl DtErrorKind {
+    /// All error kinds.
+    pub const ALL: [DtErrorKind; 5] = [
+        DtErrorKind::Mismatch,
+        DtErrorKind::Read,
+        DtErrorKind::Write,
+        DtErrorKind::ShortRead,
+        DtErrorKind::ShortWrite,
+    ];
+}
+
+impl std::fmt::Display for DtErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DtErrorKind::Write => write!(f, "write error"),
+            DtErrorKind::Read => write!(f, "read error"),
+            DtErrorKind::Mismatch => write!(f, "data mismatch"),
+            DtErrorKind::ShortRead => write!(f, "short read"),
+            DtErrorKind::ShortWrite => write!(f, "short write"),
+        }
+    }
+}
+
+/// Record of a bad region that was found during write or verify.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DtErrorRecord {
+    /// Kind of the error.
+    pub kind:       DtErrorKind,
+    /// Absolute byte offset of the bad region on the device.
+    pub offset:     u64,
+    /// Length of the bad region, in bytes.
+    pub length:     u64,
+    /// Description of the first error in the region.
+    pub message:    String,
+}
+
+/// Builder for the configuration of a Disktest instance.
+pub struct DisktestBuilder {
+    algorithm:      DtStreamType,
+    seed:           Vec<u8>,
+    kdf:            DtKdf,
+    threads:        usize,
+    chunk_size:     Option<usize>,
+    quiet_level:    u8,
+    abort:          Option<Arc<AtomicBool>>,
+    pause:          Option<Arc<AtomicBool>>,
+    keep_going:     bool,
+    max_errors:     Option<usize>,
+    header:         Option<u64>,
+    max_rate:       Option<u64>,
+    zones:          Option<usize>,
+    verify_stride:  u64,
+    read_retries:   u32,
+    skip:           Vec<(u64, u64)>,
+    mmap:           bool,
+    sync_every:     Option<u64>,
+    cpus:           Option<Vec<usize>>,
+    observers:      Vec<Box<dyn ProgressObserver>>,
+}
+
+impl DisktestBuilder {
+    /// Create a new builder with the default configuration.
+    pub fn new() -> DisktestBuilder {
+        DisktestBuilder {
+            algorithm:      DtStreamType::CHACHA20,
+            seed:           Vec::new(),
+            kdf:            DtKdf::default(),
+            threads:        1,
+            chunk_size:     None,
+            quiet_level:    0,
+            abort:          None,
+            pause:          None,
+            keep_going:     false,
+            max_errors:     None,
+            header:         None,
+            max_rate:       None,
+            zones:          None,
+            verify_stride:  1,
+            read_retries:   0,
+            skip:           Vec::new(),
+            mmap:           false,
+            sync_every:     None,
+            cpus:           None,
+            observers:      Vec::new(),
+        }
+    }
+
+    /// Select the algorithm of the pseudo random stream.
+    /// Default: CHACHA20
+    pub fn algorithm(mut self, algorithm: DtStreamType) -> DisktestBuilder {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Set the seed of the pseudo random stream.
+    /// A seed is required.
+    pub fn seed(mut self, seed: Vec<u8>) -> DisktestBuilder {
+        self.seed = seed;
+        self
+    }
+
+    /// Select the key derivation function that turns the seed into the generator key.
+    /// The key is derived once, when the instance is built.
+    /// Default: PBKDF2-HMAC-SHA512 with kdf::ITERATIONS iterations
+    pub fn kdf(mut self, kdf: DtKdf) -> DisktestBuilder {
+        self.kdf = kdf;
+        self
+    }
+
+    /// Write a fixed byte pattern instead of a pseudo random stream.
+    /// This selects the PATTERN algorithm and replaces the seed.
+    pub fn pattern(mut self, pattern: Vec<u8>) -> DisktestBuilder {
+        self.algorithm = DtStreamType::PATTERN;
+        self.seed = pattern;
+        self
+    }
+
+    /// Set the number of generator threads.
+    /// 0 selects one thread per CPU. Default: 1
+    pub fn threads(mut self, threads: usize) -> DisktestBuilder {
+        self.threads = threads;
+        self
+    }
+
+    /// Set the size of the chunks that are generated by each thread, in bytes.
+    /// This must be a multiple of DtStreamType::base_size().
+    /// Default: DtStreamType::default_chunk_size()
+    pub fn chunk_size(mut self, chunk_size: usize) -> DisktestBuilder {
+        self.chunk_size = Some(chunk_size);
+        self
+    }
+
+    /// Set the quiet level of the console output.
+    /// 0: Normal verbosity, 1: Reduced verbosity, 2: Quiet.
+    /// Default: 0
+    pub fn quiet_level(mut self, quiet_level: u8) -> DisktestBuilder {
+        self.quiet_level = quiet_level;
+        self
+    }
+
+    /// Set the abort flag. Writing true to it aborts the running operation.
+    pub fn abort(mut self, abort: Arc<AtomicBool>) -> DisktestBuilder {
+        self.abort = Some(abort);
+        self
+    }
+
+    /// Set the pause flag. While it is true, the running operation is paused.
+    /// The written data is synced and no further I/O is issued until it is false again.
+    /// The paused time is excluded from the rates.
+    pub fn pause(mut self, pause: Arc<AtomicBool>) -> DisktestBuilder {
+        self.pause = Some(pause);
+        self
+    }
+
+    /// Enable or disable keep-going mode.
+    /// In keep-going mode verification does not stop at the first error.
+    /// All bad regions are recorded instead.
+    /// Default: false
+    pub fn keep_going(mut self, keep_going: bool) -> DisktestBuilder {
+        self.keep_going = keep_going;
+        self
+    }
+
+    /// Stop the operation in keep-going mode, once count bad regions have been found.
+    /// Default: Unlimited
+    pub fn max_errors(mut self, count: usize) -> DisktestBuilder {
+        self.max_errors = Some(count);
+        self
+    }
+
+    /// Reserve the header at the absolute byte offset of the device.
+    /// The header is written by Disktest::write_header() after writing.
+    /// Its HEADER_SIZE bytes are not compared during verification.
+    /// Default: No header
+    pub fn header(mut self, offset: u64) -> DisktestBuilder {
+        self.header = Some(offset);
+        self
+    }
+
+    /// Limit the throughput of all operations to rate bytes per second.
+    /// Default: Unlimited
+    pub fn max_rate(mut self, rate: u64) -> DisktestBuilder {
+        self.max_rate = Some(rate);
+        self
+    }
+
+    /// Measure the throughput in count zones of equal size.
+    /// The zones are only measured, if the number of bytes to process is known.
+    /// Default: Not measured
+    pub fn zones(mut self, count: usize) -> DisktestBuilder {
+        self.zones = Some(count);
+        self
+    }
+
+    /// Verify only every stride-th chunk and skip the chunks in between.
+    /// A quick pass still touches all regions of the device.
+    /// Default: 1 (Verify everything)
+    pub fn verify_stride(mut self, stride: u64) -> DisktestBuilder {
+        self.verify_stride = stride;
+        self
+    }
+
+    /// Retry a failed read up to retries times with exponential backoff.
+    /// The retries read the failed region block by block,
+    /// so that only the persistently unreadable blocks are recorded as bad regions.
+    /// Default: 0 (No retries)
+    pub fn read_retries(mut self, retries: u32) -> DisktestBuilder {
+        self.read_retries = retries;
+        self
+    }
+
+    /// Skip the known bad regions of the device.
+    /// Each region is given as absolute byte offset and length.
+    /// The regions are neither written nor read, and they are never reported as bad regions.
+    /// Default: Nothing is skipped
+    pub fn skip_regions(mut self, regions: Vec<(u64, u64)>) -> DisktestBuilder {
+        self.skip = regions;
+        self
+    }
+
+    /// Verify regular files with memory mapped I/O.
+    /// The data is compared in place in the mapped file, instead of being copied
+    /// to a read buffer. Read errors of the file can't be handled, so this is meant
+    /// for image files. Disk devices, direct I/O, known bad regions and the
+    /// verification behind a write always use regular reads.
+    /// Default: false
+    pub fn mmap(mut self, mmap: bool) -> DisktestBuilder {
+        self.mmap = mmap;
+        self
+    }
+
+    /// Sync the written data to the device each time bytes have been written.
+    /// This bounds the amount of data in the write caches.
+    /// Default: Only synced at the end and at the checkpoints
+    pub fn sync_every(mut self, bytes: u64) -> DisktestBuilder {
+        self.sync_every = Some(bytes);
+        self
+    }
+
+    /// Pin the threads to the CPUs.
+    /// The I/O thread is pinned to the first CPU and the generator threads
+    /// to the remaining CPUs round-robin. With a single CPU all threads share it.
+    /// Default: Not pinned
+    pub fn cpu_set(mut self, cpus: Vec<usize>) -> DisktestBuilder {
+        self.cpus = Some(cpus);
+        self
+    }
+
+    /// Add an observer that receives the progress, error and status events.
+    /// The console output is always active, unless the quiet level is 2.
+    pub fn progress_observer(mut self, observer: Box<dyn ProgressObserver>) -> DisktestBuilder {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Enable the machine readable JSON progress stream.
+    pub fn progress_json(self, sink: JsonSink) -> DisktestBuilder {
+        self.progress_observer(Box::new(JsonProgress::new(sink)))
+    }
+
+    /// Check the configuration and create the Disktest instance.
+    pub fn build(self) -> ah::Result<Disktest> {
+        if self.seed.is_empty() {
+            return Err(DtError::InvalidParameter("No seed specified.".to_string()).into());
+        }
+        if self.threads > u16::MAX as usize + 1 {
+            return Err(DtError::InvalidParameter(
+                format!("Invalid number of threads: {}", self.threads)).into());
+        }
+        if let Some(chunk_size) = self.chunk_size {
+            let base_size = self.algorithm.base_size();
+            if chunk_size == 0 || chunk_size % base_size != 0 {
+                return Err(DtError::InvalidParameter(
+                    format!("The chunk size {} is not a multiple \
+                            of the {} base size {} bytes.",
+                            chunk_size, self.algorithm, base_size)).into());
+            }
+        }
+        if self.zones == Some(0) {
+            return Err(DtError::InvalidParameter("The number of zones must not be zero.".to_string()).into());
+        }
+        if self.max_errors == Some(0) {
+            return Err(DtError::InvalidParameter("The maximum number of errors must not be zero.".to_string()).into());
+        }
+        if self.verify_stride == 0 {
+            return Err(DtError::InvalidParameter("The verify stride must not be zero.".to_string()).into());
+        }
+        if self.sync_every == Some(0) {
+            return Err(DtError::InvalidParameter("The sync interval must not be zero.".to_string()).into());
+        }
+        if let Some(cpus) = &self.cpus {
+            if cpus.is_empty() {
+                return Err(DtError::InvalidParameter("The CPU set is empty.".to_string()).into());
+            }
+            if !affinity_supported() {
+                return Err(DtError::InvalidParameter(
+                    "Pinning threads to CPUs is not supported on this operating system.".to_string()).into());
+            }
+        }
+        let key = match stream_key(self.algorithm, &self.seed, &self.kdf) {
+            Ok(key) => key,
+            Err(e) => return Err(DtError::InvalidParameter(e.to_string()).into()),
+        };
+        Ok(Disktest::from_builder(self, key))
+    }
+}
+
+impl Default for DisktestBuilder {
+    fn default() -> DisktestBuilder {
+        DisktestBuilder::new()
+    }
+}
+
+/// Handle to cancel a running Disktest operation from another thread.
+#[derive(Clone, Debug)]
+pub struct DisktestHandle {
+    cancel:         Arc<AtomicBool>,
+}
+
+impl DisktestHandle {
+    /// Stop the running write or verify operation.
+    /// The operation syncs the written data and returns the number of bytes
+    /// processed so far. All further operations stop after the first chunk.
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+
+    /// Check whether the operation has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+}
+
+/// The write and verify engine.
+pub struct Disktest {
+    stream_agg:     DtStreamAgg,
+    algorithm:      DtStreamType,
+    seed:           Vec<u8>,
+    kdf:            DtKdf,
+    nr_threads:     usize,
+    seed_fingerprint: String,
+    header:         Option<u64>,
+    abort:          Option<Arc<AtomicBool>>,
+    pause:          Option<Arc<AtomicBool>>,
+    cancel:         Arc<AtomicBool>,
+    keep_going:     bool,
+    max_errors:     Option<usize>,
+    errors:         Vec<DtErrorRecord>,
+    latency:        DtLatency,
+    nr_zones:       Option<usize>,
+    zones:          Option<DtZones>,
+    verify_stride:  u64,
+    read_retries:   u32,
+    transient_errors: u64,
+    /// Sorted and merged known bad regions (offset, length).
+    skip:           Vec<(u64, u64)>,
+    mmap:           bool,
+    sync_every:     Option<u64>,
+    checkpoint:     Option<Arc<AtomicU64>>,
+    /// The last position that was stored to the checkpoint.
+    position:       u64,
+    trail:          Option<DtTrail>,
+    follow:         Option<(DtTrail, u64)>,
+    checkpoint_time: Instant,
+    log_count:      u64,
+    throttle:       Option<Throttle>,
+    io_cpu:         Option<usize>,
+    progress:       Progress,
+}
+
+impl Disktest {
+    /// Unlimited max_bytes.
+    pub const UNLIMITED: u64 = u64::MAX;
+
+    /// Create a new Disktest instance with the default configuration.
+    /// See DisktestBuilder for more options.
+    pub fn new(algorithm:   DtStreamType,
+               seed:        Vec<u8>,
+               nr_threads:  usize,
+               abort:       Option<Arc<AtomicBool>>) -> Disktest {
+        let key = stream_key(algorithm, &seed, &DtKdf::default())
+            .expect("Key derivation with the default KDF failed");
+        Disktest::from_builder(DisktestBuilder {
+            algorithm,
+            seed,
+            threads: nr_threads,
+            abort,
+            ..DisktestBuilder::new()
+        }, key)
+    }
+
+    /// Create a new Disktest instance from the builder configuration.
+    /// The key is the generator key derived from the builder seed.
+    fn from_builder(builder: DisktestBuilder, key: Vec<u8>) -> Disktest {
+        let nr_threads = if builder.threads == 0 { num_cpus::get() } else { builder.threads };
+        let chunk_factor = match builder.chunk_size {
+            Some(chunk_size) => chunk_size / builder.algorithm.base_size(),
+            None => builder.algorithm.default_chunk_factor(),
+        };
+
+        let mut stream_agg = DtStreamAgg::new(builder.algorithm, key,
+                                              nr_threads, chunk_factor);
+        if let Some(cpus) = &builder.cpus {
+            stream_agg.set_cpus(if cpus.len() > 1 { &cpus[1..] } else { cpus });
+        }
+
+        let mut observers: Vec<Box<dyn ProgressObserver>> = Vec::new();
+        if builder.quiet_level < 2 {
+            observers.push(Box::new(ConsoleProgress::new(builder.quiet_level)));
+        }
+        observers.extend(builder.observers);
+
+        Disktest {
+            seed_fingerprint: seed_fingerprint(&builder.seed),
+            stream_agg,
+            algorithm: builder.algorithm,
+            seed: builder.seed,
+            kdf: builder.kdf,
+            nr_threads,
+            header: builder.header,
+            abort: builder.abort,
+            pause: builder.pause,
+            cancel: Arc::new(AtomicBool::new(false)),
+            keep_going: builder.keep_going,
+            max_errors: builder.max_errors,
+            errors: Vec::new(),
+            latency: DtLatency::new(),
+            nr_zones: builder.zones,
+            zones: None,
+            verify_stride: builder.verify_stride,
+            read_retries: builder.read_retries,
+            transient_errors: 0,
+            skip: merge_regions(builder.skip),
+            mmap: builder.mmap,
+            sync_every: builder.sync_every,
+            checkpoint: None,
+            position: 0,
+            trail: None,
+            follow: None,
+            checkpoint_time: Instant::now(),
+            log_count: 0,
+            throttle: builder.max_rate.map(Throttle::new),
+            io_cpu: builder.cpus.as_ref().map(|cpus| cpus[0]),
+            progress: Progress::new(observers),
+        }
+    }
+
+    /// Get a handle to cancel the operations of this instance from another thread.
+    pub fn handle(&self) -> DisktestHandle {
+        DisktestHandle {
+            cancel: Arc::clone(&self.cancel),
+        }
+    }
+
+    /// Get the absolute byte position up to which the data was processed
+    /// (written and synced, or verified), e.g. after the operation was aborted.
+    pub fn get_position(&self) -> u64 {
+        self.position
+    }
+
+    /// Get the records of all errors that occurred.
+    pub fn get_errors(&self) -> &[DtErrorRecord] {
+        &self.errors
+    }
+
+    /// Get the number of read errors that disappeared on a retry.
+    pub fn get_transient_errors(&self) -> u64 {
+        self.transient_errors
+    }
+
+    /// Get the latency histogram of the read and write calls of the last operation.
+    pub fn get_latency(&self) -> &DtLatency {
+        &self.latency
+    }
+
+    /// Get the zone throughput of the last write or verify operation, if measured.
+    pub fn get_zones(&self) -> Option<&DtZones> {
+        self.zones.as_ref()
+    }
+
+    /// Turn this instance into a reader of its pseudo random stream,
+    /// starting at the absolute byte offset.
+    pub fn stream_reader(self, offset: u64) -> ah::Result<DtStreamReader> {
+        DtStreamReader::new(self.stream_agg, offset)
+    }
+
+    /// Record an error for a bad region and return it.
+    /// The region is merged into the previous record, if it directly follows it.
+    fn record_error(&mut self,
+                    kind: DtErrorKind,
+                    offset: u64,
+                    length: u64,
+                    error: DtError) -> ah::Error {
+        match self.errors.last_mut() {
+            Some(last) if last.kind == kind && last.offset + last.length == offset => {
+                last.length += length;
+            },
+            _ => {
+                let record = DtErrorRecord {
+                    kind,
+                    offset,
+                    length,
+                    message: error.to_string(),
+                };
+                self.progress.error(&record);
+                self.errors.push(record);
+            },
+        }
+        error.into()
+    }
+
+    /// Retry the read of buffer at the absolute position pos, after it failed with error.
+    /// The data is read block by block. A failing block is retried with exponential backoff.
+    /// Returns the number of bytes read, which is less than the buffer at the end of the disk.
+    /// If a block still fails after all retries, then the number of bytes read
+    /// up to this block and its last error is returned.
+    fn retry_read(&mut self,
+                  file: &mut DisktestFile,
+                  pos: u64,
+                  buffer: &mut [u8],
+                  block_size: u64,
+                  error: io::Error) -> Result<usize, (usize, io::Error)> {
+        let mut error = error;
+        let mut count = 0;
+        // Number of failed reads of the current block.
+        let mut failures = 1;
+        while count < buffer.len() {
+            let offset = pos + count as u64;
+            if failures > 0 {
+                if failures > self.read_retries {
+                    self.progress.message(&format!("Read error at byte {} persists after {} retries.",
+                                                   offset, self.read_retries));
+                    return Err((count, error));
+                }
+                let factor = 1 << min(failures - 1, 16);
+                thread::sleep(min(READ_RETRY_DELAY * factor, READ_RETRY_MAX_DELAY));
+            }
+            let len = min((block_size - offset % block_size) as usize, buffer.len() - count);
+            let result = file.seek(offset).and_then(|_| file.read(&mut buffer[count..count+len]));
+            match result {
+                Ok(0) => break, // End of the disk.
+                Ok(n) => {
+                    if failures > 0 {
+                        self.transient_errors += 1;
+                        self.progress.message(&format!("Transient read error at byte {} \
+                                                       recovered after {} {}.",
+                                                       offset, failures,
+                                                       if failures == 1 { "retry" } else { "retries" }));
+                    }
+                    failures = 0;
+                    count += n;
+                },
+                Err(e) => {
+                    failures += 1;
+                    error = e;
+                },
+            }
+        }
+        Ok(count)
+    }
+
+    /// Get the next known bad region to skip that ends after the absolute position pos.
+    /// The region is aligned outwards to align. Returns its begin and end.
+    fn next_skip(&self, pos: u64, align: u64) -> Option<(u64, u64)> {
+        self.skip.iter()
+            .map(|&(offset, length)| (offset - offset % align,
+                                      (offset + length).div_ceil(align) * align))
+            .find(|&(_, end)| end > pos)
+    }
+
+    /// Write data at the absolute position pos and leave out the known bad regions.
+    fn write_skipping(&self,
+                      file: &mut DisktestFile,
+                      pos: u64,
+                      data: &[u8],
+                      align: u64) -> io::Result<()> {
+        if self.skip.is_empty() {
+            return file.write(data);
+        }
+        let mut count = 0;
+        while count < data.len() {
+            let offset = pos + count as u64;
+            let left = (data.len() - count) as u64;
+            match self.next_skip(offset, align) {
+                Some((begin, end)) if begin <= offset => {
+                    count += min(end - offset, left) as usize;
+                    file.seek(pos + count as u64)?;
+                },
+                next => {
+                    let len = match next {
+                        Some((begin, _)) => min(begin - offset, left) as usize,
+                        None => left as usize,
+                    };
+                    file.write(&data[count..count+len])?;
+                    count += len;
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Check whether the operation has to stop after the recorded error.
+    /// Returns the error to stop with, or None to keep going.
+    fn stop_on_error(&mut self, error: ah::Error) -> Option<ah::Error> {
+        if !self.keep_going {
+            return Some(error);
+        }
+        match self.max_errors {
+            Some(max_errors) if self.errors.len() >= max_errors => {
+                self.progress.message(&format!("Stopped after {} bad regions.", self.errors.len()));
+                Some(DtError::BadRegions {
+                    count:  self.errors.len(),
+                    length: self.errors.iter().map(|e| e.length).sum(),
+                }.into())
+            },
+            _ => None,
+        }
+    }
+
+    /// Get the granularity of bad regions.
+    fn error_block_size(file: &DisktestFile) -> u64 {
+        if file.is_direct() {
+            DIRECT_IO_ALIGN as u64
+        } else {
+            file.get_sector_size().map(|s| s as u64).unwrap_or(DEFAULT_ERROR_BLOCK_SIZE)
+        }
+    }
+
+    /// Set the checkpoint position.
+    /// The absolute byte position up to which the data is known to be
+    /// processed (written and synced, or verified) is stored to it.
+    pub fn set_checkpoint(&mut self, checkpoint: Arc<AtomicU64>) {
+        self.checkpoint = Some(checkpoint);
+    }
+
+    /// Publish the end position of the written data to the trail while writing.
+    pub fn set_trail(&mut self, trail: DtTrail) {
+        self.trail = Some(trail);
+    }
+
+    /// Follow the trail of a concurrent write while verifying.
+    /// Only the data that has been written at least lag bytes before is read.
+    /// Verification stops at the end of the written data, once the write is finished.
+    pub fn follow_trail(&mut self, trail: DtTrail, lag: u64) {
+        self.follow = Some((trail, lag));
+    }
+
+    /// Wait until len bytes at the absolute position may be read from the followed trail.
+    /// Returns the number of bytes that may be read.
+    /// That is less than len or 0 at the end of a finished write.
+    fn wait_trail(&self, position: u64, len: usize) -> ah::Result<usize> {
+        let (trail, lag) = match &self.follow {
+            Some(follow) => follow,
+            None => return Ok(len),
+        };
+        loop {
+            let (written, finished) = trail.get();
+            if finished {
+                return Ok(min(len as u64, written.saturating_sub(position)) as usize);
+            }
+            if position + len as u64 + lag <= written {
+                return Ok(len);
+            }
+            if self.cancel.load(Ordering::Relaxed) {
+                return Ok(0);
+            }
+            if let Some(abort) = &self.abort {
+                if abort.load(Ordering::Relaxed) {
+                    return Err(DtError::Aborted.into());
+                }
+            }
+            thread::sleep(TRAIL_POLL_INTERVAL);
+        }
+    }
+
+    /// Wait while the pause flag is set.
+    /// If sync is true, then the written data is synced before,
+    /// so that the device is idle during the pause.
+    /// position is the absolute position up to which the data was processed.
+    fn pause(&mut self, file: &mut DisktestFile, position: u64, sync: bool) -> ah::Result<()> {
+        let pause = match &self.pause {
+            Some(pause) if pause.load(Ordering::Relaxed) => Arc::clone(pause),
+            _ => return Ok(()),
+        };
+        if sync {
+            if let Err(e) = file.sync() {
+                return Err(DtError::Io { op: DtIoOp::Sync, offset: position, source: e }.into());
+            }
+            self.checkpoint_store(position);
+        }
+        self.progress.message(&format!("Paused at byte {}.", position));
+        let begin = Instant::now();
+        while pause.load(Ordering::Relaxed) {
+            if self.cancel.load(Ordering::Relaxed) ||
+               self.abort.as_ref().is_some_and(|a| a.load(Ordering::Relaxed)) {
+                break;
+            }
+            std::thread::sleep(PAUSE_POLL_INTERVAL);
+        }
+        self.progress.pause(begin.elapsed());
+        self.progress.message("Resumed.");
+        Ok(())
+    }
+
+    /// Store the absolute position to the checkpoint, if any.
+    fn checkpoint_store(&mut self, position: u64) {
+        self.position = position;
+        if let Some(checkpoint) = &self.checkpoint {
+            checkpoint.store(position, Ordering::Relaxed);
+            self.checkpoint_time = Instant::now();
+        }
+    }
+
+    /// Check if it is time to write the next checkpoint.
+    fn checkpoint_due(&self) -> bool {
+        self.checkpoint.is_some() &&
+        self.checkpoint_time.elapsed().as_secs() >= CHECKPOINT_SEC_THRES
+    }
+
+    /// Reset logging.
+    /// max_bytes is the total number of bytes to process.
+    fn log_reset(&mut self, max_bytes: u64) {
+        self.log_count = 0;
+        self.progress.set_total(if max_bytes == Disktest::UNLIMITED {
+            None
+        } else {
+            Some(max_bytes)
+        });
+    }
+
+    /// Log progress.
+    fn log(&mut self,
+           inc_processed: usize,
+           abs_processed: u64) {
+
+        // Increment byte count.
+        // Only if byte count is bigger than threshold, then check time.
+        // This reduces the number of calls to Instant::now.
+        self.log_count += inc_processed as u64;
+        if self.log_count >= LOG_BYTE_THRES {
+            let now = Instant::now();
+            // Check if it's time to send the next progress update.
+            if self.progress.due(now) {
+                self.progress.update(abs_processed, self.errors.len(), now);
+            }
+            self.log_count = 0;
+        }
+    }
+
+    /// Get the number of bytes of the area of max_bytes that are read,
+    /// if only every stride-th chunk is read.
+    fn stride_bytes(&self, max_bytes: u64, stride: u64) -> u64 {
+        if stride <= 1 || max_bytes == Disktest::UNLIMITED {
+            return max_bytes;
+        }
+        let chunk_size = self.stream_agg.get_chunk_size() as u64;
+        let chunks = max_bytes.div_ceil(chunk_size);
+        let last = (chunks - 1) / stride * stride;
+        last / stride * chunk_size + min(chunk_size, max_bytes - last * chunk_size)
+    }
+
+    /// Create the zones for the area of max_bytes at seek, if zones are configured.
+    /// The zones can't be measured, if the size of the area is unknown.
+    fn new_zones(&self, seek: u64, max_bytes: u64) -> Option<DtZones> {
+        match self.nr_zones {
+            Some(count) if max_bytes != Disktest::UNLIMITED => Some(DtZones::new(seek, max_bytes, count)),
+            _ => None,
+        }
+    }
+
+    /// Wait until count more bytes may be transferred, if the rate is limited.
+    fn throttle(&mut self, count: usize) {
+        let mut wait = match self.throttle.as_mut() {
+            Some(throttle) => throttle.consume(count as u64, Instant::now()),
+            None => return,
+        };
+        // Wait in small steps to stay responsive to abort and cancel requests.
+        while wait > Duration::from_secs(0) {
+            let step = wait.min(THROTTLE_STEP);
+            std::thread::sleep(step);
+            wait -= step;
+            if self.cancel.load(Ordering::Relaxed) ||
+               self.abort.as_ref().is_some_and(|a| a.load(Ordering::Relaxed)) {
+                break;
+            }
+        }
+    }
+
+    /// Limit max_bytes to the end of the disk device, if the disk size is known.
+    /// Raw disk devices might require sector aligned accesses.
+    /// Therefore, round max_bytes down to a multiple of the sector size.
+    fn limit_max_bytes(&self,
+                       file: &DisktestFile,
+                       seek: u64,
+                       max_bytes: u64) -> u64 {
+        let max_bytes = match file.get_disk_size() {
+            Some(size) if max_bytes == Disktest::UNLIMITED => size.saturating_sub(seek),
+            Some(size) => min(max_bytes, size.saturating_sub(seek)),
+            None => max_bytes,
+        };
+        let align = if file.is_direct() {
+            Some(DIRECT_IO_ALIGN as u64)
+        } else {
+            file.get_sector_size().map(|s| s as u64)
+        };
+        match align {
+            Some(align) if max_bytes != Disktest::UNLIMITED &&
+                           max_bytes % align != 0 => {
+                let aligned = max_bytes - (max_bytes % align);
+                eprintln!("WARNING: The number of bytes {} is not a multiple \
+                          of the required access alignment {} bytes. \
+                          It will be adjusted to {} bytes.",
+                          max_bytes, align, aligned);
+                aligned
+            },
+            _ => max_bytes,
+        }
+    }
+
+    /// Initialize disktest.
+    fn init(&mut self,
+            file: &mut DisktestFile,
+            phase: DtPhase,
+            seek: u64) -> ah::Result<u64> {
+
+        self.errors.clear();
+        self.latency = DtLatency::new();
+        self.zones = None;
+
+        if let Some(cpu) = self.io_cpu {
+            if let Err(e) = pin_current_thread(cpu) {
+                eprintln!("WARNING: Failed to pin the I/O thread to CPU {}: {}", cpu, e);
+            }
+        }
+
+        let chunk_size = self.stream_agg.get_chunk_size();
+        if file.is_direct() && !chunk_size.is_multiple_of(DIRECT_IO_ALIGN) {
+            return Err(DtError::InvalidParameter(
+                format!("The chunk size {} is not a multiple of the \
+                        direct I/O alignment {} bytes.",
+                        chunk_size, DIRECT_IO_ALIGN)).into());
+        }
+
+        // A scan and a comparison do not need the pseudo random stream.
+        let seek = match phase {
+            DtPhase::Scan | DtPhase::Compare => seek,
+            _ => self.stream_agg.activate(seek)?,
+        };
+        self.progress.begin(phase, file.get_path(), seek);
+
+        if let Err(e) = file.seek(seek) {
+            return Err(DtError::Io { op: DtIoOp::Seek, offset: seek, source: e }.into());
+        }
+        self.checkpoint_store(seek);
+
+        Ok(seek)
+    }
+
+    /// Finalize and flush writing.
+    fn write_finalize(&mut self,
+                      file: &mut DisktestFile,
+                      seek: u64,
+                      bytes_written: u64) -> ah::Result<()> {
+        self.progress.message("Writing stopped. Syncing...");
+        if let Err(e) = file.sync() {
+            return Err(DtError::Io { op: DtIoOp::Sync, offset: seek + bytes_written, source: e }.into());
+        }
+        self.checkpoint_store(seek + bytes_written);
+        self.progress.finish(bytes_written, &self.errors);
+
+        Ok(())
+    }
+
+    /// Run disktest in write mode.
+    pub fn write(&mut self,
+                 file: DisktestFile,
+                 seek: u64,
+                 max_bytes: u64) -> ah::Result<u64> {
+        let mut file = file;
+        let mut bytes_written = 0u64;
+        let chunk_size = self.stream_agg.get_chunk_size() as u64;
+
+        let seek = self.init(&mut file, DtPhase::Write, seek)?;
+        let max_bytes = self.limit_max_bytes(&file, seek, max_bytes);
+        self.zones = self.new_zones(seek, max_bytes);
+        let block_size = Disktest::error_block_size(&file);
+        let mut direct_buffer = if file.is_direct() {
+            Some(AlignedBuffer::new(chunk_size as usize, DIRECT_IO_ALIGN))
+        } else {
+            None
+        };
+        let mut bytes_left = max_bytes;
+        let mut bytes_synced = 0u64;
+        // The caches of each region are dropped twice: The first time starts
+        // the writeback of the dirty data and the second time drops the written back data.
+        let mut cache_regions = (0u64, 0u64);
+        let mut drop_caches = true;
+        self.log_reset(max_bytes);
+        if bytes_left == 0 {
+            self.write_finalize(&mut file, seek, bytes_written)?;
+            return Ok(bytes_written);
+        }
+        // Allocate the space of a regular file up front,
+        // so that a lack of space is detected right away.
+        if max_bytes != Disktest::UNLIMITED {
+            if let Err(e) = file.preallocate(seek, max_bytes) {
+                #[allow(clippy::unnecessary_cast)]
+                if e.raw_os_error() == Some(ENOSPC as i32) {
+                    // This is not a defect of the device. Nothing has been written.
+                    return Err(ah::format_err!("Not enough space to write {} bytes at position {}.",
+                                               max_bytes, seek));
+                }
+                eprintln!("WARNING: Failed to preallocate the file: {}", e);
+            }
+        }
+        loop {
+            // Get the next data chunk.
+            let chunk = self.stream_agg.wait_chunk()?;
+            let write_len = min(chunk_size, bytes_left) as usize;
+
+            // Write the chunk to disk.
+            // In direct I/O mode the data has to be copied to an aligned buffer first.
+            let data = match direct_buffer.as_mut() {
+                Some(buffer) => {
+                    buffer[0..write_len].copy_from_slice(&chunk.data[0..write_len]);
+                    &buffer[0..write_len]
+                },
+                None => &chunk.data[0..write_len],
+            };
+            let begin = Instant::now();
+            let result = self.write_skipping(&mut file, seek + bytes_written, data, block_size);
+            let elapsed = begin.elapsed();
+            self.latency.record(elapsed);
+            if let (Some(zones), Ok(())) = (self.zones.as_mut(), &result) {
+                zones.record(seek + bytes_written, write_len as u64, elapsed);
+            }
+            if let Err(e) = result {
+                if max_bytes == Disktest::UNLIMITED &&
+                   file.is_stdio() &&
+                   e.kind() == io::ErrorKind::BrokenPipe {
+                    // The reader closed the stream. It can't be synced anymore.
+                    self.checkpoint_store(seek + bytes_written);
+                    self.progress.finish(bytes_written, &self.errors);
+                    break; // End of stream. -> Success.
+                }
+                #[allow(clippy::unnecessary_cast)]
+                let full = e.raw_os_error() == Some(ENOSPC as i32);
+                if max_bytes == Disktest::UNLIMITED && full {
+                    // The chunk might have been written partially up to the end.
+                    // Account for it, so that exactly the written data is verified.
+                    if let Ok(pos) = file.position() {
+                        let mut partial = min(pos.saturating_sub(seek + bytes_written), write_len as u64);
+                        if file.is_direct() {
+                            // Direct I/O only writes whole blocks.
+                            partial -= partial % block_size;
+                        }
+                        bytes_written += partial;
+                    }
+                    if let Some(trail) = &self.trail {
+                        trail.advance(seek + bytes_written);
+                    }
+                    self.progress.message(&format!("No space left on the device after {} bytes.",
+                                                   bytes_written));
+                    self.write_finalize(&mut file, seek, bytes_written)?;
+                    break; // End of device. -> Success.
+                }
+                self.write_finalize(&mut file, seek, bytes_written)?;
+                let e = DtError::Io { op: DtIoOp::Write, offset: seek + bytes_written, source: e };
+                // The device is full before all bytes have been written.
+                let (kind, len) = if full {
+                    (DtErrorKind::ShortWrite, bytes_left)
+                } else {
+                    (DtErrorKind::Write, write_len as u64)
+                };
+                return Err(self.record_error(kind, seek + bytes_written, len, e));
+            }
+            self.stream_agg.recycle(chunk);
+
+            // Account for the written bytes.
+            bytes_written += write_len as u64;
+            bytes_left -= write_len as u64;
+            if let Some(trail) = &self.trail {
+                trail.advance(seek + bytes_written);
+            }
+            if bytes_left == 0 {
+                self.write_finalize(&mut file, seek, bytes_written)?;
+                break;
+            }
+            self.log(write_len, bytes_written);
+            self.throttle(write_len);
+            self.pause(&mut file, seek + bytes_written, true)?;
+
+            // Drop the caches of the written regions.
+            if drop_caches && bytes_written - cache_regions.1 >= CACHE_DROP_REGION {
+                let (begin, end) = cache_regions;
+                if let Err(e) = file.drop_range_caches(seek + begin, bytes_written - begin) {
+                    eprintln!("WARNING: Failed to drop operating system caches: {}", e);
+                    drop_caches = false;
+                }
+                cache_regions = (end, bytes_written);
+            }
+
+            // Sync the written data and store the checkpoint, if it's time.
+            if self.checkpoint_due() {
+                if let Err(e) = file.sync() {
+                    return Err(DtError::Io {
+                        op:     DtIoOp::Sync,
+                        offset: seek + bytes_written,
+                        source: e,
+                    }.into());
+                }
+                bytes_synced = bytes_written;
+                self.checkpoint_store(seek + bytes_written);
+            } else if self.sync_every.is_some_and(|n| bytes_written - bytes_synced >= n) {
+                if let Err(e) = file.sync_data() {
+                    return Err(DtError::Io {
+                        op:     DtIoOp::Sync,
+                        offset: seek + bytes_written,
+                        source: e,
+                    }.into());
+                }
+                bytes_synced = bytes_written;
+            }
+
+            if self.cancel.load(Ordering::Relaxed) {
+                self.write_finalize(&mut file, seek, bytes_written)?;
+                break; // Cancelled. -> Partial result.
+            }
+            if let Some(abort) = &self.abort {
+                if abort.load(Ordering::Relaxed) {
+                    self.write_finalize(&mut file, seek, bytes_written)?;
+                    return Err(DtError::Aborted.into());
+                }
+            }
+        }
+
+        Ok(bytes_written)
+    }
+
+    /// Finalize verification.
+    fn verify_finalize(&mut self,
+                       bytes_read: u64) -> ah::Result<()> {
+        self.progress.finish(bytes_read, &self.errors);
+
+        Ok(())
+    }
+
+    /// Handle verification failure.
+    /// offset is the absolute byte offset of the buffer on the device.
+    /// Every block that contains a mismatch is recorded as bad region.
+    /// The first mismatch of the operation is described with a hexdump.
+    /// Returns the error of the first mismatch.
+    fn verify_failed(&mut self,
+                     read_count: usize,
+                     offset: u64,
+                     buffer: &[u8],
+                     expected: &[u8],
+                     block_size: u64) -> ah::Error {
+        let first_mismatch = !self.errors.iter().any(|e| e.kind == DtErrorKind::Mismatch);
+        let mut first_error = None;
+        let mut begin = 0;
+        while begin < read_count {
+            let pos = offset + begin as u64;
+            let end = min(read_count, begin + (block_size - pos % block_size) as usize);
+            if let Some(i) = (begin..end).find(|&i| buffer[i] != expected[i]) {
+                let e = DtError::VerifyMismatch {
+                    offset: offset + i as u64,
+                    length: (end - i) as u64,
+                };
+                let e = self.record_error(DtErrorKind::Mismatch,
+                                          offset + begin as u64,
+                                          (end - begin) as u64,
+                                          e);
+                first_error.get_or_insert(e);
+            }
+            begin = end;
+        }
+        // Describe the first mismatch of the operation in detail.
+        if first_mismatch {
+            if let Some(text) = describe_mismatch(&buffer[..read_count], &expected[..read_count], offset) {
+                self.progress.message(&text);
+            }
+        }
+        first_error.expect("Internal error: verify_failed() no mismatch.")
+    }
+
+    /// Find the likely cause of a mismatch in the first chunk.
+    /// If the data does not match at all, then the device was probably written
+    /// with a different seed or algorithm, rather than being corrupt.
+    /// data is the read data and expected the expected data at the absolute offset.
+    /// Returns a hint for the user, if the cause is likely not a corrupt device.
+    fn diagnose_mismatch(&self,
+                         data: &[u8],
+                         expected: &[u8],
+                         offset: u64) -> Option<String> {
+        // Skip the header at the start of the data, if any.
+        let begin = match self.header_overlap(offset, data.len()) {
+            Some((0, end)) => end,
+            _ => 0,
+        };
+        let end = min(data.len(), begin + DIAGNOSE_LEN);
+        if begin >= end {
+            return None;
+        }
+        let count_equal = |a: &[u8], b: &[u8]| {
+            a.iter().zip(b).filter(|(x, y)| x == y).count()
+        };
+
+        // A few bad bytes are a corruption. Entirely different data is not.
+        if count_equal(&data[begin..end], &expected[begin..end]) > (end - begin) / 16 {
+            return None;
+        }
+
+        if data[begin..end].iter().all(|&b| b == data[begin]) {
+            return Some(format!("Hint: The device contains only 0x{:02X} bytes. \
+                                It has probably not been written by disktest.",
+                                data[begin]));
+        }
+
+        // Try all other algorithms with the same seed.
+        // The key does not depend on the algorithm, except for PATTERN.
+        let key = match self.kdf.derive(&self.seed) {
+            Ok(key) => key,
+            Err(_) => return None,
+        };
+        for &algorithm in DtStreamType::ALL.iter() {
+            if algorithm == self.algorithm ||
+               algorithm == DtStreamType::PATTERN ||
+               !offset.is_multiple_of(algorithm.base_size() as u64) {
+                continue;
+            }
+            let mut agg = DtStreamAgg::new(algorithm, key.clone(), 1, 1);
+            if agg.activate(offset).is_err() {
+                continue;
+            }
+            let mut stream = Vec::with_capacity(end);
+            while stream.len() < end {
+                match agg.wait_chunk() {
+                    Ok(chunk) => {
+                        stream.extend_from_slice(&chunk.data);
+                        agg.recycle(chunk);
+                    },
+                    Err(_) => break,
+                }
+            }
+            if stream.len() >= end && data[begin..end] == stream[begin..end] {
+                return Some(format!("Hint: The data matches the {} algorithm. \
+                                    Please verify with --algorithm {}.",
+                                    algorithm, algorithm));
+            }
+        }
+
+        Some("Hint: The data does not match at all. The device has probably been written \
+             with a different --seed, --algorithm or --kdf, or it has not been written by disktest."
+             .to_string())
+    }
+
+    /// Run disktest in verify mode.
+    pub fn verify(&mut self,
+                  file: DisktestFile,
+                  seek: u64,
+                  max_bytes: u64) -> ah::Result<u64> {
+        self.read(file, None, seek, max_bytes, DtPhase::Verify)
+    }
+
+    /// Run disktest in read-only scan mode.
+    /// The data is read, but not compared to the pseudo random stream.
+    /// Only unreadable regions are reported.
+    pub fn scan(&mut self,
+                file: DisktestFile,
+                seek: u64,
+                max_bytes: u64) -> ah::Result<u64> {
+        self.read(file, None, seek, max_bytes, DtPhase::Scan)
+    }
+
+    /// Run disktest in compare mode.
+    /// The device is read from seek in parallel to the other device from other_seek
+    /// and compared to its data. All differing regions are reported. The comparison
+    /// stops at the end of the shorter device. Read errors of the other device
+    /// stop the comparison.
+    pub fn compare(&mut self,
+                   file: DisktestFile,
+                   other: DisktestFile,
+                   seek: u64,
+                   other_seek: u64,
+                   max_bytes: u64) -> ah::Result<u64> {
+        self.read(file, Some((other, other_seek)), seek, max_bytes, DtPhase::Compare)
+    }
+
+    /// Read the device and compare the data, if phase is Verify or Compare.
+    /// other is the device to compare to and its start offset in the Compare phase.
+    fn read(&mut self,
+            file: DisktestFile,
+            other: Option<(DisktestFile, u64)>,
+            seek: u64,
+            max_bytes: u64,
+            phase: DtPhase) -> ah::Result<u64> {
+        // The data written in this process or boot might still be cached.
+        // Drop the caches, so that the verification reads the device.
+        if phase == DtPhase::Verify && self.follow.is_none() && !file.is_direct() && !file.is_stdio() {
+            if let Err(e) = file.drop_caches() {
+                eprintln!("WARNING: Failed to drop the operating system caches: {}\n\
+                           WARNING: The verification might read cached data instead of \
+                           the data on the device. Use direct I/O to make sure that \
+                           the device is read.", e);
+            }
+        }
+
+        // Regular files are verified in place, if memory mapped I/O is enabled.
+        if phase == DtPhase::Verify && self.mmap && other.is_none() &&
+           self.follow.is_none() && self.skip.is_empty() {
+            if let Some(file_size) = file.mappable_size() {
+                return self.verify_mmap(file, file_size, seek, max_bytes);
+            }
+        }
+
+        let mut file = file;
+        let mut bytes_read = 0u64;
+
+        let mut seek = self.init(&mut file, phase, seek)?;
+        let mut max_bytes = self.limit_max_bytes(&file, seek, max_bytes);
+        let stride = if phase == DtPhase::Verify { self.verify_stride } else { 1 };
+        let mut other = match other {
+            Some((other, other_seek)) => {
+                max_bytes = self.limit_max_bytes(&other, other_seek, max_bytes);
+                Some(CompareReader::start(other, other_seek, self.stream_agg.get_chunk_size())?)
+            },
+            None => None,
+        };
+        self.zones = self.new_zones(seek, max_bytes);
+        let mut bytes_left = max_bytes;
+        self.log_reset(self.stride_bytes(max_bytes, stride));
+        if bytes_left == 0 {
+            self.verify_finalize(bytes_read)?;
+            return Ok(bytes_read);
+        }
+
+        let readbuf_len = self.stream_agg.get_chunk_size();
+        let block_size = Disktest::error_block_size(&file);
+        let mut buffer = AlignedBuffer::new(readbuf_len, DIRECT_IO_ALIGN);
+        // Unreadable and skipped regions of the read buffer.
+        let mut uncompared: Vec<(usize, usize)> = Vec::new();
+        let mut read_count = 0;
+        let mut read_len = min(readbuf_len as u64, bytes_left) as usize;
+        loop {
+            // Wait for the followed write, before starting the next chunk.
+            if read_count == 0 && self.follow.is_some() {
+                read_len = self.wait_trail(seek + bytes_read, read_len)?;
+                if file.is_direct() {
+                    read_len -= read_len % DIRECT_IO_ALIGN;
+                }
+                if read_len == 0 {
+                    self.verify_finalize(bytes_read)?;
+                    break;
+                }
+            }
+
+            // Known bad regions are neither read nor compared.
+            let pos = seek + bytes_read + read_count as u64;
+            let mut io_len = read_len - read_count;
+            let mut skipped = false;
+            match self.next_skip(pos, block_size) {
+                Some((skip_begin, skip_end)) if skip_begin <= pos => {
+                    io_len = min(skip_end - pos, io_len as u64) as usize;
+                    skipped = true;
+                    uncompared.push((read_count, read_count + io_len));
+                    if let Err(e) = file.seek(pos + io_len as u64) {
+                        return Err(DtError::Io {
+                            op:     DtIoOp::Seek,
+                            offset: pos + io_len as u64,
+                            source: e,
+                        }.into());
+                    }
+                },
+                Some((skip_begin, _)) => io_len = min(skip_begin - pos, io_len as u64) as usize,
+                None => (),
+            }
+
+            // Read the next chunk from disk.
+            let mut end_of_disk = false;
+            let result = if skipped {
+                Ok(io_len)
+            } else {
+                let begin = Instant::now();
+                let result = file.read(&mut buffer[read_count..read_count+io_len]);
+                let elapsed = begin.elapsed();
+                self.latency.record(elapsed);
+                if let (Some(zones), Ok(n)) = (self.zones.as_mut(), &result) {
+                    zones.record(pos, *n as u64, elapsed);
+                }
+                result
+            };
+            let result = match result {
+                Err(e) if self.read_retries > 0 && !file.is_stdio() => {
+                    match self.retry_read(&mut file, pos, &mut buffer[read_count..read_count+io_len],
+                                          block_size, e) {
+                        Ok(n) => Ok(n),
+                        Err((n, e)) => {
+                            read_count += n;
+                            Err(e)
+                        },
+                    }
+                },
+                result => result,
+            };
+            match result {
+                Ok(n) => {
+                    read_count += n;
+                    end_of_disk = n == 0;
+                },
+                Err(e) => {
+                    let pos = seek + bytes_read + read_count as u64;
+                    let len = min((block_size - pos % block_size) as usize,
+                                  read_len - read_count);
+                    let e = DtError::Io { op: DtIoOp::Read, offset: pos, source: e };
+                    let e = self.record_error(DtErrorKind::Read, pos, len as u64, e);
+                    if let Some(e) = self.stop_on_error(e) {
+                        return Err(e);
+                    }
+
+                    // Skip the unreadable block.
+                    uncompared.push((read_count, read_count + len));
+                    read_count += len;
+                    if let Err(e) = file.seek(pos + len as u64) {
+                        return Err(DtError::Io {
+                            op:     DtIoOp::Seek,
+                            offset: pos + len as u64,
+                            source: e,
+                        }.into());
+                    }
+                },
+            };
+
+            // Check if the read buffer is full, or if we are the the end of the disk.
+            assert!(read_count <= read_len);
+            if read_count == read_len || (read_count > 0 && end_of_disk) {
+                // Compare the read buffer to the other device or to the pseudo random sequence.
+                // Unreadable blocks have already been recorded and are not compared.
+                if let Some(other) = other.as_mut() {
+                    let expected = other.next_chunk()?;
+                    if expected.len() < read_count {
+                        // End of the other device.
+                        read_count = expected.len();
+                        end_of_disk = true;
+                    }
+                    for (begin, end) in uncompared.drain(..) {
+                        let end = min(end, read_count);
+                        if begin < end {
+                            buffer[begin..end].copy_from_slice(&expected[begin..end]);
+                        }
+                    }
+                    if buffer[..read_count] != expected[..read_count] {
+                        let e = self.verify_failed(read_count, seek + bytes_read,
+                                                   &buffer, &expected, block_size);
+                        if let Some(e) = self.stop_on_error(e) {
+                            return Err(e);
+                        }
+                    }
+                } else if phase == DtPhase::Verify {
+                    let chunk = self.stream_agg.wait_chunk()?;
+                    for (begin, end) in uncompared.drain(..) {
+                        buffer[begin..end].copy_from_slice(&chunk.data[begin..end]);
+                    }
+                    if let Some((begin, end)) = self.header_overlap(seek + bytes_read, read_count) {
+                        buffer[begin..end].copy_from_slice(&chunk.data[begin..end]);
+                    }
+                    if buffer[..read_count] != chunk.data[..read_count] {
+                        let e = self.verify_failed(read_count, seek + bytes_read,
+                                                   &buffer, &chunk.data, block_size);
+                        if bytes_read == 0 {
+                            if let Some(hint) = self.diagnose_mismatch(&buffer[..read_count],
+                                                                       &chunk.data[..read_count],
+                                                                       seek) {
+                                self.progress.message(&hint);
+                            }
+                        }
+                        if let Some(e) = self.stop_on_error(e) {
+                            return Err(e);
+                        }
+                    }
+                    self.stream_agg.recycle(chunk);
+                } else {
+                    uncompared.clear();
+                }
+
+                // Account for the read bytes.
+                bytes_read += read_count as u64;
+                bytes_left -= read_count as u64;
+                self.checkpoint_store(seek + bytes_read);
+                if bytes_left == 0 {
+                    self.verify_finalize(bytes_read)?;
+                    break;
+                }
+                self.log(read_count, bytes_read);
+                self.throttle(read_count);
+                self.pause(&mut file, seek + bytes_read, false)?;
+
+                // Skip the chunks between the verified chunks.
+                if stride > 1 && !end_of_disk {
+                    let skip = min((stride - 1) * readbuf_len as u64, bytes_left);
+                    seek += skip;
+                    bytes_left -= skip;
+                    if bytes_left == 0 {
+                        self.verify_finalize(bytes_read)?;
+                        break;
+                    }
+                    if let Err(e) = file.seek(seek + bytes_read) {
+                        return Err(DtError::Io {
+                            op:     DtIoOp::Seek,
+                            offset: seek + bytes_read,
+                            source: e,
+                        }.into());
+                    }
+                    self.stream_agg.activate(seek + bytes_read)?;
+                }
+                read_count = 0;
+                read_len = min(readbuf_len as u64, bytes_left) as usize;
+            }
+
+            // End of the disk?
+            if end_of_disk {
+                if phase == DtPhase::Verify && max_bytes != Disktest::UNLIMITED && bytes_left > 0 {
+                    // The device is shorter than the data to be verified.
+                    let pos = seek + bytes_read;
+                    let e = DtError::Io {
+                        op:     DtIoOp::Read,
+                        offset: pos,
+                        source: io::Error::new(io::ErrorKind::UnexpectedEof,
+                                               "The device ended before all data could be read."),
+                    };
+                    let e = self.record_error(DtErrorKind::ShortRead, pos, bytes_left, e);
+                    if let Some(e) = self.stop_on_error(e) {
+                        self.verify_finalize(bytes_read)?;
+                        return Err(e);
+                    }
+                }
+                self.verify_finalize(bytes_read)?;
+                break;
+            }
+
+            if self.cancel.load(Ordering::Relaxed) {
+                self.verify_finalize(bytes_read)?;
+                break; // Cancelled. -> Partial result.
+            }
+            if let Some(abort) = &self.abort {
+                if abort.load(Ordering::Relaxed) {
+                    self.verify_finalize(bytes_read)?;
+                    return Err(DtError::Aborted.into());
+                }
+            }
+        }
+
+        if !self.errors.is_empty() {
+            return Err(DtError::BadRegions {
+                count:  self.errors.len(),
+                length: self.errors.iter().map(|e| e.length).sum(),
+            }.into());
+        }
+
+        Ok(bytes_read)
+    }
+
+    /// Verify a regular file with memory mapped I/O.
+    /// The data is compared in place in the mapped windows of the file,
+    /// so it is not copied to a read buffer. file_size is the size of the file.
+    fn verify_mmap(&mut self,
+                   file: DisktestFile,
+                   file_size: u64,
+                   seek: u64,
+                   max_bytes: u64) -> ah::Result<u64> {
+        let mut file = file;
+        let mut bytes_read = 0u64;
+
+        let mut seek = self.init(&mut file, DtPhase::Verify, seek)?;
+        let max_bytes = self.limit_max_bytes(&file, seek, max_bytes);
+        let stride = self.verify_stride;
+        self.zones = self.new_zones(seek, max_bytes);
+        let mut bytes_left = max_bytes;
+        self.log_reset(self.stride_bytes(max_bytes, stride));
+
+        let chunk_size = self.stream_agg.get_chunk_size();
+        let block_size = Disktest::error_block_size(&file);
+        let mut window: Option<DtMmap> = None;
+        while bytes_left > 0 {
+            let pos = seek + bytes_read;
+            let want = min(chunk_size as u64, bytes_left) as usize;
+            let len = min(want as u64, file_size.saturating_sub(pos)) as usize;
+            let end_of_disk = len < want;
+
+            if len > 0 {
+                // Map the next window, if the chunk is not inside of the current one.
+                if !window.as_ref().is_some_and(|w| w.contains(pos, len)) {
+                    // Unmap the old window first.
+                    drop(window.take());
+                    let begin = pos - pos % MMAP_WINDOW;
+                    let end = min(max(begin + MMAP_WINDOW, pos + len as u64), file_size);
+                    match file.map(begin, (end - begin) as usize) {
+                        Ok(w) => window = Some(w),
+                        Err(e) => {
+                            return Err(DtError::Io {
+                                op:     DtIoOp::Read,
+                                offset: begin,
+                                source: e,
+                            }.into());
+                        },
+                    }
+                }
+                let data = window.as_ref().unwrap().slice(pos, len);
+
+                // The pages are read from the file while they are compared.
+                let chunk = self.stream_agg.wait_chunk()?;
+                let header = self.header_overlap(pos, len);
+                let begin = Instant::now();
+                let equal = match header {
+                    Some((hbegin, hend)) => data[..hbegin] == chunk.data[..hbegin] &&
+                                            data[hend..] == chunk.data[hend..len],
+                    None => *data == chunk.data[..len],
+                };
+                let elapsed = begin.elapsed();
+                self.latency.record(elapsed);
+                if let Some(zones) = self.zones.as_mut() {
+                    zones.record(pos, len as u64, elapsed);
+                }
+                if !equal {
+                    // Only a mismatching chunk is copied for the error report.
+                    let mut buffer = data.to_vec();
+                    if let Some((hbegin, hend)) = header {
+                        buffer[hbegin..hend].copy_from_slice(&chunk.data[hbegin..hend]);
+                    }
+                    let e = self.verify_failed(len, pos, &buffer, &chunk.data, block_size);
+                    if bytes_read == 0 {
+                        if let Some(hint) = self.diagnose_mismatch(&buffer, &chunk.data[..len], seek) {
+                            self.progress.message(&hint);
+                        }
+                    }
+                    if let Some(e) = self.stop_on_error(e) {
+                        return Err(e);
+                    }
+                }
+                self.stream_agg.recycle(chunk);
+
+                bytes_read += len as u64;
+                bytes_left -= len as u64;
+                self.checkpoint_store(seek + bytes_read);
+            }
+
+            if end_of_disk {
+                if max_bytes != Disktest::UNLIMITED {
+                    // The file is shorter than the data to be verified.
+                    let pos = seek + bytes_read;
+                    let e = DtError::Io {
+                        op:     DtIoOp::Read,
+                        offset: pos,
+                        source: io::Error::new(io::ErrorKind::UnexpectedEof,
+                                               "The device ended before all data could be read."),
+                    };
+                    let e = self.record_error(DtErrorKind::ShortRead, pos, bytes_left, e);
+                    if let Some(e) = self.stop_on_error(e) {
+                        self.verify_finalize(bytes_read)?;
+                        return Err(e);
+                    }
+                }
+                break;
+            }
+            if bytes_left == 0 {
+                break;
+            }
+            self.log(len, bytes_read);
+            self.throttle(len);
+            self.pause(&mut file, seek + bytes_read, false)?;
+
+            // Skip the chunks between the verified chunks.
+            if stride > 1 {
+                let skip = min((stride - 1) * chunk_size as u64, bytes_left);
+                seek += skip;
+                bytes_left -= skip;
+                if bytes_left == 0 {
+                    break;
+                }
+                self.stream_agg.activate(seek + bytes_read)?;
+            }
+
+            if self.cancel.load(Ordering::Relaxed) {
+                break; // Cancelled. -> Partial result.
+            }
+            if let Some(abort) = &self.abort {
+                if abort.load(Ordering::Relaxed) {
+                    self.verify_finalize(bytes_read)?;
+                    return Err(DtError::Aborted.into());
+                }
+            }
+        }
+        self.verify_finalize(bytes_read)?;
+
+        if !self.errors.is_empty() {
+            return Err(DtError::BadRegions {
+                count:  self.errors.len(),
+                length: self.errors.iter().map(|e| e.length).sum(),
+            }.into());
+        }
+
+        Ok(bytes_read)
+    }
+
+    /// Get the range of a buffer at the absolute byte offset that overlaps the header.
+    /// The header is not compared during verification.
+    fn header_overlap(&self, offset: u64, len: usize) -> Option<(usize, usize)> {
+        let header = self.header?;
+        let begin = header.max(offset);
+        let end = (header + HEADER_SIZE as u64).min(offset + len as u64);
+        if begin < end {
+            Some(((begin - offset) as usize, (end - offset) as usize))
+        } else {
+            None
+        }
+    }
+
+    /// Write the header to the device.
+    /// bytes is the number of bytes that have been written, including the header.
+    pub fn write_header(&mut self,
+                        file: DisktestFile,
+                        bytes: u64) -> ah::Result<()> {
+        let mut file = file;
+        let offset = match self.header {
+            Some(offset) => offset,
+            None => return Err(DtError::InvalidParameter("No header configured.".to_string()).into()),
+        };
+        let header = DtHeader {
+            version:            HEADER_VERSION,
+            algorithm:          self.algorithm,
+            kdf:                self.kdf,
+            threads:            self.nr_threads,
+            chunk_size:         self.stream_agg.get_chunk_size(),
+            offset,
+            bytes,
+            seed_fingerprint:   self.seed_fingerprint.clone(),
+        };
+        let mut buffer = AlignedBuffer::new(HEADER_SIZE, DIRECT_IO_ALIGN);
+        buffer.copy_from_slice(&header.to_bytes());
+
+        if let Err(e) = file.seek(offset) {
+            return Err(DtError::Io { op: DtIoOp::Seek, offset, source: e }.into());
+        }
+        if let Err(e) = file.write(&buffer) {
+            return Err(DtError::Io { op: DtIoOp::Write, offset, source: e }.into());
+        }
+        if let Err(e) = file.sync() {
+            return Err(DtError::Io { op: DtIoOp::Sync, offset, source: e }.into());
+        }
+        Ok(())
+    }
+
+    /// Read the header at the absolute byte offset of the device.
+    /// Returns None, if there is no header.
+    pub fn read_header(file: DisktestFile,
+                       offset: u64) -> ah::Result<Option<DtHeader>> {
+        let mut file = file;
+        let mut buffer = AlignedBuffer::new(HEADER_SIZE, DIRECT_IO_ALIGN);
+        if let Err(e) = file.seek(offset) {
+            return Err(DtError::Io { op: DtIoOp::Seek, offset, source: e }.into());
+        }
+        match Disktest::read_full(&mut file, &mut buffer) {
+            Ok(n) if n == HEADER_SIZE => DtHeader::from_bytes(&buffer),
+            Ok(_) => Ok(None),
+            Err(e) => Err(DtError::Io { op: DtIoOp::Read, offset, source: e }.into()),
+        }
+    }
+
+    /// Read into the buffer until it is full or the end of the disk is reached.
+    /// Returns the number of bytes read.
+    fn read_full(file: &mut DisktestFile, buffer: &mut [u8]) -> io::Result<usize> {
+        let mut count = 0;
+        while count < buffer.len() {
+            match file.read(&mut buffer[count..])? {
+                0 => break,
+                n => count += n,
+            }
+        }
+        Ok(count)
+    }
+
+    /// Write the test data of one segment, read it back and compare it.
+    /// offset is the absolute byte offset of the segment on the device.
+    fn test_segment(&mut self,
+                    file: &mut DisktestFile,
+                    offset: u64,
+                    buffer: &mut [u8],
+                    chunk: &DtStreamChunk,
+                    block_size: u64) -> ah::Result<()> {
+        let len = buffer.len();
+
+        buffer.copy_from_slice(&chunk.data[0..len]);
+        if let Err(e) = file.seek(offset) {
+            return Err(DtError::Io { op: DtIoOp::Seek, offset, source: e }.into());
+        }
+        if let Err(e) = file.write(buffer) {
+            let e = DtError::Io { op: DtIoOp::Write, offset, source: e };
+            return Err(self.record_error(DtErrorKind::Write, offset, len as u64, e));
+        }
+        if let Err(e) = file.sync() {
+            return Err(DtError::Io { op: DtIoOp::Sync, offset, source: e }.into());
+        }
+
+        if let Err(e) = file.seek(offset) {
+            return Err(DtError::Io { op: DtIoOp::Seek, offset, source: e }.into());
+        }
+        match Disktest::read_full(file, buffer) {
+            Ok(n) if n == len => (),
+            Ok(n) => {
+                let e = DtError::Io {
+                    op:     DtIoOp::Read,
+                    offset: offset + n as u64,
+                    source: io::Error::from(io::ErrorKind::UnexpectedEof),
+                };
+                return Err(self.record_error(DtErrorKind::Read, offset + n as u64,
+                                             (len - n) as u64, e));
+            },
+            Err(e) => {
+                let e = DtError::Io { op: DtIoOp::Read, offset, source: e };
+                return Err(self.record_error(DtErrorKind::Read, offset, len as u64, e));
+            },
+        }
+        if buffer[..] != chunk.data[0..len] {
+            return Err(self.verify_failed(len, offset, buffer, &chunk.data, block_size));
+        }
+        Ok(())
+    }
+
+    /// Write the original data of one segment back to the device.
+    fn restore_segment(&mut self,
+                       file: &mut DisktestFile,
+                       offset: u64,
+                       backup: &[u8]) -> ah::Result<()> {
+        let result = file.seek(offset)
+            .and_then(|_| file.write(backup))
+            .and_then(|_| file.sync());
+        if let Err(e) = result {
+            eprintln!("ERROR: Failed to restore the original data of {} bytes at offset {}. \
+                      The original data of this region might be lost.",
+                      backup.len(), offset);
+            let e = DtError::Io { op: DtIoOp::Write, offset, source: e };
+            return Err(self.record_error(DtErrorKind::Write, offset, backup.len() as u64, e));
+        }
+        Ok(())
+    }
+
+    /// Run disktest in non-destructive read-write mode.
+    /// The device is tested in segments of the chunk size.
+    /// The original data of each segment is read and kept in memory.
+    /// Then the pseudo random stream is written to the segment, read back and compared.
+    /// Finally the original data is written back to the segment.
+    /// The device must not be in use by anything else during the test.
+    pub fn nondestructive(&mut self,
+                          file: DisktestFile,
+                          seek: u64,
+                          max_bytes: u64) -> ah::Result<u64> {
+        self.test_segments(file, seek, max_bytes, DtPhase::Nondestructive)
+    }
+
+    /// Run disktest in destructive surface test mode.
+    /// The pseudo random stream is written to each segment of the chunk size,
+    /// immediately read back and compared, before the next segment is tested.
+    /// This reports the errors precisely located while the test is running.
+    /// The file should be opened with direct I/O, so that the data is read
+    /// back from the device instead of the operating system caches.
+    pub fn surface(&mut self,
+                   file: DisktestFile,
+                   seek: u64,
+                   max_bytes: u64) -> ah::Result<u64> {
+        self.test_segments(file, seek, max_bytes, DtPhase::Surface)
+    }
+
+    /// Test the device segment by segment.
+    /// The original data of the segments is restored in the Nondestructive phase.
+    fn test_segments(&mut self,
+                     file: DisktestFile,
+                     seek: u64,
+                     max_bytes: u64,
+                     phase: DtPhase) -> ah::Result<u64> {
+        let mut file = file;
+        let mut bytes_tested = 0u64;
+        let restore = phase == DtPhase::Nondestructive;
+
+        let seek = self.init(&mut file, phase, seek)?;
+        let max_bytes = self.limit_max_bytes(&file, seek, max_bytes);
+        if !restore && max_bytes == Disktest::UNLIMITED {
+            // The end of the device is not detected without reading it first.
+            return Err(DtError::InvalidParameter(
+                "The size of the device is unknown. \
+                The number of bytes to test must be specified.".to_string()).into());
+        }
+        let mut bytes_left = max_bytes;
+        self.log_reset(max_bytes);
+
+        let chunk_size = self.stream_agg.get_chunk_size();
+        let block_size = Disktest::error_block_size(&file);
+        let mut backup = AlignedBuffer::new(chunk_size, DIRECT_IO_ALIGN);
+        let mut buffer = AlignedBuffer::new(chunk_size, DIRECT_IO_ALIGN);
+        while bytes_left > 0 {
+            let offset = seek + bytes_tested;
+            let len = min(chunk_size as u64, bytes_left) as usize;
+            let chunk = self.stream_agg.wait_chunk()?;
+
+            // Read and keep the original data of the segment.
+            // A segment that can't be read completely is not written to.
+            let result = if restore {
+                Disktest::read_full(&mut file, &mut backup[0..len])
+            } else {
+                Ok(len)
+            };
+            let (count, readable) = match result {
+                Ok(n) => (n, true),
+                Err(e) => {
+                    let e = DtError::Io { op: DtIoOp::Read, offset, source: e };
+                    let e = self.record_error(DtErrorKind::Read, offset, len as u64, e);
+                    if let Some(e) = self.stop_on_error(e) {
+                        self.verify_finalize(bytes_tested)?;
+                        return Err(e);
+                    }
+                    (len, false)
+                },
+            };
+            if count == 0 {
+                self.stream_agg.recycle(chunk);
+                break; // End of the disk.
+            }
+
+            if readable {
+                // Test the segment and always restore the original data afterwards.
+                let result = self.test_segment(&mut file, offset,
+                                               &mut buffer[0..count], &chunk, block_size);
+                if restore {
+                    if let Err(e) = self.restore_segment(&mut file, offset, &backup[0..count]) {
+                        self.verify_finalize(bytes_tested)?;
+                        return Err(e);
+                    }
+                }
+                if let Err(e) = result {
+                    if let Some(e) = self.stop_on_error(e) {
+                        self.verify_finalize(bytes_tested)?;
+                        return Err(e);
+                    }
+                }
+            }
+            if let Err(e) = file.seek(offset + count as u64) {
+                return Err(DtError::Io {
+                    op:     DtIoOp::Seek,
+                    offset: offset + count as u64,
+                    source: e,
+                }.into());
+            }
+            self.stream_agg.recycle(chunk);
+
+            // Account for the tested bytes.
+            bytes_tested += count as u64;
+            bytes_left -= count as u64;
+            self.checkpoint_store(seek + bytes_tested);
+            self.log(count, bytes_tested);
+            self.throttle(count);
+            self.pause(&mut file, seek + bytes_tested, true)?;
+            if count < len {
+                break; // End of the disk.
+            }
+
+            if self.cancel.load(Ordering::Relaxed) {
+                break; // Cancelled. -> Partial result.
+            }
+            if let Some(abort) = &self.abort {
+                if abort.load(Ordering::Relaxed) {
+                    self.verify_finalize(bytes_tested)?;
+                    return Err(DtError::Aborted.into());
+                }
+            }
+        }
+        self.verify_finalize(bytes_tested)?;
+
+        if !self.errors.is_empty() {
+            return Err(DtError::BadRegions {
+                count:  self.errors.len(),
+                length: self.errors.iter().map(|e| e.length).sum(),
+            }.into());
+        }
+
+        Ok(bytes_tested)
+    }
+
+    /// Check the real usable capacity of the device.
+    /// Address dependent markers are written across the claimed capacity
+    /// and read back afterwards. This detects fake devices that wrap around
+    /// addresses or drop the writes beyond their real capacity.
+    /// The file must be opened for reading and writing.
+    pub fn capacity_check(&mut self,
+                          file: DisktestFile,
+                          seek: u64,
+                          max_bytes: u64) -> ah::Result<DtCapacity> {
+        let mut file = file;
+        self.errors.clear();
+
+        let claimed = self.limit_max_bytes(&file, seek, max_bytes);
+        if claimed == Disktest::UNLIMITED {
+            return Err(DtError::InvalidParameter(
+                "The capacity of the device is unknown. \
+                The number of bytes to check must be specified.".to_string()).into());
+        }
+        let positions: Vec<u64> = marker_positions(claimed, DEFAULT_NR_MARKERS)
+            .iter()
+            .map(|p| seek + p)
+            .collect();
+        let total = (positions.len() * MARKER_SIZE) as u64;
+        let token: u64 = rand::random();
+        let mut buffer = AlignedBuffer::new(MARKER_SIZE, DIRECT_IO_ALIGN);
+        let mut found: Vec<Option<u64>> = vec![None; positions.len()];
+        let mut written = vec![false; positions.len()];
+
+        // Write all markers in ascending order.
+        // Wrapped around addresses overwrite the markers at lower addresses.
+        self.progress.begin(DtPhase::Write, file.get_path(), seek);
+        self.log_reset(total);
+        for (i, &pos) in positions.iter().enumerate() {
+            make_marker(&mut buffer, token, pos);
+            let result = file.seek(pos).and_then(|_| file.write(&buffer));
+            if let Err(e) = result {
+                let e = DtError::Io { op: DtIoOp::Write, offset: pos, source: e };
+                self.record_error(DtErrorKind::Write, pos, MARKER_SIZE as u64, e);
+            } else {
+                written[i] = true;
+            }
+            self.log(MARKER_SIZE, ((i + 1) * MARKER_SIZE) as u64);
+            if let Some(abort) = &self.abort {
+                if abort.load(Ordering::Relaxed) {
+                    return Err(DtError::Aborted.into());
+                }
+            }
+        }
+        if let Err(e) = file.sync() {
+            return Err(DtError::Io { op: DtIoOp::Sync, offset: seek, source: e }.into());
+        }
+        self.progress.finish(total, &self.errors);
+        if let Err(e) = file.drop_caches() {
+            eprintln!("WARNING: Failed to drop operating system caches: {}", e);
+        }
+
+        // Read all markers back.
+        self.progress.begin(DtPhase::Verify, file.get_path(), seek);
+        self.log_reset(total);
+        for (i, &pos) in positions.iter().enumerate() {
+            let result = file.seek(pos).and_then(|_| Disktest::read_full(&mut file, &mut buffer));
+            match result {
+                Ok(n) if n == MARKER_SIZE => {
+                    if written[i] {
+                        found[i] = parse_marker(&buffer, token);
+                    }
+                },
+                Ok(_) => (),
+                Err(e) => {
+                    let e = DtError::Io { op: DtIoOp::Read, offset: pos, source: e };
+                    self.record_error(DtErrorKind::Read, pos, MARKER_SIZE as u64, e);
+                },
+            }
+            self.log(MARKER_SIZE, ((i + 1) * MARKER_SIZE) as u64);
+            if let Some(abort) = &self.abort {
+                if abort.load(Ordering::Relaxed) {
+                    return Err(DtError::Aborted.into());
+                }
+            }
+        }
+        self.progress.finish(total, &self.errors);
+
+        // The usable capacity ends after the last good marker before the first bad one.
+        let bad = found.iter()
+            .zip(&positions)
+            .filter(|(f, p)| **f != Some(**p))
+            .count();
+        let usable = match first_bad_marker(&positions, &found) {
+            Some(0) => 0,
+            Some(i) => positions[i - 1] - seek + MARKER_SIZE as u64,
+            None => claimed,
+        };
+        let resolution = if positions.len() > 1 { positions[1] - positions[0] } else { claimed };
+
+        Ok(DtCapacity {
+            claimed,
+            usable,
+            markers: positions.len(),
+            bad,
+            resolution,
+        })
+    }
+
+    /// Run random accesses of RANDOM_IO_SIZE bytes for the given duration.
+    /// The accesses are spread over the region of max_bytes starting at seek.
+    /// Random data is written, if write is true. Otherwise the data is only read.
+    /// The time of the final sync is included in the result of a write run.
+    pub fn random_io(&mut self,
+                     file: DisktestFile,
+                     seek: u64,
+                     max_bytes: u64,
+                     write: bool,
+                     duration: Duration) -> ah::Result<DtRandomIo> {
+        let mut file = file;
+        self.errors.clear();
+
+        let region = self.limit_max_bytes(&file, seek, max_bytes);
+        if region == Disktest::UNLIMITED || region < RANDOM_IO_SIZE as u64 {
+            return Err(DtError::InvalidParameter(
+                format!("The random I/O region must be at least {} bytes and limited.",
+                        RANDOM_IO_SIZE)).into());
+        }
+        if !write {
+            if let Err(e) = file.drop_caches() {
+                eprintln!("WARNING: Failed to drop operating system caches: {}", e);
+            }
+        }
+
+        let mut rng = rand::thread_rng();
+        let mut buffer = AlignedBuffer::new(RANDOM_IO_SIZE, DIRECT_IO_ALIGN);
+        if write {
+            rng.fill(&mut buffer[..]);
+        }
+        let mut ops = 0u64;
+        let begin = Instant::now();
+        while begin.elapsed() < duration {
+            let pos = seek + random_offset(&mut rng, region).unwrap_or(0);
+            if let Err(e) = file.seek(pos) {
+                return Err(DtError::Io { op: DtIoOp::Seek, offset: pos, source: e }.into());
+            }
+            if write {
+                if let Err(e) = file.write(&buffer) {
+                    return Err(DtError::Io { op: DtIoOp::Write, offset: pos, source: e }.into());
+                }
+            } else {
+                match Disktest::read_full(&mut file, &mut buffer) {
+                    Ok(n) if n == RANDOM_IO_SIZE => (),
+                    Ok(_) => return Err(DtError::Io {
+                        op:     DtIoOp::Read,
+                        offset: pos,
+                        source: io::Error::from(io::ErrorKind::UnexpectedEof),
+                    }.into()),
+                    Err(e) => return Err(DtError::Io { op: DtIoOp::Read, offset: pos, source: e }.into()),
+                }
+            }
+            ops += 1;
+            self.throttle(RANDOM_IO_SIZE);
+
+            if self.cancel.load(Ordering::Relaxed) {
+                break; // Cancelled. -> Partial result.
+            }
+            if let Some(abort) = &self.abort {
+                if abort.load(Ordering::Relaxed) {
+                    return Err(DtError::Aborted.into());
+                }
+            }
+        }
+        if write {
+            if let Err(e) = file.sync() {
+                return Err(DtError::Io { op: DtIoOp::Sync, offset: seek, source: e }.into());
+            }
+        }
+
+        Ok(DtRandomIo {
+            ops,
+            bytes:      ops * RANDOM_IO_SIZE as u64,
+            elapsed:    begin.elapsed(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generator::{GeneratorAes128, GeneratorAes256, GeneratorBlake3,
+                           GeneratorChaCha8, GeneratorChaCha12, GeneratorChaCha20, GeneratorCRC,
+                           GeneratorCRC32C, GeneratorPattern, GeneratorShake256};
+    use crate::kdf::RAW_KEY_SIZE;
+    use crate::progress::ProgressInfo;
+    #[cfg(target_os="linux")]
+    use std::ffi::CString;
+    use std::path::Path;
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    /// Create a DisktestFile of an open file.
+    fn test_file(file: &File, path: &Path, quiet_level: u8) -> DisktestFile {
+        DisktestFile {
+            file: Some(DtFileHandle::File(file.try_clone().unwrap())),
+            path: path.to_path_buf(),
+            disk_size: None,
+            sector_size: None,
+            direct: false,
+            seek_offset: 0,
+            write_count: 0,
+            quiet_level,
+            stream_pos: None,
+        }
+    }
+
+    fn run_test(algorithm: DtStreamType, base_size: usize, chunk_factor: usize) {
+        let mut tfile = NamedTempFile::new().unwrap();
+        let pstr = String::from(tfile.path().to_str().unwrap());
+        let path = Path::new(&pstr);
+        let file = tfile.as_file_mut();
+        let mut loc_file = file.try_clone().unwrap();
+        let seed = vec![42, 43, 44, 45];
+        let nr_threads = 2;
+        let mut dt = DisktestBuilder::new()
+            .algorithm(algorithm)
+            .seed(seed)
+            .threads(nr_threads)
+            .chunk_size(base_size * chunk_factor)
+            .build()
+            .unwrap();
+
+        let mk_file = || test_file(file, path, 0);
+
+        // Write a couple of bytes and verify them.
+        let nr_bytes = 1000;
+        assert_eq!(dt.write(mk_file(), 0, nr_bytes).unwrap(), nr_bytes);
+        assert_eq!(dt.verify(mk_file(), 0, u64::MAX).unwrap(), nr_bytes);
+
+        // Write a couple of bytes and verify half of them.
+        let nr_bytes = 1000;
+        loc_file.set_len(0).unwrap();
+        assert_eq!(dt.write(mk_file(), 0, nr_bytes).unwrap(), nr_bytes);
+        assert_eq!(dt.verify(mk_file(), 0, nr_bytes / 2).unwrap(), nr_bytes / 2);
+
+        // Write a big chunk that is aggregated and verify it.
+        loc_file.set_len(0).unwrap();
+        let nr_bytes = (base_size * chunk_factor * nr_threads * 2 + 100) as u64;
+        assert_eq!(dt.write(mk_file(), 0, nr_bytes).unwrap(), nr_bytes);
+        assert_eq!(dt.verify(mk_file(), 0, u64::MAX).unwrap(), nr_bytes);
+
+        // Check whether write rewinds the file.
+        let nr_bytes = 1000;
+        loc_file.set_len(100).unwrap();
+        loc_file.seek(SeekFrom::Start(10)).unwrap();
+        assert_eq!(dt.write(mk_file(), 0, nr_bytes).unwrap(), nr_bytes);
+        assert_eq!(dt.verify(mk_file(), 0, u64::MAX).unwrap(), nr_bytes);
+
+        // Modify the written data and assert failure.
+        let nr_bytes = 1000;
+        loc_file.set_len(0).unwrap();
+        assert_eq!(dt.write(mk_file(), 0, nr_bytes).unwrap(), nr_bytes);
+        loc_file.seek(SeekFrom::Start(10)).unwrap();
+        writeln!(loc_file, "X").unwrap();
+        match dt.verify(mk_file(), 0, nr_bytes) {
+            Ok(_) => panic!("Verify of modified data did not fail!"),
+            Err(e) => {
+                assert_eq!(e.to_string(), "Data MISMATCH at byte 10!");
+                assert!(matches!(e.downcast_ref::<DtError>(),
+                                 Some(DtError::VerifyMismatch { offset: 10, length: 502 })));
+            },
+        }
+        assert_eq!(dt.get_errors().len(), 1);
+        assert_eq!(dt.get_errors()[0].kind, DtErrorKind::Mismatch);
+        assert_eq!(dt.get_errors()[0].offset, 0);
+        assert_eq!(dt.get_errors()[0].length, 512);
+
+        // Modify the written data at several places and record all bad regions.
+        let nr_bytes = 2000;
+        loc_file.set_len(0).unwrap();
+        assert_eq!(dt.write(mk_file(), 0, nr_bytes).unwrap(), nr_bytes);
+        for pos in &[10, 600, 1800] {
+            loc_file.seek(SeekFrom::Start(*pos)).unwrap();
+            writeln!(loc_file, "X").unwrap();
+        }
+        dt.keep_going = true;
+        match dt.verify(mk_file(), 0, nr_bytes) {
+            Ok(_) => panic!("Verify of modified data did not fail!"),
+            Err(e) => assert_eq!(e.to_string(),
+                                 "Verification found 2 bad regions with a total of 1.5 kiB (1.5 kB)!"),
+        }
+        let errors = dt.get_errors();
+        assert_eq!(errors.len(), 2);
+        assert_eq!((errors[0].offset, errors[0].length), (0, 1024));
+        assert_eq!(errors[0].message, "Data MISMATCH at byte 10!");
+        assert_eq!((errors[1].offset, errors[1].length), (1536, 464));
+        assert_eq!(errors[1].message, "Data MISMATCH at byte 1800 = 1.8 kiB (1.8 kB)!");
+        dt.keep_going = false;
+
+        // Check verify with seek.
+        loc_file.set_len(0).unwrap();
+        let nr_bytes = (base_size * chunk_factor * nr_threads * 10) as u64;
+        assert_eq!(dt.write(mk_file(), 0, nr_bytes).unwrap(), nr_bytes);
+        for offset in (0..nr_bytes).step_by(base_size * chunk_factor / 2) {
+            let bytes_verified = dt.verify(mk_file(), offset, u64::MAX).unwrap();
+            assert!(bytes_verified > 0 && bytes_verified <= nr_bytes);
+        }
+
+        // Check write with seek.
+        loc_file.set_len(0).unwrap();
+        let nr_bytes = (base_size * chunk_factor * nr_threads * 10) as u64;
+        assert_eq!(dt.write(mk_file(), 0, nr_bytes).unwrap(), nr_bytes);
+        let offset = (base_size * chunk_factor * nr_threads * 2) as u64;
+        assert_eq!(dt.write(mk_file(), offset, nr_bytes).unwrap(), nr_bytes);
+        assert_eq!(dt.verify(mk_file(), 0, u64::MAX).unwrap(), nr_bytes + offset);
+    }
+
+    #[test]
+    fn test_chacha8() {
+        run_test(DtStreamType::CHACHA8,
+                 GeneratorChaCha8::BASE_SIZE,
+                 GeneratorChaCha8::CHUNK_FACTOR);
+    }
+
+    #[test]
+    fn test_chacha12() {
+        run_test(DtStreamType::CHACHA12,
+                 GeneratorChaCha12::BASE_SIZE,
+                 GeneratorChaCha12::CHUNK_FACTOR);
+    }
+
+    #[test]
+    fn test_chacha20() {
+        run_test(DtStreamType::CHACHA20,
+                 GeneratorChaCha20::BASE_SIZE,
+                 GeneratorChaCha20::CHUNK_FACTOR);
+    }
+
+    #[test]
+    fn test_aes128() {
+        run_test(DtStreamType::AES128,
+                 GeneratorAes128::BASE_SIZE,
+                 GeneratorAes128::CHUNK_FACTOR);
+    }
+
+    #[test]
+    fn test_aes256() {
+        run_test(DtStreamType::AES256,
+                 GeneratorAes256::BASE_SIZE,
+                 GeneratorAes256::CHUNK_FACTOR);
+    }
+
+    #[test]
+    fn test_blake3() {
+        run_test(DtStreamType::BLAKE3,
+                 GeneratorBlake3::BASE_SIZE,
+                 GeneratorBlake3::CHUNK_FACTOR);
+    }
+
+    #[test]
+    fn test_shake256() {
+        run_test(DtStreamType::SHAKE256,
+                 GeneratorShake256::BASE_SIZE,
+                 GeneratorShake256::CHUNK_FACTOR);
+    }
+
+    #[test]
+    fn test_crc() {
+        run_test(DtStreamType::CRC,
+                 GeneratorCRC::BASE_SIZE,
+                 GeneratorCRC::CHUNK_FACTOR);
+    }
+
+    #[test]
+    fn test_crc_chunk_size() {
+        run_test(DtStreamType::CRC,
+                 GeneratorCRC::BASE_SIZE,
+                 4);
+    }
+
+    #[test]
+    fn test_crc32c() {
+        run_test(DtStreamType::CRC32C,
+                 GeneratorCRC32C::BASE_SIZE,
+                 GeneratorCRC32C::CHUNK_FACTOR);
+    }
+
+    #[test]
+    fn test_pattern() {
+        run_test(DtStreamType::PATTERN,
+                 GeneratorPattern::BASE_SIZE,
+                 GeneratorPattern::CHUNK_FACTOR);
+    }
+
+    #[test]
+    fn test_builder() {
+        let builder = || DisktestBuilder::new().seed(vec![1, 2, 3]);
+        assert!(builder().build().is_ok());
+        assert!(DisktestBuilder::new().build().is_err());
+        assert!(builder().threads(u16::MAX as usize + 2).build().is_err());
+        assert!(builder().chunk_size(0).build().is_err());
+        assert!(builder().chunk_size(GeneratorChaCha20::BASE_SIZE + 1).build().is_err());
+        assert!(DisktestBuilder::new().pattern(vec![]).build().is_err());
+        assert!(builder().cpu_set(vec![]).build().is_err());
+        assert!(builder().zones(0).build().is_err());
+        assert!(builder().verify_stride(0).build().is_err());
+        assert!(builder().kdf(DtKdf::Pbkdf2 { iterations: 0 }).build().is_err());
+        assert!(builder().kdf(DtKdf::Raw).build().is_err());
+        assert!(DisktestBuilder::new().seed(vec![7; RAW_KEY_SIZE]).kdf(DtKdf::Raw).build().is_ok());
+        assert_eq!(builder().cpu_set(vec![0]).build().is_ok(), affinity_supported());
+        let dt = DisktestBuilder::new().pattern(vec![0xAA, 0x55]).build().unwrap();
+        assert_eq!(dt.stream_agg.get_chunk_size(), DtStreamType::PATTERN.default_chunk_size());
+        let dt = builder()
+            .algorithm(DtStreamType::CRC)
+            .chunk_size(GeneratorCRC::BASE_SIZE * 3)
+            .quiet_level(2)
+            .keep_going(true)
+            .build()
+            .unwrap();
+        assert_eq!(dt.stream_agg.get_chunk_size(), GeneratorCRC::BASE_SIZE * 3);
+        assert!(dt.keep_going);
+    }
+
+    #[test]
+    fn test_stdio() {
+        assert!(DisktestFile::open(STDIO_PATH, true, true, false, false, 2).is_err());
+        assert!(DisktestFile::open(STDIO_PATH, false, true, true, false, 2).is_err());
+        assert!(DisktestFile::open(STDIO_PATH, false, true, false, true, 2).is_err());
+        let file = DisktestFile::open(STDIO_PATH, false, true, false, false, 2).unwrap();
+        assert!(file.is_stdio());
+        assert_eq!(file.get_disk_size(), None);
+        let mut file = DisktestFile::open(STDIO_PATH, true, false, false, false, 2).unwrap();
+        assert!(file.is_stdio());
+        assert!(file.write(&[0]).is_err());
+        // The stream starts at the first seek and can't be moved afterwards.
+        assert_eq!(file.seek(4096).unwrap(), 4096);
+        assert_eq!(file.seek(4096).unwrap(), 4096);
+        assert!(file.seek(0).is_err());
+        assert!(file.drop_caches().is_ok());
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut tfile = NamedTempFile::new().unwrap();
+        let path = tfile.path().to_path_buf();
+        let file = tfile.as_file_mut();
+        let mut dt = DisktestBuilder::new()
+            .seed(vec![1, 2, 3])
+            .quiet_level(2)
+            .build()
+            .unwrap();
+        let mk_file = || test_file(file, &path, 2);
+        let chunk_size = dt.stream_agg.get_chunk_size() as u64;
+
+        let handle = dt.handle();
+        assert!(!handle.is_cancelled());
+        handle.cancel();
+        assert!(handle.is_cancelled());
+        assert_eq!(dt.write(mk_file(), 0, chunk_size * 10).unwrap(), chunk_size);
+        assert_eq!(dt.verify(mk_file(), 0, Disktest::UNLIMITED).unwrap(), chunk_size);
+    }
+
+    #[test]
+    fn test_abort() {
+        let mut tfile = NamedTempFile::new().unwrap();
+        let path = tfile.path().to_path_buf();
+        let file = tfile.as_file_mut();
+        let abort = Arc::new(AtomicBool::new(false));
+        let mut dt = DisktestBuilder::new()
+            .seed(vec![1, 2, 3])
+            .quiet_level(2)
+            .abort(Arc::clone(&abort))
+            .build()
+            .unwrap();
+        let mk_file = || test_file(file, &path, 2);
+        let chunk_size = dt.stream_agg.get_chunk_size() as u64;
+
+        assert_eq!(dt.write(mk_file(), 0, chunk_size * 10).unwrap(), chunk_size * 10);
+        assert_eq!(dt.get_position(), chunk_size * 10);
+
+        // The position of the synced data is known after the abort.
+        abort.store(true, Ordering::Relaxed);
+        let e = dt.write(mk_file(), chunk_size * 2, chunk_size * 4).unwrap_err();
+        assert!(matches!(e.downcast_ref::<DtError>(), Some(DtError::Aborted)));
+        assert_eq!(dt.get_position(), chunk_size * 3);
+        let e = dt.verify(mk_file(), 0, Disktest::UNLIMITED).unwrap_err();
+        assert!(matches!(e.downcast_ref::<DtError>(), Some(DtError::Aborted)));
+        assert_eq!(dt.get_position(), chunk_size);
+    }
+
+    #[test]
+    fn test_pause() {
+        let mut tfile = NamedTempFile::new().unwrap();
+        let path = tfile.path().to_path_buf();
+        let file = tfile.as_file_mut();
+        let pause = Arc::new(AtomicBool::new(true));
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut dt = DisktestBuilder::new()
+            .seed(vec![1, 2, 3])
+            .quiet_level(2)
+            .pause(Arc::clone(&pause))
+            .progress_observer(Box::new(TestObserver { events: Arc::clone(&events) }))
+            .build()
+            .unwrap();
+        let mk_file = || test_file(file, &path, 2);
+        let chunk_size = dt.stream_agg.get_chunk_size() as u64;
+
+        // The write pauses after the first chunk until the pause flag is cleared.
+        let delay = Duration::from_millis(300);
+        let resume = {
+            let pause = Arc::clone(&pause);
+            thread::spawn(move || {
+                thread::sleep(delay);
+                pause.store(false, Ordering::Relaxed);
+            })
+        };
+        let begin = Instant::now();
+        assert_eq!(dt.write(mk_file(), 0, chunk_size * 3).unwrap(), chunk_size * 3);
+        assert!(begin.elapsed() >= delay);
+        resume.join().unwrap();
+        assert_eq!(dt.verify(mk_file(), 0, chunk_size * 3).unwrap(), chunk_size * 3);
+        let events = events.lock().unwrap();
+        let messages: Vec<&String> = events.iter().filter(|e| e.starts_with("message ")).collect();
+        assert_eq!(messages[..2], [&format!("message Paused at byte {}.", chunk_size),
+                                   &"message Resumed.".to_string()]);
+        assert!(!messages[2..].iter().any(|m| m.contains("Paused")));
+    }
+
+    #[test]
+    fn test_scan() {
+        let mut tfile = NamedTempFile::new().unwrap();
+        let path = tfile.path().to_path_buf();
+        let file = tfile.as_file_mut();
+        file.write_all(&[0x5A; 10000]).unwrap();
+        let mut dt = DisktestBuilder::new()
+            .seed(vec![1, 2, 3])
+            .quiet_level(2)
+            .build()
+            .unwrap();
+        let mk_file = || test_file(file, &path, 2);
+
+        // Any readable data passes the scan.
+        assert_eq!(dt.scan(mk_file(), 0, Disktest::UNLIMITED).unwrap(), 10000);
+        assert_eq!(dt.scan(mk_file(), 1000, 5000).unwrap(), 5000);
+        assert!(dt.get_errors().is_empty());
+        assert!(dt.get_latency().count() > 0);
+        assert_eq!(dt.get_latency().buckets().iter().sum::<u64>(), dt.get_latency().count());
+        assert!(dt.get_zones().is_none());
+        assert!(dt.verify(mk_file(), 0, Disktest::UNLIMITED).is_err());
+
+        // The zones are only measured, if the size is known.
+        let mut dt = DisktestBuilder::new()
+            .seed(vec![1, 2, 3])
+            .quiet_level(2)
+            .zones(4)
+            .build()
+            .unwrap();
+        assert_eq!(dt.scan(mk_file(), 1000, 8000).unwrap(), 8000);
+        let zones = dt.get_zones().unwrap().zones();
+        assert_eq!(zones.len(), 4);
+        assert_eq!((zones[1].offset, zones[1].length), (3000, 2000));
+        assert_eq!(zones.iter().map(|z| z.bytes).sum::<u64>(), 8000);
+        assert_eq!(dt.scan(mk_file(), 0, Disktest::UNLIMITED).unwrap(), 10000);
+        assert!(dt.get_zones().is_none());
+    }
+
+    #[test]
+    fn test_retry_read() {
+        let mut tfile = NamedTempFile::new().unwrap();
+        let path = tfile.path().to_path_buf();
+        let file = tfile.as_file_mut();
+        let data: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+        file.write_all(&data).unwrap();
+        let mut dt = DisktestBuilder::new()
+            .seed(vec![1, 2, 3])
+            .quiet_level(2)
+            .read_retries(2)
+            .build()
+            .unwrap();
+        let mk_file = |open: bool| {
+            let mut dfile = test_file(file, &path, 2);
+            if !open {
+                dfile.file = None;
+            }
+            dfile
+        };
+
+        // A transient error is recovered by the first retry.
+        let mut buffer = vec![0; 2000];
+        let res = dt.retry_read(&mut mk_file(true), 100, &mut buffer, 512, io::Error::other("foo"));
+        assert_eq!(res.unwrap(), 2000);
+        assert_eq!(buffer[..], data[100..2100]);
+        assert_eq!(dt.get_transient_errors(), 1);
+
+        // The end of the disk.
+        let res = dt.retry_read(&mut mk_file(true), 2500, &mut buffer, 512, io::Error::other("foo"));
+        assert_eq!(res.unwrap(), 500);
+        assert_eq!(dt.get_transient_errors(), 2);
+
+        // A persistent error.
+        let res = dt.retry_read(&mut mk_file(false), 0, &mut buffer, 512, io::Error::other("foo"));
+        assert_eq!(res.unwrap_err().0, 0);
+        assert_eq!(dt.get_transient_errors(), 2);
+        assert!(dt.get_errors().is_empty());
+    }
+
+    #[test]
+    fn test_skip_regions() {
+        assert_eq!(merge_regions(vec![(10, 5), (0, 10), (30, 0), (12, 10), (40, 2)]),
+                   vec![(0, 22), (40, 2)]);
+
+        let mut tfile = NamedTempFile::new().unwrap();
+        let path = tfile.path().to_path_buf();
+        let file = tfile.as_file_mut();
+        file.write_all(&[0x5A; 4096 * 3 + 100]).unwrap();
+        let mut loc_file = file.try_clone().unwrap();
+        let mk_file = || test_file(file, &path, 2);
+        let builder = || {
+            DisktestBuilder::new()
+                .seed(vec![1, 2, 3])
+                .threads(2)
+                .chunk_size(4096)
+                .quiet_level(2)
+        };
+        let skip = vec![(5000, 3000), (1000, 100), (12000, 300)];
+
+        // The skipped regions are aligned to the blocks and not written.
+        let mut dt = builder().skip_regions(skip.clone()).build().unwrap();
+        assert_eq!(dt.write(mk_file(), 0, 4096 * 3 + 100).unwrap(), 4096 * 3 + 100);
+        let data = std::fs::read(&path).unwrap();
+        assert_eq!(data.len(), 4096 * 3 + 100);
+        assert!(data[512..1536].iter().all(|&b| b == 0x5A));
+        assert!(data[4608..8192].iter().all(|&b| b == 0x5A));
+        assert!(data[11776..].iter().all(|&b| b == 0x5A));
+        assert!(!data[..512].iter().all(|&b| b == 0x5A));
+        assert!(!data[8192..11776].iter().all(|&b| b == 0x5A));
+
+        // The skipped regions are not verified.
+        loc_file.seek(SeekFrom::Start(6000)).unwrap();
+        loc_file.write_all(b"X").unwrap();
+        let mut dt = builder().skip_regions(skip.clone()).build().unwrap();
+        assert_eq!(dt.verify(mk_file(), 0, 4096 * 3 + 100).unwrap(), 4096 * 3 + 100);
+        assert_eq!(dt.verify(mk_file(), 5000, 4000).unwrap(), 4000);
+        assert!(dt.get_errors().is_empty());
+        let mut dt = builder().keep_going(true).build().unwrap();
+        assert!(dt.verify(mk_file(), 0, Disktest::UNLIMITED).is_err());
+        assert_eq!(dt.get_errors().iter().map(|e| (e.offset, e.length)).collect::<Vec<_>>(),
+                   vec![(512, 1024), (4608, 3584), (11776, 612)]);
+
+        // Outside of the skipped regions corruptions are still detected.
+        loc_file.seek(SeekFrom::Start(9000)).unwrap();
+        loc_file.write_all(b"X").unwrap();
+        let mut dt = builder().skip_regions(skip).build().unwrap();
+        match dt.verify(mk_file(), 0, Disktest::UNLIMITED) {
+            Err(e) => assert!(matches!(e.downcast_ref::<DtError>(),
+                                       Some(DtError::VerifyMismatch { offset: 9000, .. }))),
+            Ok(_) => panic!("Verify of modified data did not fail!"),
+        }
+    }
+
+    #[test]
+    fn test_max_errors() {
+        let mut tfile = NamedTempFile::new().unwrap();
+        let path = tfile.path().to_path_buf();
+        let file = tfile.as_file_mut();
+        let mut loc_file = file.try_clone().unwrap();
+        let mk_file = || test_file(file, &path, 2);
+        let builder = || {
+            DisktestBuilder::new()
+                .seed(vec![1, 2, 3])
+                .threads(2)
+                .chunk_size(4096)
+                .quiet_level(2)
+                .keep_going(true)
+        };
+        assert!(builder().max_errors(0).build().is_err());
+        let mut dt = builder().build().unwrap();
+        assert_eq!(dt.write(mk_file(), 0, 4096 * 4).unwrap(), 4096 * 4);
+        for offset in [100, 4096 + 100, 4096 * 3 + 100] {
+            loc_file.seek(SeekFrom::Start(offset)).unwrap();
+            loc_file.write_all(b"X").unwrap();
+        }
+
+        // The verification stops at the second bad region.
+        let mut dt = builder().max_errors(2).build().unwrap();
+        match dt.verify(mk_file(), 0, Disktest::UNLIMITED) {
+            Err(e) => assert!(matches!(e.downcast_ref::<DtError>(),
+                                       Some(DtError::BadRegions { count: 2, length: 1024 }))),
+            Ok(_) => panic!("Verify of modified data did not fail!"),
+        }
+        assert_eq!(dt.get_errors().len(), 2);
+
+        // All bad regions are found below the limit.
+        let mut dt = builder().max_errors(4).build().unwrap();
+        match dt.verify(mk_file(), 0, Disktest::UNLIMITED) {
+            Err(e) => assert!(matches!(e.downcast_ref::<DtError>(),
+                                       Some(DtError::BadRegions { count: 3, .. }))),
+            Ok(_) => panic!("Verify of modified data did not fail!"),
+        }
+    }
+
+    #[test]
+    fn test_short_read() {
+        let mut tfile = NamedTempFile::new().unwrap();
+        let path = tfile.path().to_path_buf();
+        let file = tfile.as_file_mut();
+        let mk_file = || test_file(file, &path, 2);
+        let builder = || {
+            DisktestBuilder::new()
+                .seed(vec![1, 2, 3])
+                .quiet_level(2)
+        };
+        let mut dt = builder().build().unwrap();
+        assert_eq!(dt.write(mk_file(), 0, 1000).unwrap(), 1000);
+
+        // The device ends before all data has been verified.
+        match dt.verify(mk_file(), 0, 5000) {
+            Err(e) => assert!(matches!(e.downcast_ref::<DtError>(),
+                                       Some(DtError::Io { op: DtIoOp::Read, offset: 1000, .. }))),
+            Ok(_) => panic!("Verify of short data did not fail!"),
+        }
+        assert_eq!(dt.get_errors().len(), 1);
+        assert_eq!(dt.get_errors()[0].kind, DtErrorKind::ShortRead);
+        assert_eq!((dt.get_errors()[0].offset, dt.get_errors()[0].length), (1000, 4000));
+
+        // The end of the device is not an error without a size.
+        let mut dt = builder().keep_going(true).build().unwrap();
+        assert_eq!(dt.verify(mk_file(), 0, Disktest::UNLIMITED).unwrap(), 1000);
+        assert!(dt.verify(mk_file(), 0, 1500).is_err());
+        assert_eq!(dt.get_errors()[0].kind, DtErrorKind::ShortRead);
+        assert_eq!((dt.get_errors()[0].offset, dt.get_errors()[0].length), (1000, 500));
+    }
+
+    #[test]
+    fn test_verify_stride() {
+        let mut tfile = NamedTempFile::new().unwrap();
+        let path = tfile.path().to_path_buf();
+        let file = tfile.as_file_mut();
+        let mut loc_file = file.try_clone().unwrap();
+        let mk_file = || test_file(file, &path, 2);
+        let builder = || {
+            DisktestBuilder::new()
+                .seed(vec![1, 2, 3])
+                .threads(2)
+                .chunk_size(4096)
+                .quiet_level(2)
+        };
+        let mut dt = builder().build().unwrap();
+        assert_eq!(dt.write(mk_file(), 0, 4096 * 10 + 100).unwrap(), 4096 * 10 + 100);
+
+        // Only every third chunk is verified.
+        let mut dt = builder().verify_stride(3).build().unwrap();
+        assert_eq!(dt.stride_bytes(4096 * 10 + 100, 3), 4096 * 4);
+        assert_eq!(dt.stride_bytes(4096 * 9 + 100, 3), 4096 * 3 + 100);
+        assert_eq!(dt.verify(mk_file(), 0, Disktest::UNLIMITED).unwrap(), 4096 * 4);
+        assert_eq!(dt.verify(mk_file(), 4096, Disktest::UNLIMITED).unwrap(), 4096 * 3 + 100);
+        assert_eq!(dt.verify(mk_file(), 0, 4096 * 4).unwrap(), 4096 * 2);
+
+        // Corruptions in the skipped chunks are not detected.
+        loc_file.seek(SeekFrom::Start(4096 + 10)).unwrap();
+        loc_file.write_all(b"X").unwrap();
+        assert_eq!(dt.verify(mk_file(), 0, Disktest::UNLIMITED).unwrap(), 4096 * 4);
+        loc_file.seek(SeekFrom::Start(4096 * 6 + 10)).unwrap();
+        loc_file.write_all(b"X").unwrap();
+        match dt.verify(mk_file(), 0, Disktest::UNLIMITED) {
+            Err(e) => assert!(matches!(e.downcast_ref::<DtError>(),
+                                       Some(DtError::VerifyMismatch { offset: 24586, .. }))),
+            Ok(_) => panic!("Verify of modified data did not fail!"),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_verify_mmap() {
+        let mut tfile = NamedTempFile::new().unwrap();
+        let path = tfile.path().to_path_buf();
+        let file = tfile.as_file_mut();
+        let mut loc_file = file.try_clone().unwrap();
+        let mk_file = || test_file(file, &path, 2);
+        let builder = || {
+            DisktestBuilder::new()
+                .seed(vec![1, 2, 3])
+                .threads(2)
+                .chunk_size(4096)
+                .quiet_level(2)
+                .mmap(true)
+        };
+        let len = 4096 * 10 + 100;
+        let mut dt = builder().build().unwrap();
+        assert_eq!(dt.write(mk_file(), 0, len).unwrap(), len);
+        assert_eq!(mk_file().mappable_size(), Some(len));
+        assert_eq!(dt.verify(mk_file(), 0, Disktest::UNLIMITED).unwrap(), len);
+        assert_eq!(dt.verify(mk_file(), 8192, 4096 * 3).unwrap(), 4096 * 3);
+        let mut dt = builder().verify_stride(3).build().unwrap();
+        assert_eq!(dt.verify(mk_file(), 0, Disktest::UNLIMITED).unwrap(), 4096 * 4);
+
+        // The file ends before all data has been verified.
+        let mut dt = builder().build().unwrap();
+        assert!(dt.verify(mk_file(), 0, len + 1000).is_err());
+        assert_eq!(dt.get_errors()[0].kind, DtErrorKind::ShortRead);
+        assert_eq!((dt.get_errors()[0].offset, dt.get_errors()[0].length), (len, 1000));
+
+        // All mismatches are found.
+        loc_file.seek(SeekFrom::Start(4096 + 10)).unwrap();
+        loc_file.write_all(b"X").unwrap();
+        loc_file.seek(SeekFrom::Start(4096 * 10 + 50)).unwrap();
+        loc_file.write_all(b"X").unwrap();
+        let mut dt = builder().build().unwrap();
+        match dt.verify(mk_file(), 0, Disktest::UNLIMITED) {
+            Err(e) => assert!(matches!(e.downcast_ref::<DtError>(),
+                                       Some(DtError::VerifyMismatch { offset: 4106, .. }))),
+            Ok(_) => panic!("Verify of modified data did not fail!"),
+        }
+        let mut dt = builder().keep_going(true).build().unwrap();
+        assert!(dt.verify(mk_file(), 0, Disktest::UNLIMITED).is_err());
+        let offsets: Vec<u64> = dt.get_errors().iter().map(|e| e.offset).collect();
+        assert_eq!(offsets, vec![4096, 4096 * 10]);
+    }
+
+    #[test]
+    fn test_sync_writes() {
+        let tfile = NamedTempFile::new().unwrap();
+        let path = tfile.path().to_str().unwrap().to_string();
+        let mut dt = DisktestBuilder::new()
+            .seed(vec![1, 2, 3])
+            .quiet_level(2)
+            .build()
+            .unwrap();
+        let file = DisktestFile::open(&path, false, true, false, true, 2).unwrap();
+        assert_eq!(dt.write(file, 0, 1024 * 1024).unwrap(), 1024 * 1024);
+        let file = DisktestFile::open(&path, true, false, false, false, 2).unwrap();
+        assert_eq!(dt.verify(file, 0, Disktest::UNLIMITED).unwrap(), 1024 * 1024);
+
+        // Periodic syncs.
+        assert!(DisktestBuilder::new().seed(vec![1, 2, 3]).sync_every(0).build().is_err());
+        let mut dt = DisktestBuilder::new()
+            .seed(vec![1, 2, 3])
+            .chunk_size(4096)
+            .sync_every(10000)
+            .quiet_level(2)
+            .build()
+            .unwrap();
+        let file = DisktestFile::open(&path, false, true, false, false, 2).unwrap();
+        assert_eq!(dt.write(file, 0, 4096 * 20 + 100).unwrap(), 4096 * 20 + 100);
+        let file = DisktestFile::open(&path, true, false, false, false, 2).unwrap();
+        assert_eq!(dt.verify(file, 0, 4096 * 20 + 100).unwrap(), 4096 * 20 + 100);
+    }
+
+    /// A size limited tmpfs, that runs full like a device.
+    /// It is unmounted when it is dropped.
+    #[cfg(target_os="linux")]
+    struct SmallTmpfs {
+        dir: tempfile::TempDir,
+    }
+
+    #[cfg(target_os="linux")]
+    impl SmallTmpfs {
+        /// Mount a tmpfs of size bytes. This requires root privileges.
+        fn mount(size: u64) -> Option<SmallTmpfs> {
+            let dir = tempfile::tempdir().unwrap();
+            let target = CString::new(dir.path().to_str().unwrap()).unwrap();
+            let fstype = CString::new("tmpfs").unwrap();
+            let data = CString::new(format!("size={}", size)).unwrap();
+            let ret = unsafe { libc::mount(fstype.as_ptr(), target.as_ptr(), fstype.as_ptr(),
+                                           0, data.as_ptr() as *const libc::c_void) };
+            if ret == 0 {
+                Some(SmallTmpfs { dir })
+            } else {
+                None
+            }
+        }
+
+        fn path(&self, name: &str) -> String {
+            self.dir.path().join(name).to_str().unwrap().to_string()
+        }
+    }
+
+    #[cfg(target_os="linux")]
+    impl Drop for SmallTmpfs {
+        fn drop(&mut self) {
+            let target = CString::new(self.dir.path().to_str().unwrap()).unwrap();
+            unsafe { libc::umount(target.as_ptr()) };
+        }
+    }
+
+    #[cfg(target_os="linux")]
+    #[test]
+    fn test_device_full() {
+        let size = 4096 * 4 * 3 + 4096;
+        let fs = match SmallTmpfs::mount(size) {
+            Some(fs) => fs,
+            None => return, // Not running as root.
+        };
+        let path = fs.path("file");
+        let builder = || {
+            DisktestBuilder::new()
+                .seed(vec![1, 2, 3])
+                .chunk_size(4096 * 4)
+                .quiet_level(2)
+                .build()
+                .unwrap()
+        };
+
+        // The device runs full in the middle of a chunk.
+        let mut dt = builder();
+        let file = DisktestFile::open(&path, false, true, false, false, 2).unwrap();
+        assert_eq!(dt.write(file, 0, Disktest::UNLIMITED).unwrap(), size);
+        assert!(dt.get_errors().is_empty());
+        let file = DisktestFile::open(&path, true, false, false, false, 2).unwrap();
+        assert_eq!(builder().verify(file, 0, Disktest::UNLIMITED).unwrap(), size);
+
+        // The same with direct I/O. The tmpfs allocates whole pages,
+        // so it runs full at a block boundary and no partial block is written.
+        std::fs::remove_file(&path).unwrap();
+        let file = match DisktestFile::open(&path, false, true, true, false, 2) {
+            Ok(file) => file,
+            Err(_) => return, // tmpfs supports direct I/O since Linux 6.6.
+        };
+        let mut dt = builder();
+        assert_eq!(dt.write(file, 0, Disktest::UNLIMITED).unwrap(), size);
+        assert!(dt.get_errors().is_empty());
+        let file = DisktestFile::open(&path, true, false, true, false, 2).unwrap();
+        assert_eq!(builder().verify(file, 0, Disktest::UNLIMITED).unwrap(), size);
+    }
+
+    #[test]
+    fn test_follow_trail() {
+        let tfile = NamedTempFile::new().unwrap();
+        let path = tfile.path().to_str().unwrap().to_string();
+        let builder = || {
+            DisktestBuilder::new()
+                .seed(vec![1, 2, 3])
+                .chunk_size(4096)
+                .max_rate(1024 * 1024)
+                .quiet_level(2)
+        };
+        let nr_bytes = 4096 * 50 + 100;
+
+        // The verification trails the write and stops at its end.
+        let trail = DtTrail::new();
+        let mut writer = builder().build().unwrap();
+        writer.set_trail(trail.clone());
+        let mut verifier = builder().build().unwrap();
+        verifier.follow_trail(trail.clone(), 4096 * 4);
+        let file = DisktestFile::open(&path, false, true, false, false, 2).unwrap();
+        let write_thread = thread::spawn(move || {
+            let result = writer.write(file, 0, nr_bytes);
+            trail.finish();
+            result
+        });
+        let file = DisktestFile::open(&path, true, false, false, false, 2).unwrap();
+        assert_eq!(verifier.verify(file, 0, Disktest::UNLIMITED).unwrap(), nr_bytes);
+        assert_eq!(write_thread.join().unwrap().unwrap(), nr_bytes);
+
+        // Nothing is verified, if nothing has been written.
+        let trail = DtTrail::new();
+        trail.finish();
+        let mut verifier = builder().build().unwrap();
+        verifier.follow_trail(trail, 0);
+        let file = DisktestFile::open(&path, true, false, false, false, 2).unwrap();
+        assert_eq!(verifier.verify(file, 0, Disktest::UNLIMITED).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_compare() {
+        let data: Vec<u8> = (0..10000).map(|i| (i % 251) as u8).collect();
+        let mk_file = |data: &[u8]| {
+            let mut tfile = NamedTempFile::new().unwrap();
+            tfile.as_file_mut().write_all(data).unwrap();
+            let file = test_file(tfile.as_file(), tfile.path(), 2);
+            (tfile, file)
+        };
+        let mut dt = DisktestBuilder::new()
+            .seed(vec![1, 2, 3])
+            .chunk_size(4096)
+            .keep_going(true)
+            .quiet_level(2)
+            .build()
+            .unwrap();
+
+        // Identical data.
+        let (_a, file_a) = mk_file(&data);
+        let (_b, file_b) = mk_file(&data);
+        assert_eq!(dt.compare(file_a, file_b, 0, 0, Disktest::UNLIMITED).unwrap(), 10000);
+        assert!(dt.get_errors().is_empty());
+
+        // All differing blocks are reported.
+        let mut other = data.clone();
+        other[100] ^= 0xFF;
+        other[5000..5010].iter_mut().for_each(|b| *b ^= 0xFF);
+        other[9999] ^= 0xFF;
+        let (_a, file_a) = mk_file(&data);
+        let (_b, file_b) = mk_file(&other);
+        match dt.compare(file_a, file_b, 0, 0, Disktest::UNLIMITED) {
+            Err(e) => match e.downcast_ref::<DtError>() {
+                Some(DtError::BadRegions { count: 3, length: 1296 }) => (),
+                e => panic!("Unexpected error {:?}", e),
+            },
+            Ok(_) => panic!("Differences not detected"),
+        }
+        let errors: Vec<(u64, u64)> = dt.get_errors().iter().map(|e| (e.offset, e.length)).collect();
+        assert_eq!(errors, vec![(0, 512), (4608, 512), (9728, 272)]);
+        assert!(dt.get_errors().iter().all(|e| e.kind == DtErrorKind::Mismatch));
+
+        // Partial comparison.
+        let (_a, file_a) = mk_file(&data);
+        let (_b, file_b) = mk_file(&other);
+        assert_eq!(dt.compare(file_a, file_b, 1000, 1000, 3000).unwrap(), 3000);
+
+        // Different offsets.
+        let (_a, file_a) = mk_file(&data[..6000]);
+        let (_b, file_b) = mk_file(&data[2000..]);
+        assert_eq!(dt.compare(file_a, file_b, 3000, 1000, Disktest::UNLIMITED).unwrap(), 3000);
+        let (_a, file_a) = mk_file(&data);
+        let (_b, file_b) = mk_file(&data);
+        assert!(dt.compare(file_a, file_b, 3000, 1000, 1000).is_err());
+        let errors: Vec<(u64, u64)> = dt.get_errors().iter().map(|e| (e.offset, e.length)).collect();
+        assert_eq!(errors, vec![(3000, 1000)]);
+
+        // The comparison stops at the end of the shorter device.
+        let (_a, file_a) = mk_file(&data);
+        let (_b, file_b) = mk_file(&data[..6000]);
+        assert_eq!(dt.compare(file_a, file_b, 0, 0, Disktest::UNLIMITED).unwrap(), 6000);
+        let (_a, file_a) = mk_file(&data[..6000]);
+        let (_b, file_b) = mk_file(&data);
+        assert_eq!(dt.compare(file_a, file_b, 0, 0, Disktest::UNLIMITED).unwrap(), 6000);
+    }
+
+    #[test]
+    fn test_nondestructive() {
+        let mut tfile = NamedTempFile::new().unwrap();
+        let path = tfile.path().to_path_buf();
+        let file = tfile.as_file_mut();
+        let original: Vec<u8> = (0..10000).map(|i| (i % 251) as u8).collect();
+        file.write_all(&original).unwrap();
+        let mut dt = DisktestBuilder::new()
+            .seed(vec![1, 2, 3])
+            .chunk_size(4096)
+            .quiet_level(2)
+            .build()
+            .unwrap();
+        let mk_file = || test_file(file, &path, 2);
+
+        // The original data is restored after each segment.
+        assert_eq!(dt.nondestructive(mk_file(), 0, Disktest::UNLIMITED).unwrap(), 10000);
+        assert_eq!(dt.nondestructive(mk_file(), 4096, 5000).unwrap(), 5000);
+        assert!(dt.get_errors().is_empty());
+        assert_eq!(std::fs::read(&path).unwrap(), original);
+    }
+
+    #[test]
+    fn test_surface() {
+        let mut tfile = NamedTempFile::new().unwrap();
+        let path = tfile.path().to_path_buf();
+        let file = tfile.as_file_mut();
+        let mut dt = DisktestBuilder::new()
+            .seed(vec![1, 2, 3])
+            .chunk_size(4096)
+            .quiet_level(2)
+            .build()
+            .unwrap();
+        let mk_file = || test_file(file, &path, 2);
+
+        // The size of the device must be known.
+        assert!(dt.surface(mk_file(), 0, Disktest::UNLIMITED).is_err());
+
+        // The tested data stays on the device and can be verified.
+        assert_eq!(dt.surface(mk_file(), 0, 10000).unwrap(), 10000);
+        assert!(dt.get_errors().is_empty());
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 10000);
+        assert_eq!(dt.verify(mk_file(), 0, Disktest::UNLIMITED).unwrap(), 10000);
+    }
+
+    #[test]
+    fn test_header() {
+        let tfile = NamedTempFile::new().unwrap();
+        let path = tfile.path().to_path_buf();
+        let mk_file = || test_file(tfile.as_file(), &path, 2);
+        let mk_dt = |header| {
+            let builder = DisktestBuilder::new()
+                .algorithm(DtStreamType::CRC)
+                .seed(vec![1, 2, 3])
+                .threads(2)
+                .quiet_level(2);
+            if header {
+                builder.header(0).build().unwrap()
+            } else {
+                builder.build().unwrap()
+            }
+        };
+
+        assert_eq!(Disktest::read_header(mk_file(), 0).unwrap(), None);
+        let mut dt = mk_dt(true);
+        assert_eq!(dt.write(mk_file(), 0, 100000).unwrap(), 100000);
+        dt.write_header(mk_file(), 100000).unwrap();
+
+        let header = Disktest::read_header(mk_file(), 0).unwrap().unwrap();
+        assert_eq!(header.algorithm, DtStreamType::CRC);
+        assert_eq!(header.threads, 2);
+        assert_eq!(header.bytes, 100000);
+        assert!(header.check_seed(&[1, 2, 3]));
+
+        // The header is not compared.
+        assert_eq!(dt.verify(mk_file(), 0, 100000).unwrap(), 100000);
+        assert!(mk_dt(false).verify(mk_file(), 0, 100000).is_err());
+        assert!(mk_dt(false).write_header(mk_file(), 100000).is_err());
+    }
+
+    #[test]
+    fn test_capacity_check() {
+        let tfile = NamedTempFile::new().unwrap();
+        let path = tfile.path().to_path_buf();
+        let mut dt = DisktestBuilder::new()
+            .seed(vec![1, 2, 3])
+            .quiet_level(2)
+            .build()
+            .unwrap();
+        let mk_file = || test_file(tfile.as_file(), &path, 2);
+
+        let cap = dt.capacity_check(mk_file(), 0, 1024 * 1024).unwrap();
+        assert_eq!(cap, DtCapacity {
+            claimed:    1024 * 1024,
+            usable:     1024 * 1024,
+            markers:    256,
+            bad:        0,
+            resolution: 4096,
+        });
+        assert!(!cap.is_fake());
+        let cap = dt.capacity_check(mk_file(), 8192, 1024 * 1024 * 10).unwrap();
+        assert_eq!(cap.usable, 1024 * 1024 * 10);
+        assert_eq!(cap.resolution, 8192);
+
+        // The capacity of a regular file is unknown.
+        assert!(dt.capacity_check(mk_file(), 0, Disktest::UNLIMITED).is_err());
+    }
+
+    struct TestObserver {
+        events:     Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    impl ProgressObserver for TestObserver {
+        fn begin(&mut self, phase: DtPhase, _path: &Path, seek: u64) {
+            self.events.lock().unwrap().push(format!("begin {} {}", phase, seek));
+        }
+
+        fn error(&mut self, error: &DtErrorRecord) {
+            self.events.lock().unwrap().push(format!("error {} {}", error.kind, error.offset));
+        }
+
+        fn message(&mut self, message: &str) {
+            self.events.lock().unwrap().push(format!("message {}", message));
+        }
+
+        fn finish(&mut self, info: &ProgressInfo, errors: &[DtErrorRecord]) {
+            self.events.lock().unwrap().push(format!("finish {} {} {} {}",
+                                                     info.phase, info.offset,
+                                                     info.bytes, errors.len()));
+        }
+    }
+
+    #[test]
+    fn test_random_io() {
+        let mut tfile = NamedTempFile::new().unwrap();
+        let path = tfile.path().to_path_buf();
+        let file = tfile.as_file_mut();
+        file.write_all(&[0x5A; 4096 * 8]).unwrap();
+        let mut loc_file = file.try_clone().unwrap();
+        let mut dt = DisktestBuilder::new()
+            .seed(vec![1, 2, 3])
+            .quiet_level(2)
+            .build()
+            .unwrap();
+        let mk_file = || test_file(file, &path, 2);
+        let duration = Duration::from_millis(50);
+
+        let r = dt.random_io(mk_file(), 4096, 4096 * 4, false, duration).unwrap();
+        assert!(r.ops > 0);
+        assert_eq!(r.bytes, r.ops * RANDOM_IO_SIZE as u64);
+        assert!(r.elapsed >= duration);
+
+        // Only the region is written.
+        let r = dt.random_io(mk_file(), 4096, 4096 * 4, true, duration).unwrap();
+        assert!(r.ops > 0);
+        let mut data = vec![];
+        loc_file.seek(SeekFrom::Start(0)).unwrap();
+        loc_file.read_to_end(&mut data).unwrap();
+        assert_eq!(data.len(), 4096 * 8);
+        assert!(data[..4096].iter().all(|&b| b == 0x5A));
+        assert!(data[4096 * 5..].iter().all(|&b| b == 0x5A));
+        assert!(data[4096..4096 * 5].iter().any(|&b| b != 0x5A));
+
+        // The region must hold at least one access.
+        assert!(dt.random_io(mk_file(), 0, 4095, false, duration).is_err());
+        assert!(dt.random_io(mk_file(), 0, Disktest::UNLIMITED, false, duration).is_err());
+    }
+
+    #[test]
+    fn test_observer() {
+        let mut tfile = NamedTempFile::new().unwrap();
+        let path = tfile.path().to_path_buf();
+        let file = tfile.as_file_mut();
+        let mut loc_file = file.try_clone().unwrap();
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut dt = DisktestBuilder::new()
+            .seed(vec![1, 2, 3])
+            .quiet_level(2)
+            .keep_going(true)
+            .progress_observer(Box::new(TestObserver { events: Arc::clone(&events) }))
+            .build()
+            .unwrap();
+        let mk_file = || test_file(file, &path, 2);
+
+        assert_eq!(dt.write(mk_file(), 0, 2000).unwrap(), 2000);
+        loc_file.seek(SeekFrom::Start(1000)).unwrap();
+        writeln!(loc_file, "X").unwrap();
+        assert!(dt.verify(mk_file(), 0, 2000).is_err());
+        let mut events = events.lock().unwrap().clone();
+        // The first mismatch is described.
+        assert!(events.remove(5).starts_with("message First mismatch at byte 1000 "));
+        assert_eq!(events, vec![
+            "begin write 0",
+            "message Writing stopped. Syncing...",
+            "finish write 2000 2000 0",
+            "begin verify 0",
+            "error data mismatch 512",
+            "finish verify 2000 2000 1",
+        ]);
+    }
+
+    #[test]
+    fn test_diagnose_mismatch() {
+        let tfile = NamedTempFile::new().unwrap();
+        let path = tfile.path().to_path_buf();
+        let mk_file = || test_file(tfile.as_file(), &path, 2);
+        let verify = |algorithm, seed| {
+            let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+            let mut dt = DisktestBuilder::new()
+                .algorithm(algorithm)
+                .seed(seed)
+                .quiet_level(2)
+                .progress_observer(Box::new(TestObserver { events: Arc::clone(&events) }))
+                .build()
+                .unwrap();
+            assert!(dt.verify(mk_file(), 0, 100000).is_err());
+            let events = events.lock().unwrap();
+            events.iter().find(|e| e.starts_with("message Hint")).cloned()
+        };
+
+        tfile.as_file().write_all(&[0; 100000]).unwrap();
+        assert_eq!(verify(DtStreamType::CHACHA20, vec![1, 2, 3]).unwrap(),
+                   "message Hint: The device contains only 0x00 bytes. \
+                   It has probably not been written by disktest.");
+
+        let mut dt = DisktestBuilder::new()
+            .algorithm(DtStreamType::CRC)
+            .seed(vec![1, 2, 3])
+            .quiet_level(2)
+            .build()
+            .unwrap();
+        assert_eq!(dt.write(mk_file(), 0, 100000).unwrap(), 100000);
+        assert_eq!(verify(DtStreamType::CHACHA20, vec![1, 2, 3]).unwrap(),
+                   "message Hint: The data matches the CRC algorithm. \
+                   Please verify with --algorithm CRC.");
+        assert!(verify(DtStreamType::CRC, vec![1, 2, 4]).unwrap()
+                .starts_with("message Hint: The data does not match at all."));
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -115,6 +115,43 @@ fn os_drop_file_caches(_file: File,
     Err(ah::format_err!("Not supported on this operating system."))
 }
 
+#[cfg(any(target_os="linux", target_os="android", target_os="freebsd"))]
+fn os_drop_range_caches(file: &File,
+                        offset: u64,
+                        size: u64) -> ah::Result<()> {
+    use libc::{posix_fadvise, POSIX_FADV_DONTNEED, off_t};
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+
+    // Dirty pages are not dropped, but their writeback is started.
+    let ret = unsafe { posix_fadvise(file.as_raw_fd(),
+                                     offset as off_t,
+                                     size as off_t,
+                                     POSIX_FADV_DONTNEED) };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(ah::format_err!("{}", io::Error::from_raw_os_error(ret)))
+    }
+}
+
+#[cfg(not(any(target_os="linux", target_os="android", target_os="freebsd")))]
+fn os_drop_range_caches(_file: &File,
+                        _offset: u64,
+                        _size: u64) -> ah::Result<()> {
+    // There is no equivalent. The caches are dropped when the file is closed.
+    Ok(())
+}
+
+/// Try to drop the operating system caches of a region of the file
+/// without closing it. The caches of data that has not been
+/// written back to the device yet might not be dropped.
+pub fn drop_range_caches(file: &File,
+                         offset: u64,
+                         size: u64) -> ah::Result<()> {
+    os_drop_range_caches(file, offset, size)
+}
+
 /// Consume a file object, close it and try to drop all operating system caches.
 pub fn drop_file_caches(file: File,
                         path: &Path,
@@ -136,6 +173,7 @@ mod tests {
         let path = tdir.path().join("test_drop_file_caches");
         let mut file = File::create(&path).unwrap();
         file.write_all(&[42u8; 4096]).unwrap();
+        drop_range_caches(&file, 0, 4096).unwrap();
         drop_file_caches(file, &path, 0, 4096).unwrap();
     }
 }
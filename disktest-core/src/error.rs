@@ -0,0 +1,160 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+
+use crate::util::prettybytes;
+use std::fmt;
+use std::io;
+
+/// I/O operation that failed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DtIoOp {
+    Seek,
+    Read,
+    Write,
+    Sync,
+}
+
+/// Errors of the Disktest engine.
+///
+/// The engine returns these errors wrapped in anyhow::Error.
+/// Use `error.downcast_ref::<DtError>()` to get the typed error.
+#[derive(Debug)]
+pub enum DtError {
+    /// An I/O operation failed at the absolute byte offset.
+    Io {
+        op:         DtIoOp,
+        offset:     u64,
+        source:     io::Error,
+    },
+    /// The data read from the device does not match the written data.
+    /// offset is the absolute offset of the first corrupt byte.
+    /// length is the number of bytes from offset to the end of the bad region.
+    VerifyMismatch {
+        offset:     u64,
+        length:     u64,
+    },
+    /// Verification in keep-going mode found bad regions.
+    BadRegions {
+        count:      usize,
+        length:     u64,
+    },
+    /// The device has less usable capacity than it claims to have.
+    FakeCapacity {
+        claimed:    u64,
+        usable:     u64,
+    },
+    /// The operation was aborted.
+    Aborted,
+    /// The run has been stopped at its time limit before it was complete.
+    TimeLimit,
+    /// A parameter is invalid.
+    InvalidParameter(String),
+}
+
+impl DtError {
+    /// Check whether the error is caused by corrupt data on the device.
+    pub fn is_corruption(&self) -> bool {
+        matches!(self, DtError::VerifyMismatch { .. } |
+                       DtError::BadRegions { .. } |
+                       DtError::FakeCapacity { .. })
+    }
+}
+
+impl fmt::Display for DtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DtError::Io { op: DtIoOp::Seek, offset, source } =>
+                write!(f, "File seek to {} failed: {}", offset, source),
+            DtError::Io { op: DtIoOp::Read, offset, source } =>
+                write!(f, "Read error at {}: {}", prettybytes(*offset, true, true), source),
+            DtError::Io { op: DtIoOp::Write, offset, source } =>
+                write!(f, "Write error at {}: {}", prettybytes(*offset, true, true), source),
+            DtError::Io { op: DtIoOp::Sync, source, .. } =>
+                write!(f, "Sync failed: {}", source),
+            DtError::VerifyMismatch { offset, .. } if *offset >= 1024 =>
+                write!(f, "Data MISMATCH at byte {} = {}!",
+                       offset, prettybytes(*offset, true, true)),
+            DtError::VerifyMismatch { offset, .. } =>
+                write!(f, "Data MISMATCH at byte {}!", offset),
+            DtError::BadRegions { count, length } =>
+                write!(f, "Verification found {} bad regions with a total of {}!",
+                       count, prettybytes(*length, true, true)),
+            DtError::FakeCapacity { claimed, usable } =>
+                write!(f, "The device claims to have {}, but only {} are usable!",
+                       prettybytes(*claimed, true, true), prettybytes(*usable, true, true)),
+            DtError::Aborted =>
+                write!(f, "Aborted by signal!"),
+            DtError::TimeLimit =>
+                write!(f, "The time limit has been reached. The run is incomplete."),
+            DtError::InvalidParameter(message) =>
+                write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for DtError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DtError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow as ah;
+
+    #[test]
+    fn test_display() {
+        let e = DtError::Io {
+            op:     DtIoOp::Write,
+            offset: 2048,
+            source: io::Error::other("foo"),
+        };
+        assert_eq!(e.to_string(), "Write error at 2.0 kiB (2.0 kB): foo");
+        assert!(std::error::Error::source(&e).is_some());
+        assert!(!e.is_corruption());
+        let e = DtError::VerifyMismatch { offset: 10, length: 502 };
+        assert_eq!(e.to_string(), "Data MISMATCH at byte 10!");
+        assert!(e.is_corruption());
+        let e = DtError::BadRegions { count: 2, length: 512 };
+        assert_eq!(e.to_string(), "Verification found 2 bad regions with a total of 512 bytes!");
+        assert!(e.is_corruption());
+        let e = DtError::FakeCapacity { claimed: 4096, usable: 1024 };
+        assert_eq!(e.to_string(),
+                   "The device claims to have 4.0 kiB (4.1 kB), but only 1.0 kiB (1.0 kB) are usable!");
+        assert!(e.is_corruption());
+        let e = DtError::TimeLimit;
+        assert_eq!(e.to_string(), "The time limit has been reached. The run is incomplete.");
+        assert!(!e.is_corruption());
+    }
+
+    #[test]
+    fn test_downcast() {
+        let e: ah::Error = DtError::Aborted.into();
+        assert!(matches!(e.downcast_ref::<DtError>(), Some(DtError::Aborted)));
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
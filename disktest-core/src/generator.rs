@@ -19,36 +19,51 @@
 // 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
 //
 
+mod aes;
+mod blake3;
 mod chacha;
 mod crc;
+mod crc32c;
+mod pattern;
+mod shake;
 
 use anyhow as ah;
-use crate::util::prettybytes;
 
+pub use crate::generator::aes::GeneratorAes128;
+pub use crate::generator::aes::GeneratorAes256;
+pub use crate::generator::blake3::GeneratorBlake3;
 pub use crate::generator::chacha::GeneratorChaCha8;
 pub use crate::generator::chacha::GeneratorChaCha12;
 pub use crate::generator::chacha::GeneratorChaCha20;
 pub use crate::generator::crc::GeneratorCRC;
+pub use crate::generator::crc32c::GeneratorCRC32C;
+pub use crate::generator::pattern::GeneratorPattern;
+pub use crate::generator::shake::GeneratorShake256;
 
 pub trait NextRandom {
     /// Get the size of the next() output with count = 1, in bytes.
     fn get_base_size(&self) -> usize;
 
+    /// Generate the next chunks into buf.
+    /// The length of buf must be a multiple of the base size.
+    fn next_into(&mut self, buf: &mut [u8]);
+
     /// Generate the next chunks.
     /// count: The number of chunks to return.
     /// Returns all chunks concatenated in a Vec.
-    fn next(&mut self, count: usize) -> Vec<u8>;
-
-    /// Seek the algorithm to the specified offset.
-    fn seek(&mut self, byte_offset: u64) -> ah::Result<()> {
-        if byte_offset == 0 {
-            Ok(())
-        } else {
-            Err(ah::format_err!("The selected random number generator \
-                                does not support seeking to byte offset {}.",
-                                prettybytes(byte_offset, true, true)))
-        }
+    #[cfg(test)]
+    fn next(&mut self, count: usize) -> Vec<u8> {
+        let mut buf = vec![0; self.get_base_size() * count];
+        self.next_into(&mut buf);
+        buf
     }
+
+    /// Seek the algorithm to the specified byte offset.
+    /// byte_offset must be a multiple of the base size.
+    /// Every algorithm must be able to seek in O(1), independent of the offset.
+    /// Therefore, verifying the end of a big device doesn't require
+    /// generating the data for the whole device.
+    fn seek(&mut self, byte_offset: u64) -> ah::Result<()>;
 }
 
 // vim: ts=4 sw=4 expandtab
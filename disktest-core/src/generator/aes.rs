@@ -0,0 +1,218 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+
+use anyhow as ah;
+use crate::generator::NextRandom;
+use crate::util::fold;
+use crypto::aes::{ctr, KeySize};
+use crypto::symmetriccipher::SynchronousStreamCipher;
+
+/// Size of the AES block, in bytes.
+const AES_BLOCK_SIZE: usize = 16;
+
+macro_rules! GeneratorAes {
+    ( $Generator:ident,
+      $KeySize:ident,
+      $key_len:literal,
+      $testmodule:ident,
+      $testresult0:literal,
+      $testresult1:literal,
+      $testresult2:literal,
+      $testresult3:literal
+    ) => {
+
+        /// AES in counter mode.
+        /// The hardware AES instructions are used, if the CPU supports them.
+        pub struct $Generator {
+            key:        [u8; $key_len],
+            cipher:     Box<dyn SynchronousStreamCipher>,
+            zeros:      Vec<u8>,
+        }
+
+        impl $Generator {
+            /// Size of the algorithm base output data.
+            pub const BASE_SIZE: usize = 1024 * 4;
+            /// Chunk size. Multiple of the generator base size.
+            pub const CHUNK_FACTOR: usize = 768;
+
+            pub fn new(seed: &[u8]) -> $Generator {
+                assert!(!seed.is_empty());
+                let mut key = [0u8; $key_len];
+                key.copy_from_slice(&fold(seed, $key_len));
+
+                $Generator {
+                    key,
+                    cipher: $Generator::new_cipher(&key, 0),
+                    zeros:  vec![0; $Generator::BASE_SIZE],
+                }
+            }
+
+            /// Create the cipher that starts at the counter block number.
+            fn new_cipher(key: &[u8], block: u128) -> Box<dyn SynchronousStreamCipher> {
+                ctr(KeySize::$KeySize, key, &block.to_be_bytes())
+            }
+        }
+
+        impl NextRandom for $Generator {
+            fn get_base_size(&self) -> usize {
+                $Generator::BASE_SIZE
+            }
+
+            fn next_into(&mut self, buf: &mut [u8]) {
+                debug_assert_eq!(buf.len() % $Generator::BASE_SIZE, 0);
+
+                // The key stream is the encrypted counter.
+                for chunk in buf.chunks_mut($Generator::BASE_SIZE) {
+                    self.cipher.process(&self.zeros, chunk);
+                }
+            }
+
+            fn seek(&mut self, byte_offset: u64) -> ah::Result<()> {
+                if byte_offset % $Generator::BASE_SIZE as u64 != 0 {
+                    return Err(ah::format_err!("AES seek: Byte offset is not a \
+                                               multiple of the base size ({} bytes).",
+                                               $Generator::BASE_SIZE));
+                }
+
+                let block = byte_offset / AES_BLOCK_SIZE as u64;
+                self.cipher = $Generator::new_cipher(&self.key, block as u128);
+
+                Ok(())
+            }
+        }
+
+        #[cfg(test)]
+        mod $testmodule {
+            use super::*;
+
+            #[test]
+            fn test_cmp_result() {
+                let mut a = $Generator::new(&[1,2,3]);
+                fn reduce(acc: u32, (i, x): (usize, &u8)) -> u32 {
+                    acc.rotate_left(i as u32) ^ (*x as u32)
+                }
+                assert_eq!(a.next(1).iter().enumerate().fold(0, reduce), $testresult0);
+                assert_eq!(a.next(1).iter().enumerate().fold(0, reduce), $testresult1);
+                assert_eq!(a.next(2).iter().enumerate().fold(0, reduce), $testresult2);
+                assert_eq!(a.next(3).iter().enumerate().fold(0, reduce), $testresult3);
+            }
+
+            #[test]
+            fn test_seed_equal() {
+                let mut a = $Generator::new(&[1,2,3]);
+                let mut b = $Generator::new(&[1,2,3]);
+                let res_a = vec![a.next(1), a.next(1)];
+                let res_b = vec![b.next(1), b.next(1)];
+                assert_eq!(res_a, res_b);
+                assert_ne!(res_a[0], res_a[1]);
+            }
+
+            #[test]
+            fn test_seed_diff() {
+                let mut a = $Generator::new(&[1,2,3]);
+                let mut b = $Generator::new(&[1,2,4]);
+                assert_ne!(a.next(1), b.next(1));
+                assert_ne!(a.next(1), b.next(1));
+            }
+
+            #[test]
+            fn test_concat_equal() {
+                let mut a = $Generator::new(&[1,2,3]);
+                let mut b = $Generator::new(&[1,2,3]);
+                let mut buf_a = a.next(1);
+                buf_a.append(&mut a.next(1));
+                let buf_b = b.next(2);
+                assert_eq!(buf_a, buf_b);
+            }
+
+            #[test]
+            fn test_seek() {
+                let mut a = $Generator::new(&[1,2,3]);
+                let mut b = $Generator::new(&[1,2,3]);
+                b.seek($Generator::BASE_SIZE as u64 * 2).unwrap();
+                let bdata = b.next(1);
+                assert_ne!(a.next(1), bdata);
+                assert_ne!(a.next(1), bdata);
+                assert_eq!(a.next(1), bdata);
+                assert_ne!(a.next(1), bdata);
+            }
+
+            #[test]
+            fn test_seek_far() {
+                // Seeking must not depend on the offset. 10 TB is reached instantly.
+                let base = $Generator::BASE_SIZE as u64;
+                let far = (10_000_000_000_000 / base) * base;
+                let mut a = $Generator::new(&[1,2,3]);
+                let mut b = $Generator::new(&[1,2,3]);
+                let start = a.next(1);
+                a.seek(far).unwrap();
+                b.seek(far + base).unwrap();
+                let adata = a.next(2);
+                assert_eq!(&adata[$Generator::BASE_SIZE..], b.next(1).as_slice());
+                assert_ne!(&adata[..$Generator::BASE_SIZE], start.as_slice());
+                a.seek(0).unwrap();
+                assert_eq!(a.next(1), start);
+                assert!(a.seek(far + 1).is_err());
+            }
+        }
+    };
+}
+
+GeneratorAes!(GeneratorAes128,
+              KeySize128,
+              16,
+              tests_aes128,
+              2237780124,
+              1772685929,
+              2121442384,
+              3240744049);
+
+GeneratorAes!(GeneratorAes256,
+              KeySize256,
+              32,
+              tests_aes256,
+              1924781147,
+              1328677620,
+              116598472,
+              860949041);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_stream() {
+        // NIST SP 800-38A, F.5.1 CTR-AES128.Encrypt, first block.
+        let key = [0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6,
+                   0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf, 0x4f, 0x3c];
+        let plain = [0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96,
+                     0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93, 0x17, 0x2a];
+        let cipher = [0x87, 0x4d, 0x61, 0x91, 0xb6, 0x20, 0xe3, 0x26,
+                      0x1b, 0xef, 0x68, 0x64, 0x99, 0x0d, 0xb6, 0xce];
+        let counter = 0xf0f1f2f3f4f5f6f7f8f9fafbfcfdfeffu128;
+        let mut out = [0u8; AES_BLOCK_SIZE];
+        GeneratorAes128::new_cipher(&key, counter).process(&plain, &mut out);
+        assert_eq!(out, cipher);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,159 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+
+use anyhow as ah;
+use crate::generator::NextRandom;
+use crate::util::fold;
+
+/// BLAKE3 in extendable output mode (XOF).
+pub struct GeneratorBlake3 {
+    reader:     blake3::OutputReader,
+}
+
+impl GeneratorBlake3 {
+    /// Size of the algorithm base output data.
+    pub const BASE_SIZE: usize = 1024 * 4;
+    /// Chunk size. Multiple of the generator base size.
+    pub const CHUNK_FACTOR: usize = 768;
+
+    pub fn new(seed: &[u8]) -> GeneratorBlake3 {
+        assert!(!seed.is_empty());
+        let mut key = [0u8; blake3::KEY_LEN];
+        key.copy_from_slice(&fold(seed, blake3::KEY_LEN));
+
+        GeneratorBlake3 {
+            reader: blake3::Hasher::new_keyed(&key).finalize_xof(),
+        }
+    }
+}
+
+impl NextRandom for GeneratorBlake3 {
+    fn get_base_size(&self) -> usize {
+        GeneratorBlake3::BASE_SIZE
+    }
+
+    fn next_into(&mut self, buf: &mut [u8]) {
+        debug_assert_eq!(buf.len() % GeneratorBlake3::BASE_SIZE, 0);
+        self.reader.fill(buf);
+    }
+
+    fn seek(&mut self, byte_offset: u64) -> ah::Result<()> {
+        if !byte_offset.is_multiple_of(GeneratorBlake3::BASE_SIZE as u64) {
+            return Err(ah::format_err!("BLAKE3 seek: Byte offset is not a \
+                                       multiple of the base size ({} bytes).",
+                                       GeneratorBlake3::BASE_SIZE));
+        }
+
+        self.reader.set_position(byte_offset);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cmp_result() {
+        let mut a = GeneratorBlake3::new(&[1,2,3]);
+        fn reduce(acc: u32, (i, x): (usize, &u8)) -> u32 {
+            acc.rotate_left(i as u32) ^ (*x as u32)
+        }
+        assert_eq!(a.next(1).iter().enumerate().fold(0, reduce), 757634178);
+        assert_eq!(a.next(1).iter().enumerate().fold(0, reduce), 3886975999);
+        assert_eq!(a.next(2).iter().enumerate().fold(0, reduce), 2928499584);
+        assert_eq!(a.next(3).iter().enumerate().fold(0, reduce), 3572639711);
+    }
+
+    #[test]
+    fn test_seed_equal() {
+        let mut a = GeneratorBlake3::new(&[1,2,3]);
+        let mut b = GeneratorBlake3::new(&[1,2,3]);
+        let res_a = vec![a.next(1), a.next(1)];
+        let res_b = vec![b.next(1), b.next(1)];
+        assert_eq!(res_a, res_b);
+        assert_ne!(res_a[0], res_a[1]);
+    }
+
+    #[test]
+    fn test_seed_diff() {
+        let mut a = GeneratorBlake3::new(&[1,2,3]);
+        let mut b = GeneratorBlake3::new(&[1,2,4]);
+        assert_ne!(a.next(1), b.next(1));
+        assert_ne!(a.next(1), b.next(1));
+    }
+
+    #[test]
+    fn test_concat_equal() {
+        let mut a = GeneratorBlake3::new(&[1,2,3]);
+        let mut b = GeneratorBlake3::new(&[1,2,3]);
+        let mut buf_a = a.next(1);
+        buf_a.append(&mut a.next(1));
+        let buf_b = b.next(2);
+        assert_eq!(buf_a, buf_b);
+    }
+
+    #[test]
+    fn test_seek() {
+        let mut a = GeneratorBlake3::new(&[1,2,3]);
+        let mut b = GeneratorBlake3::new(&[1,2,3]);
+        b.seek(GeneratorBlake3::BASE_SIZE as u64 * 2).unwrap();
+        let bdata = b.next(1);
+        assert_ne!(a.next(1), bdata);
+        assert_ne!(a.next(1), bdata);
+        assert_eq!(a.next(1), bdata);
+        assert_ne!(a.next(1), bdata);
+    }
+
+    #[test]
+    fn test_seek_far() {
+        // Seeking must not depend on the offset. 10 TB is reached instantly.
+        let base = GeneratorBlake3::BASE_SIZE as u64;
+        let far = (10_000_000_000_000 / base) * base;
+        let mut a = GeneratorBlake3::new(&[1,2,3]);
+        let mut b = GeneratorBlake3::new(&[1,2,3]);
+        let start = a.next(1);
+        a.seek(far).unwrap();
+        b.seek(far + base).unwrap();
+        let adata = a.next(2);
+        assert_eq!(&adata[GeneratorBlake3::BASE_SIZE..], b.next(1).as_slice());
+        assert_ne!(&adata[..GeneratorBlake3::BASE_SIZE], start.as_slice());
+        a.seek(0).unwrap();
+        assert_eq!(a.next(1), start);
+        assert!(a.seek(far + 1).is_err());
+    }
+
+    #[test]
+    fn test_key_stream() {
+        // BLAKE3 keyed hash of the empty input, 32 byte output.
+        let mut key = [0u8; blake3::KEY_LEN];
+        for (i, k) in key.iter_mut().enumerate() {
+            *k = i as u8;
+        }
+        let expected = blake3::keyed_hash(&key, b"");
+        let mut a = GeneratorBlake3::new(&key);
+        assert_eq!(&a.next(1)[..32], expected.as_bytes());
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -42,12 +42,12 @@ macro_rules! GeneratorChaCha {
 
         impl $Generator {
             /// Size of the algorithm base output data.
-            pub const BASE_SIZE: usize = 1024 * 1024 * 3;
+            pub const BASE_SIZE: usize = 1024 * 4;
             /// Chunk size. Multiple of the generator base size.
-            pub const CHUNK_FACTOR: usize = 1;
+            pub const CHUNK_FACTOR: usize = 768;
 
-            pub fn new(seed: &Vec<u8>) -> $Generator {
-                assert!(seed.len() > 0);
+            pub fn new(seed: &[u8]) -> $Generator {
+                assert!(!seed.is_empty());
                 let mut folded_seed = [0u8; 32];
                 folded_seed.copy_from_slice(&fold(seed, 32));
 
@@ -64,16 +64,11 @@ macro_rules! GeneratorChaCha {
                 $Generator::BASE_SIZE
             }
 
-            fn next(&mut self, count: usize) -> Vec<u8> {
-                let mut buf = Vec::with_capacity($Generator::BASE_SIZE * count);
+            fn next_into(&mut self, buf: &mut [u8]) {
+                debug_assert_eq!(buf.len() % $Generator::BASE_SIZE, 0);
 
-                // All bytes will be overwritten by fill().
-                // Don't initialize. Just resize.
-                unsafe { buf.set_len(buf.capacity()); }
                 // Write pseudo random data to all bytes.
-                self.rng.fill(buf.as_mut_slice());
-
-                buf
+                self.rng.fill(buf);
             }
 
             fn seek(&mut self, byte_offset: u64) -> ah::Result<()> {
@@ -95,6 +90,7 @@ macro_rules! GeneratorChaCha {
         }
 
         #[cfg(test)]
+        #[allow(clippy::useless_vec)]
         mod $testmodule {
             use super::*;
 
@@ -104,10 +100,11 @@ macro_rules! GeneratorChaCha {
                 fn reduce(acc: u32, (i, x): (usize, &u8)) -> u32 {
                     acc.rotate_left(i as u32) ^ (*x as u32)
                 }
-                assert_eq!(a.next(1).iter().enumerate().fold(0, reduce), $testresult0);
-                assert_eq!(a.next(1).iter().enumerate().fold(0, reduce), $testresult1);
-                assert_eq!(a.next(2).iter().enumerate().fold(0, reduce), $testresult2);
-                assert_eq!(a.next(3).iter().enumerate().fold(0, reduce), $testresult3);
+                let f = $Generator::CHUNK_FACTOR;
+                assert_eq!(a.next(f).iter().enumerate().fold(0, reduce), $testresult0);
+                assert_eq!(a.next(f).iter().enumerate().fold(0, reduce), $testresult1);
+                assert_eq!(a.next(f * 2).iter().enumerate().fold(0, reduce), $testresult2);
+                assert_eq!(a.next(f * 3).iter().enumerate().fold(0, reduce), $testresult3);
             }
 
             #[test]
@@ -163,6 +160,24 @@ macro_rules! GeneratorChaCha {
                 assert_eq!(a.next(1), bdata);
                 assert_ne!(a.next(1), bdata);
             }
+
+            #[test]
+            fn test_seek_far() {
+                // Seeking must not depend on the offset. 10 TB is reached instantly.
+                let base = $Generator::BASE_SIZE as u64;
+                let far = (10_000_000_000_000 / base) * base;
+                let mut a = $Generator::new(&[1,2,3]);
+                let mut b = $Generator::new(&[1,2,3]);
+                let start = a.next(1);
+                a.seek(far).unwrap();
+                b.seek(far + base).unwrap();
+                let adata = a.next(2);
+                assert_eq!(&adata[$Generator::BASE_SIZE..], b.next(1).as_slice());
+                assert_ne!(&adata[..$Generator::BASE_SIZE], start.as_slice());
+                a.seek(0).unwrap();
+                assert_eq!(a.next(1), start);
+                assert!(a.seek(far + 1).is_err());
+            }
         }
     };
 }
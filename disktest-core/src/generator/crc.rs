@@ -39,8 +39,8 @@ impl GeneratorCRC {
     const CRC_SIZE: usize = 64 / 8;
     const FOLDED_SEED_SIZE: usize = 64 / 8;
 
-    pub fn new(seed: &Vec<u8>) -> GeneratorCRC {
-        assert!(seed.len() > 0);
+    pub fn new(seed: &[u8]) -> GeneratorCRC {
+        assert!(!seed.is_empty());
 
         let crc = crc64::Digest::new(crc64::ECMA);
 
@@ -60,12 +60,9 @@ impl NextRandom for GeneratorCRC {
         GeneratorCRC::BASE_SIZE
     }
 
-    fn next(&mut self, count: usize) -> Vec<u8> {
-        let mut buf = Vec::with_capacity(GeneratorCRC::BASE_SIZE * count);
-
-        // All bytes will be overwritten below.
-        // Don't initialize. Just resize.
-        unsafe { buf.set_len(buf.capacity()); }
+    fn next_into(&mut self, buf: &mut [u8]) {
+        debug_assert_eq!(buf.len() % GeneratorCRC::BASE_SIZE, 0);
+        let count = buf.len() / GeneratorCRC::BASE_SIZE;
 
         for i in 0..count {
             let chunk_offs = i * GeneratorCRC::BASE_SIZE;
@@ -92,11 +89,10 @@ impl NextRandom for GeneratorCRC {
                 buf[begin..end].copy_from_slice(&crc);
             }
         }
-        buf
     }
 
     fn seek(&mut self, byte_offset: u64) -> ah::Result<()> {
-        if byte_offset % GeneratorCRC::BASE_SIZE as u64 != 0 {
+        if !byte_offset.is_multiple_of(GeneratorCRC::BASE_SIZE as u64) {
             return Err(ah::format_err!("CRC seek: Byte offset is not a \
                                        multiple of the base size ({} bytes).",
                                        GeneratorCRC::BASE_SIZE));
@@ -109,6 +105,7 @@ impl NextRandom for GeneratorCRC {
 }
 
 #[cfg(test)]
+#[allow(clippy::useless_vec)]
 mod tests {
     use super::*;
 
@@ -177,6 +174,24 @@ mod tests {
         assert_eq!(a.next(1), bdata);
         assert_ne!(a.next(1), bdata);
     }
+
+    #[test]
+    fn test_seek_far() {
+        // Seeking must not depend on the offset. 10 TB is reached instantly.
+        let base = GeneratorCRC::BASE_SIZE as u64;
+        let far = (10_000_000_000_000 / base) * base;
+        let mut a = GeneratorCRC::new(&[1,2,3]);
+        let mut b = GeneratorCRC::new(&[1,2,3]);
+        let start = a.next(1);
+        a.seek(far).unwrap();
+        b.seek(far + base).unwrap();
+        let adata = a.next(2);
+        assert_eq!(&adata[GeneratorCRC::BASE_SIZE..], b.next(1).as_slice());
+        assert_ne!(&adata[..GeneratorCRC::BASE_SIZE], start.as_slice());
+        a.seek(0).unwrap();
+        assert_eq!(a.next(1), start);
+        assert!(a.seek(far + 1).is_err());
+    }
 }
 
 // vim: ts=4 sw=4 expandtab
@@ -0,0 +1,275 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+use crate::generator::NextRandom;
+use crate::util::fold;
+
+/// Number of independent CRC lanes.
+/// The CRC instructions have a latency of several cycles,
+/// so independent lanes keep the CPU pipeline busy.
+const LANES: usize = 4;
+
+/// Bytes generated by each lane per step.
+const STEP_SIZE: usize = 8;
+
+/// One CRC32C step over a 64 bit word, without pre- and post-inversion.
+fn step_sw(crc: u32, data: u64) -> u32 {
+    !crc32c::crc32c_append(!crc, &data.to_le_bytes())
+}
+
+/// CRC32C step with the SSE 4.2 instruction.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse4.2")]
+#[inline]
+unsafe fn step_sse42(crc: u32, data: u64) -> u32 {
+    std::arch::x86_64::_mm_crc32_u64(crc as u64, data) as u32
+}
+
+/// CRC32C step with the ARMv8 CRC instruction.
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "crc")]
+#[inline]
+unsafe fn step_armv8(crc: u32, data: u64) -> u32 {
+    std::arch::aarch64::__crc32cd(crc, data)
+}
+
+/// Fill the buffer with one base size block.
+#[inline(always)]
+fn fill_block<F: Fn(u32, u64) -> u32>(step: F, seed: u64, counter: u64, buf: &mut [u8]) {
+    // Initialize the lanes based on the seed and current counter.
+    let mut crc = [0u32; LANES];
+    for (lane, crc) in crc.iter_mut().enumerate() {
+        *crc = step(step(lane as u32, seed), counter);
+    }
+
+    // Fast inner loop.
+    for (i, out) in buf.chunks_exact_mut(LANES * STEP_SIZE).enumerate() {
+        for (lane, crc) in crc.iter_mut().enumerate() {
+            let lo = step(*crc, i as u64);
+            let hi = step(lo, i as u64);
+            *crc = hi;
+            let data = (lo as u64) | ((hi as u64) << 32);
+            out[lane * STEP_SIZE..(lane + 1) * STEP_SIZE].copy_from_slice(&data.to_le_bytes());
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse4.2")]
+unsafe fn fill_blocks_sse42(seed: u64, counter: u64, buf: &mut [u8]) {
+    for (i, block) in buf.chunks_exact_mut(GeneratorCRC32C::BASE_SIZE).enumerate() {
+        fill_block(|crc, data| step_sse42(crc, data), seed, counter + i as u64, block);
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "crc")]
+unsafe fn fill_blocks_armv8(seed: u64, counter: u64, buf: &mut [u8]) {
+    for (i, block) in buf.chunks_exact_mut(GeneratorCRC32C::BASE_SIZE).enumerate() {
+        fill_block(|crc, data| step_armv8(crc, data), seed, counter + i as u64, block);
+    }
+}
+
+fn fill_blocks_sw(seed: u64, counter: u64, buf: &mut [u8]) {
+    for (i, block) in buf.chunks_exact_mut(GeneratorCRC32C::BASE_SIZE).enumerate() {
+        fill_block(step_sw, seed, counter + i as u64, block);
+    }
+}
+
+/// Check whether the CPU has CRC32C instructions.
+fn have_hw() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    {
+        is_x86_feature_detected!("sse4.2")
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        std::arch::is_aarch64_feature_detected!("crc")
+    }
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        false
+    }
+}
+
+/// Fast, but not cryptographically secure generator based on CRC32C.
+/// The SSE 4.2 or ARMv8 CRC instructions are used, if the CPU supports them.
+pub struct GeneratorCRC32C {
+    seed:       u64,
+    counter:    u64,
+    hw:         bool,
+}
+
+impl GeneratorCRC32C {
+    /// Size of the algorithm base output data.
+    pub const BASE_SIZE: usize = 1024 * 4;
+    /// Chunk size. Multiple of the generator base size.
+    pub const CHUNK_FACTOR: usize = 768;
+
+    const FOLDED_SEED_SIZE: usize = 64 / 8;
+
+    pub fn new(seed: &[u8]) -> GeneratorCRC32C {
+        assert!(!seed.is_empty());
+
+        let mut folded_seed = [0u8; GeneratorCRC32C::FOLDED_SEED_SIZE];
+        folded_seed.copy_from_slice(&fold(seed, GeneratorCRC32C::FOLDED_SEED_SIZE));
+
+        GeneratorCRC32C {
+            seed:       u64::from_le_bytes(folded_seed),
+            counter:    0,
+            hw:         have_hw(),
+        }
+    }
+}
+
+impl NextRandom for GeneratorCRC32C {
+    fn get_base_size(&self) -> usize {
+        GeneratorCRC32C::BASE_SIZE
+    }
+
+    fn next_into(&mut self, buf: &mut [u8]) {
+        debug_assert_eq!(buf.len() % GeneratorCRC32C::BASE_SIZE, 0);
+
+        if self.hw {
+            // The CPU features have been checked in have_hw().
+            #[cfg(target_arch = "x86_64")]
+            unsafe { fill_blocks_sse42(self.seed, self.counter, buf) };
+            #[cfg(target_arch = "aarch64")]
+            unsafe { fill_blocks_armv8(self.seed, self.counter, buf) };
+        } else {
+            fill_blocks_sw(self.seed, self.counter, buf);
+        }
+        self.counter += (buf.len() / GeneratorCRC32C::BASE_SIZE) as u64;
+    }
+
+    fn seek(&mut self, byte_offset: u64) -> ah::Result<()> {
+        if !byte_offset.is_multiple_of(GeneratorCRC32C::BASE_SIZE as u64) {
+            return Err(ah::format_err!("CRC32C seek: Byte offset is not a \
+                                       multiple of the base size ({} bytes).",
+                                       GeneratorCRC32C::BASE_SIZE));
+        }
+
+        self.counter = byte_offset / GeneratorCRC32C::BASE_SIZE as u64;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cmp_result() {
+        let mut a = GeneratorCRC32C::new(&[1,2,3]);
+        fn reduce(acc: u32, (i, x): (usize, &u8)) -> u32 {
+            acc.rotate_left(i as u32) ^ (*x as u32)
+        }
+        assert_eq!(a.next(1).iter().enumerate().fold(0, reduce), 221347772);
+        assert_eq!(a.next(1).iter().enumerate().fold(0, reduce), 3829750276);
+        assert_eq!(a.next(2).iter().enumerate().fold(0, reduce), 3916703160);
+        assert_eq!(a.next(3).iter().enumerate().fold(0, reduce), 786119216);
+    }
+
+    #[test]
+    fn test_seed_equal() {
+        let mut a = GeneratorCRC32C::new(&[1,2,3]);
+        let mut b = GeneratorCRC32C::new(&[1,2,3]);
+        let res_a = vec![a.next(1), a.next(1)];
+        let res_b = vec![b.next(1), b.next(1)];
+        assert_eq!(res_a, res_b);
+        assert_ne!(res_a[0], res_a[1]);
+    }
+
+    #[test]
+    fn test_seed_diff() {
+        let mut a = GeneratorCRC32C::new(&[1,2,3]);
+        let mut b = GeneratorCRC32C::new(&[1,2,4]);
+        assert_ne!(a.next(1), b.next(1));
+        assert_ne!(a.next(1), b.next(1));
+    }
+
+    #[test]
+    fn test_concat_equal() {
+        let mut a = GeneratorCRC32C::new(&[1,2,3]);
+        let mut b = GeneratorCRC32C::new(&[1,2,3]);
+        let mut buf_a = a.next(1);
+        buf_a.append(&mut a.next(1));
+        let buf_b = b.next(2);
+        assert_eq!(buf_a, buf_b);
+    }
+
+    #[test]
+    fn test_seek() {
+        let mut a = GeneratorCRC32C::new(&[1,2,3]);
+        let mut b = GeneratorCRC32C::new(&[1,2,3]);
+        b.seek(GeneratorCRC32C::BASE_SIZE as u64 * 2).unwrap();
+        let bdata = b.next(1);
+        assert_ne!(a.next(1), bdata);
+        assert_ne!(a.next(1), bdata);
+        assert_eq!(a.next(1), bdata);
+        assert_ne!(a.next(1), bdata);
+    }
+
+    #[test]
+    fn test_seek_far() {
+        // Seeking must not depend on the offset. 10 TB is reached instantly.
+        let base = GeneratorCRC32C::BASE_SIZE as u64;
+        let far = (10_000_000_000_000 / base) * base;
+        let mut a = GeneratorCRC32C::new(&[1,2,3]);
+        let mut b = GeneratorCRC32C::new(&[1,2,3]);
+        let start = a.next(1);
+        a.seek(far).unwrap();
+        b.seek(far + base).unwrap();
+        let adata = a.next(2);
+        assert_eq!(&adata[GeneratorCRC32C::BASE_SIZE..], b.next(1).as_slice());
+        assert_ne!(&adata[..GeneratorCRC32C::BASE_SIZE], start.as_slice());
+        a.seek(0).unwrap();
+        assert_eq!(a.next(1), start);
+        assert!(a.seek(far + 1).is_err());
+    }
+
+    #[test]
+    fn test_hw_equal_sw() {
+        // The hardware and the software implementation must generate the same data.
+        let mut a = GeneratorCRC32C::new(&[1,2,3]);
+        let mut b = GeneratorCRC32C::new(&[1,2,3]);
+        b.hw = false;
+        assert_eq!(a.next(3), b.next(3));
+    }
+
+    #[test]
+    fn test_step() {
+        // CRC32C check value of "123456789".
+        let data = u64::from_le_bytes(*b"12345678");
+        let crc = step_sw(!0, data);
+        assert_eq!(crc32c::crc32c_append(!crc, b"9"), 0xE3069283);
+        if have_hw() {
+            #[cfg(target_arch = "x86_64")]
+            assert_eq!(unsafe { step_sse42(!0, data) }, crc);
+            #[cfg(target_arch = "aarch64")]
+            assert_eq!(unsafe { step_armv8(!0, data) }, crc);
+        }
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,137 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+
+use anyhow as ah;
+use crate::generator::NextRandom;
+
+/// Fixed byte pattern that is repeated over the whole stream.
+/// This is not random at all.
+pub struct GeneratorPattern {
+    len:        usize,
+    block:      Vec<u8>,
+    offset:     u64,
+}
+
+impl GeneratorPattern {
+    /// Size of the algorithm base output data.
+    pub const BASE_SIZE: usize = 1024 * 4;
+    /// Chunk size. Multiple of the generator base size.
+    pub const CHUNK_FACTOR: usize = 768;
+
+    pub fn new(pattern: &[u8]) -> GeneratorPattern {
+        assert!(!pattern.is_empty());
+
+        // Pre-expand the pattern, so that every base size block
+        // can be copied, independent of its pattern phase.
+        let len = pattern.len();
+        let block = pattern.iter()
+            .cycle()
+            .take(GeneratorPattern::BASE_SIZE + len)
+            .copied()
+            .collect();
+
+        GeneratorPattern {
+            len,
+            block,
+            offset: 0,
+        }
+    }
+}
+
+impl NextRandom for GeneratorPattern {
+    fn get_base_size(&self) -> usize {
+        GeneratorPattern::BASE_SIZE
+    }
+
+    fn next_into(&mut self, buf: &mut [u8]) {
+        debug_assert_eq!(buf.len() % GeneratorPattern::BASE_SIZE, 0);
+
+        for chunk in buf.chunks_mut(GeneratorPattern::BASE_SIZE) {
+            let phase = (self.offset % self.len as u64) as usize;
+            chunk.copy_from_slice(&self.block[phase..phase + GeneratorPattern::BASE_SIZE]);
+            self.offset += GeneratorPattern::BASE_SIZE as u64;
+        }
+    }
+
+    fn seek(&mut self, byte_offset: u64) -> ah::Result<()> {
+        if !byte_offset.is_multiple_of(GeneratorPattern::BASE_SIZE as u64) {
+            return Err(ah::format_err!("Pattern seek: Byte offset is not a \
+                                       multiple of the base size ({} bytes).",
+                                       GeneratorPattern::BASE_SIZE));
+        }
+
+        self.offset = byte_offset;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern() {
+        let mut a = GeneratorPattern::new(&[0xAA, 0x55]);
+        let data = a.next(2);
+        assert_eq!(data.len(), GeneratorPattern::BASE_SIZE * 2);
+        assert!(data.chunks(2).all(|x| x == [0xAA, 0x55]));
+
+        let mut a = GeneratorPattern::new(&[0xFF]);
+        assert!(a.next(1).iter().all(|x| *x == 0xFF));
+    }
+
+    #[test]
+    fn test_concat_equal() {
+        // The pattern length does not divide the base size.
+        let mut a = GeneratorPattern::new(&[1,2,3]);
+        let mut b = GeneratorPattern::new(&[1,2,3]);
+        let mut buf_a = a.next(1);
+        buf_a.append(&mut a.next(1));
+        let buf_b = b.next(2);
+        assert_eq!(buf_a, buf_b);
+        assert!(buf_b.chunks(3).all(|x| x == &[1,2,3][..x.len()]));
+    }
+
+    #[test]
+    fn test_seek() {
+        let mut a = GeneratorPattern::new(&[1,2,3]);
+        let mut b = GeneratorPattern::new(&[1,2,3]);
+        b.seek(GeneratorPattern::BASE_SIZE as u64 * 2).unwrap();
+        let bdata = b.next(1);
+        assert_ne!(a.next(1), bdata);
+        assert_ne!(a.next(1), bdata);
+        assert_eq!(a.next(1), bdata);
+        assert!(a.seek(1).is_err());
+    }
+
+    #[test]
+    fn test_seek_far() {
+        let base = GeneratorPattern::BASE_SIZE as u64;
+        let far = (10_000_000_000_000 / base) * base;
+        let mut a = GeneratorPattern::new(&[1,2,3]);
+        a.seek(far).unwrap();
+        assert_eq!(a.next(1)[0], [1,2,3][(far % 3) as usize]);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,189 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+
+use anyhow as ah;
+use crate::generator::NextRandom;
+use crate::util::fold;
+use crypto::digest::Digest;
+use crypto::sha3::Sha3;
+
+/// Size of the key, in bytes.
+const KEY_SIZE: usize = 32;
+
+/// SHAKE256 (SHA-3 extendable output function) in counter mode.
+/// Every base size block is the SHAKE256 output of the key and the block number.
+pub struct GeneratorShake256 {
+    key:        [u8; KEY_SIZE],
+    block:      u64,
+}
+
+impl GeneratorShake256 {
+    /// Size of the algorithm base output data.
+    pub const BASE_SIZE: usize = 1024 * 4;
+    /// Chunk size. Multiple of the generator base size.
+    pub const CHUNK_FACTOR: usize = 768;
+
+    pub fn new(seed: &[u8]) -> GeneratorShake256 {
+        assert!(!seed.is_empty());
+        let mut key = [0u8; KEY_SIZE];
+        key.copy_from_slice(&fold(seed, KEY_SIZE));
+
+        GeneratorShake256 {
+            key,
+            block: 0,
+        }
+    }
+}
+
+/// Calculate the SHAKE256 of the input parts into out.
+fn shake256(input: &[&[u8]], out: &mut [u8]) {
+    let mut shake = Sha3::shake_256();
+    for data in input {
+        shake.input(data);
+    }
+    shake.result(out);
+}
+
+impl NextRandom for GeneratorShake256 {
+    fn get_base_size(&self) -> usize {
+        GeneratorShake256::BASE_SIZE
+    }
+
+    fn next_into(&mut self, buf: &mut [u8]) {
+        debug_assert_eq!(buf.len() % GeneratorShake256::BASE_SIZE, 0);
+
+        for chunk in buf.chunks_mut(GeneratorShake256::BASE_SIZE) {
+            shake256(&[&self.key, &self.block.to_le_bytes()], chunk);
+            self.block += 1;
+        }
+    }
+
+    fn seek(&mut self, byte_offset: u64) -> ah::Result<()> {
+        if !byte_offset.is_multiple_of(GeneratorShake256::BASE_SIZE as u64) {
+            return Err(ah::format_err!("SHAKE256 seek: Byte offset is not a \
+                                       multiple of the base size ({} bytes).",
+                                       GeneratorShake256::BASE_SIZE));
+        }
+
+        self.block = byte_offset / GeneratorShake256::BASE_SIZE as u64;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cmp_result() {
+        let mut a = GeneratorShake256::new(&[1,2,3]);
+        fn reduce(acc: u32, (i, x): (usize, &u8)) -> u32 {
+            acc.rotate_left(i as u32) ^ (*x as u32)
+        }
+        assert_eq!(a.next(1).iter().enumerate().fold(0, reduce), 3923376267);
+        assert_eq!(a.next(1).iter().enumerate().fold(0, reduce), 4184918389);
+        assert_eq!(a.next(2).iter().enumerate().fold(0, reduce), 1227627542);
+        assert_eq!(a.next(3).iter().enumerate().fold(0, reduce), 3823511360);
+    }
+
+    #[test]
+    fn test_seed_equal() {
+        let mut a = GeneratorShake256::new(&[1,2,3]);
+        let mut b = GeneratorShake256::new(&[1,2,3]);
+        let res_a = vec![a.next(1), a.next(1)];
+        let res_b = vec![b.next(1), b.next(1)];
+        assert_eq!(res_a, res_b);
+        assert_ne!(res_a[0], res_a[1]);
+    }
+
+    #[test]
+    fn test_seed_diff() {
+        let mut a = GeneratorShake256::new(&[1,2,3]);
+        let mut b = GeneratorShake256::new(&[1,2,4]);
+        assert_ne!(a.next(1), b.next(1));
+        assert_ne!(a.next(1), b.next(1));
+    }
+
+    #[test]
+    fn test_concat_equal() {
+        let mut a = GeneratorShake256::new(&[1,2,3]);
+        let mut b = GeneratorShake256::new(&[1,2,3]);
+        let mut buf_a = a.next(1);
+        buf_a.append(&mut a.next(1));
+        let buf_b = b.next(2);
+        assert_eq!(buf_a, buf_b);
+    }
+
+    #[test]
+    fn test_seek() {
+        let mut a = GeneratorShake256::new(&[1,2,3]);
+        let mut b = GeneratorShake256::new(&[1,2,3]);
+        b.seek(GeneratorShake256::BASE_SIZE as u64 * 2).unwrap();
+        let bdata = b.next(1);
+        assert_ne!(a.next(1), bdata);
+        assert_ne!(a.next(1), bdata);
+        assert_eq!(a.next(1), bdata);
+        assert_ne!(a.next(1), bdata);
+    }
+
+    #[test]
+    fn test_seek_far() {
+        // Seeking must not depend on the offset. 10 TB is reached instantly.
+        let base = GeneratorShake256::BASE_SIZE as u64;
+        let far = (10_000_000_000_000 / base) * base;
+        let mut a = GeneratorShake256::new(&[1,2,3]);
+        let mut b = GeneratorShake256::new(&[1,2,3]);
+        let start = a.next(1);
+        a.seek(far).unwrap();
+        b.seek(far + base).unwrap();
+        let adata = a.next(2);
+        assert_eq!(&adata[GeneratorShake256::BASE_SIZE..], b.next(1).as_slice());
+        assert_ne!(&adata[..GeneratorShake256::BASE_SIZE], start.as_slice());
+        a.seek(0).unwrap();
+        assert_eq!(a.next(1), start);
+        assert!(a.seek(far + 1).is_err());
+    }
+
+    #[test]
+    fn test_shake256() {
+        // FIPS 202 SHAKE256 of the empty message, first 32 bytes.
+        let expected = [0x46, 0xb9, 0xdd, 0x2b, 0x0b, 0xa8, 0x8d, 0x13,
+                        0x23, 0x3b, 0x3f, 0xeb, 0x74, 0x3e, 0xeb, 0x24,
+                        0x3f, 0xcd, 0x52, 0xea, 0x62, 0xb8, 0x1b, 0x82,
+                        0xb5, 0x0c, 0x27, 0x64, 0x6e, 0xd5, 0x76, 0x2f];
+        let mut out = [0u8; 32];
+        shake256(&[], &mut out);
+        assert_eq!(out, expected);
+
+        // The generator output is SHAKE256(KEY | BLOCK).
+        let mut a = GeneratorShake256::new(&[1,2,3]);
+        a.seek(GeneratorShake256::BASE_SIZE as u64 * 5).unwrap();
+        let mut key = [0u8; KEY_SIZE];
+        key[..3].copy_from_slice(&[1,2,3]);
+        let mut block = vec![0u8; GeneratorShake256::BASE_SIZE];
+        shake256(&[&key, &5u64.to_le_bytes()], &mut block);
+        assert_eq!(a.next(1), block);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
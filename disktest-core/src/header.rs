@@ -0,0 +1,215 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+use crate::kdf::DtKdf;
+use crate::stream::DtStreamType;
+use crypto::digest::Digest;
+use crypto::sha2::Sha512;
+use serde_json::{json, Value};
+
+/// Size of the on-disk header, in bytes.
+pub const HEADER_SIZE: usize = 4096;
+/// Version of the on-disk header format.
+pub const HEADER_VERSION: u64 = 1;
+
+/// Magic value at the start of the header.
+const HEADER_MAGIC: &[u8] = b"DISKTEST-HEADER\n";
+/// Name of the key derivation function in headers without "kdf_params".
+const HEADER_KDF: &str = "PBKDF2-HMAC-SHA512";
+
+/// Get the fingerprint of a seed.
+/// The fingerprint identifies the seed, but does not reveal it.
+pub fn seed_fingerprint(seed: &[u8]) -> String {
+    let mut digest = [0; 512/8];
+    let mut hash = Sha512::new();
+    hash.input_str("disktest seed fingerprint");
+    hash.input(seed);
+    hash.result(&mut digest);
+    digest[0..8].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Self-describing header at the start of the tested area of the device.
+/// It holds the parameters of the write run, so that the device
+/// can be verified without specifying them again.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DtHeader {
+    /// The format version of the header.
+    pub version:            u64,
+    /// The algorithm of the pseudo random stream.
+    pub algorithm:          DtStreamType,
+    /// The key derivation function.
+    pub kdf:                DtKdf,
+    /// The number of threads that generated the stream.
+    pub threads:            usize,
+    /// The chunk size, in bytes.
+    pub chunk_size:         usize,
+    /// The absolute byte offset of the tested area (and the header) on the device.
+    pub offset:             u64,
+    /// The number of bytes written, including the header.
+    pub bytes:              u64,
+    /// The fingerprint of the seed. See seed_fingerprint().
+    pub seed_fingerprint:   String,
+}
+
+impl DtHeader {
+    /// Serialize the header to a block of HEADER_SIZE bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut value = json!({
+            "version":          self.version,
+            "algorithm":        self.algorithm.to_string(),
+            "kdf":              self.kdf.name(),
+            "kdf_params":       self.kdf.to_string(),
+            "threads":          self.threads,
+            "chunk_size":       self.chunk_size,
+            "offset":           self.offset,
+            "bytes":            self.bytes,
+            "seed_fingerprint": self.seed_fingerprint,
+        });
+        if let DtKdf::Pbkdf2 { iterations } = self.kdf {
+            value["kdf_iterations"] = json!(iterations);
+        }
+        let text = value.to_string();
+        let mut block = vec![0; HEADER_SIZE];
+        block[0..HEADER_MAGIC.len()].copy_from_slice(HEADER_MAGIC);
+        block[HEADER_MAGIC.len()..HEADER_MAGIC.len()+text.len()].copy_from_slice(text.as_bytes());
+        block
+    }
+
+    /// Parse a header block.
+    /// Returns None, if the block does not contain a header.
+    pub fn from_bytes(block: &[u8]) -> ah::Result<Option<DtHeader>> {
+        if block.len() < HEADER_SIZE || !block.starts_with(HEADER_MAGIC) {
+            return Ok(None);
+        }
+        let text = &block[HEADER_MAGIC.len()..HEADER_SIZE];
+        let end = text.iter().position(|&b| b == 0).unwrap_or(text.len());
+        let value: Value = serde_json::from_slice(&text[0..end])
+            .map_err(|e| ah::format_err!("Invalid disktest header: {}", e))?;
+
+        let get_u64 = |name: &str| {
+            value[name].as_u64()
+                .ok_or_else(|| ah::format_err!("Invalid disktest header: No '{}'.", name))
+        };
+        let get_str = |name: &str| {
+            value[name].as_str()
+                .ok_or_else(|| ah::format_err!("Invalid disktest header: No '{}'.", name))
+        };
+        let version = get_u64("version")?;
+        if version > HEADER_VERSION {
+            return Err(ah::format_err!("Unsupported disktest header version {}.", version));
+        }
+        let kdf: DtKdf = match value["kdf_params"].as_str() {
+            Some(params) => params.parse()?,
+            None => {
+                if get_str("kdf")? != HEADER_KDF {
+                    return Err(ah::format_err!("Unsupported disktest header KDF '{}'.", get_str("kdf")?));
+                }
+                DtKdf::Pbkdf2 { iterations: get_u64("kdf_iterations")? as u32 }
+            },
+        };
+        if get_str("kdf")? != kdf.name() {
+            return Err(ah::format_err!("Unsupported disktest header KDF '{}'.", get_str("kdf")?));
+        }
+        Ok(Some(DtHeader {
+            version,
+            algorithm:          get_str("algorithm")?.parse()?,
+            kdf,
+            threads:            get_u64("threads")? as usize,
+            chunk_size:         get_u64("chunk_size")? as usize,
+            offset:             get_u64("offset")?,
+            bytes:              get_u64("bytes")?,
+            seed_fingerprint:   get_str("seed_fingerprint")?.to_string(),
+        }))
+    }
+
+    /// Check if the seed matches the seed that the header has been written with.
+    pub fn check_seed(&self, seed: &[u8]) -> bool {
+        self.seed_fingerprint == seed_fingerprint(seed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> DtHeader {
+        DtHeader {
+            version:            HEADER_VERSION,
+            algorithm:          DtStreamType::AES128,
+            kdf:                DtKdf::default(),
+            threads:            4,
+            chunk_size:         1024 * 1024,
+            offset:             4096,
+            bytes:              1000 * 1000,
+            seed_fingerprint:   seed_fingerprint(b"abc"),
+        }
+    }
+
+    #[test]
+    fn test_seed_fingerprint() {
+        assert_eq!(seed_fingerprint(b"abc").len(), 16);
+        assert_eq!(seed_fingerprint(b"abc"), seed_fingerprint(b"abc"));
+        assert_ne!(seed_fingerprint(b"abc"), seed_fingerprint(b"abd"));
+        assert!(header().check_seed(b"abc"));
+        assert!(!header().check_seed(b"abd"));
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let block = header().to_bytes();
+        assert_eq!(block.len(), HEADER_SIZE);
+        assert!(block.starts_with(b"DISKTEST-HEADER\n{"));
+        assert_eq!(DtHeader::from_bytes(&block).unwrap(), Some(header()));
+    }
+
+    #[test]
+    fn test_kdf() {
+        let mut hdr = header();
+        hdr.kdf = DtKdf::Scrypt { log_n: 10, r: 8, p: 1 };
+        assert_eq!(DtHeader::from_bytes(&hdr.to_bytes()).unwrap(), Some(hdr));
+
+        // Header without "kdf_params".
+        let text = b"{\"version\":1,\"algorithm\":\"AES128\",\"kdf\":\"PBKDF2-HMAC-SHA512\",\
+                     \"kdf_iterations\":1000,\"threads\":4,\"chunk_size\":1048576,\
+                     \"offset\":4096,\"bytes\":1000000,\"seed_fingerprint\":\"abc\"}";
+        let mut block = vec![0; HEADER_SIZE];
+        block[0..HEADER_MAGIC.len()].copy_from_slice(HEADER_MAGIC);
+        block[HEADER_MAGIC.len()..HEADER_MAGIC.len()+text.len()].copy_from_slice(text);
+        let hdr = DtHeader::from_bytes(&block).unwrap().unwrap();
+        assert_eq!(hdr.kdf, DtKdf::Pbkdf2 { iterations: 1000 });
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert_eq!(DtHeader::from_bytes(&[0; HEADER_SIZE]).unwrap(), None);
+        assert_eq!(DtHeader::from_bytes(&[0; 10]).unwrap(), None);
+        let mut block = header().to_bytes();
+        block[HEADER_MAGIC.len() + 1] = b'X';
+        assert!(DtHeader::from_bytes(&block).is_err());
+        let mut block = vec![0; HEADER_SIZE];
+        block[0..HEADER_MAGIC.len()].copy_from_slice(HEADER_MAGIC);
+        block[HEADER_MAGIC.len()..HEADER_MAGIC.len()+14].copy_from_slice(b"{\"version\":99}");
+        assert!(DtHeader::from_bytes(&block).is_err());
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,268 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+
+use anyhow as ah;
+use argon2::{Algorithm, Argon2, Params, Version};
+use crypto::digest::Digest;
+use crypto::hmac::Hmac;
+use crypto::pbkdf2::pbkdf2;
+use crypto::scrypt::{ScryptParams, scrypt};
+use crypto::sha2::Sha512;
+
+/// Default number of PBKDF2 iterations.
+pub const ITERATIONS: u32 = 50000;
+/// Default Argon2id memory cost, in kiB.
+pub const ARGON2_MEMORY: u32 = 64 * 1024;
+/// Default Argon2id number of passes.
+pub const ARGON2_ITERATIONS: u32 = 3;
+/// Default scrypt log2(N).
+pub const SCRYPT_LOG_N: u8 = 15;
+/// Default scrypt block size.
+pub const SCRYPT_R: u32 = 8;
+/// Default scrypt parallelization.
+pub const SCRYPT_P: u32 = 1;
+const DK_SIZE: usize      = 256 / 8;
+/// Size of a raw key, in bytes. See DtKdf::Raw.
+pub const RAW_KEY_SIZE: usize = DK_SIZE;
+
+/// Key derivation function that turns the seed into the generator key.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DtKdf {
+    /// PBKDF2-HMAC-SHA512 with the number of iterations.
+    Pbkdf2 { iterations: u32 },
+    /// Argon2id with the memory cost in kiB and the number of passes.
+    Argon2id { memory: u32, iterations: u32 },
+    /// scrypt with log2(N), the block size r and the parallelization p.
+    Scrypt { log_n: u8, r: u32, p: u32 },
+    /// No key derivation. The seed is the key and must be RAW_KEY_SIZE bytes long.
+    Raw,
+}
+
+impl Default for DtKdf {
+    fn default() -> Self {
+        DtKdf::Pbkdf2 { iterations: ITERATIONS }
+    }
+}
+
+impl std::str::FromStr for DtKdf {
+    type Err = ah::Error;
+
+    /// Parse "pbkdf2[:ITERATIONS]", "argon2id[:MEMORY_KIB:ITERATIONS]", "scrypt[:LOG_N:R:P]" or "raw".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let name = parts.next().unwrap_or("").to_lowercase();
+        let params: Vec<&str> = parts.collect();
+        let num = |i: usize| -> ah::Result<u64> {
+            params[i].trim().parse()
+                .map_err(|_| ah::format_err!("Invalid KDF parameter '{}'.", params[i]))
+        };
+        let kdf = match (name.as_str(), params.len()) {
+            ("pbkdf2", 0) => DtKdf::default(),
+            ("pbkdf2", 1) => DtKdf::Pbkdf2 { iterations: num(0)? as u32 },
+            ("argon2id", 0) => DtKdf::Argon2id { memory: ARGON2_MEMORY, iterations: ARGON2_ITERATIONS },
+            ("argon2id", 2) => DtKdf::Argon2id { memory: num(0)? as u32, iterations: num(1)? as u32 },
+            ("scrypt", 0) => DtKdf::Scrypt { log_n: SCRYPT_LOG_N, r: SCRYPT_R, p: SCRYPT_P },
+            ("scrypt", 3) => DtKdf::Scrypt { log_n: num(0)? as u8, r: num(1)? as u32, p: num(2)? as u32 },
+            ("raw", 0) => DtKdf::Raw,
+            ("pbkdf2", _) | ("argon2id", _) | ("scrypt", _) | ("raw", _) => {
+                return Err(ah::format_err!("Wrong number of KDF parameters: {}", s));
+            },
+            _ => return Err(ah::format_err!("Unknown KDF: {}", s)),
+        };
+        kdf.check()?;
+        Ok(kdf)
+    }
+}
+
+impl std::fmt::Display for DtKdf {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DtKdf::Pbkdf2 { iterations } => write!(f, "pbkdf2:{}", iterations),
+            DtKdf::Argon2id { memory, iterations } => write!(f, "argon2id:{}:{}", memory, iterations),
+            DtKdf::Scrypt { log_n, r, p } => write!(f, "scrypt:{}:{}:{}", log_n, r, p),
+            DtKdf::Raw => write!(f, "raw"),
+        }
+    }
+}
+
+impl DtKdf {
+    /// Get the full name of the function.
+    pub fn name(&self) -> &'static str {
+        match self {
+            DtKdf::Pbkdf2 { .. } => "PBKDF2-HMAC-SHA512",
+            DtKdf::Argon2id { .. } => "Argon2id",
+            DtKdf::Scrypt { .. } => "scrypt",
+            DtKdf::Raw => "raw",
+        }
+    }
+
+    /// Check the parameters.
+    pub fn check(&self) -> ah::Result<()> {
+        match *self {
+            DtKdf::Pbkdf2 { iterations } => {
+                if iterations == 0 {
+                    return Err(ah::format_err!("The number of PBKDF2 iterations must not be zero."));
+                }
+            },
+            DtKdf::Argon2id { memory, iterations } => {
+                argon2_params(memory, iterations)?;
+            },
+            DtKdf::Scrypt { log_n, r, p } => {
+                if log_n == 0 || log_n > 30 || r == 0 || p == 0 ||
+                   (log_n as u64) >= r as u64 * 16 || r as u64 * p as u64 >= 0x4000_0000 {
+                    return Err(ah::format_err!("Invalid scrypt parameters."));
+                }
+            },
+            DtKdf::Raw => (),
+        }
+        Ok(())
+    }
+
+    /// Derive the generator key from the seed.
+    pub fn derive(&self, seed: &[u8]) -> ah::Result<Vec<u8>> {
+        self.check()?;
+        if *self == DtKdf::Raw {
+            if seed.len() != RAW_KEY_SIZE {
+                return Err(ah::format_err!("The raw seed must be {} bytes long, but it is {} bytes long.",
+                                           RAW_KEY_SIZE, seed.len()));
+            }
+            return Ok(seed.to_vec());
+        }
+        // The key is: SEED | THREAD_ID
+        // All threads compute parts of the same stream, so the thread ID is always 0.
+        let mut key = seed.to_vec();
+        key.extend_from_slice(&0_u32.to_le_bytes());
+        let salt = derive_salt(&key);
+
+        let mut dk = [0; DK_SIZE];
+        match *self {
+            DtKdf::Pbkdf2 { iterations } => {
+                // Use HMAC-SHA512 as PRF.
+                let mut mac = Hmac::new(Sha512::new(), &key);
+                pbkdf2(&mut mac, &salt, iterations, &mut dk);
+            },
+            DtKdf::Argon2id { memory, iterations } => {
+                let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13,
+                                         argon2_params(memory, iterations)?);
+                if let Err(e) = argon2.hash_password_into(&key, &salt, &mut dk) {
+                    return Err(ah::format_err!("Argon2id failed: {}", e));
+                }
+            },
+            DtKdf::Scrypt { log_n, r, p } => {
+                scrypt(&key, &salt, &ScryptParams::new(log_n, r, p), &mut dk);
+            },
+            DtKdf::Raw => unreachable!(),
+        }
+        Ok(dk.to_vec())
+    }
+}
+
+/// Get the Argon2id parameters.
+fn argon2_params(memory: u32, iterations: u32) -> ah::Result<Params> {
+    Params::new(memory, iterations, 1, Some(DK_SIZE))
+        .map_err(|e| ah::format_err!("Invalid Argon2id parameters: {}", e))
+}
+
+/// Generate a bad salt substitution from the key.
+fn derive_salt(key: &[u8]) -> [u8; 512/8] {
+    // Generate the salt from the key.
+    // That's not a great salt, but good enough for our purposes.
+    let mut salt = [0; 512/8];
+    let mut salt_hash = Sha512::new();
+    salt_hash.input_str("disktest salt");
+    salt_hash.input(key);
+    salt_hash.result(&mut salt);
+
+    salt
+}
+
+#[cfg(test)]
+#[allow(clippy::useless_vec)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_salt() {
+        assert_eq!(derive_salt(&vec![1,2,3]).to_vec(),
+                   derive_salt(&vec![1,2,3]).to_vec());
+
+        assert_ne!(derive_salt(&vec![1,2,3]).to_vec(),
+                   derive_salt(&vec![1,2,4]).to_vec());
+    }
+
+    #[test]
+    fn test_pbkdf2() {
+        assert_eq!(DtKdf::default().derive(&[1,2,3]).unwrap(),
+                   DtKdf::Pbkdf2 { iterations: ITERATIONS }.derive(&[1,2,3]).unwrap());
+        assert_ne!(DtKdf::default().derive(&[1,2,3]).unwrap(),
+                   DtKdf::default().derive(&[1,2,4]).unwrap());
+        assert_ne!(DtKdf::default().derive(&[1,2,3]).unwrap(),
+                   DtKdf::Pbkdf2 { iterations: 1000 }.derive(&[1,2,3]).unwrap());
+        assert_eq!(DtKdf::default().derive(&[1,2,3]).unwrap().len(), DK_SIZE);
+    }
+
+    #[test]
+    fn test_argon2id_scrypt() {
+        let argon2 = DtKdf::Argon2id { memory: 64, iterations: 1 };
+        let scrypt = DtKdf::Scrypt { log_n: 4, r: 8, p: 1 };
+        assert_eq!(argon2.derive(&[1,2,3]).unwrap(), argon2.derive(&[1,2,3]).unwrap());
+        assert_eq!(scrypt.derive(&[1,2,3]).unwrap(), scrypt.derive(&[1,2,3]).unwrap());
+        assert_ne!(argon2.derive(&[1,2,3]).unwrap(), argon2.derive(&[1,2,4]).unwrap());
+        assert_ne!(scrypt.derive(&[1,2,3]).unwrap(), scrypt.derive(&[1,2,4]).unwrap());
+        assert_ne!(argon2.derive(&[1,2,3]).unwrap(), scrypt.derive(&[1,2,3]).unwrap());
+        assert_eq!(scrypt.derive(&[1,2,3]).unwrap().len(), DK_SIZE);
+    }
+
+    #[test]
+    fn test_raw() {
+        let key: Vec<u8> = (0..RAW_KEY_SIZE as u8).collect();
+        assert_eq!(DtKdf::Raw.derive(&key).unwrap(), key);
+        assert!(DtKdf::Raw.derive(&key[1..]).is_err());
+        assert!(DtKdf::Raw.derive(&[0; RAW_KEY_SIZE + 1]).is_err());
+        assert_eq!("raw".parse::<DtKdf>().unwrap(), DtKdf::Raw);
+        assert_eq!(DtKdf::Raw.to_string(), "raw");
+        assert!("raw:1".parse::<DtKdf>().is_err());
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("pbkdf2".parse::<DtKdf>().unwrap(), DtKdf::default());
+        assert_eq!("PBKDF2:1000".parse::<DtKdf>().unwrap(), DtKdf::Pbkdf2 { iterations: 1000 });
+        assert_eq!("argon2id".parse::<DtKdf>().unwrap(),
+                   DtKdf::Argon2id { memory: ARGON2_MEMORY, iterations: ARGON2_ITERATIONS });
+        assert_eq!("argon2id:1024:2".parse::<DtKdf>().unwrap(),
+                   DtKdf::Argon2id { memory: 1024, iterations: 2 });
+        assert_eq!("scrypt:10:8:2".parse::<DtKdf>().unwrap(), DtKdf::Scrypt { log_n: 10, r: 8, p: 2 });
+        for kdf in &["pbkdf2:1000", "argon2id:1024:2", "scrypt:10:8:2"] {
+            assert_eq!(kdf.parse::<DtKdf>().unwrap().to_string(), *kdf);
+        }
+        assert!("pbkdf2:0".parse::<DtKdf>().is_err());
+        assert!("pbkdf2:x".parse::<DtKdf>().is_err());
+        assert!("pbkdf2:1:2".parse::<DtKdf>().is_err());
+        assert!("argon2id:1:1".parse::<DtKdf>().is_err());
+        assert!("scrypt:0:8:1".parse::<DtKdf>().is_err());
+        assert!("scrypt:40:8:1".parse::<DtKdf>().is_err());
+        assert!("bcrypt".parse::<DtKdf>().is_err());
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
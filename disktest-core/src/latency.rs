@@ -0,0 +1,164 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+
+use std::time::Duration;
+
+/// Number of histogram buckets.
+/// Bucket 0 holds latencies below 1 µs and bucket i holds latencies
+/// of at least 2^(i-1) µs and below 2^i µs. The last bucket holds all longer latencies.
+pub const LATENCY_BUCKETS: usize = 32;
+
+/// Histogram of I/O latencies.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DtLatency {
+    buckets:    [u64; LATENCY_BUCKETS],
+    count:      u64,
+    max:        Duration,
+}
+
+impl Default for DtLatency {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DtLatency {
+    /// Create an empty histogram.
+    pub fn new() -> DtLatency {
+        DtLatency {
+            buckets:    [0; LATENCY_BUCKETS],
+            count:      0,
+            max:        Duration::from_secs(0),
+        }
+    }
+
+    /// Get the bucket index of a latency.
+    fn bucket(latency: Duration) -> usize {
+        let us = latency.as_micros().min(u64::MAX as u128) as u64;
+        let index = (64 - us.leading_zeros()) as usize;
+        index.min(LATENCY_BUCKETS - 1)
+    }
+
+    /// Get the exclusive upper bound of a bucket.
+    /// Returns None for the last bucket, which is unbounded.
+    pub fn bucket_limit(index: usize) -> Option<Duration> {
+        if index < LATENCY_BUCKETS - 1 {
+            Some(Duration::from_micros(1 << index))
+        } else {
+            None
+        }
+    }
+
+    /// Record the latency of one I/O operation.
+    pub fn record(&mut self, latency: Duration) {
+        self.buckets[DtLatency::bucket(latency)] += 1;
+        self.count += 1;
+        self.max = self.max.max(latency);
+    }
+
+    /// Add all recorded latencies of another histogram to this one.
+    pub fn merge(&mut self, other: &DtLatency) {
+        for (a, b) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *a += b;
+        }
+        self.count += other.count;
+        self.max = self.max.max(other.max);
+    }
+
+    /// Get the number of recorded operations.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Get the longest recorded latency.
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// Get the number of operations in each bucket.
+    pub fn buckets(&self) -> &[u64; LATENCY_BUCKETS] {
+        &self.buckets
+    }
+
+    /// Get the latency below which the given percentage of all operations completed.
+    /// The result is the upper bound of the bucket that contains the percentile,
+    /// but it never exceeds the longest recorded latency.
+    pub fn percentile(&self, percent: f64) -> Duration {
+        let target = (self.count as f64 * percent / 100.0).ceil().max(1.0) as u64;
+        let mut sum = 0;
+        for (i, &count) in self.buckets.iter().enumerate() {
+            sum += count;
+            if sum >= target {
+                return match DtLatency::bucket_limit(i) {
+                    Some(limit) => limit.min(self.max),
+                    None => self.max,
+                };
+            }
+        }
+        self.max
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket() {
+        assert_eq!(DtLatency::bucket(Duration::from_nanos(999)), 0);
+        assert_eq!(DtLatency::bucket(Duration::from_micros(1)), 1);
+        assert_eq!(DtLatency::bucket(Duration::from_micros(3)), 2);
+        assert_eq!(DtLatency::bucket(Duration::from_micros(4)), 3);
+        assert_eq!(DtLatency::bucket(Duration::from_secs(100000)), LATENCY_BUCKETS - 1);
+        assert_eq!(DtLatency::bucket_limit(3), Some(Duration::from_micros(8)));
+        assert_eq!(DtLatency::bucket_limit(LATENCY_BUCKETS - 1), None);
+    }
+
+    #[test]
+    fn test_percentile() {
+        let mut lat = DtLatency::new();
+        assert_eq!(lat.percentile(50.0), Duration::from_secs(0));
+        for _ in 0..990 {
+            lat.record(Duration::from_micros(100));
+        }
+        for _ in 0..9 {
+            lat.record(Duration::from_millis(10));
+        }
+        lat.record(Duration::from_millis(900));
+        assert_eq!(lat.count(), 1000);
+        assert_eq!(lat.max(), Duration::from_millis(900));
+        assert_eq!(lat.percentile(50.0), Duration::from_micros(128));
+        assert_eq!(lat.percentile(99.0), Duration::from_micros(128));
+        assert_eq!(lat.percentile(99.5), Duration::from_micros(16384));
+        assert_eq!(lat.percentile(99.9), Duration::from_micros(16384));
+        assert_eq!(lat.percentile(100.0), Duration::from_millis(900));
+
+        let mut other = DtLatency::new();
+        other.record(Duration::from_secs(2));
+        lat.merge(&other);
+        assert_eq!(lat.count(), 1001);
+        assert_eq!(lat.max(), Duration::from_secs(2));
+        assert_eq!(lat.buckets().iter().sum::<u64>(), 1001);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
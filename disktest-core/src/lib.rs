@@ -0,0 +1,93 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+//! Disktest core library.
+//!
+//! This crate contains the write and verify engine of the disktest
+//! hard drive tester. It writes a reproducible pseudo random stream to a
+//! device (or file) and verifies it later against the same stream.
+//!
+//! Disktest instances are configured with the `DisktestBuilder`.
+//! The progress of a running test is reported to `ProgressObserver`s.
+//! Errors of the engine can be downcast to `DtError`.
+//! The pseudo random stream itself can be consumed with a `DtStreamReader`.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use disktest_core::{Disktest, DisktestBuilder, DisktestFile, DtStreamType};
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let mut disktest = DisktestBuilder::new()
+//!     .algorithm(DtStreamType::CHACHA20)
+//!     .seed(b"my secret seed".to_vec())
+//!     .threads(0)
+//!     .build()?;
+//!
+//! // Write the pseudo random stream to the whole device.
+//! let file = DisktestFile::open("/dev/sdX", false, true, false, false, 0)?;
+//! let written = disktest.write(file, 0, Disktest::UNLIMITED)?;
+//!
+//! // Verify the written data.
+//! let file = DisktestFile::open("/dev/sdX", true, false, false, false, 0)?;
+//! disktest.verify(file, 0, written)?;
+//! # Ok(())
+//! # }
+//! ```
+
+mod affinity;
+mod aligned_buffer;
+mod buffer_pool;
+mod capacity;
+pub mod disk_size;
+mod disktest;
+mod drop_caches;
+mod error;
+mod generator;
+mod header;
+mod kdf;
+mod latency;
+mod mismatch;
+mod mmap;
+mod preallocate;
+mod progress;
+mod random_io;
+mod reader;
+mod stream;
+mod stream_aggregator;
+mod throttle;
+mod trail;
+pub mod util;
+mod zones;
+
+pub use crate::capacity::DtCapacity;
+pub use crate::disktest::{Disktest, DisktestBuilder, DisktestFile, DisktestHandle, DtErrorKind, DtErrorRecord, DtStreamType, STDIO_PATH};
+pub use crate::error::{DtError, DtIoOp};
+pub use crate::header::{DtHeader, HEADER_SIZE, seed_fingerprint};
+pub use crate::kdf::{DtKdf, RAW_KEY_SIZE};
+pub use crate::latency::{DtLatency, LATENCY_BUCKETS};
+pub use crate::progress::{ConsoleProgress, DtPhase, JsonProgress, JsonSink, ProgressInfo, ProgressObserver, format_progress, open_json_sink};
+pub use crate::reader::{DtStreamChunks, DtStreamReader};
+pub use crate::random_io::{DtRandomIo, RANDOM_IO_SIZE};
+pub use crate::trail::DtTrail;
+pub use crate::zones::{DtZone, DtZones};
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,147 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use std::cmp::min;
+use std::fmt::Write;
+
+/// Number of bytes per line of the hexdump.
+const DUMP_LINE_LEN: usize = 16;
+/// Number of lines of the hexdump.
+const DUMP_LINES: usize = 4;
+
+/// Bit flip statistics of mismatching data.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BitFlips {
+    /// Number of differing bytes.
+    pub bytes:      usize,
+    /// Number of bits that are 1 instead of 0.
+    pub set:        u64,
+    /// Number of bits that are 0 instead of 1.
+    pub cleared:    u64,
+    /// Number of differing bytes with exactly one flipped bit.
+    pub single:     usize,
+}
+
+impl BitFlips {
+    /// Count the flipped bits of data compared to the expected data.
+    pub fn count(data: &[u8], expected: &[u8]) -> BitFlips {
+        let mut flips = BitFlips::default();
+        for (&d, &e) in data.iter().zip(expected) {
+            let diff = d ^ e;
+            if diff != 0 {
+                flips.bytes += 1;
+                flips.set += (diff & d).count_ones() as u64;
+                flips.cleared += (diff & e).count_ones() as u64;
+                if diff.count_ones() == 1 {
+                    flips.single += 1;
+                }
+            }
+        }
+        flips
+    }
+}
+
+/// Format one line of the hexdump.
+fn dump_line(text: &mut String, label: &str, data: &[u8]) {
+    let _ = write!(text, "{}", label);
+    for b in data {
+        let _ = write!(text, " {:02X}", b);
+    }
+    text.push('\n');
+}
+
+/// Describe the first mismatch of data compared to the expected data.
+/// offset is the absolute byte offset of the data on the device.
+/// The description contains a hexdump of the expected and the actual bytes
+/// around the first differing byte, with the differing bytes marked,
+/// and the bit flip statistics of the whole data.
+/// Returns None, if the data does not differ.
+pub fn describe_mismatch(data: &[u8], expected: &[u8], offset: u64) -> Option<String> {
+    let len = min(data.len(), expected.len());
+    let first = (0..len).find(|&i| data[i] != expected[i])?;
+
+    // Start one line before the line of the first differing byte.
+    let begin = (first / DUMP_LINE_LEN).saturating_sub(1) * DUMP_LINE_LEN;
+    let end = min(len, begin + DUMP_LINES * DUMP_LINE_LEN);
+    let mut text = format!("First mismatch at byte {} (E: expected, A: actual, ^^: differing):\n",
+                           offset + first as u64);
+    for line in (begin..end).step_by(DUMP_LINE_LEN) {
+        let line_end = min(end, line + DUMP_LINE_LEN);
+        let pos = offset + line as u64;
+        dump_line(&mut text, &format!("  {:016X}  E:", pos), &expected[line..line_end]);
+        dump_line(&mut text, "                    A:", &data[line..line_end]);
+        if (line..line_end).any(|i| data[i] != expected[i]) {
+            let marks: String = (line..line_end)
+                .map(|i| if data[i] != expected[i] { " ^^" } else { "   " })
+                .collect();
+            let _ = writeln!(text, "                      {}", marks.trim_end());
+        }
+    }
+
+    let flips = BitFlips::count(&data[..len], &expected[..len]);
+    let _ = write!(text, "Bit flips: {} of {} bytes differ, {} bits flipped \
+                          (0->1: {}, 1->0: {}), {} bytes with a single bit flip.",
+                   flips.bytes, len, flips.set + flips.cleared,
+                   flips.set, flips.cleared, flips.single);
+    Some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bit_flips() {
+        let flips = BitFlips::count(&[0x00, 0xFF, 0x0F, 0x12], &[0x01, 0xFF, 0xF0, 0x12]);
+        assert_eq!(flips, BitFlips { bytes: 2, set: 4, cleared: 5, single: 1 });
+        assert_eq!(BitFlips::count(&[1, 2], &[1, 2]), BitFlips::default());
+    }
+
+    #[test]
+    fn test_describe_mismatch() {
+        let expected: Vec<u8> = (0..100).collect();
+        assert!(describe_mismatch(&expected, &expected, 0).is_none());
+
+        let mut data = expected.clone();
+        data[40] ^= 0x01;
+        data[42] = 0xFF;
+        let text = describe_mismatch(&data, &expected, 0x1000).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "First mismatch at byte 4136 (E: expected, A: actual, ^^: differing):");
+        // The dump starts one line before the first mismatch.
+        assert!(lines[1].starts_with("  0000000000001010  E: 10 11 12"));
+        assert!(lines[2].starts_with("                    A: 10 11 12"));
+        assert!(lines[3].starts_with("  0000000000001020  E:"));
+        assert_eq!(lines[4], "                    A: 20 21 22 23 24 25 26 27 29 29 FF 2B 2C 2D 2E 2F");
+        assert_eq!(lines[5], "                                               ^^    ^^");
+        assert!(lines[6].starts_with("  0000000000001030  E:"));
+        assert!(lines[8].starts_with("  0000000000001040  E:"));
+        assert_eq!(lines[10], "Bit flips: 2 of 100 bytes differ, 6 bits flipped \
+                               (0->1: 6, 1->0: 0), 1 bytes with a single bit flip.");
+        assert_eq!(lines.len(), 11);
+
+        // Short data at the start.
+        let text = describe_mismatch(&data[40..44], &expected[40..44], 40).unwrap();
+        assert!(text.contains("  0000000000000028  E: 28 29 2A 2B\n"));
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
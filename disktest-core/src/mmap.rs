@@ -0,0 +1,132 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+
+use std::fs::File;
+use std::io;
+
+/// Size of the mapped windows of a file, in bytes.
+/// This is a multiple of the page size of all supported platforms.
+pub const MMAP_WINDOW: u64 = 64 * 1024 * 1024;
+
+/// A read-only memory mapped window of a file.
+pub struct DtMmap {
+    #[cfg(unix)]
+    ptr:            *mut libc::c_void,
+    /// Absolute byte offset of the window in the file.
+    offset:         u64,
+    len:            usize,
+}
+
+impl DtMmap {
+    /// Check whether memory mapping is supported on this operating system.
+    pub fn supported() -> bool {
+        cfg!(unix)
+    }
+
+    /// Map len bytes of the file starting at the absolute offset read-only.
+    /// The offset must be a multiple of the page size.
+    #[cfg(unix)]
+    pub fn map(file: &File, offset: u64, len: usize) -> io::Result<DtMmap> {
+        use std::os::unix::io::AsRawFd;
+        use std::ptr::null_mut;
+
+        if len == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Empty memory map."));
+        }
+        let ptr = unsafe { libc::mmap(null_mut(),
+                                      len,
+                                      libc::PROT_READ,
+                                      libc::MAP_SHARED,
+                                      file.as_raw_fd(),
+                                      offset as libc::off_t) };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        // The window is read sequentially. The advice is only a hint.
+        unsafe { libc::madvise(ptr, len, libc::MADV_SEQUENTIAL) };
+        Ok(DtMmap {
+            ptr,
+            offset,
+            len,
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn map(_file: &File, _offset: u64, _len: usize) -> io::Result<DtMmap> {
+        Err(io::Error::other("Memory mapped I/O is not supported on this operating system."))
+    }
+
+    /// Check whether len bytes at the absolute offset are inside of the window.
+    pub fn contains(&self, offset: u64, len: usize) -> bool {
+        offset >= self.offset && offset + len as u64 <= self.offset + self.len as u64
+    }
+
+    /// Get len bytes at the absolute offset.
+    /// The range must be inside of the window.
+    pub fn slice(&self, offset: u64, len: usize) -> &[u8] {
+        assert!(self.contains(offset, len));
+        let begin = (offset - self.offset) as usize;
+        &self.data()[begin..begin + len]
+    }
+
+    #[cfg(unix)]
+    fn data(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+
+    #[cfg(not(unix))]
+    fn data(&self) -> &[u8] {
+        &[]
+    }
+}
+
+#[cfg(unix)]
+impl Drop for DtMmap {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr, self.len) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_mmap() {
+        let mut tfile = NamedTempFile::new().unwrap();
+        let data: Vec<u8> = (0..3 * 4096).map(|i| (i % 251) as u8).collect();
+        tfile.write_all(&data).unwrap();
+        let file = File::open(tfile.path()).unwrap();
+        let map = DtMmap::map(&file, 4096, 2 * 4096).unwrap();
+        assert!(map.contains(4096, 4096));
+        assert!(map.contains(4096 + 100, 2 * 4096 - 100));
+        assert!(!map.contains(0, 4096));
+        assert!(!map.contains(4096, 2 * 4096 + 1));
+        assert_eq!(map.slice(5000, 100), &data[5000..5100]);
+        assert!(DtMmap::map(&file, 0, 0).is_err());
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
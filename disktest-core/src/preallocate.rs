@@ -0,0 +1,114 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use std::fs::File;
+use std::io;
+
+#[cfg(target_os="linux")]
+fn os_preallocate(file: &File, offset: u64, len: u64) -> io::Result<()> {
+    use libc::{fallocate, off_t, FALLOC_FL_KEEP_SIZE};
+    use std::os::unix::io::AsRawFd;
+
+    // The size of the file is not changed. It grows while it is written.
+    let ret = unsafe { fallocate(file.as_raw_fd(),
+                                 FALLOC_FL_KEEP_SIZE,
+                                 offset as off_t,
+                                 len as off_t) };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(target_os="windows")]
+fn os_preallocate(file: &File, offset: u64, len: u64) -> io::Result<()> {
+    use std::mem::size_of;
+    use std::os::windows::io::AsRawHandle;
+    use winapi::um::{
+        minwinbase::FileAllocationInfo,
+        winbase::{FILE_ALLOCATION_INFO, SetFileInformationByHandle},
+    };
+
+    // An allocation size below the end of the file would truncate it.
+    if offset + len <= file.metadata()?.len() {
+        return Ok(());
+    }
+    let mut info: FILE_ALLOCATION_INFO = unsafe { std::mem::zeroed() };
+    unsafe { *info.AllocationSize.QuadPart_mut() = (offset + len) as i64 };
+    let ok = unsafe { SetFileInformationByHandle(file.as_raw_handle() as _,
+                                                 FileAllocationInfo,
+                                                 &mut info as *mut _ as _,
+                                                 size_of::<FILE_ALLOCATION_INFO>() as u32) };
+    if ok == 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os="linux", target_os="windows")))]
+fn os_preallocate(_file: &File, _offset: u64, _len: u64) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported,
+                       "Preallocation is not supported on this operating system."))
+}
+
+/// Allocate the space of len bytes at offset of a regular file on the file system,
+/// so that a lack of space is detected before writing.
+pub fn preallocate(file: &File, offset: u64, len: u64) -> io::Result<()> {
+    os_preallocate(file, offset, len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    #[cfg(any(target_os="linux", target_os="windows"))]
+    fn test_preallocate() {
+        let tfile = NamedTempFile::new().unwrap();
+        let file = tfile.as_file();
+        match preallocate(file, 4096, 1024 * 1024) {
+            // Not all file systems support preallocation.
+            Err(e) if e.kind() == io::ErrorKind::Unsupported => (),
+            result => result.unwrap(),
+        }
+        if cfg!(target_os="linux") {
+            assert_eq!(file.metadata().unwrap().len(), 0);
+        }
+    }
+
+    #[test]
+    #[cfg(any(target_os="linux", target_os="windows"))]
+    fn test_preallocate_keeps_size() {
+        let tfile = NamedTempFile::new().unwrap();
+        let file = tfile.as_file();
+        file.set_len(1024 * 1024).unwrap();
+        match preallocate(file, 4096, 64 * 1024) {
+            Err(e) if e.kind() == io::ErrorKind::Unsupported => (),
+            result => result.unwrap(),
+        }
+        assert_eq!(file.metadata().unwrap().len(), 1024 * 1024);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
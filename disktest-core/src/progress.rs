@@ -0,0 +1,524 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+use crate::disktest::DtErrorRecord;
+use crate::util::prettybytes;
+use hhmmss::Hhmmss;
+use std::io::{IsTerminal, Write, stderr, stdout};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Interval of the progress updates sent to the observers.
+const PROGRESS_SEC_THRES: u64   = 1;
+/// Update interval of the live progress line on a terminal.
+const LIVE_SEC_THRES: u64       = 1;
+/// Update interval of the progress lines, if the output is not a terminal.
+const LINE_SEC_THRES: u64       = 10;
+
+/// Phase of a Disktest run.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DtPhase {
+    Write,
+    Verify,
+    /// Read-only scan without comparison.
+    Scan,
+    /// Non-destructive test that restores the original data.
+    Nondestructive,
+    /// Comparison of two devices.
+    Compare,
+    /// Destructive surface test that writes, reads back and compares each segment.
+    Surface,
+}
+
+impl std::fmt::Display for DtPhase {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DtPhase::Write => write!(f, "write"),
+            DtPhase::Verify => write!(f, "verify"),
+            DtPhase::Scan => write!(f, "scan"),
+            DtPhase::Nondestructive => write!(f, "nondestructive"),
+            DtPhase::Compare => write!(f, "compare"),
+            DtPhase::Surface => write!(f, "surface"),
+        }
+    }
+}
+
+/// Snapshot of the progress of a write or verify phase.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProgressInfo {
+    pub phase:      DtPhase,
+    /// Current absolute byte position on the device.
+    pub offset:     u64,
+    /// Number of bytes processed in this phase.
+    pub bytes:      u64,
+    /// Total number of bytes to process in this phase, if known.
+    pub total:      Option<u64>,
+    /// Time since the start of the phase.
+    pub elapsed:    Duration,
+    /// Rate since the previous progress update, in bytes per second.
+    pub rate:       u64,
+    /// Average rate since the start of the phase, in bytes per second.
+    pub avg_rate:   u64,
+    /// Number of bad regions found so far.
+    pub errors:     usize,
+}
+
+/// Observer of the progress of a Disktest instance.
+/// All methods have empty default implementations.
+pub trait ProgressObserver: Send {
+    /// A write or verify phase of path begins at the absolute position seek.
+    fn begin(&mut self, _phase: DtPhase, _path: &Path, _seek: u64) {
+    }
+
+    /// Periodic progress update.
+    fn progress(&mut self, _info: &ProgressInfo) {
+    }
+
+    /// A new bad region has been found.
+    fn error(&mut self, _error: &DtErrorRecord) {
+    }
+
+    /// Informational status message.
+    fn message(&mut self, _message: &str) {
+    }
+
+    /// The phase finished.
+    /// errors contains all bad regions that have been found in the phase.
+    fn finish(&mut self, _info: &ProgressInfo, _errors: &[DtErrorRecord]) {
+    }
+}
+
+/// Destination of the machine readable JSON progress stream.
+pub type JsonSink = Arc<Mutex<dyn Write + Send>>;
+
+/// Open the destination of the JSON progress stream.
+/// target is "stderr", "stdout" or the number of an open file descriptor.
+pub fn open_json_sink(target: &str) -> ah::Result<JsonSink> {
+    match target {
+        "stderr" => Ok(Arc::new(Mutex::new(stderr()))),
+        "stdout" => Ok(Arc::new(Mutex::new(stdout()))),
+        fd => os_open_fd(fd),
+    }
+}
+
+#[cfg(unix)]
+fn os_open_fd(fd: &str) -> ah::Result<JsonSink> {
+    use std::fs::File;
+    use std::os::unix::io::FromRawFd;
+
+    let fd: libc::c_int = match fd.parse() {
+        Ok(fd) if fd >= 0 => fd,
+        _ => return Err(ah::format_err!("Invalid progress stream target: {}", fd)),
+    };
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } < 0 {
+        return Err(ah::format_err!("Invalid progress stream file descriptor {}: {}",
+                                   fd, std::io::Error::last_os_error()));
+    }
+    Ok(Arc::new(Mutex::new(unsafe { File::from_raw_fd(fd) })))
+}
+
+#[cfg(not(unix))]
+fn os_open_fd(fd: &str) -> ah::Result<JsonSink> {
+    Err(ah::format_err!("Invalid progress stream target: {}. \
+                        File descriptors are not supported on this operating system.", fd))
+}
+
+/// Calculate the rate in bytes per second.
+fn rate(bytes: u64, elapsed: Duration) -> u64 {
+    let secs = elapsed.as_secs_f64();
+    if secs > 0.0 { (bytes as f64 / secs) as u64 } else { 0 }
+}
+
+/// Format a JSON progress stream line.
+fn format_json(info: &ProgressInfo, done: bool) -> String {
+    serde_json::json!({
+        "phase":        info.phase.to_string(),
+        "offset":       info.offset,
+        "bytes":        info.bytes,
+        "total":        info.total,
+        "rate":         info.rate,
+        "avg_rate":     info.avg_rate,
+        "elapsed":      info.elapsed.as_secs_f64(),
+        "errors":       info.errors,
+        "done":         done,
+    }).to_string()
+}
+
+/// Format a progress line.
+/// If done is true, then the line is the final line.
+pub fn format_progress(info: &ProgressInfo, done: bool) -> String {
+    let (prefix, suffix) = match (info.phase, done) {
+        (DtPhase::Write, false) => ("Wrote ", " ..."),
+        (DtPhase::Write, true) => ("Done. Wrote ", "."),
+        (DtPhase::Verify, false) => ("Verified ", " ..."),
+        (DtPhase::Verify, true) => ("Done. Verified ", "."),
+        (DtPhase::Scan, false) => ("Scanned ", " ..."),
+        (DtPhase::Scan, true) => ("Done. Scanned ", "."),
+        (DtPhase::Nondestructive, false) => ("Tested ", " ..."),
+        (DtPhase::Nondestructive, true) => ("Done. Tested ", "."),
+        (DtPhase::Compare, false) => ("Compared ", " ..."),
+        (DtPhase::Compare, true) => ("Done. Compared ", "."),
+        (DtPhase::Surface, false) => ("Tested ", " ..."),
+        (DtPhase::Surface, true) => ("Done. Tested ", "."),
+    };
+    let percent = match info.total {
+        Some(total) if total > 0 => {
+            format!(" ({:.1}%)", info.bytes as f64 * 100.0 / total as f64)
+        },
+        _ => "".to_string(),
+    };
+    let rate = if done {
+        format!("avg {}/s", prettybytes(info.avg_rate, true, false))
+    } else {
+        format!("{}/s, avg {}/s",
+                prettybytes(info.rate, true, false),
+                prettybytes(info.avg_rate, true, false))
+    };
+    let eta = match info.total {
+        Some(total) if !done && info.bytes > 0 && total >= info.bytes => {
+            let remaining = info.elapsed.as_secs_f64() *
+                            (total - info.bytes) as f64 / info.bytes as f64;
+            format!(", ETA {}", Duration::from_secs(remaining as u64).hhmmss())
+        },
+        _ => "".to_string(),
+    };
+    format!("{}{}{} @ {}, elapsed {}{}{}",
+            prefix,
+            prettybytes(info.bytes, true, true),
+            percent,
+            rate,
+            info.elapsed.hhmmss(),
+            eta,
+            suffix)
+}
+
+/// Human readable progress on the console.
+/// On a terminal the progress is shown as one live line that is updated in place.
+/// Otherwise a progress line is printed every couple of seconds.
+pub struct ConsoleProgress {
+    quiet_level:    u8,
+    live:           bool,
+    last_time:      Instant,
+    line_len:       usize,
+}
+
+impl ConsoleProgress {
+    /// Create a new console progress indicator.
+    /// quiet_level 0: Show everything, 1: Only show the begin and the end, 2: Quiet.
+    pub fn new(quiet_level: u8) -> ConsoleProgress {
+        ConsoleProgress {
+            quiet_level,
+            live:           stdout().is_terminal(),
+            last_time:      Instant::now(),
+            line_len:       0,
+        }
+    }
+
+    /// Show a progress line.
+    fn show(&mut self, line: &str, done: bool) {
+        if self.live {
+            // Overwrite the previous live line.
+            let pad = " ".repeat(self.line_len.saturating_sub(line.len()));
+            print!("\r{}{}", line, pad);
+            self.line_len = line.len();
+            if done {
+                self.end_line();
+            } else {
+                let _ = stdout().flush();
+            }
+        } else {
+            println!("{}", line);
+        }
+    }
+
+    /// Terminate the live progress line, if any.
+    fn end_line(&mut self) {
+        if self.line_len > 0 {
+            println!();
+            self.line_len = 0;
+        }
+    }
+
+    /// Print the map of all bad regions.
+    fn print_error_map(&self, errors: &[DtErrorRecord]) {
+        let total: u64 = errors.iter().map(|e| e.length).sum();
+        println!("\nBad region map ({} regions, {}):",
+                 errors.len(),
+                 prettybytes(total, true, true));
+        for error in errors {
+            println!("    {:>15} - {:>15} ({:>10} bytes): {}",
+                     error.offset,
+                     error.offset + error.length,
+                     error.length,
+                     error.kind);
+        }
+    }
+}
+
+impl ProgressObserver for ConsoleProgress {
+    fn begin(&mut self, phase: DtPhase, path: &Path, seek: u64) {
+        self.last_time = Instant::now();
+        if self.quiet_level < 2 {
+            self.end_line();
+            println!("{} {:?}, starting at position {}...",
+                     match phase {
+                         DtPhase::Write => "Writing",
+                         DtPhase::Verify => "Verifying",
+                         DtPhase::Scan => "Scanning",
+                         DtPhase::Nondestructive => "Testing non-destructively",
+                         DtPhase::Compare => "Comparing",
+                         DtPhase::Surface => "Surface testing",
+                     },
+                     path,
+                     prettybytes(seek, true, true));
+        }
+    }
+
+    fn progress(&mut self, info: &ProgressInfo) {
+        let thres = if self.live { LIVE_SEC_THRES } else { LINE_SEC_THRES };
+        let now = Instant::now();
+        if self.quiet_level == 0 &&
+           now.duration_since(self.last_time).as_secs() >= thres {
+            self.show(&format_progress(info, false), false);
+            self.last_time = now;
+        }
+    }
+
+    fn message(&mut self, message: &str) {
+        if self.quiet_level < 2 {
+            self.end_line();
+            println!("{}", message);
+        }
+    }
+
+    fn finish(&mut self, info: &ProgressInfo, errors: &[DtErrorRecord]) {
+        if self.quiet_level < 2 {
+            self.show(&format_progress(info, true), true);
+            if !errors.is_empty() {
+                self.print_error_map(errors);
+            }
+        }
+    }
+}
+
+impl Drop for ConsoleProgress {
+    fn drop(&mut self) {
+        self.end_line();
+    }
+}
+
+/// Machine readable progress stream with one JSON object per line.
+pub struct JsonProgress {
+    sink:           JsonSink,
+}
+
+impl JsonProgress {
+    /// Create a new JSON progress stream that is written to sink.
+    pub fn new(sink: JsonSink) -> JsonProgress {
+        JsonProgress {
+            sink,
+        }
+    }
+
+    /// Write one line to the stream.
+    fn emit(&self, info: &ProgressInfo, done: bool) {
+        if let Ok(mut sink) = self.sink.lock() {
+            let _ = writeln!(sink, "{}", format_json(info, done));
+            let _ = sink.flush();
+        }
+    }
+}
+
+impl ProgressObserver for JsonProgress {
+    fn progress(&mut self, info: &ProgressInfo) {
+        self.emit(info, false);
+    }
+
+    fn finish(&mut self, info: &ProgressInfo, _errors: &[DtErrorRecord]) {
+        self.emit(info, true);
+    }
+}
+
+/// Progress tracking of a Disktest instance.
+/// Calculates the progress information and distributes it to all observers.
+pub(crate) struct Progress {
+    observers:      Vec<Box<dyn ProgressObserver>>,
+    phase:          DtPhase,
+    seek:           u64,
+    total:          Option<u64>,
+    begin_time:     Instant,
+    last_time:      Instant,
+    last_bytes:     u64,
+}
+
+impl Progress {
+    /// Create a new progress tracker.
+    pub fn new(observers: Vec<Box<dyn ProgressObserver>>) -> Progress {
+        let now = Instant::now();
+        Progress {
+            observers,
+            phase:          DtPhase::Write,
+            seek:           0,
+            total:          None,
+            begin_time:     now,
+            last_time:      now,
+            last_bytes:     0,
+        }
+    }
+
+    /// Begin a phase of path at the absolute seek position.
+    pub fn begin(&mut self, phase: DtPhase, path: &Path, seek: u64) {
+        let now = Instant::now();
+        self.phase = phase;
+        self.seek = seek;
+        self.total = None;
+        self.begin_time = now;
+        self.last_time = now;
+        self.last_bytes = 0;
+        for observer in &mut self.observers {
+            observer.begin(phase, path, seek);
+        }
+    }
+
+    /// Set the total number of bytes to process, if known.
+    pub fn set_total(&mut self, total: Option<u64>) {
+        self.total = total;
+    }
+
+    /// Exclude the paused duration from the elapsed time and the rates.
+    pub fn pause(&mut self, paused: Duration) {
+        self.begin_time += paused;
+        self.last_time += paused;
+    }
+
+    /// Check if it's time for the next progress update.
+    pub fn due(&self, now: Instant) -> bool {
+        now.duration_since(self.last_time).as_secs() >= PROGRESS_SEC_THRES
+    }
+
+    /// Calculate the progress information.
+    fn info(&self, bytes: u64, errors: usize, now: Instant) -> ProgressInfo {
+        let elapsed = now - self.begin_time;
+        ProgressInfo {
+            phase:      self.phase,
+            offset:     self.seek + bytes,
+            bytes,
+            total:      self.total,
+            elapsed,
+            rate:       rate(bytes.saturating_sub(self.last_bytes), now - self.last_time),
+            avg_rate:   rate(bytes, elapsed),
+            errors,
+        }
+    }
+
+    /// Send a progress update to all observers.
+    pub fn update(&mut self, bytes: u64, errors: usize, now: Instant) {
+        let info = self.info(bytes, errors, now);
+        for observer in &mut self.observers {
+            observer.progress(&info);
+        }
+        self.last_time = now;
+        self.last_bytes = bytes;
+    }
+
+    /// Notify all observers about a new bad region.
+    pub fn error(&mut self, error: &DtErrorRecord) {
+        for observer in &mut self.observers {
+            observer.error(error);
+        }
+    }
+
+    /// Send a status message to all observers.
+    pub fn message(&mut self, message: &str) {
+        for observer in &mut self.observers {
+            observer.message(message);
+        }
+    }
+
+    /// Notify all observers about the end of the phase.
+    pub fn finish(&mut self, bytes: u64, errors: &[DtErrorRecord]) {
+        let info = self.info(bytes, errors.len(), Instant::now());
+        for observer in &mut self.observers {
+            observer.finish(&info, errors);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(phase: DtPhase, bytes: u64, total: Option<u64>,
+            elapsed: u64, rate: u64) -> ProgressInfo {
+        ProgressInfo {
+            phase,
+            offset:     bytes + 500,
+            bytes,
+            total,
+            elapsed:    Duration::from_secs(elapsed),
+            rate,
+            avg_rate:   bytes / elapsed,
+            errors:     3,
+        }
+    }
+
+    #[test]
+    fn test_format_progress() {
+        const MIB: u64 = 1024 * 1024;
+        assert_eq!(format_progress(&info(DtPhase::Write, MIB * 10, Some(MIB * 40), 10, MIB * 2),
+                                   false),
+                   "Wrote 10.0 MiB (10.5 MB) (25.0%) @ 2.0 MiB/s, avg 1.0 MiB/s, \
+                   elapsed 00:00:10, ETA 00:00:30 ...");
+        assert_eq!(format_progress(&info(DtPhase::Verify, MIB * 10, None, 5, MIB), false),
+                   "Verified 10.0 MiB (10.5 MB) @ 1.0 MiB/s, avg 2.0 MiB/s, \
+                   elapsed 00:00:05 ...");
+        assert_eq!(format_progress(&info(DtPhase::Write, MIB * 40, Some(MIB * 40), 20, 0),
+                                   true),
+                   "Done. Wrote 40.0 MiB (41.9 MB) (100.0%) @ avg 2.0 MiB/s, \
+                   elapsed 00:00:20.");
+    }
+
+    #[test]
+    fn test_format_json() {
+        let json: serde_json::Value = serde_json::from_str(
+            &format_json(&info(DtPhase::Verify, 1000, None, 2, 300), false)).unwrap();
+        assert_eq!(json["phase"], "verify");
+        assert_eq!(json["offset"], 1500);
+        assert_eq!(json["bytes"], 1000);
+        assert_eq!(json["total"], serde_json::Value::Null);
+        assert_eq!(json["rate"], 300);
+        assert_eq!(json["avg_rate"], 500);
+        assert_eq!(json["elapsed"], 2.0);
+        assert_eq!(json["errors"], 3);
+        assert_eq!(json["done"], false);
+    }
+
+    #[test]
+    fn test_open_json_sink() {
+        assert!(open_json_sink("stderr").is_ok());
+        assert!(open_json_sink("stdout").is_ok());
+        assert!(open_json_sink("foo").is_err());
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,95 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+
+use rand::Rng;
+use std::time::Duration;
+
+/// Size of one random access, in bytes.
+/// This is a multiple of all common sector sizes and of the direct I/O alignment.
+pub const RANDOM_IO_SIZE: usize = 4096;
+
+/// Result of a random I/O benchmark.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DtRandomIo {
+    /// The number of completed accesses.
+    pub ops:        u64,
+    /// The number of transferred bytes.
+    pub bytes:      u64,
+    /// The time that the accesses took.
+    pub elapsed:    Duration,
+}
+
+impl DtRandomIo {
+    /// Get the number of accesses per second.
+    pub fn iops(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 { self.ops as f64 / secs } else { 0.0 }
+    }
+
+    /// Get the number of transferred bytes per second.
+    pub fn rate(&self) -> u64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 { (self.bytes as f64 / secs) as u64 } else { 0 }
+    }
+}
+
+/// Get a random access offset within a region of the given size.
+/// The offset is relative to the start of the region and aligned to RANDOM_IO_SIZE.
+/// Returns None, if the region is smaller than one access.
+pub fn random_offset<R: Rng>(rng: &mut R, region: u64) -> Option<u64> {
+    let blocks = region / RANDOM_IO_SIZE as u64;
+    if blocks == 0 {
+        None
+    } else {
+        Some(rng.gen_range(0, blocks) * RANDOM_IO_SIZE as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_offset() {
+        let mut rng = rand::thread_rng();
+        assert_eq!(random_offset(&mut rng, 0), None);
+        assert_eq!(random_offset(&mut rng, 4095), None);
+        assert_eq!(random_offset(&mut rng, 8191), Some(0));
+        for _ in 0..1000 {
+            let offset = random_offset(&mut rng, 4096 * 10 + 100).unwrap();
+            assert_eq!(offset % 4096, 0);
+            assert!(offset <= 4096 * 9);
+        }
+    }
+
+    #[test]
+    fn test_iops() {
+        let r = DtRandomIo { ops: 100, bytes: 409600, elapsed: Duration::from_secs(2) };
+        assert_eq!(r.iops(), 50.0);
+        assert_eq!(r.rate(), 204800);
+        let r = DtRandomIo { ops: 0, bytes: 0, elapsed: Duration::from_secs(0) };
+        assert_eq!(r.iops(), 0.0);
+        assert_eq!(r.rate(), 0);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,220 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+use crate::stream::DtStreamChunk;
+use crate::stream_aggregator::DtStreamAgg;
+use std::cmp::min;
+use std::io::{self, Read};
+
+/// Reader of the pseudo random stream of a Disktest instance.
+///
+/// The reader returns exactly the bytes that `Disktest::write()` writes
+/// at the same absolute positions. The on-disk header is not part of the stream.
+/// The stream is endless. It can be consumed with `std::io::Read`
+/// or chunk by chunk with `chunks()`.
+///
+/// # Example
+///
+/// ```
+/// use disktest_core::{DisktestBuilder, DtStreamType};
+/// use std::io::Read;
+///
+/// # fn main() -> anyhow::Result<()> {
+/// let mut reader = DisktestBuilder::new()
+///     .algorithm(DtStreamType::CRC)
+///     .seed(b"my secret seed".to_vec())
+///     .build()?
+///     .stream_reader(0)?;
+/// let mut data = vec![0; 4096];
+/// reader.read_exact(&mut data)?;
+/// assert_eq!(reader.position(), 4096);
+/// # Ok(())
+/// # }
+/// ```
+pub struct DtStreamReader {
+    stream_agg:     DtStreamAgg,
+    /// The current chunk, if any.
+    chunk:          Option<DtStreamChunk>,
+    /// Number of consumed bytes of the current chunk.
+    chunk_pos:      usize,
+    position:       u64,
+}
+
+impl DtStreamReader {
+    /// Create a reader that starts at the absolute byte offset of the stream.
+    pub(crate) fn new(stream_agg: DtStreamAgg, offset: u64) -> ah::Result<DtStreamReader> {
+        let mut stream_agg = stream_agg;
+        let chunk_size = stream_agg.get_chunk_size() as u64;
+        let chunk_offset = offset - offset % chunk_size;
+        stream_agg.activate(chunk_offset)?;
+        let mut reader = DtStreamReader {
+            stream_agg,
+            chunk:          None,
+            chunk_pos:      0,
+            position:       chunk_offset,
+        };
+        // Skip the beginning of the first chunk.
+        reader.current()?;
+        reader.consume((offset - chunk_offset) as usize);
+        Ok(reader)
+    }
+
+    /// Get the absolute byte offset of the next byte of the stream.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Get the size of the chunks that are generated at once, in bytes.
+    pub fn chunk_size(&self) -> usize {
+        self.stream_agg.get_chunk_size()
+    }
+
+    /// Get an endless iterator over the chunks of the stream in order.
+    /// The first chunk is shorter, if the reader is not at a chunk boundary.
+    pub fn chunks(&mut self) -> DtStreamChunks<'_> {
+        DtStreamChunks {
+            reader: self,
+        }
+    }
+
+    /// Get the remaining data of the current chunk.
+    /// The next chunk is generated, if the current chunk has been consumed.
+    fn current(&mut self) -> ah::Result<&[u8]> {
+        let consumed = match &self.chunk {
+            Some(chunk) => self.chunk_pos >= chunk.data.len(),
+            None => true,
+        };
+        if consumed {
+            if let Some(chunk) = self.chunk.take() {
+                self.stream_agg.recycle(chunk);
+            }
+            self.chunk = Some(self.stream_agg.wait_chunk()?);
+            self.chunk_pos = 0;
+        }
+        Ok(&self.chunk.as_ref().unwrap().data[self.chunk_pos..])
+    }
+
+    /// Mark count bytes of the current chunk as consumed.
+    fn consume(&mut self, count: usize) {
+        self.chunk_pos += count;
+        self.position += count as u64;
+    }
+
+    /// Get the remaining data of the current chunk and consume it.
+    fn next_chunk(&mut self) -> ah::Result<Vec<u8>> {
+        let len = self.current()?.len();
+        let data = if self.chunk_pos == 0 {
+            // Hand out the whole chunk without copying it.
+            self.chunk.take().unwrap().data
+        } else {
+            self.current()?.to_vec()
+        };
+        self.consume(len);
+        Ok(data)
+    }
+}
+
+impl Read for DtStreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let data = self.current().map_err(io::Error::other)?;
+        let count = min(buf.len(), data.len());
+        buf[..count].copy_from_slice(&data[..count]);
+        self.consume(count);
+        Ok(count)
+    }
+}
+
+/// Iterator over the chunks of a `DtStreamReader`.
+pub struct DtStreamChunks<'a> {
+    reader:         &'a mut DtStreamReader,
+}
+
+impl Iterator for DtStreamChunks<'_> {
+    type Item = ah::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.reader.next_chunk())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::disktest::{DisktestBuilder, DisktestFile};
+    use crate::stream_aggregator::DtStreamType;
+    use std::fs;
+    use std::io::Read;
+    use tempfile::NamedTempFile;
+
+    fn builder() -> DisktestBuilder {
+        DisktestBuilder::new()
+            .algorithm(DtStreamType::CRC)
+            .seed(vec![1, 2, 3])
+            .threads(2)
+            .quiet_level(2)
+    }
+
+    #[test]
+    fn test_reader() {
+        // The stream is identical to the written data.
+        let tfile = NamedTempFile::new().unwrap();
+        let path = tfile.path().to_str().unwrap();
+        let chunk_size = builder().build().unwrap().stream_reader(0).unwrap().chunk_size();
+        let len = chunk_size * 3 + 1000;
+        let file = DisktestFile::open(path, false, true, false, false, 2).unwrap();
+        builder().build().unwrap().write(file, 0, len as u64).unwrap();
+        let written = fs::read(path).unwrap();
+
+        let mut reader = builder().build().unwrap().stream_reader(0).unwrap();
+        assert_eq!(reader.chunk_size(), chunk_size);
+        let mut data = vec![0; len];
+        reader.read_exact(&mut data).unwrap();
+        assert_eq!(data, written);
+        assert_eq!(reader.position(), len as u64);
+
+        // Unaligned start.
+        let mut reader = builder().build().unwrap().stream_reader(1234).unwrap();
+        assert_eq!(reader.position(), 1234);
+        let mut data = vec![0; chunk_size];
+        reader.read_exact(&mut data).unwrap();
+        assert_eq!(data[..], written[1234..1234 + chunk_size]);
+
+        // Chunks.
+        let mut reader = builder().build().unwrap().stream_reader(1000).unwrap();
+        let chunks: Vec<Vec<u8>> = reader.chunks().take(3).map(|c| c.unwrap()).collect();
+        assert_eq!(chunks[0].len(), chunk_size - 1000);
+        assert_eq!(chunks[1].len(), chunk_size);
+        assert_eq!(chunks.concat()[..], written[1000..chunk_size * 3]);
+        assert_eq!(reader.position(), chunk_size as u64 * 3);
+
+        // Mixed read and chunks.
+        let mut reader = builder().build().unwrap().stream_reader(0).unwrap();
+        let mut data = vec![0; 100];
+        reader.read_exact(&mut data).unwrap();
+        let chunk = reader.chunks().next().unwrap().unwrap();
+        assert_eq!(chunk[..], written[100..chunk_size]);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
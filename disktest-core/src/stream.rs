@@ -0,0 +1,550 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+use crate::affinity::pin_current_thread;
+use crate::buffer_pool::BufferPool;
+use crate::generator::{GeneratorAes128, GeneratorAes256, GeneratorBlake3,
+                       GeneratorChaCha8, GeneratorChaCha12, GeneratorChaCha20, GeneratorCRC,
+                       GeneratorCRC32C, GeneratorPattern, GeneratorShake256, NextRandom};
+use crate::kdf::DtKdf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::thread;
+
+/// Stream algorithm type.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DtStreamType {
+    CHACHA8,
+    CHACHA12,
+    CHACHA20,
+    AES128,
+    AES256,
+    BLAKE3,
+    SHAKE256,
+    CRC,
+    CRC32C,
+    /// Fixed byte pattern. The seed is the pattern.
+    PATTERN,
+}
+
+impl DtStreamType {
+    /// All algorithms.
+    pub const ALL: [DtStreamType; 10] = [
+        DtStreamType::CHACHA8,
+        DtStreamType::CHACHA12,
+        DtStreamType::CHACHA20,
+        DtStreamType::AES128,
+        DtStreamType::AES256,
+        DtStreamType::BLAKE3,
+        DtStreamType::SHAKE256,
+        DtStreamType::CRC,
+        DtStreamType::CRC32C,
+        DtStreamType::PATTERN,
+    ];
+
+    /// Get the size of the generator output, in bytes.
+    /// The chunk size must be a multiple of this size.
+    pub fn base_size(&self) -> usize {
+        match self {
+            DtStreamType::CHACHA8 => GeneratorChaCha8::BASE_SIZE,
+            DtStreamType::CHACHA12 => GeneratorChaCha12::BASE_SIZE,
+            DtStreamType::CHACHA20 => GeneratorChaCha20::BASE_SIZE,
+            DtStreamType::AES128 => GeneratorAes128::BASE_SIZE,
+            DtStreamType::AES256 => GeneratorAes256::BASE_SIZE,
+            DtStreamType::BLAKE3 => GeneratorBlake3::BASE_SIZE,
+            DtStreamType::SHAKE256 => GeneratorShake256::BASE_SIZE,
+            DtStreamType::CRC => GeneratorCRC::BASE_SIZE,
+            DtStreamType::CRC32C => GeneratorCRC32C::BASE_SIZE,
+            DtStreamType::PATTERN => GeneratorPattern::BASE_SIZE,
+        }
+    }
+
+    /// Get the default chunk factor of the generator.
+    pub fn default_chunk_factor(&self) -> usize {
+        match self {
+            DtStreamType::CHACHA8 => GeneratorChaCha8::CHUNK_FACTOR,
+            DtStreamType::CHACHA12 => GeneratorChaCha12::CHUNK_FACTOR,
+            DtStreamType::CHACHA20 => GeneratorChaCha20::CHUNK_FACTOR,
+            DtStreamType::AES128 => GeneratorAes128::CHUNK_FACTOR,
+            DtStreamType::AES256 => GeneratorAes256::CHUNK_FACTOR,
+            DtStreamType::BLAKE3 => GeneratorBlake3::CHUNK_FACTOR,
+            DtStreamType::SHAKE256 => GeneratorShake256::CHUNK_FACTOR,
+            DtStreamType::CRC => GeneratorCRC::CHUNK_FACTOR,
+            DtStreamType::CRC32C => GeneratorCRC32C::CHUNK_FACTOR,
+            DtStreamType::PATTERN => GeneratorPattern::CHUNK_FACTOR,
+        }
+    }
+
+    /// Get the default chunk size, in bytes.
+    pub fn default_chunk_size(&self) -> usize {
+        self.base_size() * self.default_chunk_factor()
+    }
+}
+
+impl std::str::FromStr for DtStreamType {
+    type Err = ah::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "CHACHA8" => Ok(DtStreamType::CHACHA8),
+            "CHACHA12" => Ok(DtStreamType::CHACHA12),
+            "CHACHA20" => Ok(DtStreamType::CHACHA20),
+            "AES128" => Ok(DtStreamType::AES128),
+            "AES256" => Ok(DtStreamType::AES256),
+            "BLAKE3" => Ok(DtStreamType::BLAKE3),
+            "SHAKE256" => Ok(DtStreamType::SHAKE256),
+            "CRC" => Ok(DtStreamType::CRC),
+            "CRC32C" => Ok(DtStreamType::CRC32C),
+            "PATTERN" => Ok(DtStreamType::PATTERN),
+            x => Err(ah::format_err!("Unknown algorithm: {}", x)),
+        }
+    }
+}
+
+impl std::fmt::Display for DtStreamType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            DtStreamType::CHACHA8 => "CHACHA8",
+            DtStreamType::CHACHA12 => "CHACHA12",
+            DtStreamType::CHACHA20 => "CHACHA20",
+            DtStreamType::AES128 => "AES128",
+            DtStreamType::AES256 => "AES256",
+            DtStreamType::BLAKE3 => "BLAKE3",
+            DtStreamType::SHAKE256 => "SHAKE256",
+            DtStreamType::CRC => "CRC",
+            DtStreamType::CRC32C => "CRC32C",
+            DtStreamType::PATTERN => "PATTERN",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Data chunk that contains the computed PRNG data.
+pub struct DtStreamChunk {
+    #[allow(dead_code)]
+    pub index: u64,
+    pub data: Vec<u8>,
+}
+
+/// Derive the generator key from the seed.
+/// All threads compute parts of the same stream.
+/// Therefore, the key does not depend on the number of threads.
+/// The PATTERN algorithm uses the seed as-is.
+pub fn stream_key(stype: DtStreamType, seed: &[u8], kdf: &DtKdf) -> ah::Result<Vec<u8>> {
+    match stype {
+        DtStreamType::PATTERN => Ok(seed.to_vec()),
+        _ => kdf.derive(seed),
+    }
+}
+
+/// Thread worker function, that computes the chunks.
+/// The first chunk starts at byte_offset of the pseudo random stream.
+/// The following chunks start stride bytes after the previous chunk.
+#[allow(clippy::too_many_arguments)]
+fn thread_worker(stype:         DtStreamType,
+                 chunk_factor:  usize,
+                 key:           Vec<u8>,
+                 thread_id:     u32,
+                 cpu:           Option<usize>,
+                 byte_offset:   u64,
+                 stride:        u64,
+                 abort:         Arc<AtomicBool>,
+                 error:         Arc<AtomicBool>,
+                 pool:          BufferPool,
+                 tx:            SyncSender<DtStreamChunk>) {
+    if let Some(cpu) = cpu {
+        if let Err(e) = pin_current_thread(cpu) {
+            eprintln!("WARNING: Failed to pin generator thread {} to CPU {}: {}", thread_id, cpu, e);
+        }
+    }
+
+    // Construct the generator algorithm.
+    let mut generator: Box<dyn NextRandom> = match stype {
+        DtStreamType::CHACHA8 => Box::new(GeneratorChaCha8::new(&key)),
+        DtStreamType::CHACHA12 => Box::new(GeneratorChaCha12::new(&key)),
+        DtStreamType::CHACHA20 => Box::new(GeneratorChaCha20::new(&key)),
+        DtStreamType::AES128 => Box::new(GeneratorAes128::new(&key)),
+        DtStreamType::AES256 => Box::new(GeneratorAes256::new(&key)),
+        DtStreamType::BLAKE3 => Box::new(GeneratorBlake3::new(&key)),
+        DtStreamType::SHAKE256 => Box::new(GeneratorShake256::new(&key)),
+        DtStreamType::CRC => Box::new(GeneratorCRC::new(&key)),
+        DtStreamType::CRC32C => Box::new(GeneratorCRC32C::new(&key)),
+        DtStreamType::PATTERN => Box::new(GeneratorPattern::new(&key)),
+    };
+    drop(key);
+
+    // Run the generator work loop.
+    let mut index = 0;
+    let mut byte_offset = byte_offset;
+    while !abort.load(Ordering::Relaxed) {
+        // Seek the generator to the start of the next chunk.
+        if let Err(e) = generator.seek(byte_offset) {
+            eprintln!("ERROR in generator thread {}: {}", thread_id, e);
+            error.store(true, Ordering::Release);
+            return;
+        }
+        byte_offset += stride;
+
+        // Get the next chunk from the generator.
+        let mut data = pool.get(generator.get_base_size() * chunk_factor);
+        generator.next_into(&mut data);
+
+        let chunk = DtStreamChunk {
+            index,
+            data,
+        };
+        index += 1;
+
+        // Send the chunk to the main thread.
+        // This blocks, if the chunk buffer is full.
+        // Sending fails, if the receiver has been dropped. -> Stop.
+        if tx.send(chunk).is_err() {
+            break;
+        }
+    }
+}
+
+/// PRNG stream.
+pub struct DtStream {
+    stype:          DtStreamType,
+    key:            Vec<u8>,
+    thread_id:      u32,
+    cpu:            Option<usize>,
+    num_threads:    usize,
+    chunk_factor:   usize,
+    rx:             Option<Receiver<DtStreamChunk>>,
+    is_active:      bool,
+    thread_join:    Option<thread::JoinHandle<()>>,
+    abort:          Arc<AtomicBool>,
+    error:          Arc<AtomicBool>,
+    pool:           BufferPool,
+}
+
+impl DtStream {
+    /// Maximum number of chunks that the thread will compute in advance.
+    const LEVEL_THRES: usize        = 8;
+
+    /// Create a new stream for thread_id of num_threads.
+    /// Each thread computes every num_threads'th chunk of the pseudo random stream.
+    /// The key is the result of stream_key().
+    /// The chunk buffers are taken from pool.
+    pub fn new(stype:           DtStreamType,
+               key:             Vec<u8>,
+               thread_id:       u32,
+               num_threads:     usize,
+               chunk_factor:    usize,
+               pool:            BufferPool) -> DtStream {
+        assert!(num_threads > 0);
+        assert!(chunk_factor > 0);
+
+        let abort = Arc::new(AtomicBool::new(false));
+        let error = Arc::new(AtomicBool::new(false));
+
+        DtStream {
+            stype,
+            key,
+            thread_id,
+            cpu: None,
+            num_threads,
+            chunk_factor,
+            rx: None,
+            is_active: false,
+            thread_join: None,
+            abort,
+            error,
+            pool,
+        }
+    }
+
+    /// Pin the worker thread to the CPU.
+    /// This takes effect the next time the thread is started.
+    pub fn set_cpu(&mut self, cpu: usize) {
+        self.cpu = Some(cpu);
+    }
+
+    /// Stop the worker thread.
+    /// Does nothing, if the thread is not running.
+    fn stop(&mut self) {
+        self.is_active = false;
+        self.abort.store(true, Ordering::Release);
+        // Dropping the receiver wakes up the thread, if it is blocked in send.
+        self.rx = None;
+        if let Some(thread_join) = self.thread_join.take() {
+            thread_join.join().unwrap();
+        }
+        self.abort.store(false, Ordering::Release);
+    }
+
+    /// Spawn the worker thread.
+    /// Panics, if the thread is already running.
+    fn start(&mut self, byte_offset: u64) {
+        assert!(!self.is_active);
+        assert!(self.thread_join.is_none());
+
+        // Initialize thread communication
+        self.abort.store(false, Ordering::Release);
+        self.error.store(false, Ordering::Release);
+        let (tx, rx) = sync_channel(DtStream::LEVEL_THRES);
+        self.rx = Some(rx);
+
+        // Spawn the worker thread.
+        let thread_stype = self.stype;
+        let thread_chunk_factor = self.chunk_factor;
+        let thread_key = self.key.to_vec();
+        let thread_id = self.thread_id;
+        let thread_cpu = self.cpu;
+        let thread_byte_offset = byte_offset;
+        let thread_stride = (self.get_chunk_size() * self.num_threads) as u64;
+        let thread_abort = Arc::clone(&self.abort);
+        let thread_error = Arc::clone(&self.error);
+        let thread_pool = self.pool.clone();
+        self.thread_join = Some(thread::spawn(move || {
+            thread_worker(thread_stype,
+                          thread_chunk_factor,
+                          thread_key,
+                          thread_id,
+                          thread_cpu,
+                          thread_byte_offset,
+                          thread_stride,
+                          thread_abort,
+                          thread_error,
+                          thread_pool,
+                          tx);
+        }));
+        self.is_active = true;
+    }
+
+    /// Check if the thread exited due to an error.
+    #[inline]
+    fn is_thread_error(&self) -> bool {
+        self.error.load(Ordering::Relaxed)
+    }
+
+    /// Activate the worker thread.
+    /// byte_offset is the position of the first chunk in the pseudo random stream.
+    pub fn activate(&mut self, byte_offset: u64) -> ah::Result<()> {
+        self.stop();
+        self.start(byte_offset);
+
+        Ok(())
+    }
+
+    /// Check if the worker thread is currently running.
+    #[inline]
+    pub fn is_active(&self) -> bool {
+        self.is_active
+    }
+
+    /// Get the size of the chunk returned by get_chunk(), in bytes.
+    pub fn get_chunk_size(&self) -> usize {
+        self.stype.base_size() * self.chunk_factor
+    }
+
+    /// Wait for the next chunk from the thread.
+    /// Blocks until the thread computed the chunk.
+    pub fn wait_chunk(&mut self) -> ah::Result<DtStreamChunk> {
+        if !self.is_active() {
+            return Err(ah::format_err!("Generator stream is not active."));
+        }
+        match self.rx.as_ref().map(|rx| rx.recv()) {
+            Some(Ok(chunk)) => Ok(chunk),
+            _ if self.is_thread_error() => {
+                Err(ah::format_err!("Generator stream thread aborted with an error."))
+            },
+            _ => Err(ah::format_err!("Generator stream thread exited unexpectedly.")),
+        }
+    }
+}
+
+impl Drop for DtStream {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(algorithm: DtStreamType) -> Vec<u8> {
+        stream_key(algorithm, &[1,2,3], &DtKdf::default()).unwrap()
+    }
+
+    fn run_base_test(algorithm: DtStreamType) {
+        println!("stream base test");
+        let mut s = DtStream::new(algorithm, key(algorithm), 0, 1,
+                                  algorithm.default_chunk_factor(), BufferPool::new(1));
+        s.activate(0).unwrap();
+        assert!(s.is_active());
+
+        assert_eq!(s.get_chunk_size(), algorithm.base_size() * s.chunk_factor);
+        assert!(s.get_chunk_size() > 0);
+        assert!(algorithm.base_size() > 0);
+        assert!(s.chunk_factor > 0);
+
+        let mut results_first = vec![];
+        for count in 0..5 {
+            let chunk = s.wait_chunk().unwrap();
+            println!("{}: index={} data[0]={}",
+                     count, chunk.index, chunk.data[0]);
+            results_first.push(chunk.data[0]);
+            assert_eq!(chunk.index, count);
+        }
+        match algorithm {
+            DtStreamType::CHACHA8 => {
+                assert_eq!(results_first, vec![66, 209, 254, 224, 203]);
+            }
+            DtStreamType::CHACHA12 => {
+                assert_eq!(results_first, vec![200, 202, 12, 60, 234]);
+            }
+            DtStreamType::CHACHA20 => {
+                assert_eq!(results_first, vec![206, 236, 87, 55, 170]);
+            }
+            DtStreamType::AES128 => {
+                assert_eq!(results_first, vec![245, 111, 10, 107, 133]);
+            }
+            DtStreamType::AES256 => {
+                assert_eq!(results_first, vec![177, 44, 111, 110, 155]);
+            }
+            DtStreamType::BLAKE3 => {
+                assert_eq!(results_first, vec![182, 115, 10, 177, 238]);
+            }
+            DtStreamType::SHAKE256 => {
+                assert_eq!(results_first, vec![124, 3, 157, 156, 219]);
+            }
+            DtStreamType::CRC => {
+                assert_eq!(results_first, vec![108, 99, 114, 196, 213]);
+            }
+            DtStreamType::CRC32C => {
+                assert_eq!(results_first, vec![5, 6, 242, 31, 235]);
+            }
+            DtStreamType::PATTERN => {
+                assert_eq!(results_first, vec![1, 1, 1, 1, 1]);
+            }
+        }
+    }
+
+    fn run_offset_test(algorithm: DtStreamType) {
+        println!("stream offset test");
+        // a: start at chunk offset 0
+        let mut a = DtStream::new(algorithm, key(algorithm), 0, 1,
+                                  algorithm.default_chunk_factor(), BufferPool::new(1));
+        a.activate(0).unwrap();
+
+        // b: start at chunk offset 1
+        let mut b = DtStream::new(algorithm, key(algorithm), 0, 1,
+                                  algorithm.default_chunk_factor(), BufferPool::new(1));
+        b.activate(a.get_chunk_size() as u64).unwrap();
+
+        let achunk = a.wait_chunk().unwrap();
+        let bchunk = b.wait_chunk().unwrap();
+        assert!(achunk.data != bchunk.data);
+        let achunk = a.wait_chunk().unwrap();
+        assert!(achunk.data == bchunk.data);
+    }
+
+    #[test]
+    fn test_stream_type() {
+        for alg in &[DtStreamType::CHACHA8, DtStreamType::CHACHA12,
+                     DtStreamType::CHACHA20, DtStreamType::AES128,
+                     DtStreamType::AES256, DtStreamType::BLAKE3,
+                     DtStreamType::SHAKE256, DtStreamType::CRC,
+                     DtStreamType::CRC32C, DtStreamType::PATTERN] {
+            assert_eq!(alg.to_string().parse::<DtStreamType>().unwrap(), *alg);
+            assert_eq!(alg.to_string().to_lowercase().parse::<DtStreamType>().unwrap(), *alg);
+            assert_eq!(alg.default_chunk_size() % alg.base_size(), 0);
+        }
+        assert!("CHACHA".parse::<DtStreamType>().is_err());
+    }
+
+    #[test]
+    fn test_chacha8() {
+        let alg = DtStreamType::CHACHA8;
+        run_base_test(alg);
+        run_offset_test(alg);
+    }
+
+    #[test]
+    fn test_chacha12() {
+        let alg = DtStreamType::CHACHA12;
+        run_base_test(alg);
+        run_offset_test(alg);
+    }
+
+    #[test]
+    fn test_chacha20() {
+        let alg = DtStreamType::CHACHA20;
+        run_base_test(alg);
+        run_offset_test(alg);
+    }
+
+    #[test]
+    fn test_aes128() {
+        let alg = DtStreamType::AES128;
+        run_base_test(alg);
+        run_offset_test(alg);
+    }
+
+    #[test]
+    fn test_aes256() {
+        let alg = DtStreamType::AES256;
+        run_base_test(alg);
+        run_offset_test(alg);
+    }
+
+    #[test]
+    fn test_blake3() {
+        let alg = DtStreamType::BLAKE3;
+        run_base_test(alg);
+        run_offset_test(alg);
+    }
+
+    #[test]
+    fn test_shake256() {
+        let alg = DtStreamType::SHAKE256;
+        run_base_test(alg);
+        run_offset_test(alg);
+    }
+
+    #[test]
+    fn test_crc() {
+        let alg = DtStreamType::CRC;
+        run_base_test(alg);
+        run_offset_test(alg);
+    }
+
+    #[test]
+    fn test_crc32c() {
+        let alg = DtStreamType::CRC32C;
+        run_base_test(alg);
+        run_offset_test(alg);
+    }
+
+    #[test]
+    fn test_pattern() {
+        let alg = DtStreamType::PATTERN;
+        run_base_test(alg);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
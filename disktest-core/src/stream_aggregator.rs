@@ -20,10 +20,9 @@
 //
 
 use anyhow as ah;
+use crate::buffer_pool::BufferPool;
 use crate::stream::DtStream;
 use crate::util::prettybytes;
-use std::thread;
-use std::time::Duration;
 
 pub use crate::stream::DtStreamType;
 pub use crate::stream::DtStreamChunk;
@@ -33,19 +32,23 @@ pub struct DtStreamAgg {
     streams:        Vec<DtStream>,
     current_index:  usize,
     is_active:      bool,
+    pool:           BufferPool,
 }
 
 impl DtStreamAgg {
-    pub fn new(stype:       DtStreamType,
-               seed:        Vec<u8>,
-               num_threads: usize) -> DtStreamAgg {
+    pub fn new(stype:           DtStreamType,
+               key:             Vec<u8>,
+               num_threads:     usize,
+               chunk_factor:    usize) -> DtStreamAgg {
 
         assert!(num_threads > 0);
-        assert!(num_threads <= std::u16::MAX as usize + 1);
+        assert!(num_threads <= u16::MAX as usize + 1);
 
+        let pool = BufferPool::new(num_threads * 2);
         let mut streams = Vec::with_capacity(num_threads);
         for i in 0..num_threads {
-            streams.push(DtStream::new(stype, seed.to_vec(), i as u32));
+            streams.push(DtStream::new(stype, key.to_vec(), i as u32,
+                                      num_threads, chunk_factor, pool.clone()));
         }
 
         DtStreamAgg {
@@ -53,6 +56,14 @@ impl DtStreamAgg {
             streams,
             current_index: 0,
             is_active: false,
+            pool,
+        }
+    }
+
+    /// Pin the worker threads round-robin to the CPUs.
+    pub fn set_cpus(&mut self, cpus: &[usize]) {
+        for (i, stream) in self.streams.iter_mut().enumerate() {
+            stream.set_cpu(cpus[i % cpus.len()]);
         }
     }
 
@@ -61,7 +72,7 @@ impl DtStreamAgg {
         let chunk_size = self.get_chunk_size() as u64;
 
         // Calculate the stream index from the byte_offset.
-        if byte_offset % chunk_size != 0 {
+        if !byte_offset.is_multiple_of(chunk_size) {
             let good_offset = byte_offset - (byte_offset % chunk_size);
             eprintln!("WARNING: The seek offset {} (= {}) is not a multiple \
                 of the chunk size {} bytes (= {}). \n\
@@ -77,15 +88,18 @@ impl DtStreamAgg {
         let chunk_index = byte_offset / chunk_size;
         self.current_index = (chunk_index % self.num_threads as u64) as usize;
 
-        // Calculate the per stream byte offset and activate all streams.
+        // Calculate the offset of the first chunk of each stream and activate all streams.
+        // Stream i computes the chunks i, i + num_threads, i + 2 * num_threads, ...
+        let num_threads = self.num_threads as u64;
         for (i, stream) in self.streams.iter_mut().enumerate() {
-            let iteration = chunk_index / self.num_threads as u64;
+            let iteration = chunk_index / num_threads;
 
-            let thread_offset = if i < self.current_index {
-                (iteration + 1) * chunk_size
+            let thread_chunk = if i < self.current_index {
+                (iteration + 1) * num_threads + i as u64
             } else {
-                iteration * chunk_size
+                iteration * num_threads + i as u64
             };
+            let thread_offset = thread_chunk * chunk_size;
 
             stream.activate(thread_offset)?;
         }
@@ -103,43 +117,43 @@ impl DtStreamAgg {
         self.streams[0].get_chunk_size()
     }
 
-    #[inline]
-    fn get_chunk(&mut self) -> ah::Result<Option<DtStreamChunk>> {
-        if self.is_active() {
-            if let Some(chunk) = self.streams[self.current_index].get_chunk()? {
-                self.current_index = (self.current_index + 1) % self.num_threads;
-                Ok(Some(chunk))
-            } else {
-                Ok(None)
-            }
-        } else {
-            Ok(None)
-        }
-    }
-
+    /// Wait for the next chunk of the pseudo random stream.
+    /// Blocks until the chunk is available.
     pub fn wait_chunk(&mut self) -> ah::Result<DtStreamChunk> {
         if self.is_active() {
-            loop {
-                if let Some(chunk) = self.get_chunk()? {
-                    break Ok(chunk);
-                }
-                thread::sleep(Duration::from_millis(1));
-            }
+            let chunk = self.streams[self.current_index].wait_chunk()?;
+            self.current_index = (self.current_index + 1) % self.num_threads;
+            Ok(chunk)
         } else {
             panic!("wait_chunk() called, but stream aggregator is stopped.");
         }
     }
+
+    /// Return a used chunk, so that its buffer can be reused for the next chunks.
+    pub fn recycle(&self, chunk: DtStreamChunk) {
+        self.pool.put(chunk.data);
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::generator::{GeneratorChaCha8, GeneratorChaCha12, GeneratorChaCha20, GeneratorCRC};
+    use crate::generator::{GeneratorAes128, GeneratorAes256, GeneratorBlake3,
+                           GeneratorChaCha8, GeneratorChaCha12, GeneratorChaCha20, GeneratorCRC,
+                           GeneratorCRC32C, GeneratorPattern, GeneratorShake256};
+    use crate::kdf::DtKdf;
+    use crate::stream::stream_key;
     use super::*;
 
+    fn key(algorithm: DtStreamType) -> Vec<u8> {
+        stream_key(algorithm, &[1,2,3], &DtKdf::default()).unwrap()
+    }
+
+    #[allow(clippy::bool_assert_comparison)]
     fn run_base_test(algorithm: DtStreamType, gen_base_size: usize, chunk_factor: usize) {
         println!("stream aggregator base test");
         let num_threads = 2;
-        let mut agg = DtStreamAgg::new(algorithm, vec![1,2,3], num_threads);
+        let mut agg = DtStreamAgg::new(algorithm, key(algorithm), num_threads,
+                                       algorithm.default_chunk_factor());
         agg.activate(0).unwrap();
         assert_eq!(agg.is_active(), true);
 
@@ -207,10 +221,12 @@ mod tests {
         let num_threads = 2;
 
         for offset in 0..5 {
-            let mut a = DtStreamAgg::new(algorithm, vec![1,2,3], num_threads);
+            let mut a = DtStreamAgg::new(algorithm, key(algorithm), num_threads,
+                                       algorithm.default_chunk_factor());
             a.activate(0).unwrap();
 
-            let mut b = DtStreamAgg::new(algorithm, vec![1,2,3], num_threads);
+            let mut b = DtStreamAgg::new(algorithm, key(algorithm), num_threads,
+                                       algorithm.default_chunk_factor());
             b.activate(a.get_chunk_size() as u64 * offset).unwrap();
 
             // Until offset the chunks must not be equal.
@@ -226,6 +242,33 @@ mod tests {
         }
     }
 
+    fn run_layout_test(algorithm: DtStreamType) {
+        println!("stream aggregator layout test");
+        let base_size = algorithm.base_size();
+        let read = |num_threads, chunk_factor, offset, len| {
+            let mut agg = DtStreamAgg::new(algorithm, key(algorithm), num_threads, chunk_factor);
+            agg.activate(offset).unwrap();
+            let mut data = vec![];
+            while data.len() < len {
+                let chunk = agg.wait_chunk().unwrap();
+                data.extend_from_slice(&chunk.data);
+                agg.recycle(chunk);
+            }
+            data.truncate(len);
+            data
+        };
+
+        // The data must not depend on the number of threads and the chunk size.
+        let len = base_size * 4 * 6;
+        let expected = read(1, 4, 0, len);
+        assert_eq!(read(3, 4, 0, len), expected);
+        assert_eq!(read(2, 2, 0, len), expected);
+        assert_eq!(read(4, 1, 0, len), expected);
+        let offset = base_size * 4 * 2;
+        assert_eq!(read(3, 4, offset as u64, len - offset), expected[offset..]);
+        assert_eq!(read(2, 2, offset as u64, len - offset), expected[offset..]);
+    }
+
     #[test]
     fn test_chacha8() {
         let alg = DtStreamType::CHACHA8;
@@ -233,6 +276,7 @@ mod tests {
                       GeneratorChaCha8::BASE_SIZE,
                       GeneratorChaCha8::CHUNK_FACTOR);
         run_offset_test(alg);
+        run_layout_test(alg);
     }
 
     #[test]
@@ -242,6 +286,7 @@ mod tests {
                       GeneratorChaCha12::BASE_SIZE,
                       GeneratorChaCha12::CHUNK_FACTOR);
         run_offset_test(alg);
+        run_layout_test(alg);
     }
 
     #[test]
@@ -251,6 +296,47 @@ mod tests {
                       GeneratorChaCha20::BASE_SIZE,
                       GeneratorChaCha20::CHUNK_FACTOR);
         run_offset_test(alg);
+        run_layout_test(alg);
+    }
+
+    #[test]
+    fn test_aes128() {
+        let alg = DtStreamType::AES128;
+        run_base_test(alg,
+                      GeneratorAes128::BASE_SIZE,
+                      GeneratorAes128::CHUNK_FACTOR);
+        run_offset_test(alg);
+        run_layout_test(alg);
+    }
+
+    #[test]
+    fn test_aes256() {
+        let alg = DtStreamType::AES256;
+        run_base_test(alg,
+                      GeneratorAes256::BASE_SIZE,
+                      GeneratorAes256::CHUNK_FACTOR);
+        run_offset_test(alg);
+        run_layout_test(alg);
+    }
+
+    #[test]
+    fn test_blake3() {
+        let alg = DtStreamType::BLAKE3;
+        run_base_test(alg,
+                      GeneratorBlake3::BASE_SIZE,
+                      GeneratorBlake3::CHUNK_FACTOR);
+        run_offset_test(alg);
+        run_layout_test(alg);
+    }
+
+    #[test]
+    fn test_shake256() {
+        let alg = DtStreamType::SHAKE256;
+        run_base_test(alg,
+                      GeneratorShake256::BASE_SIZE,
+                      GeneratorShake256::CHUNK_FACTOR);
+        run_offset_test(alg);
+        run_layout_test(alg);
     }
 
     #[test]
@@ -260,6 +346,26 @@ mod tests {
                       GeneratorCRC::BASE_SIZE,
                       GeneratorCRC::CHUNK_FACTOR);
         run_offset_test(alg);
+        run_layout_test(alg);
+    }
+
+    #[test]
+    fn test_crc32c() {
+        let alg = DtStreamType::CRC32C;
+        run_base_test(alg,
+                      GeneratorCRC32C::BASE_SIZE,
+                      GeneratorCRC32C::CHUNK_FACTOR);
+        run_offset_test(alg);
+        run_layout_test(alg);
+    }
+
+    #[test]
+    fn test_pattern() {
+        // The base and offset tests do not apply, because the data is not random.
+        let alg = DtStreamType::PATTERN;
+        assert_eq!(alg.default_chunk_size(),
+                   GeneratorPattern::BASE_SIZE * GeneratorPattern::CHUNK_FACTOR);
+        run_layout_test(alg);
     }
 }
 
@@ -0,0 +1,81 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use std::time::{Duration, Instant};
+
+/// Amount of data that may be transferred at once without waiting,
+/// as duration at the maximum rate.
+const BURST_TIME: f64 = 0.1;
+
+/// Token bucket that limits the throughput to a maximum rate.
+pub struct Throttle {
+    /// Maximum rate, in bytes per second.
+    rate:       f64,
+    /// Available tokens, in bytes. Negative, if more than the budget has been consumed.
+    tokens:     f64,
+    last:       Instant,
+}
+
+impl Throttle {
+    /// Create a new throttle for the maximum rate in bytes per second.
+    pub fn new(rate: u64) -> Throttle {
+        Throttle {
+            rate:       rate.max(1) as f64,
+            tokens:     0.0,
+            last:       Instant::now(),
+        }
+    }
+
+    /// Consume count bytes at the time now.
+    /// Returns the time to wait before the next transfer.
+    pub fn consume(&mut self, count: u64, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = now;
+        let burst = self.rate * BURST_TIME;
+        self.tokens = (self.tokens + elapsed * self.rate).min(burst) - count as f64;
+        if self.tokens < 0.0 {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        } else {
+            Duration::from_secs(0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttle() {
+        let begin = Instant::now();
+        let mut t = Throttle::new(1000);
+        assert_eq!(t.consume(500, begin), Duration::from_millis(500));
+        // The debt is paid back after waiting.
+        let now = begin + Duration::from_millis(500);
+        assert_eq!(t.consume(100, now), Duration::from_millis(100));
+        // A long pause only gives the burst budget.
+        let now = now + Duration::from_secs(10);
+        assert_eq!(t.consume(100, now), Duration::from_secs(0));
+        assert_eq!(t.consume(100, now), Duration::from_millis(100));
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
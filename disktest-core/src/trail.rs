@@ -0,0 +1,77 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Position of a running write that a concurrent verification trails.
+///
+/// The writing Disktest instance publishes the end of the written data
+/// (see `Disktest::set_trail()`) and the verifying instance only reads
+/// data that has been written before (see `Disktest::follow_trail()`).
+#[derive(Clone, Debug, Default)]
+pub struct DtTrail {
+    position:       Arc<AtomicU64>,
+    finished:       Arc<AtomicBool>,
+}
+
+impl DtTrail {
+    /// Create a new trail at position 0.
+    pub fn new() -> DtTrail {
+        Default::default()
+    }
+
+    /// Publish the absolute end position of the written data.
+    pub fn advance(&self, position: u64) {
+        self.position.store(position, Ordering::Release);
+    }
+
+    /// Mark the write as finished. No more data will be written.
+    /// This must also be called, if the write failed.
+    pub fn finish(&self) {
+        self.finished.store(true, Ordering::Release);
+    }
+
+    /// Get the end position of the written data and whether the write is finished.
+    pub fn get(&self) -> (u64, bool) {
+        // Load the flag first. The position is final, if the write is finished.
+        let finished = self.finished.load(Ordering::Acquire);
+        (self.position.load(Ordering::Acquire), finished)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trail() {
+        let trail = DtTrail::new();
+        assert_eq!(trail.get(), (0, false));
+        let other = trail.clone();
+        other.advance(4096);
+        assert_eq!(trail.get(), (4096, false));
+        other.finish();
+        assert_eq!(trail.get(), (4096, true));
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -79,7 +79,7 @@ pub fn prettybytes(count: u64, binary: bool, decimal: bool) -> String {
                 let len = ret.len();
                 if len > 0 { ret.push_str(" ("); }
                 ret.push_str(&dec);
-                if len > 0 { ret.push_str(")"); }
+                if len > 0 { ret.push(')'); }
             }
         }
     }
@@ -134,10 +134,31 @@ pub fn parsebytes(s: &str) -> Result<u64, <u64 as std::str::FromStr>::Err> {
     }
 }
 
+/// Parse a percentage (e.g. 50% or 12.5%).
+/// Returns None, if the string is no percentage.
+pub fn parsepercent(s: &str) -> Option<Result<f64, String>> {
+    let s = s.trim().strip_suffix('%')?;
+    Some(match s.trim().parse::<f64>() {
+        Ok(x) if (0.0..=100.0).contains(&x) => Ok(x),
+        Ok(_) => Err("The percentage must be between 0% and 100%.".to_string()),
+        Err(e) => Err(e.to_string()),
+    })
+}
+
+/// Get the percentage of the size in bytes.
+/// The result is rounded down to a multiple of 4 kiB, except for 100%.
+pub fn percent_of(percent: f64, size: u64) -> u64 {
+    if percent >= 100.0 {
+        return size;
+    }
+    let bytes = ((size as f64 * percent / 100.0) as u64).min(size);
+    bytes - bytes % (4 * KIB)
+}
+
 /// Fold a byte vector into a smaller byte vector using XOR operation.
 /// If output_size is bigger than input.len(), the trailing bytes
 /// will be filled with zeros.
-pub fn fold(input: &Vec<u8>, output_size: usize) -> Vec<u8> {
+pub fn fold(input: &[u8], output_size: usize) -> Vec<u8> {
     let mut output = vec![0; output_size];
 
     if output_size > 0 {
@@ -231,6 +252,25 @@ mod tests {
     }
 
     #[test]
+    fn test_parsepercent() {
+        assert_eq!(parsepercent("42"), None);
+        assert_eq!(parsepercent("42k"), None);
+        assert_eq!(parsepercent("50%"), Some(Ok(50.0)));
+        assert_eq!(parsepercent(" 12.5 % "), Some(Ok(12.5)));
+        assert_eq!(parsepercent("0%"), Some(Ok(0.0)));
+        assert_eq!(parsepercent("100%"), Some(Ok(100.0)));
+        assert!(parsepercent("101%").unwrap().is_err());
+        assert!(parsepercent("-1%").unwrap().is_err());
+        assert!(parsepercent("x%").unwrap().is_err());
+
+        assert_eq!(percent_of(50.0, 1000 * MIB), 500 * MIB);
+        assert_eq!(percent_of(10.0, 100_000), 8192);
+        assert_eq!(percent_of(0.0, 100_000), 0);
+        assert_eq!(percent_of(100.0, 100_000), 100_000);
+    }
+
+    #[test]
+    #[allow(clippy::useless_vec)]
     fn test_fold() {
         assert_eq!(fold(&vec![0x55, 0x55, 0xAA, 0xAA], 2),
                    vec![0xFF, 0xFF]);
@@ -247,7 +287,7 @@ mod tests {
         assert_eq!(fold(&vec![0x12, 0x34, 0x56, 0x78], 6),
                    vec![0x12, 0x34, 0x56, 0x78, 0x00, 0x00]);
         assert_eq!(fold(&vec![0x12, 0x34, 0x56, 0x78], 0),
-                   vec![]);
+                   Vec::<u8>::new());
     }
 }
 
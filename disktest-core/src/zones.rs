@@ -0,0 +1,146 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+
+use std::time::Duration;
+
+/// Throughput measurement of one zone of the device.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DtZone {
+    /// Absolute byte offset of the zone on the device.
+    pub offset:     u64,
+    /// Length of the zone, in bytes.
+    pub length:     u64,
+    /// Number of bytes that have been transferred in the zone.
+    pub bytes:      u64,
+    /// The time that the read or write calls in the zone took.
+    pub time:       Duration,
+}
+
+impl DtZone {
+    /// Get the average throughput of the zone, in bytes per second.
+    pub fn rate(&self) -> u64 {
+        let secs = self.time.as_secs_f64();
+        if secs > 0.0 { (self.bytes as f64 / secs) as u64 } else { 0 }
+    }
+}
+
+/// Throughput measurement of a device area that is divided into zones of equal size.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DtZones {
+    zones:      Vec<DtZone>,
+}
+
+impl DtZones {
+    /// Divide the area of length bytes at the absolute offset start into count zones.
+    /// The number of zones is limited to the number of bytes.
+    pub fn new(start: u64, length: u64, count: usize) -> DtZones {
+        let count = (count as u64).min(length).max(1);
+        let zones = (0..count).map(|i| {
+            let begin = (length as u128 * i as u128 / count as u128) as u64;
+            let end = (length as u128 * (i + 1) as u128 / count as u128) as u64;
+            DtZone {
+                offset: start + begin,
+                length: end - begin,
+                bytes:  0,
+                time:   Duration::from_secs(0),
+            }
+        }).collect();
+        DtZones {
+            zones,
+        }
+    }
+
+    /// Record the transfer of bytes at the absolute offset that took the given time.
+    /// A transfer that crosses zone boundaries is split proportionally.
+    pub fn record(&mut self, offset: u64, bytes: u64, time: Duration) {
+        if bytes == 0 {
+            return;
+        }
+        let end = offset + bytes;
+        let first = self.zones.partition_point(|z| z.offset + z.length <= offset);
+        for zone in self.zones[first..].iter_mut() {
+            if zone.offset >= end {
+                break;
+            }
+            let overlap = end.min(zone.offset + zone.length) - offset.max(zone.offset);
+            zone.bytes += overlap;
+            zone.time += time.mul_f64(overlap as f64 / bytes as f64);
+        }
+    }
+
+    /// Add the measurements of another run over the same zones.
+    /// Returns false and does nothing, if the zones are different.
+    pub fn merge(&mut self, other: &DtZones) -> bool {
+        let same = self.zones.len() == other.zones.len() &&
+                   self.zones.iter().zip(&other.zones)
+                       .all(|(a, b)| a.offset == b.offset && a.length == b.length);
+        if same {
+            for (a, b) in self.zones.iter_mut().zip(&other.zones) {
+                a.bytes += b.bytes;
+                a.time += b.time;
+            }
+        }
+        same
+    }
+
+    /// Get all zones in ascending order.
+    pub fn zones(&self) -> &[DtZone] {
+        &self.zones
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new() {
+        let zones = DtZones::new(100, 1000, 3);
+        let z: Vec<(u64, u64)> = zones.zones().iter().map(|z| (z.offset, z.length)).collect();
+        assert_eq!(z, vec![(100, 333), (433, 333), (766, 334)]);
+        assert_eq!(DtZones::new(0, 2, 10).zones().len(), 2);
+        assert_eq!(DtZones::new(0, 0, 10).zones().len(), 1);
+    }
+
+    #[test]
+    fn test_record() {
+        let mut zones = DtZones::new(0, 400, 4);
+        zones.record(0, 100, Duration::from_secs(1));
+        zones.record(150, 100, Duration::from_secs(2));
+        zones.record(390, 10, Duration::from_secs(0));
+        let z = zones.zones();
+        assert_eq!((z[0].bytes, z[0].time), (100, Duration::from_secs(1)));
+        assert_eq!((z[1].bytes, z[1].time), (50, Duration::from_secs(1)));
+        assert_eq!((z[2].bytes, z[2].time), (50, Duration::from_secs(1)));
+        assert_eq!((z[3].bytes, z[3].time), (10, Duration::from_secs(0)));
+        assert_eq!(z[0].rate(), 100);
+        assert_eq!(z[3].rate(), 0);
+
+        let other = zones.clone();
+        assert!(zones.merge(&other));
+        assert_eq!(zones.zones()[1].bytes, 100);
+        assert!(!zones.merge(&DtZones::new(0, 400, 2)));
+        assert_eq!(zones.zones()[1].bytes, 100);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -21,15 +21,38 @@
 
 use anyhow as ah;
 use clap::ErrorKind::{HelpDisplayed, VersionDisplayed};
-use clap::{App, Arg};
-use crate::disktest::{DtStreamType, Disktest};
-use crate::seed::gen_seed_string;
-use crate::util::parsebytes;
+use clap::{App, AppSettings, Arg, SubCommand};
+use crate::badblocks::DEFAULT_LIST_BLOCK_SIZE;
+use crate::nvme::NvmeErase;
+use crate::priority::{IoPriority, NICE_MAX, NICE_MIN};
+use crate::ranges::{merge_ranges, parse_range, read_ranges};
+use crate::remote::read_token;
+use crate::seed::{RANDOM_SEED, gen_seed_string, read_seed_file};
+use crate::wipe::{WipePass, parse_wipe_scheme};
+use disktest_core::util::{parsebytes, parsepercent, percent_of};
+use disktest_core::{DtKdf, DtStreamType, Disktest, RAW_KEY_SIZE, STDIO_PATH};
 use std::ffi::OsString;
 use std::fmt::Display;
+use std::path::Path;
+use std::time::Duration;
 
 /// Length of the generated seed.
-const DEFAULT_GEN_SEED_LEN: usize = 70;
+pub const DEFAULT_GEN_SEED_LEN: usize = 70;
+
+/// Get the length of a generated seed for the key derivation function.
+/// A raw seed is the key itself and must have the exact key size.
+pub fn gen_seed_len(kdf: DtKdf) -> usize {
+    match kdf {
+        DtKdf::Raw => RAW_KEY_SIZE,
+        _ => DEFAULT_GEN_SEED_LEN,
+    }
+}
+
+/// Default size of the region used by the bench subcommand.
+const DEFAULT_BENCH_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// Maximum --chunk-size.
+const MAX_CHUNK_SIZE: u64 = 1024 * 1024 * 1024;
 
 const ABOUT: &str = "\
 Hard Disk (HDD), Solid State Disk (SSD), USB Stick, Memory Card (e.g. SD-Card) tester.\n\n\
@@ -40,71 +63,1062 @@ const HELP_DEVICE: &str = "\
 Device node of the disk or file path to access.\n\
 On Linux compatible systems this may be the /dev/sdX or /dev/mmcblkX or similar
 device node of the disk. It may also be an arbitrary path to a location in a filesystem.\n\
-On Windows this may be a path to the location on the disk to be tested (e.g. D:\\testfile).";
+On Windows this may be a path to the location on the disk to be tested (e.g. D:\\testfile) \
+or a raw physical drive (e.g. \\\\.\\PhysicalDrive2).\n\
+The device - writes the stream to stdout or verifies the stream read from stdin \
+(e.g. for piping it through dd or ssh). \
+Writing to stdout requires a --seed and suppresses the informational output.";
 
 const HELP_WRITE: &str = "\
 Write pseudo random data to the device. \
-If this option is not given, then disktest will operate in \
-verify-only mode instead, as if only --verify was given.
-If both --write and --verify are specified, then the device \
-will first be written and then be verified with the same seed.";
+If --verify is given, then the written bytes will be verified with the same seed \
+in the same run and a summary of both phases is printed at the end.";
+
+const HELP_WRITE_VERIFY: &str = "\
+Verify the written data after writing.";
+
+const HELP_VERIFY_BEHIND: &str = "\
+Verify the written data while writing continues. \
+The verification trails the write by the given number of bytes (e.g. 1G) \
+and detects gross failures long before the write pass is finished. \
+The data is read with direct I/O, so that it is read from the device \
+instead of the operating system caches. This implies --verify.";
+
+const HELP_LOOP: &str = "\
+Endurance loop: Alternate write and verify passes forever, e.g. to soak-test \
+flaky enclosures and cables. The loop ends at the first error, at --max-errors, \
+after --rounds cycles or at --max-time. Reaching --max-time after at least one complete \
+cycle is a success. The cycle count and the cumulative bytes are printed after every cycle. \
+This implies --verify.";
 
 const HELP_VERIFY: &str = "\
-In verify-mode the disk will be read and compared to the expected pseudo random sequence. \
-If both --write and --verify are specified, then the device \
-will first be written and then be verified with the same seed.";
+Read the device and compare it to the expected pseudo random sequence.";
+
+const HELP_SEED_ROUND: &str = "\
+Verify the data of the specified round of a multi-round write run. \
+Every round after the first one writes with its own sub-seed derived from the --seed, \
+so the device holds the data of the last round. Default: 1";
+
+const HELP_VERIFY_ZERO: &str = "\
+Check that every byte of the device is zero (e.g. after a secure erase). \
+All non-conforming regions are reported. This implies --keep-going.";
+
+const HELP_VERIFY_VALUE: &str = "\
+Check that every byte of the device equals the given value (e.g. 0xFF). \
+All non-conforming regions are reported. This implies --keep-going.";
+
+const HELP_EXPECT_IMAGE: &str = "\
+Verify the device against the contents of the image file \
+instead of the pseudo random stream (e.g. after flashing an OS image). \
+The image from --image-seek is compared to the device from --seek. \
+All differing regions are reported. The device must not be shorter \
+than the compared part of the image.";
+
+const HELP_IMAGE_SEEK: &str = "\
+Byte offset in the --expect-image file where the comparison starts. \
+Default: 0";
+
+const HELP_WIPE: &str = "\
+Overwrite the device with pseudo random data. \
+This can be used to delete existing data on the disk.";
+
+const HELP_WIPE_SCHEME: &str = "\
+The overwrite passes of the wipe. \
+This is either one of the well-known schemes \
+random (one random pass, default), \
+dod (DoD 5220.22-M: zeros, ones, random), \
+schneier (ones, zeros, 5 random passes) or \
+gutmann (35 passes), \
+or a comma separated list of passes. \
+A pass is zero, one, random or a hexadecimal byte pattern (e.g. 0xAA55).";
+
+const HELP_WIPE_VERIFY: &str = "\
+Verify the data of the last pass after wiping.";
+
+const HELP_BENCH: &str = "\
+Measure the sequential write and read speed and the random 4K \
+read and write speed (IOPS) of the device. \
+By default the first 1 GiB of the device is overwritten.";
+
+const HELP_SCAN: &str = "\
+Read every sector of the device without writing or comparing any data. \
+This reports all unreadable regions and the read speed \
+and keeps the data on the device.";
+
+const HELP_COMPARE: &str = "\
+Compare the data of two devices or of a device and an image file. \
+Both are read in parallel and all differing regions are reported. \
+The comparison stops at the end of the shorter one. \
+Neither of them is modified.";
+
+const HELP_OTHER: &str = "\
+Device node or file path of the device to compare to.";
+
+const HELP_NONDESTRUCTIVE: &str = "\
+Test the device without destroying the data on it. \
+The device is tested in segments of the chunk size. \
+The original data of each segment is read and kept in memory, \
+then the pseudo random stream is written to the segment and verified, \
+and finally the original data is written back. \
+The device must not be mounted or in use during the test. \
+If disktest is killed during the test, the data of the current segment is lost. \
+Press Ctrl-C to stop the test safely.";
+
+const HELP_SURFACE: &str = "\
+Destructive surface test. The pseudo random stream is written to each chunk, \
+immediately read back with direct I/O and compared, before the next chunk is tested. \
+This is slower than writing and verifying the whole device, \
+but reports every error precisely located while the test is running. \
+This overwrites the data on the device.";
+
+const HELP_CAPACITY_CHECK: &str = "\
+Detect fake devices that claim more capacity than they actually have. \
+Address dependent markers are written across the claimed capacity of the device \
+and read back afterwards. Addresses that wrap around to lower addresses \
+or that drop the written data are detected and the real usable capacity is printed. \
+This overwrites the data at the marker positions.";
+
+const HELP_TRIM_CHECK: &str = "\
+Check whether the device honors deterministic read after TRIM. \
+The range given by --seek and --bytes (default: the whole device) is discarded (TRIM) \
+and read back. The device passes, if it returns zeros (RZAT). \
+It is also reported, whether the data read after the discard is deterministic (DRAT). \
+This destroys the data in the range.";
+
+const HELP_SECURE_ERASE: &str = "\
+Erase an ATA drive with the drive's own ATA SECURITY ERASE UNIT command \
+and verify afterwards that every byte reads back as zero. \
+The --report contains the result of the erase and of the verification. \
+This requires hdparm. The drive's security must not be frozen. \
+This destroys all data on the drive.";
+
+const HELP_ENHANCED: &str = "\
+Use ENHANCED SECURITY ERASE UNIT instead of SECURITY ERASE UNIT. \
+The enhanced erase also erases the reallocated sectors, if the drive supports it.";
+
+const HELP_NVME_ERASE: &str = "\
+Erase an NVMe drive with the drive's own Format NVM or Sanitize command, \
+wait for the completion and verify the result. \
+After a user data erase or block erase every byte must read back as zero. \
+After a cryptographic erase the data must differ from the data before the erase. \
+The --report contains the result of the erase and of the verification. \
+Sanitize erases all namespaces of the controller. \
+This destroys all data on the drive.";
+
+const HELP_NVME_METHOD: &str = "\
+The NVMe erase method:\n\
+'format': Format NVM with user data erase (default).\n\
+'format-crypto': Format NVM with cryptographic erase.\n\
+'sanitize-block': Sanitize with block erase.\n\
+'sanitize-crypto': Sanitize with crypto erase.";
+
+const HELP_FILL: &str = "\
+Test the free space of a mounted file system without destroying it. \
+DEVICE is a directory on the file system. \
+The free space is filled with numbered test files of up to 1 GiB, \
+which are verified and deleted afterwards.";
+
+const HELP_INFO: &str = "\
+Print information about the device: \
+Model, serial number, firmware revision, bus, medium type, size, sector sizes \
+and the S.M.A.R.T. health (requires smartctl).";
+
+const HELP_SERVE: &str = "\
+Run as agent on a test station. \
+The agent accepts write and verify runs from controllers that connect \
+with 'disktest remote' and sends them the output, the progress and the report of the run. \
+The connection is authenticated with the shared --token-file, but it is not encrypted. \
+Therefore, only use it in trusted networks or through a tunnel.";
+
+const HELP_LISTEN: &str = "\
+The address to accept controllers on, e.g. 0.0.0.0:9127 to accept them on all interfaces. \
+Default: 127.0.0.1 on port 9127 (Only local controllers, e.g. through a tunnel)";
+
+const HELP_SERVE_FORCE: &str = "\
+Allow the controllers to use --force.";
+
+const HELP_SERVE_ALLOW_SYSTEM_DISK: &str = "\
+Allow the controllers to use --allow-system-disk.";
+
+const HELP_SERVE_YES: &str = "\
+Allow the controllers to use --yes. \
+Without this option the agent refuses runs that destroy the data on a device.";
+
+const HELP_REMOTE: &str = "\
+Start a write or verify run on an agent that runs 'disktest serve' \
+and show its output until it finished. \
+The exit status is the exit status of the remote run. \
+Interrupting the controller stops the remote run.";
+
+const HELP_AGENT: &str = "\
+Address of the agent, e.g. station1 or 10.0.0.5:9127. \
+The default port is 9127.";
+
+const HELP_REMOTE_COMMAND: &str = "\
+The disktest command line to run on the agent, \
+e.g. write --verify /dev/sdb. \
+The device path is a path on the agent.";
+
+const HELP_TOKEN_FILE: &str = "\
+Read the shared secret token that authenticates the controller from the file. \
+The agent and the controller must use the same token.";
+
+const HELP_REMOTE_REPORT: &str = "\
+Write the report of the remote run as JSON to the specified local file.";
+
+const HELP_DIRECT: &str = "\
+Use direct I/O. This bypasses the operating system caches (O_DIRECT on Linux, \
+FILE_FLAG_NO_BUFFERING on Windows, F_NOCACHE on macOS). \
+Without direct I/O, a verification of a small device might be satisfied \
+from the cache and the measured speed might not be the speed of the device. \
+All accesses are aligned to 4096 bytes. \
+Therefore, --bytes will be rounded down to a multiple of 4096 bytes.";
+
+const HELP_SYNC_WRITES: &str = "\
+Open the device for synchronous writes (O_DSYNC or O_SYNC on Unix, \
+FILE_FLAG_WRITE_THROUGH on Windows). Every write is durable on the device, \
+when it is acknowledged. This is needed for valid power-fail \
+and write cache tests. It makes the write phase considerably slower.";
+
+const HELP_SYNC_EVERY: &str = "\
+Sync the written data to the device (fdatasync) each time the specified \
+number of bytes (e.g. 1G) has been written, instead of only at the very end. \
+This bounds the amount of unflushed data in the write caches and \
+smooths the throughput numbers.";
+
+const HELP_ROUNDS: &str = "\
+Repeat the write and/or verify cycle the specified number of times. \
+A summary is printed after each round and an aggregate summary at the end. \
+The special value 0 repeats the cycle forever, until an error occurs or \
+disktest is aborted. Default: 1";
+
+const HELP_RESUME: &str = "\
+Resume an interrupted run. \
+The current position and all parameters of the run are periodically saved to the \
+specified state file. If the state file already exists when disktest is started, \
+the run is continued from the saved position. \
+The state file is removed after the run completed successfully. \
+Note that the state file contains the --seed.";
+
+const HELP_REPORT: &str = "\
+Write a structured report of the run to the specified JSON file when the run ended. \
+The report contains the device, the parameters, the number of processed bytes, \
+the throughput, all errors with their device offsets, the duration and the result.";
+
+const HELP_REPORT_JUNIT: &str = "\
+Write the result of the run to the specified file in the JUnit XML format, \
+so that CI pipelines can gate on it. There is one test case per device, \
+or one test case per range with --range.";
+
+const HELP_META: &str = "\
+Save the parameters of the run to the specified JSON metadata file after writing. \
+In verify-only mode the parameters are read back from the file, \
+so that write and verify use consistent settings, \
+even if they are run on different machines. \
+The file contains a fingerprint of the --seed, but not the seed itself, \
+unless the seed has been generated.";
+
+const HELP_SMART: &str = "\
+Read the key S.M.A.R.T. attributes of the device (reallocated sectors, pending sectors, \
+CRC errors, media errors) before and after the run and print their changes. \
+This requires the smartctl tool of smartmontools.";
+
+const HELP_KERNEL_LOG: &str = "\
+Follow the kernel log (/dev/kmsg) during the run and collect the I/O error messages \
+about the device together with the position of the test at that time. \
+The messages are printed at the end and added to the --report. \
+This is only supported on Linux and usually requires root privileges.";
+
+const HELP_LATENCY: &str = "\
+Measure the latency of every read and write call and print a histogram \
+and the 50th, 95th and 99.9th percentiles at the end. \
+The latencies are also added to the --report.";
+
+const HELP_TUI: &str = "\
+Show a full-screen terminal user interface with a progress bar, a throughput graph, \
+the bad regions and the device information during the run. \
+Press p to pause and resume the test and q to abort it. \
+The summary is printed after the interface is closed.";
+
+const HELP_OUTPUT: &str = "\
+The output format. text: Human readable output (default). \
+summary-json: Print nothing during the run and write exactly one JSON object \
+with the final result (the --report content) to stdout. \
+This implies --quiet 2. Errors are still printed to stderr.";
+
+const HELP_ZONES: &str = "\
+Divide the tested area into the given number of zones of equal size \
+and print the average throughput of each zone at the end. \
+Zones that are much slower than the others are marked. \
+The zone throughput is also added to the --report.";
+
+const HELP_VERIFY_STRIDE: &str = "\
+Verify only every Nth chunk and skip the chunks in between. \
+A quick verification pass still touches all regions of the device \
+(e.g. the outer and inner tracks or all flash dies) without reading everything. \
+Default: 1 (Verify everything)";
+
+const HELP_MMAP: &str = "\
+Verify regular files with memory mapped I/O. The data is compared in place \
+in the mapped file instead of being copied by read calls, which is faster for \
+large image files. Read errors can't be handled with memory mapped I/O. \
+Therefore, disk devices are always verified with regular reads.";
+
+const HELP_KEEP_GOING: &str = "\
+Do not abort the verification at the first data mismatch or read error. \
+Instead, record every bad region and print a map of all bad regions at the end.";
+
+const HELP_NO_HEADER: &str = "\
+Do not use the on-disk header. \
+By default a small header with the parameters of the run \
+(algorithm, number of bytes, seed fingerprint) \
+is written to the first 4 kiB of the device after writing. \
+A later verify run reads the header, so that only the --seed has to be given. \
+The header region is not verified. \
+Use --no-header for strict coverage of the whole device.";
+
+const HELP_SERIAL: &str = "\
+Select the device by its serial number instead of the device node. \
+The serial number is resolved to the current device node at the start of the run, \
+so that scripts don't write to whatever device happens to have a node today. \
+Only whole disks are matched. This is only supported on Linux.";
+
+const HELP_BY_ID: &str = "\
+Select the device by its /dev/disk/by-id path or by the name in /dev/disk/by-id \
+(e.g. usb-SanDisk_Ultra_4C530001-0:0). \
+The id is resolved to the current device node at the start of the run.";
+
+const HELP_PRESERVE_PTABLE: &str = "\
+Preserve the partition table. \
+The first and the last MiB of the device, which hold the MBR and the primary and backup GPT, \
+are backed up before the run and are excluded from the test. \
+The start of the test is rounded up to the chunk size. \
+After the run they are written back, so that the device doesn't have to be repartitioned. \
+The same option has to be given for a later verify run.";
+
+const HELP_DEVICES_FROM: &str = "\
+Test all devices listed in the specified file one after another. \
+Each line holds a device path, optionally followed by options \
+that override the command line for this device (e.g. /dev/sdc --bytes 1G). \
+The options are separated by whitespace. Empty lines and lines starting with # are ignored. \
+The --report contains a section for every device. \
+Files like --meta, --resume, --bad-blocks and --skip-list have to be given per device.";
+
+const HELP_BAD_BLOCKS: &str = "\
+Write the numbers of all blocks that contain errors to the specified file \
+when the run ended. The file uses the badblocks(8) format and can directly \
+be passed to mke2fs -l or e2fsck -l. \
+Note that the block numbers are counted from the start of the device.";
+
+const HELP_SKIP_LIST: &str = "\
+Skip the known bad blocks listed in the specified file. \
+The file uses the badblocks(8) format, as written by --bad-blocks of an earlier run \
+or by badblocks -o. The listed blocks are neither written nor verified \
+and do not fail the test. This allows regression testing of a device with a few known defects.";
+
+const HELP_RANGE: &str = "\
+Only process the byte range START:LEN (e.g. 1TiB:100MiB) instead of --seek and --bytes. \
+This option can be given multiple times. All ranges are processed in one run \
+with one summary and one --report, e.g. to re-test the regions that failed before. \
+Overlapping ranges are merged. The on-disk header is not used with ranges.";
+
+const HELP_RANGES_FROM: &str = "\
+Read the byte ranges from the specified file, one START:LEN range per line. \
+Empty lines and lines starting with # are ignored. See --range.";
+
+const HELP_LIST_BLOCK_SIZE: &str = "\
+The block size that is used for the --bad-blocks and --skip-list lists, in bytes. \
+This must match the block size of the filesystem (e.g. mke2fs -b). \
+Default: 4096";
+
+const HELP_MAX_ERRORS: &str = "\
+Keep going through errors, but stop the run once N distinct bad regions have been found. \
+This implies --keep-going.";
+
+const HELP_READ_RETRIES: &str = "\
+Retry a failed read up to N times with exponential backoff, \
+starting at 100 ms. The retries read the failed region block by block. \
+Errors that disappear on a retry are reported as transient errors. \
+Only the blocks that still fail are reported as read errors. \
+Default: 0 (No retries)";
+
+const HELP_FORCE: &str = "\
+Write to the device even if it or one of its partitions is mounted, \
+used as swap space or held by another device (e.g. device-mapper, LVM or RAID). \
+Without this option writing to a device that is in use is refused.";
+
+const HELP_ALLOW_SYSTEM_DISK: &str = "\
+Write to the device even if it stores the root, boot or EFI system filesystem \
+or active swap space of the running system, directly or via device-mapper, LVM or RAID. \
+This destroys the running system. --force does not override this check.";
+
+const HELP_YES: &str = "\
+Don't ask for confirmation before destroying the data on a device. \
+Without this option the model, serial number, size and partitions of the device are printed \
+and the device name or 'yes' has to be typed in before writing to it. \
+Use this for scripting.";
+
+const HELP_MAX_RATE: &str = "\
+Limit the write and read throughput to the specified number of bytes per second. \
+Optionally with K/M/G/T suffix (e.g. 20M). \
+Default: Unlimited";
+
+const HELP_MAX_TIME: &str = "\
+Stop the run cleanly after the specified wall-clock time. \
+The time is a number of seconds or a number with one of the suffixes s, m, h or d (e.g. 4h). \
+The run exits with status 4 (incomplete), if it has been stopped at the time limit. \
+Default: Unlimited";
+
+const HELP_NICE: &str = "\
+Set the CPU scheduling niceness of the process before starting (-20 to 19). \
+Higher values mean lower priority. \
+On Windows the niceness is mapped to the nearest process priority class.";
+
+const HELP_IO_PRIORITY: &str = "\
+Set the I/O scheduling priority of the process before starting: idle, low or normal. \
+With idle, disktest only accesses the disk if no other process needs it. \
+On Windows idle and low both select the background processing mode. \
+This is not supported on macOS.";
+
+const HELP_CPU_SET: &str = "\
+Pin the threads to the list of CPUs (e.g. 0-3,6). \
+The I/O thread is pinned to the first CPU of the list and the generator threads \
+to the remaining CPUs round-robin. \
+This is supported on Linux and Windows.";
+
+const HELP_PROGRESS_JSON: &str = "\
+Emit the progress as machine readable stream with one JSON object per line. \
+The stream is written to the specified target, which is either 'stderr', 'stdout' \
+or the number of an already opened file descriptor. \
+The stream is independent of --quiet.";
+
+const HELP_METRICS_LISTEN: &str = "\
+Serve live metrics (processed bytes, throughput, errors, ETA) in the Prometheus text format \
+via HTTP on the specified address, e.g. 0.0.0.0:9100. \
+The metrics are available at the path /metrics.";
+
+const HELP_LOG: &str = "\
+Append a timestamped log of all progress and error messages to the file. \
+The log is written independently of --quiet.";
+
+const HELP_THROUGHPUT_LOG: &str = "\
+Write the throughput over time to the CSV file, e.g. to plot slow zones \
+and throttling events. Each progress update (about once per second) adds a line \
+with the UTC timestamp, the device, the phase, the elapsed seconds of the phase, \
+the absolute byte offset and the current rate in bytes per second.";
+
+const HELP_ON_SUCCESS: &str = "\
+Run the shell command, if the run finished successfully. \
+The result details are passed in the environment variables DISKTEST_RESULT, \
+DISKTEST_EXIT_STATUS, DISKTEST_MESSAGE, DISKTEST_DEVICE and DISKTEST_DURATION (in seconds).";
+
+const HELP_ON_FAILURE: &str = "\
+Run the shell command, if the run failed. \
+The same environment variables as for --on-success are passed.";
+
+const HELP_NOTIFY: &str = "\
+Show a desktop notification with the device and the result when the run finished. \
+This uses notify-send on Linux and BSD, the Notification Center on macOS \
+and a toast notification on Windows.";
+
+const HELP_LEGACY_WRITE: &str = "\
+Deprecated. Use the 'write' subcommand instead.";
+
+const HELP_LEGACY_VERIFY: &str = "\
+Deprecated. Use the 'verify' subcommand or 'write --verify' instead.";
 
 const HELP_SEEK: &str = "\
 Seek to the specified byte position on disk \
 before starting the write/verify operation. This skips the specified \
-amount of bytes on the disk and also fast forwards the random number generator.";
+amount of bytes on the disk and also fast forwards the random number generator. \
+A percentage (e.g. 90%) is relative to the size of the device \
+and rounded down to a multiple of 4 kiB.";
 
 const HELP_BYTES: &str = "\
 Number of bytes to write/verify. \
-If not given, then the whole disk will be overwritten/verified.";
+If not given, then the whole disk will be overwritten/verified. \
+A percentage (e.g. 10%) is relative to the size of the device \
+and rounded down to a multiple of 4 kiB.";
+
+const HELP_END: &str = "\
+Absolute byte position on disk where the write/verify operation ends (exclusive). \
+This is an alternative to --bytes, e.g. --seek 3TiB --end 3584GiB. \
+A percentage (e.g. 50%) is relative to the size of the device \
+and rounded down to a multiple of 4 kiB.";
 
 const HELP_ALGORITHM: &str = "\
 Select the random number generator algorithm. \
-The selection can be: CHACHA20, CHACHA12, CHACHA8 or CRC.\n\
+The selection can be: CHACHA20, CHACHA12, CHACHA8, AES128, AES256, BLAKE3, SHAKE256, CRC or CRC32C.\n\
 Default: CHACHA20.\n\
 ChaCha12 and ChaCha8 are less cryptographically secure than ChaCha20, but faster.\n\
-CRC is even faster, but not cryptographically secure at all.";
+AES128 and AES256 are AES in counter mode. They are cryptographically secure and \
+use the hardware AES instructions, if the CPU supports them.\n\
+BLAKE3 is the BLAKE3 hash function in extendable output mode. \
+It is cryptographically secure and uses the SIMD instructions of the CPU.\n\
+SHAKE256 is the SHA-3 extendable output function in counter mode. \
+It is cryptographically secure, but slow.\n\
+CRC is even faster, but not cryptographically secure at all.\n\
+CRC32C is the fastest. It is not cryptographically secure at all and \
+uses the SSE 4.2 or ARMv8 CRC instructions, if the CPU supports them.";
 
 const HELP_SEED: &str = "\
 The seed to use for random number stream generation. \
-If you want a unique pattern to be written to disk, supply a random seed to this parameter. \
-If not given, then the pseudo random sequence will be the same for everybody and \
-it will therefore not be secret.
-The seed may be any random string (e.g. a long passphrase).";
+The seed may be any random string (e.g. a long passphrase). \
+If not given or if 'random' is given, then a seed is generated \
+from the random number generator of the operating system. \
+The generated seed is printed and stored in the --report and --meta files, \
+so that it can be used for a later verification.";
+
+const HELP_SEED_FILE: &str = "\
+Read the seed from the specified file or from stdin, if '-' is given. \
+The content of the file is used as is, including any trailing newline. \
+It may contain arbitrary binary data. \
+This keeps long seeds out of the shell history and the process list.";
+
+const HELP_KDF: &str = "\
+Select the key derivation function that turns the seed into the key \
+of the random number generator. \
+The selection can be: pbkdf2[:ITERATIONS], argon2id[:MEMORY_KIB:ITERATIONS], \
+scrypt[:LOG_N:R:P] or raw (see --raw-seed).\n\
+Default: pbkdf2:50000.\n\
+Argon2id and scrypt are memory-hard and make guessing short seeds much more expensive. \
+The key is derived once at startup. \
+The selected function is stored in the on-disk header and in the --meta file, \
+so that a later verification uses the same function.";
+
+const HELP_RAW_SEED: &str = "\
+Use the seed directly as the key of the random number generator \
+without key derivation. The seed must be exactly 32 bytes long \
+(e.g. a full-entropy binary key given with --seed-file). \
+This skips the slow key derivation at startup. \
+It is the same as --kdf raw.";
+
+const HELP_PATTERN: &str = "\
+Write and verify a fixed byte pattern instead of a pseudo random stream. \
+The pattern is given in hexadecimal (e.g. 0x00, 0xFF or 0xAA55) \
+and is repeated over the whole device. \
+This can't be combined with --algorithm or --seed.";
 
 const HELP_THREADS: &str = "\
 The number of CPUs to use. \
 The special value 0 will select the maximum number of online CPUs in the system. \
 If the number of threads is equal to number of CPUs it is optimal for performance. \
-This parameter must be equal during corresponding verify and --write mode runs. \
-Otherwise the verification will fail. Default: 1";
+The written data does not depend on the number of threads. \
+Therefore, the verification may use a different number of threads. Default: 1";
+
+const HELP_CHUNK_SIZE: &str = "\
+The size of the data chunks that are generated and written at once, in bytes. \
+This must be a multiple of the algorithm base size \
+(CHACHA20, CHACHA12, CHACHA8, AES128, AES256, BLAKE3, SHAKE256, CRC32C: 4 kiB; CRC: 2 kiB). \
+With --direct it must also be a multiple of 4 kiB. \
+Small chunks suit slow devices like memory cards, big chunks suit fast devices. \
+The written data does not depend on the chunk size. \
+Default: 3 MiB";
 
 const HELP_QUIET: &str = "\
 Quiet level: 0: Normal verboseness (default). \
 1: Reduced verboseness. \
 2: No informational output.";
 
+/// The format of the output to stdout.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OutputFormat {
+    /// Human readable progress and summary.
+    Text,
+    /// Only the final report as one JSON object.
+    SummaryJson,
+}
+
+/// The operation selected on the command line.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Command {
+    /// Write and/or verify the pseudo random stream.
+    Test,
+    /// Overwrite the device with pseudo random data.
+    Wipe,
+    /// Measure the device read and write speed.
+    Bench,
+    /// Read the whole device without modifying it.
+    Scan,
+    /// Compare the data of two devices.
+    Compare,
+    /// Write and verify each segment and restore its original data.
+    Nondestructive,
+    /// Write, read back and compare each segment.
+    Surface,
+    /// Detect the real usable capacity of a fake device.
+    CapacityCheck,
+    /// Discard the device and verify that it reads back zeros.
+    TrimCheck,
+    /// Erase the drive with ATA SECURITY ERASE UNIT and verify zeros.
+    SecureErase,
+    /// Erase the drive with NVMe Format NVM or Sanitize and verify the result.
+    NvmeErase,
+    /// Fill the free space of a file system with test files.
+    Fill,
+    /// Print information about the device.
+    Info,
+    /// Accept runs from remote controllers.
+    Serve,
+    /// Start a run on a remote agent.
+    Remote,
+}
+
 /// All command line arguments.
+#[derive(Clone)]
 pub struct Args {
+    pub command:    Command,
+    /// The deprecated command line without subcommand was used.
+    pub flat_cmdline: bool,
     pub device:     String,
+    /// Select the device by serial number.
+    pub serial:     Option<String>,
+    /// Select the device by /dev/disk/by-id path.
+    pub by_id:      Option<String>,
     pub write:      bool,
     pub verify:     bool,
     pub seek:       u64,
     pub max_bytes:  u64,
+    /// --seek as percentage of the device size, if given so.
+    pub seek_percent: Option<f64>,
+    /// --bytes as percentage of the device size, if given so.
+    pub bytes_percent: Option<f64>,
+    /// The end position of --end. max_bytes is derived from it.
+    pub end:        Option<u64>,
+    /// --end as percentage of the device size, if given so.
+    pub end_percent: Option<f64>,
     pub algorithm:  DtStreamType,
-    pub seed:       String,
+    pub seed:       Vec<u8>,
     pub user_seed:  bool,
+    /// The round whose sub-seed is used. See --seed-round.
+    pub seed_round: u64,
+    pub kdf:        DtKdf,
+    pub pattern:    Option<Vec<u8>>,
+    pub wipe_passes: Vec<WipePass>,
+    /// Use the enhanced ATA security erase.
+    pub enhanced_erase: bool,
+    /// The NVMe erase method.
+    pub nvme_erase: NvmeErase,
     pub threads:    usize,
+    pub chunk_size: Option<usize>,
     pub quiet:      u8,
+    pub direct:     bool,
+    /// Open the device for synchronous writes. See --sync-writes.
+    pub sync_writes: bool,
+    /// Sync the written data each time this number of bytes has been written.
+    pub sync_every: Option<u64>,
+    pub rounds:     u64,
+    /// Endurance loop. See --loop.
+    pub loop_mode:  bool,
+    pub resume:     Option<String>,
+    pub report:     Option<String>,
+    pub report_junit: Option<String>,
+    pub keep_going: bool,
+    pub max_errors: Option<usize>,
+    pub devices_from: Option<String>,
+    pub bad_blocks: Option<String>,
+    pub skip_list:  Option<String>,
+    /// The byte ranges (offset, length) of --range and --ranges-from, sorted by offset.
+    /// Empty, if --seek and --bytes select the tested area.
+    pub ranges:     Vec<(u64, u64)>,
+    pub list_block_size: u64,
+    pub header:     bool,
+    pub preserve_ptable: bool,
+    pub meta:       Option<String>,
+    pub smart:      bool,
+    pub kernel_log: bool,
+    pub latency:    bool,
+    /// Show the full-screen terminal user interface.
+    pub tui:        bool,
+    pub output:     OutputFormat,
+    pub zones:      Option<usize>,
+    pub verify_stride: u64,
+    pub mmap:       bool,
+    /// The lag of the verification that trails the write.
+    pub verify_behind: Option<u64>,
+    pub read_retries: u32,
+    pub force:      bool,
+    pub allow_system_disk: bool,
+    pub yes:        bool,
+    pub max_rate:   Option<u64>,
+    pub max_time:   Option<Duration>,
+    pub nice:       Option<i32>,
+    pub io_priority: Option<IoPriority>,
+    pub cpu_set:    Option<Vec<usize>>,
+    pub progress_json: Option<String>,
+    pub metrics_listen: Option<String>,
+    pub log:        Option<String>,
+    pub throughput_log: Option<String>,
+    pub on_success: Option<String>,
+    pub on_failure: Option<String>,
+    pub notify:     bool,
+    /// Address of the remote agent, or the address to listen on.
+    pub agent:      String,
+    pub token:      Vec<u8>,
+    /// The command line of the remote run.
+    pub remote_args: Vec<String>,
+    /// The device to compare to.
+    pub other:      String,
+    /// The device is verified against the image file in other.
+    pub expect_image: bool,
+    /// The start offset in other.
+    pub other_seek: u64,
+}
+
+/// Build the arguments that are common to all data processing subcommands.
+fn common_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("device")
+            .index(1)
+            .required_unless_one(&["devices-from", "serial", "by-id"])
+            .help(HELP_DEVICE),
+        Arg::with_name("serial")
+            .long("serial")
+            .takes_value(true)
+            .conflicts_with_all(&["device", "by-id"])
+            .help(HELP_SERIAL),
+        Arg::with_name("by-id")
+            .long("by-id")
+            .takes_value(true)
+            .conflicts_with("device")
+            .help(HELP_BY_ID),
+        Arg::with_name("seek")
+            .long("seek")
+            .short("s")
+            .takes_value(true)
+            .help(HELP_SEEK),
+        Arg::with_name("bytes")
+            .long("bytes")
+            .short("b")
+            .takes_value(true)
+            .help(HELP_BYTES),
+        Arg::with_name("end")
+            .long("end")
+            .takes_value(true)
+            .conflicts_with("bytes")
+            .help(HELP_END),
+        Arg::with_name("algorithm")
+            .long("algorithm")
+            .short("A")
+            .takes_value(true)
+            .help(HELP_ALGORITHM),
+        Arg::with_name("seed")
+            .long("seed")
+            .short("S")
+            .takes_value(true)
+            .help(HELP_SEED),
+        Arg::with_name("seed-file")
+            .long("seed-file")
+            .takes_value(true)
+            .conflicts_with("seed")
+            .help(HELP_SEED_FILE),
+        Arg::with_name("kdf")
+            .long("kdf")
+            .takes_value(true)
+            .help(HELP_KDF),
+        Arg::with_name("raw-seed")
+            .long("raw-seed")
+            .conflicts_with("kdf")
+            .help(HELP_RAW_SEED),
+        Arg::with_name("pattern")
+            .long("pattern")
+            .takes_value(true)
+            .conflicts_with_all(&["algorithm", "seed", "seed-file", "kdf", "raw-seed"])
+            .help(HELP_PATTERN),
+        Arg::with_name("threads")
+            .long("threads")
+            .short("j")
+            .takes_value(true)
+            .help(HELP_THREADS),
+        Arg::with_name("chunk-size")
+            .long("chunk-size")
+            .takes_value(true)
+            .help(HELP_CHUNK_SIZE),
+        Arg::with_name("quiet")
+            .long("quiet")
+            .short("q")
+            .takes_value(true)
+            .help(HELP_QUIET),
+        Arg::with_name("direct")
+            .long("direct")
+            .short("D")
+            .help(HELP_DIRECT),
+        Arg::with_name("max-errors")
+            .long("max-errors")
+            .takes_value(true)
+            .help(HELP_MAX_ERRORS),
+        Arg::with_name("read-retries")
+            .long("read-retries")
+            .takes_value(true)
+            .help(HELP_READ_RETRIES),
+        Arg::with_name("force")
+            .long("force")
+            .help(HELP_FORCE),
+        Arg::with_name("allow-system-disk")
+            .long("allow-system-disk")
+            .help(HELP_ALLOW_SYSTEM_DISK),
+        Arg::with_name("yes")
+            .long("yes")
+            .short("y")
+            .help(HELP_YES),
+        Arg::with_name("max-rate")
+            .long("max-rate")
+            .takes_value(true)
+            .help(HELP_MAX_RATE),
+        Arg::with_name("max-time")
+            .long("max-time")
+            .takes_value(true)
+            .help(HELP_MAX_TIME),
+        Arg::with_name("nice")
+            .long("nice")
+            .takes_value(true)
+            .allow_hyphen_values(true)
+            .help(HELP_NICE),
+        Arg::with_name("io-priority")
+            .long("io-priority")
+            .takes_value(true)
+            .help(HELP_IO_PRIORITY),
+        Arg::with_name("cpu-set")
+            .long("cpu-set")
+            .takes_value(true)
+            .help(HELP_CPU_SET),
+        Arg::with_name("progress-json")
+            .long("progress-json")
+            .takes_value(true)
+            .help(HELP_PROGRESS_JSON),
+        Arg::with_name("metrics-listen")
+            .long("metrics-listen")
+            .takes_value(true)
+            .help(HELP_METRICS_LISTEN),
+        Arg::with_name("log")
+            .long("log")
+            .takes_value(true)
+            .help(HELP_LOG),
+        Arg::with_name("throughput-log")
+            .long("throughput-log")
+            .takes_value(true)
+            .help(HELP_THROUGHPUT_LOG),
+        Arg::with_name("on-success")
+            .long("on-success")
+            .takes_value(true)
+            .help(HELP_ON_SUCCESS),
+        Arg::with_name("on-failure")
+            .long("on-failure")
+            .takes_value(true)
+            .help(HELP_ON_FAILURE),
+        Arg::with_name("notify")
+            .long("notify")
+            .help(HELP_NOTIFY),
+    ]
+}
+
+/// Build the arguments that are specific to the write and verify operations.
+fn test_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("rounds")
+            .long("rounds")
+            .short("R")
+            .takes_value(true)
+            .help(HELP_ROUNDS),
+        Arg::with_name("resume")
+            .long("resume")
+            .takes_value(true)
+            .help(HELP_RESUME),
+        Arg::with_name("report")
+            .long("report")
+            .takes_value(true)
+            .help(HELP_REPORT),
+        Arg::with_name("report-junit")
+            .long("report-junit")
+            .takes_value(true)
+            .help(HELP_REPORT_JUNIT),
+        Arg::with_name("meta")
+            .long("meta")
+            .takes_value(true)
+            .help(HELP_META),
+        Arg::with_name("smart")
+            .long("smart")
+            .help(HELP_SMART),
+        Arg::with_name("kernel-log")
+            .long("kernel-log")
+            .help(HELP_KERNEL_LOG),
+        Arg::with_name("latency")
+            .long("latency")
+            .help(HELP_LATENCY),
+        Arg::with_name("tui")
+            .long("tui")
+            .conflicts_with("devices-from")
+            .help(HELP_TUI),
+        Arg::with_name("output")
+            .long("output")
+            .takes_value(true)
+            .conflicts_with("tui")
+            .help(HELP_OUTPUT),
+        Arg::with_name("zones")
+            .long("zones")
+            .takes_value(true)
+            .help(HELP_ZONES),
+        Arg::with_name("verify-stride")
+            .long("verify-stride")
+            .takes_value(true)
+            .help(HELP_VERIFY_STRIDE),
+        Arg::with_name("mmap")
+            .long("mmap")
+            .conflicts_with("direct")
+            .help(HELP_MMAP),
+        Arg::with_name("keep-going")
+            .long("keep-going")
+            .short("k")
+            .help(HELP_KEEP_GOING),
+        Arg::with_name("no-header")
+            .long("no-header")
+            .help(HELP_NO_HEADER),
+        Arg::with_name("preserve-ptable")
+            .long("preserve-ptable")
+            .help(HELP_PRESERVE_PTABLE),
+        Arg::with_name("devices-from")
+            .long("devices-from")
+            .takes_value(true)
+            .conflicts_with_all(&["device", "serial", "by-id", "resume", "meta", "bad-blocks", "skip-list"])
+            .help(HELP_DEVICES_FROM),
+        Arg::with_name("bad-blocks")
+            .long("bad-blocks")
+            .takes_value(true)
+            .help(HELP_BAD_BLOCKS),
+        Arg::with_name("skip-list")
+            .long("skip-list")
+            .takes_value(true)
+            .help(HELP_SKIP_LIST),
+        Arg::with_name("range")
+            .long("range")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .conflicts_with_all(&["seek", "bytes", "end", "resume"])
+            .help(HELP_RANGE),
+        Arg::with_name("ranges-from")
+            .long("ranges-from")
+            .takes_value(true)
+            .conflicts_with_all(&["seek", "bytes", "end", "resume"])
+            .help(HELP_RANGES_FROM),
+        Arg::with_name("block-size-for-list")
+            .long("block-size-for-list")
+            .takes_value(true)
+            .help(HELP_LIST_BLOCK_SIZE),
+    ]
+}
+
+/// Parse a hexadecimal byte pattern (e.g. 0xAA55).
+pub fn parse_pattern(s: &str) -> ah::Result<Vec<u8>> {
+    let hex = s.trim();
+    let hex = hex.strip_prefix("0x").or_else(|| hex.strip_prefix("0X")).unwrap_or(hex);
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(ah::format_err!("The pattern is not hexadecimal."));
+    }
+    if hex.is_empty() || !hex.len().is_multiple_of(2) {
+        return Err(ah::format_err!("The pattern must consist of whole bytes."));
+    }
+    Ok((0..hex.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i+2], 16).unwrap())
+        .collect())
+}
+
+/// Parse a duration with optional s/m/h/d suffix (e.g. 4h).
+/// A number without suffix is in seconds.
+pub fn parse_duration(s: &str) -> ah::Result<Duration> {
+    let s = s.trim();
+    let (num, factor) = match s.chars().last() {
+        Some('s') => (&s[..s.len() - 1], 1),
+        Some('m') => (&s[..s.len() - 1], 60),
+        Some('h') => (&s[..s.len() - 1], 60 * 60),
+        Some('d') => (&s[..s.len() - 1], 24 * 60 * 60),
+        _ => (s, 1),
+    };
+    let num: u64 = match num.trim().parse() {
+        Ok(x) => x,
+        Err(e) => return Err(ah::format_err!("Invalid duration: {}", e)),
+    };
+    match num.checked_mul(factor) {
+        Some(secs) => Ok(Duration::from_secs(secs)),
+        None => Err(ah::format_err!("The duration is too long.")),
+    }
+}
+
+/// Parse a list of CPUs and CPU ranges (e.g. 0-3,6).
+pub fn parse_cpu_set(s: &str) -> ah::Result<Vec<usize>> {
+    let parse = |x: &str| {
+        x.trim().parse::<usize>().map_err(|e| ah::format_err!("Invalid CPU number '{}': {}", x, e))
+    };
+    let mut cpus = vec![];
+    for item in s.split(',') {
+        match item.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (parse(first)?, parse(last)?);
+                if first > last {
+                    return Err(ah::format_err!("Invalid CPU range '{}'.", item));
+                }
+                cpus.extend(first..=last);
+            },
+            None => cpus.push(parse(item)?),
+        }
+    }
+    Ok(cpus)
+}
+
+/// Get the number of bytes from seek up to end.
+fn bytes_until(seek: u64, end: u64) -> ah::Result<u64> {
+    if end < seek {
+        return Err(ah::format_err!("--end {} is before --seek {}.", end, seek));
+    }
+    Ok(end - seek)
+}
+
+/// Resolve the percentages of --seek, --bytes and --end against the size of the device.
+/// device_size is only called, if there are percentages.
+pub fn resolve_percent(args: &mut Args, device_size: impl FnOnce(&str) -> Option<u64>) -> ah::Result<()> {
+    if args.seek_percent.is_none() && args.bytes_percent.is_none() && args.end_percent.is_none() {
+        return Ok(());
+    }
+    let size = match device_size(&args.device) {
+        Some(size) => size,
+        None => return Err(ah::format_err!("The size of {:?} is unknown. \
+                                           --seek, --bytes and --end can't be given as percentage.",
+                                           args.device)),
+    };
+    if let Some(percent) = args.seek_percent.take() {
+        args.seek = percent_of(percent, size);
+    }
+    if let Some(percent) = args.bytes_percent.take() {
+        args.max_bytes = percent_of(percent, size);
+    }
+    if let Some(percent) = args.end_percent.take() {
+        args.end = Some(percent_of(percent, size));
+    }
+    if let Some(end) = args.end {
+        args.max_bytes = bytes_until(args.seek, end)?;
+    }
+    Ok(())
 }
 
 /// Parse all command line arguments and put them into a structure.
 pub fn parse_args<I, T>(args: I) -> ah::Result<Args>
+where I: IntoIterator<Item = T>,
+      T: Into<OsString> + Clone
+{
+    parse_args_list(args, false, None)
+}
+
+/// Parse the command line of one device of the --devices-from list.
+/// The options of the line of the device override the same options of the command line.
+/// The seed of the command line is used, unless the line of the device selects another one.
+pub fn parse_device_args<I, T>(args: I, seed: Option<&[u8]>) -> ah::Result<Args>
+where I: IntoIterator<Item = T>,
+      T: Into<OsString> + Clone
+{
+    parse_args_list(args, true, seed)
+}
+
+fn parse_args_list<I, T>(args: I, device_list: bool, list_seed: Option<&[u8]>) -> ah::Result<Args>
 where I: IntoIterator<Item = T>,
       T: Into<OsString> + Clone
 {
@@ -113,51 +1127,220 @@ where I: IntoIterator<Item = T>,
         ah::format_err!("Invalid {} value: {}", param, error)
     }
 
-    let args = App::new("disktest")
+    let app = App::new("disktest")
         .about(ABOUT)
-        .arg(Arg::with_name("device")
-             .index(1)
-             .required(true)
-             .help(HELP_DEVICE))
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .setting(AppSettings::VersionlessSubcommands)
+        .args(&common_args())
         .arg(Arg::with_name("write")
              .long("write")
              .short("w")
-             .help(HELP_WRITE))
+             .help(HELP_LEGACY_WRITE))
         .arg(Arg::with_name("verify")
              .long("verify")
              .short("v")
-             .help(HELP_VERIFY))
-        .arg(Arg::with_name("seek")
-             .long("seek")
-             .short("s")
-             .takes_value(true)
-             .help(HELP_SEEK))
-        .arg(Arg::with_name("bytes")
-             .long("bytes")
-             .short("b")
-             .takes_value(true)
-             .help(HELP_BYTES))
-        .arg(Arg::with_name("algorithm")
-             .long("algorithm")
-             .short("A")
-             .takes_value(true)
-             .help(HELP_ALGORITHM))
-        .arg(Arg::with_name("seed")
-             .long("seed")
-             .short("S")
-             .takes_value(true)
-             .help(HELP_SEED))
-        .arg(Arg::with_name("threads")
-             .long("threads")
-             .short("j")
-             .takes_value(true)
-             .help(HELP_THREADS))
-        .arg(Arg::with_name("quiet")
-             .long("quiet")
-             .short("q")
-             .takes_value(true)
-             .help(HELP_QUIET))
-        .get_matches_from_safe(args);
+             .help(HELP_LEGACY_VERIFY))
+        .args(&test_args())
+        .subcommand(SubCommand::with_name("write")
+                    .about(HELP_WRITE)
+                    .args(&common_args())
+                    .args(&test_args())
+                    .arg(Arg::with_name("verify")
+                         .long("verify")
+                         .short("v")
+                         .help(HELP_WRITE_VERIFY))
+                    .arg(Arg::with_name("verify-behind")
+                         .long("verify-behind")
+                         .takes_value(true)
+                         .conflicts_with_all(&["resume", "devices-from", "range", "ranges-from"])
+                         .help(HELP_VERIFY_BEHIND))
+                    .arg(Arg::with_name("loop")
+                         .long("loop")
+                         .help(HELP_LOOP))
+                    .arg(Arg::with_name("sync-writes")
+                         .long("sync-writes")
+                         .help(HELP_SYNC_WRITES))
+                    .arg(Arg::with_name("sync-every")
+                         .long("sync-every")
+                         .takes_value(true)
+                         .help(HELP_SYNC_EVERY)))
+        .subcommand(SubCommand::with_name("verify")
+                    .about(HELP_VERIFY)
+                    .args(&common_args())
+                    .args(&test_args())
+                    .arg(Arg::with_name("seed-round")
+                         .long("seed-round")
+                         .takes_value(true)
+                         .help(HELP_SEED_ROUND))
+                    .arg(Arg::with_name("verify-zero")
+                         .long("verify-zero")
+                         .conflicts_with_all(&["verify-value", "pattern", "algorithm", "seed", "seed-file", "kdf", "raw-seed"])
+                         .help(HELP_VERIFY_ZERO))
+                    .arg(Arg::with_name("verify-value")
+                         .long("verify-value")
+                         .takes_value(true)
+                         .conflicts_with_all(&["pattern", "algorithm", "seed", "seed-file", "kdf", "raw-seed"])
+                         .help(HELP_VERIFY_VALUE))
+                    .arg(Arg::with_name("expect-image")
+                         .long("expect-image")
+                         .takes_value(true)
+                         .conflicts_with_all(&["verify-zero", "verify-value", "pattern", "algorithm",
+                                               "seed", "seed-file", "kdf", "raw-seed",
+                                               "rounds", "resume", "report", "report-junit", "meta",
+                                               "smart", "kernel-log", "latency", "tui", "output",
+                                               "zones", "verify-stride",
+                                               "devices-from", "bad-blocks", "range", "ranges-from"])
+                         .help(HELP_EXPECT_IMAGE))
+                    .arg(Arg::with_name("image-seek")
+                         .long("image-seek")
+                         .takes_value(true)
+                         .requires("expect-image")
+                         .help(HELP_IMAGE_SEEK)))
+        .subcommand(SubCommand::with_name("wipe")
+                    .about(HELP_WIPE)
+                    .args(&common_args())
+                    .arg(Arg::with_name("scheme")
+                         .long("scheme")
+                         .takes_value(true)
+                         .conflicts_with("pattern")
+                         .help(HELP_WIPE_SCHEME))
+                    .arg(Arg::with_name("verify")
+                         .long("verify")
+                         .short("v")
+                         .help(HELP_WIPE_VERIFY)))
+        .subcommand(SubCommand::with_name("bench")
+                    .about(HELP_BENCH)
+                    .args(&common_args()))
+        .subcommand(SubCommand::with_name("scan")
+                    .about(HELP_SCAN)
+                    .args(&common_args()))
+        .subcommand(SubCommand::with_name("compare")
+                    .about(HELP_COMPARE)
+                    .args(&common_args())
+                    .arg(Arg::with_name("other")
+                         .index(2)
+                         .required(true)
+                         .help(HELP_OTHER)))
+        .subcommand(SubCommand::with_name("nondestructive")
+                    .about(HELP_NONDESTRUCTIVE)
+                    .args(&common_args())
+                    .arg(Arg::with_name("keep-going")
+                         .long("keep-going")
+                         .short("k")
+                         .help(HELP_KEEP_GOING)))
+        .subcommand(SubCommand::with_name("surface")
+                    .about(HELP_SURFACE)
+                    .args(&common_args())
+                    .arg(Arg::with_name("keep-going")
+                         .long("keep-going")
+                         .short("k")
+                         .help(HELP_KEEP_GOING)))
+        .subcommand(SubCommand::with_name("capacity-check")
+                    .about(HELP_CAPACITY_CHECK)
+                    .args(&common_args()))
+        .subcommand(SubCommand::with_name("trim-check")
+                    .about(HELP_TRIM_CHECK)
+                    .args(&common_args()))
+        .subcommand(SubCommand::with_name("secure-erase")
+                    .about(HELP_SECURE_ERASE)
+                    .args(&common_args())
+                    .arg(Arg::with_name("enhanced")
+                         .long("enhanced")
+                         .help(HELP_ENHANCED))
+                    .arg(Arg::with_name("report")
+                         .long("report")
+                         .takes_value(true)
+                         .help(HELP_REPORT)))
+        .subcommand(SubCommand::with_name("nvme-erase")
+                    .about(HELP_NVME_ERASE)
+                    .args(&common_args())
+                    .arg(Arg::with_name("method")
+                         .long("method")
+                         .takes_value(true)
+                         .help(HELP_NVME_METHOD))
+                    .arg(Arg::with_name("report")
+                         .long("report")
+                         .takes_value(true)
+                         .help(HELP_REPORT)))
+        .subcommand(SubCommand::with_name("fill")
+                    .about(HELP_FILL)
+                    .args(&common_args()))
+        .subcommand(SubCommand::with_name("info")
+                    .about(HELP_INFO)
+                    .arg(Arg::with_name("device")
+                         .index(1)
+                         .required_unless_one(&["serial", "by-id"])
+                         .help(HELP_DEVICE))
+                    .arg(Arg::with_name("serial")
+                         .long("serial")
+                         .takes_value(true)
+                         .conflicts_with_all(&["device", "by-id"])
+                         .help(HELP_SERIAL))
+                    .arg(Arg::with_name("by-id")
+                         .long("by-id")
+                         .takes_value(true)
+                         .conflicts_with("device")
+                         .help(HELP_BY_ID)))
+        .subcommand(SubCommand::with_name("serve")
+                    .about(HELP_SERVE)
+                    .arg(Arg::with_name("listen")
+                         .long("listen")
+                         .takes_value(true)
+                         .help(HELP_LISTEN))
+                    .arg(Arg::with_name("token-file")
+                         .long("token-file")
+                         .takes_value(true)
+                         .required(true)
+                         .help(HELP_TOKEN_FILE))
+                    .arg(Arg::with_name("force")
+                         .long("force")
+                         .help(HELP_SERVE_FORCE))
+                    .arg(Arg::with_name("allow-system-disk")
+                         .long("allow-system-disk")
+                         .help(HELP_SERVE_ALLOW_SYSTEM_DISK))
+                    .arg(Arg::with_name("yes")
+                         .long("yes")
+                         .short("y")
+                         .help(HELP_SERVE_YES))
+                    .arg(Arg::with_name("quiet")
+                         .long("quiet")
+                         .short("q")
+                         .takes_value(true)
+                         .help(HELP_QUIET)))
+        .subcommand(SubCommand::with_name("remote")
+                    .about(HELP_REMOTE)
+                    .setting(AppSettings::TrailingVarArg)
+                    .arg(Arg::with_name("agent")
+                         .index(1)
+                         .required(true)
+                         .help(HELP_AGENT))
+                    .arg(Arg::with_name("remote-command")
+                         .index(2)
+                         .required(true)
+                         .multiple(true)
+                         .allow_hyphen_values(true)
+                         .help(HELP_REMOTE_COMMAND))
+                    .arg(Arg::with_name("token-file")
+                         .long("token-file")
+                         .takes_value(true)
+                         .required(true)
+                         .help(HELP_TOKEN_FILE))
+                    .arg(Arg::with_name("report")
+                         .long("report")
+                         .takes_value(true)
+                         .help(HELP_REMOTE_REPORT))
+                    .arg(Arg::with_name("progress-json")
+                         .long("progress-json")
+                         .takes_value(true)
+                         .help(HELP_PROGRESS_JSON)));
+    // Only for the devices of a list a later option overrides an earlier one.
+    // Everywhere else a repeated option is an error.
+    let app = if device_list {
+        app.global_setting(AppSettings::AllArgsOverrideSelf)
+    } else {
+        app
+    };
+    let args = app.get_matches_from_safe(args);
 
     let args = match args {
         Ok(x) => x,
@@ -173,42 +1356,175 @@ where I: IntoIterator<Item = T>,
         },
     };
 
-    let quiet: u8 = match args.value_of("quiet").unwrap_or("0").parse() {
+    // Select the operation and the matches that hold the operation's options.
+    let flat_cmdline = args.subcommand_name().is_none();
+    let (command, write, mut verify, args) = match args.subcommand() {
+        ("write", Some(m)) => (Command::Test, true, m.is_present("verify"), m),
+        ("verify", Some(m)) => (Command::Test, false, true, m),
+        ("wipe", Some(m)) => (Command::Wipe, true, m.is_present("verify"), m),
+        ("bench", Some(m)) => (Command::Bench, true, true, m),
+        ("scan", Some(m)) => (Command::Scan, false, false, m),
+        ("compare", Some(m)) => (Command::Compare, false, false, m),
+        ("nondestructive", Some(m)) => (Command::Nondestructive, true, true, m),
+        ("surface", Some(m)) => (Command::Surface, true, true, m),
+        ("capacity-check", Some(m)) => (Command::CapacityCheck, true, true, m),
+        ("trim-check", Some(m)) => (Command::TrimCheck, true, true, m),
+        ("secure-erase", Some(m)) => (Command::SecureErase, true, true, m),
+        ("nvme-erase", Some(m)) => (Command::NvmeErase, true, true, m),
+        ("fill", Some(m)) => (Command::Fill, true, true, m),
+        ("info", Some(m)) => (Command::Info, false, false, m),
+        ("serve", Some(m)) => (Command::Serve, false, false, m),
+        ("remote", Some(m)) => (Command::Remote, false, false, m),
+        // Deprecated flat command line without subcommand.
+        _ => (Command::Test, args.is_present("write"), args.is_present("verify"), &args),
+    };
+    let verify_behind = match args.value_of("verify-behind") {
+        Some(x) => match parsebytes(x) {
+            Ok(y) => Some(y),
+            Err(e) => return Err(param_err("--verify-behind", e)),
+        },
+        None => None,
+    };
+    let loop_mode = args.is_present("loop");
+    let sync_every = match args.value_of("sync-every") {
+        Some(x) => match parsebytes(x) {
+            Ok(y) if y > 0 => Some(y),
+            Ok(_) => return Err(param_err("--sync-every", "0")),
+            Err(e) => return Err(param_err("--sync-every", e)),
+        },
+        None => None,
+    };
+    if command == Command::Test && ((!write && !verify) || verify_behind.is_some() || loop_mode) {
+        verify = true;
+    }
+
+    let mut quiet: u8 = match args.value_of("quiet").unwrap_or("0").parse() {
         Ok(x) => x,
         Err(e) => return Err(param_err("--quiet", e)),
     };
 
-    let device = args.value_of("device").unwrap().to_string();
-
-    let write = args.is_present("write");
-    let mut verify = args.is_present("verify");
-    if !write && !verify {
-        verify = true;
+    let output = match args.value_of("output").unwrap_or("text") {
+        "text" => OutputFormat::Text,
+        "summary-json" => OutputFormat::SummaryJson,
+        x => return Err(param_err("--output", format!("Unknown format {:?}.", x))),
+    };
+    // stdout only carries the summary.
+    if output == OutputFormat::SummaryJson {
+        quiet = 2;
     }
 
-    let seek = match parsebytes(args.value_of("seek").unwrap_or("0")) {
-        Ok(x) => x,
-        Err(e) => return Err(param_err("--seek", e)),
+    // The device is empty, if the devices are read from a list.
+    let device = args.value_of("device").unwrap_or("").to_string();
+    let devices_from = args.value_of("devices-from").map(|x| x.to_string());
+
+    // Percentages are resolved by resolve_percent(), when the device is known.
+    let seek_str = args.value_of("seek").unwrap_or("0");
+    let (seek, seek_percent) = match parsepercent(seek_str) {
+        Some(Ok(x)) => (0, Some(x)),
+        Some(Err(e)) => return Err(param_err("--seek", e)),
+        None => match parsebytes(seek_str) {
+            Ok(x) => (x, None),
+            Err(e) => return Err(param_err("--seek", e)),
+        },
     };
 
-    let max_bytes = match parsebytes(args.value_of("bytes").unwrap_or(&Disktest::UNLIMITED.to_string())) {
+    let default_bytes = if command == Command::Bench {
+        DEFAULT_BENCH_BYTES
+    } else {
+        Disktest::UNLIMITED
+    };
+    let (max_bytes, bytes_percent) = match args.value_of("bytes") {
+        Some(x) => match parsepercent(x) {
+            Some(Ok(y)) => (default_bytes, Some(y)),
+            Some(Err(e)) => return Err(param_err("--bytes", e)),
+            None => match parsebytes(x) {
+                Ok(y) => (y, None),
+                Err(e) => return Err(param_err("--bytes", e)),
+            },
+        },
+        None => (default_bytes, None),
+    };
+    let (end, end_percent) = match args.value_of("end") {
+        Some(x) => match parsepercent(x) {
+            Some(Ok(y)) => (None, Some(y)),
+            Some(Err(e)) => return Err(param_err("--end", e)),
+            None => match parsebytes(x) {
+                Ok(y) => (Some(y), None),
+                Err(e) => return Err(param_err("--end", e)),
+            },
+        },
+        None => (None, None),
+    };
+    let max_bytes = match end {
+        Some(end) if seek_percent.is_none() => bytes_until(seek, end)?,
+        _ => max_bytes,
+    };
+
+    // Checking for a constant value is verifying a one byte pattern.
+    let verify_value = match args.value_of("verify-value") {
+        Some(x) => match parse_pattern(x) {
+            Ok(x) if x.len() == 1 => Some(x),
+            Ok(_) => return Err(param_err("--verify-value", x)),
+            Err(e) => return Err(param_err("--verify-value", e)),
+        },
+        None if args.is_present("verify-zero") => Some(vec![0x00]),
+        None => None,
+    };
+
+    let pattern = match args.value_of("pattern") {
+        Some(x) => match parse_pattern(x) {
+            Ok(x) => Some(x),
+            Err(e) => return Err(param_err("--pattern", e)),
+        },
+        None => verify_value.clone(),
+    };
+
+    let algorithm: DtStreamType = match args.value_of("algorithm").unwrap_or("CHACHA20").parse() {
+        Ok(DtStreamType::PATTERN) => {
+            return Err(ah::format_err!("Please use --pattern to select a fixed byte pattern."));
+        },
+        Ok(_) if pattern.is_some() => DtStreamType::PATTERN,
         Ok(x) => x,
-        Err(e) => return Err(param_err("--bytes", e)),
+        Err(_) => return Err(param_err("--algorithm", args.value_of("algorithm").unwrap())),
     };
 
-    let algorithm = match args.value_of("algorithm").unwrap_or("CHACHA20").to_uppercase().as_str() {
-        "CHACHA8" => DtStreamType::CHACHA8,
-        "CHACHA12" => DtStreamType::CHACHA12,
-        "CHACHA20" => DtStreamType::CHACHA20,
-        "CRC" => DtStreamType::CRC,
-        x => return Err(param_err("--algorithm", x)),
+    let kdf: DtKdf = match args.value_of("kdf") {
+        None if args.is_present("raw-seed") => DtKdf::Raw,
+        Some(x) => match x.parse() {
+            Ok(x) => x,
+            Err(e) => return Err(param_err("--kdf", e)),
+        },
+        None => DtKdf::default(),
     };
 
-    let (seed, user_seed) = match args.value_of("seed") {
-        Some(x) => (x.to_string(), true),
-        None => (gen_seed_string(DEFAULT_GEN_SEED_LEN), false),
+    // The pattern takes the place of the seed.
+    let (seed, user_seed) = match (args.value_of("seed"), &pattern) {
+        (_, Some(x)) => (x.iter().map(|b| format!("{:02x}", b)).collect::<String>().into_bytes(), true),
+        (_, None) if args.is_present("seed-file") => {
+            let path = args.value_of("seed-file").unwrap();
+            match read_seed_file(path) {
+                Ok(x) => (x, true),
+                Err(e) => return Err(param_err("--seed-file", e)),
+            }
+        },
+        (None, None) if list_seed.is_some() => (list_seed.unwrap().to_vec(), true),
+        (Some(RANDOM_SEED), None) if verify && !write => {
+            return Err(ah::format_err!("Verify-only mode can't use --seed {}. \
+                                       Please provide the seed of the write run.", RANDOM_SEED));
+        },
+        (Some(RANDOM_SEED), None) | (None, None) => (gen_seed_string(gen_seed_len(kdf)).into_bytes(), false),
+        (Some(x), None) => (x.as_bytes().to_vec(), true),
     };
-    if !user_seed && verify && !write {
+    if kdf == DtKdf::Raw && pattern.is_none() && seed.len() != RAW_KEY_SIZE {
+        return Err(param_err("--seed", format!("The raw seed must be {} bytes long, \
+                                                but it is {} bytes long.",
+                                               RAW_KEY_SIZE, seed.len())));
+    }
+    let resume = args.value_of("resume").map(|x| x.to_string());
+    let meta = args.value_of("meta").map(|x| x.to_string());
+    let expect_image = args.is_present("expect-image");
+    if !user_seed && verify && !write && resume.is_none() && meta.is_none() &&
+       devices_from.is_none() && !expect_image {
         return Err(ah::format_err!("Verify-only mode requires --seed. \
                                    Please either provide a --seed, \
                                    or enable --verify and --write mode."));
@@ -216,7 +1532,7 @@ where I: IntoIterator<Item = T>,
 
     let threads: usize = match args.value_of("threads").unwrap_or("1").parse() {
         Ok(x) => {
-            if x > std::u16::MAX as usize + 1 {
+            if x > u16::MAX as usize + 1 {
                 return Err(param_err("--threads", x))
             }
             x
@@ -224,17 +1540,281 @@ where I: IntoIterator<Item = T>,
         Err(e) => return Err(param_err("--threads", e)),
     };
 
+    let chunk_size = match args.value_of("chunk-size") {
+        Some(x) => match parsebytes(x) {
+            Ok(y) if y == 0 ||
+                     y > MAX_CHUNK_SIZE ||
+                     y % algorithm.base_size() as u64 != 0 => {
+                return Err(param_err("--chunk-size", x));
+            },
+            Ok(y) => Some(y as usize),
+            Err(e) => return Err(param_err("--chunk-size", e)),
+        },
+        None => None,
+    };
+
+    let wipe_passes = match parse_wipe_scheme(args.value_of("scheme").unwrap_or("random")) {
+        Ok(x) => x,
+        Err(e) => return Err(param_err("--scheme", e)),
+    };
+
+    let nvme_erase = match args.value_of("method").unwrap_or("format").parse::<NvmeErase>() {
+        Ok(x) => x,
+        Err(e) => return Err(param_err("--method", e)),
+    };
+
+    let direct = args.is_present("direct");
+    let read_retries = match args.value_of("read-retries") {
+        Some(x) => match x.parse::<u32>() {
+            Ok(y) => y,
+            Err(e) => return Err(param_err("--read-retries", e)),
+        },
+        None => 0,
+    };
+    let max_rate = match args.value_of("max-rate") {
+        Some(x) => match parsebytes(x) {
+            Ok(0) => return Err(param_err("--max-rate", x)),
+            Ok(y) => Some(y),
+            Err(e) => return Err(param_err("--max-rate", e)),
+        },
+        None => None,
+    };
+    let max_time = match args.value_of("max-time") {
+        Some(x) => match parse_duration(x) {
+            Ok(y) if y.as_secs() == 0 => return Err(param_err("--max-time", x)),
+            Ok(y) => Some(y),
+            Err(e) => return Err(param_err("--max-time", e)),
+        },
+        None => None,
+    };
+    let nice = match args.value_of("nice") {
+        Some(x) => match x.parse::<i32>() {
+            Ok(y) if !(NICE_MIN..=NICE_MAX).contains(&y) => return Err(param_err("--nice", x)),
+            Ok(y) => Some(y),
+            Err(e) => return Err(param_err("--nice", e)),
+        },
+        None => None,
+    };
+    let io_priority = match args.value_of("io-priority") {
+        Some(x) => match IoPriority::parse(x) {
+            Ok(y) => Some(y),
+            Err(e) => return Err(param_err("--io-priority", e)),
+        },
+        None => None,
+    };
+    let verify_stride = match args.value_of("verify-stride") {
+        Some(x) => match x.parse::<u64>() {
+            Ok(0) => return Err(param_err("--verify-stride", x)),
+            Ok(y) => y,
+            Err(e) => return Err(param_err("--verify-stride", e)),
+        },
+        None => 1,
+    };
+    let zones = match args.value_of("zones") {
+        Some(x) => match x.parse::<usize>() {
+            Ok(0) => return Err(param_err("--zones", x)),
+            Ok(y) => Some(y),
+            Err(e) => return Err(param_err("--zones", e)),
+        },
+        None => None,
+    };
+    let cpu_set = match args.value_of("cpu-set") {
+        Some(x) => match parse_cpu_set(x) {
+            Ok(y) => Some(y),
+            Err(e) => return Err(param_err("--cpu-set", e)),
+        },
+        None => None,
+    };
+    let progress_json = args.value_of("progress-json").map(|x| x.to_string());
+    let metrics_listen = args.value_of("metrics-listen").map(|x| x.to_string());
+
+    // The endurance loop repeats forever by default.
+    let default_rounds = if loop_mode { "0" } else { "1" };
+    let rounds: u64 = match args.value_of("rounds").unwrap_or(default_rounds).parse() {
+        Ok(x) => x,
+        Err(e) => return Err(param_err("--rounds", e)),
+    };
+
+    let seed_round = match args.value_of("seed-round").unwrap_or("1").parse::<u64>() {
+        Ok(0) => return Err(param_err("--seed-round", "0")),
+        Ok(x) => x,
+        Err(e) => return Err(param_err("--seed-round", e)),
+    };
+
+    let report = args.value_of("report").map(|x| x.to_string());
+    let report_junit = args.value_of("report-junit").map(|x| x.to_string());
+    let max_errors = match args.value_of("max-errors") {
+        Some(x) => match x.parse::<usize>() {
+            Ok(0) => return Err(param_err("--max-errors", x)),
+            Ok(y) => Some(y),
+            Err(e) => return Err(param_err("--max-errors", e)),
+        },
+        None => None,
+    };
+    let keep_going = args.is_present("keep-going") ||
+                     max_errors.is_some() ||
+                     verify_value.is_some() ||
+                     expect_image ||
+                     command == Command::Scan ||
+                     command == Command::Compare;
+
+    let bad_blocks = args.value_of("bad-blocks").map(|x| x.to_string());
+    let skip_list = args.value_of("skip-list").map(|x| x.to_string());
+    let list_block_size = match args.value_of("block-size-for-list") {
+        Some(x) => match parsebytes(x) {
+            Ok(0) => return Err(param_err("--block-size-for-list", x)),
+            Ok(x) => x,
+            Err(e) => return Err(param_err("--block-size-for-list", e)),
+        },
+        None => DEFAULT_LIST_BLOCK_SIZE,
+    };
+
+    // The device - is the stdout or the stdin stream.
+    let mut ranges = vec![];
+    if let Some(values) = args.values_of("range") {
+        for x in values {
+            match parse_range(x) {
+                Ok(range) => ranges.push(range),
+                Err(e) => return Err(param_err("--range", e)),
+            }
+        }
+    }
+    if let Some(path) = args.value_of("ranges-from") {
+        ranges.extend(read_ranges(Path::new(path))?);
+    }
+    let ranges = merge_ranges(ranges);
+
+    let header = !args.is_present("no-header") && device != STDIO_PATH && ranges.is_empty();
+    if device == STDIO_PATH {
+        if command != Command::Test {
+            return Err(ah::format_err!("The device {} can only be used by write and verify.",
+                                       STDIO_PATH));
+        }
+        if write && verify {
+            return Err(ah::format_err!("Writing to stdout and verifying from stdin \
+                                       can't be done in the same run. Please run \
+                                       'disktest write {}' and 'disktest verify {}' separately.",
+                                       STDIO_PATH, STDIO_PATH));
+        }
+        if direct || resume.is_some() || rounds != 1 || verify_stride != 1 || skip_list.is_some() ||
+           !ranges.is_empty() {
+            return Err(ah::format_err!("The device {} can't be used with --direct, \
+                                       --resume, --rounds, --verify-stride, --skip-list or --range.",
+                                       STDIO_PATH));
+        }
+        if write && !user_seed {
+            return Err(ah::format_err!("Writing to stdout requires a --seed, \
+                                       because the generated seed can't be printed."));
+        }
+        if write && output == OutputFormat::SummaryJson {
+            return Err(ah::format_err!("Writing to stdout can't be combined with \
+                                       --output summary-json."));
+        }
+        // The data is written to stdout.
+        if write {
+            quiet = 2;
+        }
+    }
+
+    let other = args.value_of("expect-image")
+        .or_else(|| args.value_of("other"))
+        .unwrap_or("").to_string();
+    let other_seek = match parsebytes(args.value_of("image-seek").unwrap_or("0")) {
+        Ok(x) => x,
+        Err(e) => return Err(param_err("--image-seek", e)),
+    };
+
+    let agent = match command {
+        Command::Serve => args.value_of("listen").unwrap_or("127.0.0.1").to_string(),
+        _ => args.value_of("agent").unwrap_or("").to_string(),
+    };
+    let token = match args.value_of("token-file") {
+        Some(x) => match read_token(x) {
+            Ok(x) => x,
+            Err(e) => return Err(param_err("--token-file", e)),
+        },
+        None => vec![],
+    };
+    let remote_args = match args.values_of("remote-command") {
+        Some(x) => x.map(|x| x.to_string()).collect(),
+        None => vec![],
+    };
+
     Ok(Args {
+        command,
+        flat_cmdline,
         device,
+        serial: args.value_of("serial").map(|x| x.to_string()),
+        by_id: args.value_of("by-id").map(|x| x.to_string()),
         write,
         verify,
         seek,
         max_bytes,
+        seek_percent,
+        bytes_percent,
+        end,
+        end_percent,
         algorithm,
         seed,
         user_seed,
+        seed_round,
+        kdf,
+        pattern,
+        wipe_passes,
+        enhanced_erase: args.is_present("enhanced"),
+        nvme_erase,
         threads,
+        chunk_size,
         quiet,
+        direct,
+        sync_writes: args.is_present("sync-writes"),
+        sync_every,
+        rounds,
+        loop_mode,
+        resume,
+        report,
+        report_junit,
+        keep_going,
+        max_errors,
+        devices_from,
+        bad_blocks,
+        skip_list,
+        ranges,
+        list_block_size,
+        header,
+        preserve_ptable: args.is_present("preserve-ptable"),
+        meta,
+        smart: args.is_present("smart"),
+        kernel_log: args.is_present("kernel-log"),
+        latency:    args.is_present("latency"),
+        tui:        args.is_present("tui"),
+        output,
+        zones,
+        verify_stride,
+        mmap: args.is_present("mmap"),
+        verify_behind,
+        read_retries,
+        force:      args.is_present("force"),
+        allow_system_disk: args.is_present("allow-system-disk"),
+        yes:        args.is_present("yes"),
+        max_rate,
+        max_time,
+        nice,
+        io_priority,
+        cpu_set,
+        progress_json,
+        metrics_listen,
+        log: args.value_of("log").map(|x| x.to_string()),
+        throughput_log: args.value_of("throughput-log").map(|x| x.to_string()),
+        on_success: args.value_of("on-success").map(|x| x.to_string()),
+        on_failure: args.value_of("on-failure").map(|x| x.to_string()),
+        notify: args.is_present("notify"),
+        agent,
+        token,
+        remote_args,
+        other,
+        expect_image,
+        other_seek,
     })
 }
 
@@ -243,6 +1823,7 @@ mod tests {
     use super::*;
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_parse_args() {
         assert!(parse_args(vec!["disktest", "--does-not-exist"]).is_err());
 
@@ -253,13 +1834,24 @@ mod tests {
         assert_eq!(a.seek, 0);
         assert_eq!(a.max_bytes, Disktest::UNLIMITED);
         assert_eq!(a.algorithm, DtStreamType::CHACHA20);
-        assert_eq!(a.seed, "x");
+        assert_eq!(a.seed, b"x");
         assert_eq!(a.user_seed, true);
         assert_eq!(a.threads, 1);
+        assert_eq!(a.chunk_size, None);
         assert_eq!(a.quiet, 0);
+        assert_eq!(a.rounds, 1);
+        assert_eq!(a.resume, None);
+        assert_eq!(a.report, None);
+        assert!(!a.keep_going);
+        assert_eq!(a.bad_blocks, None);
+        assert_eq!(a.skip_list, None);
+        assert_eq!(a.list_block_size, DEFAULT_LIST_BLOCK_SIZE);
+        assert_eq!(a.progress_json, None);
+        assert!(!a.direct);
 
         let a = parse_args(vec!["disktest", "--write", "/dev/foobar"]).unwrap();
         assert_eq!(a.device, "/dev/foobar");
+        assert!(a.flat_cmdline);
         assert_eq!(a.write, true);
         assert_eq!(a.verify, false);
         assert_eq!(a.user_seed, false);
@@ -305,17 +1897,92 @@ mod tests {
         assert_eq!(a.algorithm, DtStreamType::CHACHA8);
         let a = parse_args(vec!["disktest", "-w", "-A", "chacha12", "/dev/foobar"]).unwrap();
         assert_eq!(a.algorithm, DtStreamType::CHACHA12);
+        let a = parse_args(vec!["disktest", "-w", "--algorithm", "AES128", "/dev/foobar"]).unwrap();
+        assert_eq!(a.algorithm, DtStreamType::AES128);
+        let a = parse_args(vec!["disktest", "-w", "-Aaes256", "/dev/foobar"]).unwrap();
+        assert_eq!(a.algorithm, DtStreamType::AES256);
+        let a = parse_args(vec!["disktest", "-w", "-A", "blake3", "/dev/foobar"]).unwrap();
+        assert_eq!(a.algorithm, DtStreamType::BLAKE3);
+        let a = parse_args(vec!["disktest", "-w", "-A", "shake256", "/dev/foobar"]).unwrap();
+        assert_eq!(a.algorithm, DtStreamType::SHAKE256);
         let a = parse_args(vec!["disktest", "-w", "-A", "crc", "/dev/foobar"]).unwrap();
         assert_eq!(a.algorithm, DtStreamType::CRC);
+        let a = parse_args(vec!["disktest", "-w", "-A", "crc32c", "/dev/foobar"]).unwrap();
+        assert_eq!(a.algorithm, DtStreamType::CRC32C);
         assert!(parse_args(vec!["disktest", "-w", "-A", "invalid", "/dev/foobar"]).is_err());
 
+        let a = parse_args(vec!["disktest", "write", "--seed", "random", "/dev/foobar"]).unwrap();
+        assert_eq!(a.seed.len(), DEFAULT_GEN_SEED_LEN);
+        assert!(!a.user_seed);
+        assert!(parse_args(vec!["disktest", "verify", "--seed", "random", "/dev/foobar"]).is_err());
         let a = parse_args(vec!["disktest", "-w", "--seed", "mysecret", "/dev/foobar"]).unwrap();
-        assert_eq!(a.seed, "mysecret");
+        assert_eq!(a.seed, b"mysecret");
         assert_eq!(a.user_seed, true);
         let a = parse_args(vec!["disktest", "-w", "-S", "mysecret", "/dev/foobar"]).unwrap();
-        assert_eq!(a.seed, "mysecret");
+        assert_eq!(a.seed, b"mysecret");
         assert_eq!(a.user_seed, true);
 
+        let tdir = tempfile::tempdir().unwrap();
+        let seed_path = tdir.path().join("seed");
+        std::fs::write(&seed_path, b"\x01\x02secret").unwrap();
+        let seed_path = seed_path.to_str().unwrap();
+        let a = parse_args(vec!["disktest", "verify", "--seed-file", seed_path, "/dev/foobar"]).unwrap();
+        assert_eq!(a.seed, b"\x01\x02secret");
+        assert!(a.user_seed);
+        assert!(parse_args(vec!["disktest", "write", "--seed-file", seed_path, "-Sx", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "write", "--seed-file", "/nonexistent", "/dev/foobar"]).is_err());
+        assert_eq!(a.pattern, None);
+
+        let a = parse_args(vec!["disktest", "-w", "/dev/foobar"]).unwrap();
+        assert_eq!(a.kdf, DtKdf::default());
+        let a = parse_args(vec!["disktest", "-w", "--kdf", "scrypt:10:8:1", "/dev/foobar"]).unwrap();
+        assert_eq!(a.kdf, DtKdf::Scrypt { log_n: 10, r: 8, p: 1 });
+        let a = parse_args(vec!["disktest", "-w", "--kdf", "argon2id", "/dev/foobar"]).unwrap();
+        assert_eq!(a.kdf.name(), "Argon2id");
+        assert!(parse_args(vec!["disktest", "-w", "--kdf", "bcrypt", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "write", "--kdf", "pbkdf2", "--pattern", "ff", "/dev/foobar"]).is_err());
+
+        let a = parse_args(vec!["disktest", "verify", "--raw-seed", "-S", "0123456789abcdef0123456789abcdef",
+                                "/dev/foobar"]).unwrap();
+        assert_eq!(a.kdf, DtKdf::Raw);
+        assert_eq!(a.seed.len(), RAW_KEY_SIZE);
+        let a = parse_args(vec!["disktest", "write", "--raw-seed", "/dev/foobar"]).unwrap();
+        assert_eq!(a.seed.len(), RAW_KEY_SIZE);
+        assert!(!a.user_seed);
+        assert!(parse_args(vec!["disktest", "write", "--raw-seed", "-Sx", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "write", "--raw-seed", "--kdf", "raw", "/dev/foobar"]).is_err());
+
+        let a = parse_args(vec!["disktest", "verify", "--pattern", "0xAA55", "/dev/foobar"]).unwrap();
+        assert_eq!(a.algorithm, DtStreamType::PATTERN);
+        assert_eq!(a.pattern, Some(vec![0xAA, 0x55]));
+        assert_eq!(a.seed, b"aa55");
+        assert!(a.user_seed);
+        let a = parse_args(vec!["disktest", "write", "--pattern", "ff", "/dev/foobar"]).unwrap();
+        assert_eq!(a.pattern, Some(vec![0xFF]));
+        assert!(parse_args(vec!["disktest", "write", "--pattern", "0x", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "write", "--pattern", "0xA", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "write", "--pattern", "0xXY", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "write", "--pattern", "00", "-Sx", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "write", "--pattern", "00", "-ACRC", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "write", "-APATTERN", "/dev/foobar"]).is_err());
+
+        let a = parse_args(vec!["disktest", "verify", "--verify-zero", "/dev/foobar"]).unwrap();
+        assert_eq!(a.algorithm, DtStreamType::PATTERN);
+        assert_eq!(a.pattern, Some(vec![0x00]));
+        assert!(!a.write);
+        assert!(a.verify);
+        assert!(a.keep_going);
+        let a = parse_args(vec!["disktest", "verify", "--verify-value", "0xFF", "/dev/foobar"]).unwrap();
+        assert_eq!(a.pattern, Some(vec![0xFF]));
+        assert!(a.keep_going);
+        assert!(parse_args(vec!["disktest", "verify", "--verify-value", "0xAA55",
+                                "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "verify", "--verify-zero", "-Sx",
+                                "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "verify", "--verify-zero", "--verify-value", "0",
+                                "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "write", "--verify-zero", "/dev/foobar"]).is_err());
+
         let a = parse_args(vec!["disktest", "-w", "--threads", "24", "/dev/foobar"]).unwrap();
         assert_eq!(a.threads, 24);
         let a = parse_args(vec!["disktest", "-w", "-j24", "/dev/foobar"]).unwrap();
@@ -324,11 +1991,400 @@ mod tests {
         assert_eq!(a.threads, 0);
         assert!(parse_args(vec!["disktest", "-w", "-j65537", "/dev/foobar"]).is_err());
 
+        let a = parse_args(vec!["disktest", "write", "/dev/foobar"]).unwrap();
+        assert!(!a.flat_cmdline);
+        assert_eq!(a.command, Command::Test);
+        assert_eq!(a.device, "/dev/foobar");
+        assert!(a.write);
+        assert!(!a.verify);
+        let a = parse_args(vec!["disktest", "write", "-v", "-s", "1k", "/dev/foobar"]).unwrap();
+        assert_eq!(a.command, Command::Test);
+        assert!(a.write);
+        assert!(a.verify);
+        assert_eq!(a.seek, 1024);
+        let a = parse_args(vec!["disktest", "write", "--direct", "/dev/foobar"]).unwrap();
+        assert!(a.direct);
+        let a = parse_args(vec!["disktest", "verify", "-Sx", "-D", "/dev/foobar"]).unwrap();
+        assert!(a.direct);
+        let a = parse_args(vec!["disktest", "verify", "-Sx", "--mmap", "disk.img"]).unwrap();
+        assert!(a.mmap);
+        assert!(parse_args(vec!["disktest", "verify", "-Sx", "--mmap", "-D", "disk.img"]).is_err());
+        let a = parse_args(vec!["disktest", "write", "--rounds", "3", "/dev/foobar"]).unwrap();
+        assert_eq!(a.rounds, 3);
+        let a = parse_args(vec!["disktest", "write", "-R0", "/dev/foobar"]).unwrap();
+        assert_eq!(a.rounds, 0);
+        assert!(parse_args(vec!["disktest", "write", "-Rx", "/dev/foobar"]).is_err());
+        let a = parse_args(vec!["disktest", "verify", "-Sx", "--seed-round", "3", "/dev/foobar"]).unwrap();
+        assert_eq!(a.seed_round, 3);
+        assert!(parse_args(vec!["disktest", "verify", "-Sx", "--seed-round", "0", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "write", "--seed-round", "2", "/dev/foobar"]).is_err());
+        let a = parse_args(vec!["disktest", "write", "--loop", "/dev/foobar"]).unwrap();
+        assert!(a.loop_mode);
+        assert!(a.verify);
+        assert_eq!(a.rounds, 0);
+        let a = parse_args(vec!["disktest", "write", "--loop", "-R5", "/dev/foobar"]).unwrap();
+        assert_eq!(a.rounds, 5);
+        assert!(parse_args(vec!["disktest", "verify", "-Sx", "--loop", "/dev/foobar"]).is_err());
+        let a = parse_args(vec!["disktest", "write", "--sync-writes", "/dev/foobar"]).unwrap();
+        assert!(a.sync_writes);
+        assert!(parse_args(vec!["disktest", "verify", "-Sx", "--sync-writes", "/dev/foobar"]).is_err());
+        let a = parse_args(vec!["disktest", "write", "--sync-every", "1G", "/dev/foobar"]).unwrap();
+        assert_eq!(a.sync_every, Some(1024 * 1024 * 1024));
+        assert!(parse_args(vec!["disktest", "write", "--sync-every", "0", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "verify", "-Sx", "--sync-every", "1G", "/dev/foobar"]).is_err());
+        let a = parse_args(vec!["disktest", "verify", "-Sx", "/dev/foobar"]).unwrap();
+        assert_eq!(a.command, Command::Test);
+        assert!(!a.write);
+        assert!(a.verify);
+        assert!(parse_args(vec!["disktest", "verify", "/dev/foobar"]).is_err());
+        let a = parse_args(vec!["disktest", "verify", "--resume", "state", "/dev/foobar"]).unwrap();
+        assert_eq!(a.resume, Some("state".to_string()));
+        let a = parse_args(vec!["disktest", "write", "--report", "r.json", "/dev/foobar"]).unwrap();
+        assert_eq!(a.report, Some("r.json".to_string()));
+        let a = parse_args(vec!["disktest", "verify", "-Sx", "--keep-going", "/dev/foobar"]).unwrap();
+        assert!(a.keep_going);
+        let a = parse_args(vec!["disktest", "write", "-v", "-k", "/dev/foobar"]).unwrap();
+        assert!(a.keep_going);
+        assert_eq!(a.max_errors, None);
+        let a = parse_args(vec!["disktest", "write", "-v", "--max-errors", "10", "/dev/foobar"]).unwrap();
+        assert!(a.keep_going);
+        assert_eq!(a.max_errors, Some(10));
+        assert!(parse_args(vec!["disktest", "write", "-v", "--max-errors", "0", "/dev/foobar"]).is_err());
+        let a = parse_args(vec!["disktest", "write", "-v", "--bad-blocks", "bb.txt",
+                                "--block-size-for-list", "1k", "/dev/foobar"]).unwrap();
+        assert_eq!(a.bad_blocks, Some("bb.txt".to_string()));
+        assert_eq!(a.list_block_size, 1024);
+        let a = parse_args(vec!["disktest", "verify", "-Sx", "--skip-list", "bb.txt", "/dev/foobar"]).unwrap();
+        assert_eq!(a.skip_list, Some("bb.txt".to_string()));
+        let a = parse_args(vec!["disktest", "write", "--devices-from", "list"]).unwrap();
+        assert_eq!(a.devices_from, Some("list".to_string()));
+        assert_eq!(a.device, "");
+        let a = parse_args(vec!["disktest", "verify", "--devices-from", "list"]).unwrap();
+        assert_eq!(a.devices_from, Some("list".to_string()));
+        assert!(parse_args(vec!["disktest", "write"]).is_err());
+        let a = parse_args(vec!["disktest", "write", "--serial", "S3Z1NB0K"]).unwrap();
+        assert_eq!(a.serial, Some("S3Z1NB0K".to_string()));
+        assert_eq!(a.device, "");
+        let a = parse_args(vec!["disktest", "info", "--by-id", "usb-Stick-0:0"]).unwrap();
+        assert_eq!(a.by_id, Some("usb-Stick-0:0".to_string()));
+        assert_eq!(a.serial, None);
+        assert!(parse_args(vec!["disktest", "write", "--serial", "S", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "write", "--serial", "S", "--by-id", "x"]).is_err());
+        assert!(parse_args(vec!["disktest", "write", "--by-id", "x", "--devices-from", "list"]).is_err());
+        assert!(parse_args(vec!["disktest", "wipe", "--devices-from", "list"]).is_err());
+        assert!(parse_args(vec!["disktest", "write", "--devices-from", "list", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "write", "--devices-from", "list", "--meta", "m"]).is_err());
+        assert!(parse_args(vec!["disktest", "write", "--seek", "1", "--seek", "2", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "write", "-b", "1", "--bytes", "2", "/dev/foobar"]).is_err());
+        let a = parse_device_args(vec!["disktest", "write", "--bytes", "1G", "-R", "3", "--direct",
+                                       "/dev/foobar", "-b", "2G", "--direct"], None).unwrap();
+        assert_eq!(a.max_bytes, 2 * 1024 * 1024 * 1024);
+        assert_eq!(a.rounds, 3);
+        assert!(a.direct);
+        let a = parse_device_args(vec!["disktest", "write", "-Sx", "-b", "1M", "/dev/foobar", "-Sy"], None).unwrap();
+        assert_eq!(a.seed, b"y");
+        assert_eq!(a.max_bytes, parsebytes("1M").unwrap());
+        let a = parse_device_args(vec!["disktest", "verify", "/dev/foobar"], Some(&[0x00, 0xff][..])).unwrap();
+        assert_eq!(a.seed, b"\x00\xff");
+        assert!(a.user_seed);
+        let a = parse_device_args(vec!["disktest", "verify", "/dev/foobar", "-Sy"], Some(&[0x00, 0xff][..])).unwrap();
+        assert_eq!(a.seed, b"y");
+        assert!(parse_args(vec!["disktest", "write", "-Sx", "-b", "1M", "/dev/foobar", "-Sy"]).is_err());
+
+        assert!(parse_args(vec!["disktest", "write", "--block-size-for-list", "0",
+                                "/dev/foobar"]).is_err());
+        let a = parse_args(vec!["disktest", "write", "--chunk-size", "64k", "/dev/foobar"]).unwrap();
+        assert_eq!(a.chunk_size, Some(64 * 1024));
+        let a = parse_args(vec!["disktest", "write", "-ACRC", "--chunk-size", "2k",
+                                "/dev/foobar"]).unwrap();
+        assert_eq!(a.chunk_size, Some(2 * 1024));
+        assert!(parse_args(vec!["disktest", "write", "--chunk-size", "2k", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "write", "--chunk-size", "0", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "write", "--chunk-size", "2G", "/dev/foobar"]).is_err());
+        let a = parse_args(vec!["disktest", "wipe", "--progress-json", "3", "/dev/foobar"]).unwrap();
+        assert_eq!(a.progress_json, Some("3".to_string()));
+        assert_eq!(a.metrics_listen, None);
+        let a = parse_args(vec!["disktest", "scan", "--metrics-listen", "0.0.0.0:9100", "/dev/foobar"]).unwrap();
+        assert_eq!(a.metrics_listen, Some("0.0.0.0:9100".to_string()));
+        assert_eq!(a.log, None);
+        let a = parse_args(vec!["disktest", "write", "--log", "foo.log", "/dev/foobar"]).unwrap();
+        assert_eq!(a.log, Some("foo.log".to_string()));
+        assert_eq!(a.max_rate, None);
+        assert_eq!(a.read_retries, 0);
+        let a = parse_args(vec!["disktest", "scan", "--read-retries", "5", "/dev/foobar"]).unwrap();
+        assert_eq!(a.read_retries, 5);
+        assert!(parse_args(vec!["disktest", "scan", "--read-retries", "x", "/dev/foobar"]).is_err());
+        assert!(!a.force);
+        let a = parse_args(vec!["disktest", "write", "--force", "/dev/foobar"]).unwrap();
+        assert!(a.force);
+        assert!(!a.allow_system_disk);
+        let a = parse_args(vec!["disktest", "write", "--allow-system-disk", "/dev/foobar"]).unwrap();
+        assert!(a.allow_system_disk);
+        assert!(!a.force);
+        assert!(!a.yes);
+        let a = parse_args(vec!["disktest", "wipe", "-y", "/dev/foobar"]).unwrap();
+        assert!(a.yes);
+        let a = parse_args(vec!["disktest", "scan", "--max-rate", "20M", "/dev/foobar"]).unwrap();
+        assert_eq!(a.max_rate, Some(20 * 1024 * 1024));
+        assert!(parse_args(vec!["disktest", "scan", "--max-rate", "0", "/dev/foobar"]).is_err());
+        assert_eq!(a.max_time, None);
+        let a = parse_args(vec!["disktest", "write", "--max-time", "4h", "/dev/foobar"]).unwrap();
+        assert_eq!(a.max_time, Some(Duration::from_secs(4 * 3600)));
+        assert!(parse_args(vec!["disktest", "write", "--max-time", "0", "/dev/foobar"]).is_err());
+        assert_eq!(a.nice, None);
+        assert_eq!(a.io_priority, None);
+        let a = parse_args(vec!["disktest", "scan", "--nice", "-5", "--io-priority", "idle",
+                                "/dev/foobar"]).unwrap();
+        assert_eq!(a.nice, Some(-5));
+        assert_eq!(a.io_priority, Some(IoPriority::Idle));
+        assert!(parse_args(vec!["disktest", "scan", "--nice", "20", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "scan", "--io-priority", "high", "/dev/foobar"]).is_err());
+        assert_eq!(a.cpu_set, None);
+        let a = parse_args(vec!["disktest", "write", "--cpu-set", "0-1", "/dev/foobar"]).unwrap();
+        assert_eq!(a.cpu_set, Some(vec![0, 1]));
+        let a = parse_args(vec!["disktest", "write", "/dev/foobar"]).unwrap();
+        assert!(a.header);
+        let a = parse_args(vec!["disktest", "verify", "-Sx", "--no-header", "/dev/foobar"]).unwrap();
+        assert!(!a.header);
+        assert!(!a.preserve_ptable);
+        let a = parse_args(vec!["disktest", "write", "--preserve-ptable", "/dev/foobar"]).unwrap();
+        assert!(a.preserve_ptable);
+        assert!(!a.smart);
+        let a = parse_args(vec!["disktest", "write", "--smart", "/dev/foobar"]).unwrap();
+        assert!(a.smart);
+        assert!(!a.kernel_log);
+        let a = parse_args(vec!["disktest", "verify", "--kernel-log", "-Sx", "/dev/foobar"]).unwrap();
+        assert!(a.kernel_log);
+        assert!(!a.latency);
+        let a = parse_args(vec!["disktest", "write", "--latency", "/dev/foobar"]).unwrap();
+        assert!(a.latency);
+        assert!(!a.tui);
+        let a = parse_args(vec!["disktest", "write", "--tui", "/dev/foobar"]).unwrap();
+        assert!(a.tui);
+        assert!(parse_args(vec!["disktest", "write", "--tui", "--devices-from", "list"]).is_err());
+        assert_eq!(a.output, OutputFormat::Text);
+        let a = parse_args(vec!["disktest", "verify", "--output", "summary-json", "-Sx", "/dev/foobar"]).unwrap();
+        assert_eq!(a.output, OutputFormat::SummaryJson);
+        assert_eq!(a.quiet, 2);
+        assert!(parse_args(vec!["disktest", "write", "--output", "xml", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "write", "--output", "summary-json", "--tui", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "write", "--output", "summary-json", "-Sx", "-"]).is_err());
+        assert!(a.ranges.is_empty());
+        let a = parse_args(vec!["disktest", "write", "--range", "1G:1M", "--range", "0:4k", "/dev/foobar"]).unwrap();
+        assert_eq!(a.ranges, vec![(0, 4096), (1024 * 1024 * 1024, 1024 * 1024)]);
+        assert!(!a.header);
+        assert!(parse_args(vec!["disktest", "write", "--range", "0:4k", "--seek", "1M", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "write", "--range", "0", "/dev/foobar"]).is_err());
+        assert_eq!(a.zones, None);
+        let a = parse_args(vec!["disktest", "write", "--zones", "20", "/dev/foobar"]).unwrap();
+        assert_eq!(a.zones, Some(20));
+        assert!(parse_args(vec!["disktest", "write", "--zones", "0", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "write", "--zones", "x", "/dev/foobar"]).is_err());
+        assert_eq!(a.verify_stride, 1);
+        assert_eq!(a.verify_behind, None);
+        let a = parse_args(vec!["disktest", "write", "--verify-behind", "1G", "/dev/foobar"]).unwrap();
+        assert_eq!(a.verify_behind, Some(1024 * 1024 * 1024));
+        assert!(a.write);
+        assert!(a.verify);
+        assert!(parse_args(vec!["disktest", "write", "--verify-behind", "x", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "verify", "--verify-behind", "1G", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "write", "--verify-behind", "1G", "-Sx", "-"]).is_err());
+        let a = parse_args(vec!["disktest", "verify", "-Sx", "--verify-stride", "100", "/dev/foobar"]).unwrap();
+        assert_eq!(a.verify_stride, 100);
+        assert!(parse_args(vec!["disktest", "verify", "-Sx", "--verify-stride", "0", "/dev/foobar"]).is_err());
+        let a = parse_args(vec!["disktest", "wipe", "/dev/foobar"]).unwrap();
+        assert_eq!(a.command, Command::Wipe);
+        assert!(a.write);
+        assert!(!a.verify);
+        assert_eq!(a.wipe_passes, vec![WipePass::Random]);
+        let a = parse_args(vec!["disktest", "wipe", "--scheme", "dod", "-v", "/dev/foobar"]).unwrap();
+        assert_eq!(a.wipe_passes.len(), 3);
+        assert!(a.verify);
+        assert!(parse_args(vec!["disktest", "wipe", "--scheme", "foo", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "wipe", "--scheme", "dod", "--pattern", "00",
+                                "/dev/foobar"]).is_err());
+        let a = parse_args(vec!["disktest", "bench", "/dev/foobar"]).unwrap();
+        assert_eq!(a.command, Command::Bench);
+        assert_eq!(a.max_bytes, DEFAULT_BENCH_BYTES);
+        let a = parse_args(vec!["disktest", "scan", "-b", "1M", "/dev/foobar"]).unwrap();
+        assert_eq!(a.command, Command::Scan);
+        assert!(!a.write);
+        assert!(!a.verify);
+        assert!(a.keep_going);
+        assert_eq!(a.max_bytes, 1024 * 1024);
+        let a = parse_args(vec!["disktest", "compare", "/dev/foobar", "foo.img"]).unwrap();
+        assert_eq!(a.command, Command::Compare);
+        assert_eq!(a.device, "/dev/foobar");
+        assert_eq!(a.other, "foo.img");
+        assert!(!a.write);
+        assert!(!a.verify);
+        assert!(a.keep_going);
+        assert!(parse_args(vec!["disktest", "compare", "/dev/foobar"]).is_err());
+        assert!(!a.expect_image);
+        let a = parse_args(vec!["disktest", "verify", "--expect-image", "os.img",
+                                "--image-seek", "1k", "-b", "2M", "/dev/foobar"]).unwrap();
+        assert_eq!(a.command, Command::Test);
+        assert!(a.expect_image);
+        assert_eq!(a.other, "os.img");
+        assert_eq!(a.other_seek, 1024);
+        assert_eq!(a.max_bytes, 2 * 1024 * 1024);
+        assert!(!a.write);
+        assert!(a.verify);
+        assert!(a.keep_going);
+        assert!(parse_args(vec!["disktest", "verify", "--expect-image", "os.img",
+                                "-Sx", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "verify", "--image-seek", "1k", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "write", "--expect-image", "os.img",
+                                "/dev/foobar"]).is_err());
+        let a = parse_args(vec!["disktest", "nondestructive", "-k", "/dev/foobar"]).unwrap();
+        assert_eq!(a.command, Command::Nondestructive);
+        assert!(a.write);
+        assert!(a.verify);
+        assert!(a.keep_going);
+        assert!(parse_args(vec!["disktest", "nondestructive", "--rounds", "2",
+                                "/dev/foobar"]).is_err());
+        let a = parse_args(vec!["disktest", "surface", "/dev/foobar"]).unwrap();
+        assert_eq!(a.command, Command::Surface);
+        assert!(a.write);
+        assert!(a.verify);
+        assert!(!a.keep_going);
+        let a = parse_args(vec!["disktest", "capacity-check", "/dev/foobar"]).unwrap();
+        assert_eq!(a.command, Command::CapacityCheck);
+        assert_eq!(a.max_bytes, u64::MAX);
+        let a = parse_args(vec!["disktest", "trim-check", "--bytes", "1G", "/dev/foobar"]).unwrap();
+        assert_eq!(a.command, Command::TrimCheck);
+        assert_eq!(a.max_bytes, 1024 * 1024 * 1024);
+        let a = parse_args(vec!["disktest", "secure-erase", "/dev/foobar"]).unwrap();
+        assert_eq!(a.command, Command::SecureErase);
+        assert!(!a.enhanced_erase);
+        assert_eq!(a.report, None);
+        let a = parse_args(vec!["disktest", "secure-erase", "--enhanced", "--report", "r.json",
+                                "/dev/foobar"]).unwrap();
+        assert!(a.enhanced_erase);
+        assert_eq!(a.report, Some("r.json".to_string()));
+        let a = parse_args(vec!["disktest", "nvme-erase", "/dev/foobar"]).unwrap();
+        assert_eq!(a.command, Command::NvmeErase);
+        assert_eq!(a.nvme_erase, NvmeErase::Format);
+        let a = parse_args(vec!["disktest", "nvme-erase", "--method", "sanitize-crypto",
+                                "/dev/foobar"]).unwrap();
+        assert_eq!(a.nvme_erase, NvmeErase::SanitizeCrypto);
+        assert!(parse_args(vec!["disktest", "nvme-erase", "--method", "foo", "/dev/foobar"]).is_err());
+        let a = parse_args(vec!["disktest", "fill", "/mnt/foobar"]).unwrap();
+        assert_eq!(a.command, Command::Fill);
+        assert_eq!(a.device, "/mnt/foobar");
+        assert_eq!(a.max_bytes, u64::MAX);
+        let a = parse_args(vec!["disktest", "info", "/dev/foobar"]).unwrap();
+        assert_eq!(a.command, Command::Info);
+        assert_eq!(a.device, "/dev/foobar");
+
+        let a = parse_args(vec!["disktest", "write", "-Sx", "-"]).unwrap();
+        assert_eq!(a.device, "-");
+        assert_eq!(a.quiet, 2);
+        assert!(!a.header);
+        let a = parse_args(vec!["disktest", "verify", "-Sx", "-b", "1M", "-"]).unwrap();
+        assert_eq!(a.device, "-");
+        assert_eq!(a.quiet, 0);
+        assert!(!a.header);
+        assert!(parse_args(vec!["disktest", "write", "-"]).is_err());
+        assert!(parse_args(vec!["disktest", "write", "--verify", "-Sx", "-"]).is_err());
+        assert!(parse_args(vec!["disktest", "verify", "-Sx", "--direct", "-"]).is_err());
+        assert!(parse_args(vec!["disktest", "verify", "-Sx", "-R", "2", "-"]).is_err());
+        assert!(parse_args(vec!["disktest", "verify", "-Sx", "--verify-stride", "2", "-"]).is_err());
+        assert!(parse_args(vec!["disktest", "verify", "-Sx", "--skip-list", "bb.txt", "-"]).is_err());
+        assert!(parse_args(vec!["disktest", "scan", "-"]).is_err());
+
+        let tdir = tempfile::tempdir().unwrap();
+        let token = tdir.path().join("token");
+        std::fs::write(&token, "secret\n").unwrap();
+        let token = token.to_str().unwrap();
+        let a = parse_args(vec!["disktest", "serve", "--token-file", token]).unwrap();
+        assert_eq!(a.command, Command::Serve);
+        assert_eq!(a.agent, "127.0.0.1");
+        assert_eq!(a.token, b"secret");
+        assert!(!a.force && !a.allow_system_disk && !a.yes);
+        let a = parse_args(vec!["disktest", "serve", "--token-file", token, "--force", "--yes"]).unwrap();
+        assert!(a.force && !a.allow_system_disk && a.yes);
+        let a = parse_args(vec!["disktest", "serve", "--listen", "10.0.0.5:1234",
+                                "--token-file", token]).unwrap();
+        assert_eq!(a.agent, "10.0.0.5:1234");
+        assert!(parse_args(vec!["disktest", "serve"]).is_err());
+        let a = parse_args(vec!["disktest", "remote", "--token-file", token, "--report", "r.json",
+                                "station1", "write", "--verify", "-Sx", "/dev/sdb"]).unwrap();
+        assert_eq!(a.command, Command::Remote);
+        assert_eq!(a.agent, "station1");
+        assert_eq!(a.remote_args, vec!["write", "--verify", "-Sx", "/dev/sdb"]);
+        assert_eq!(a.report, Some("r.json".to_string()));
+        assert!(parse_args(vec!["disktest", "remote", "--token-file", token, "station1"]).is_err());
+        assert!(parse_args(vec!["disktest", "remote", "station1", "write", "/dev/sdb"]).is_err());
+
         let a = parse_args(vec!["disktest", "-w", "--quiet", "2", "/dev/foobar"]).unwrap();
         assert_eq!(a.quiet, 2);
         let a = parse_args(vec!["disktest", "-w", "-q2", "/dev/foobar"]).unwrap();
         assert_eq!(a.quiet, 2);
     }
+
+    #[test]
+    fn test_resolve_percent() {
+        let gib = 1024 * 1024 * 1024;
+        let mut a = parse_args(vec!["disktest", "write", "--seek", "90%", "/dev/foobar"]).unwrap();
+        assert_eq!(a.seek_percent, Some(90.0));
+        assert_eq!(a.max_bytes, Disktest::UNLIMITED);
+        resolve_percent(&mut a, |_| Some(100 * gib)).unwrap();
+        assert_eq!(a.seek, 90 * gib);
+        assert_eq!(a.seek_percent, None);
+        assert_eq!(a.max_bytes, Disktest::UNLIMITED);
+
+        let mut a = parse_args(vec!["disktest", "verify", "-Sx", "--seek", "1G", "--bytes", "12.5%",
+                                    "/dev/foobar"]).unwrap();
+        resolve_percent(&mut a, |_| Some(100 * gib)).unwrap();
+        assert_eq!(a.seek, gib);
+        assert_eq!(a.max_bytes, 12 * gib + gib / 2);
+        assert!(resolve_percent(&mut a, |_| None).is_ok());
+
+        let mut a = parse_args(vec!["disktest", "write", "--bytes", "10%", "/dev/foobar"]).unwrap();
+        assert!(resolve_percent(&mut a, |_| None).is_err());
+        assert!(parse_args(vec!["disktest", "write", "--seek", "101%", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "write", "--bytes", "x%", "/dev/foobar"]).is_err());
+
+        let a = parse_args(vec!["disktest", "write", "--seek", "3T", "--end", "3584G", "/dev/foobar"]).unwrap();
+        assert_eq!(a.seek, 3 * 1024 * gib);
+        assert_eq!(a.max_bytes, 512 * gib);
+        assert!(parse_args(vec!["disktest", "write", "--seek", "2G", "--end", "1G", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "write", "--bytes", "1G", "--end", "2G", "/dev/foobar"]).is_err());
+        let mut a = parse_args(vec!["disktest", "write", "--seek", "50%", "--end", "60G", "/dev/foobar"]).unwrap();
+        resolve_percent(&mut a, |_| Some(100 * gib)).unwrap();
+        assert_eq!(a.max_bytes, 10 * gib);
+        let mut a = parse_args(vec!["disktest", "write", "--seek", "10G", "--end", "50%", "/dev/foobar"]).unwrap();
+        resolve_percent(&mut a, |_| Some(100 * gib)).unwrap();
+        assert_eq!(a.max_bytes, 40 * gib);
+        let mut a = parse_args(vec!["disktest", "write", "--seek", "60%", "--end", "50%", "/dev/foobar"]).unwrap();
+        assert!(resolve_percent(&mut a, |_| Some(100 * gib)).is_err());
+    }
+
+    #[test]
+    fn test_parse_cpu_set() {
+        assert_eq!(parse_cpu_set("3").unwrap(), vec![3]);
+        assert_eq!(parse_cpu_set("0-3,6").unwrap(), vec![0, 1, 2, 3, 6]);
+        assert_eq!(parse_cpu_set("2, 4-5").unwrap(), vec![2, 4, 5]);
+        assert!(parse_cpu_set("").is_err());
+        assert!(parse_cpu_set("3-1").is_err());
+        assert!(parse_cpu_set("0,x").is_err());
+        assert!(parse_cpu_set("0-").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("30m").unwrap(), Duration::from_secs(30 * 60));
+        assert_eq!(parse_duration("4h").unwrap(), Duration::from_secs(4 * 3600));
+        assert_eq!(parse_duration("2d").unwrap(), Duration::from_secs(2 * 86400));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration("4x").is_err());
+        assert!(parse_duration("-4h").is_err());
+        assert!(parse_duration("18446744073709551615d").is_err());
+    }
 }
 
 // vim: ts=4 sw=4 expandtab
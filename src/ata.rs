@@ -0,0 +1,184 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+use std::process::Command;
+
+/// The temporary user password that is set for the erase.
+/// The drive clears it again after a successful erase.
+const ERASE_PASSWORD: &str = "disktest";
+
+/// The ATA security state of a drive, as reported by hdparm -I.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AtaSecurity {
+    pub supported:  bool,
+    pub enabled:    bool,
+    pub locked:     bool,
+    pub frozen:     bool,
+    /// The drive supports ENHANCED SECURITY ERASE UNIT.
+    pub enhanced:   bool,
+    /// The estimated duration of SECURITY ERASE UNIT (e.g. "2min").
+    pub erase_time: Option<String>,
+    /// The estimated duration of ENHANCED SECURITY ERASE UNIT.
+    pub enhanced_time: Option<String>,
+}
+
+/// Run hdparm with the arguments.
+fn hdparm(args: &[&str]) -> ah::Result<String> {
+    let output = match Command::new("hdparm").args(args).output() {
+        Ok(x) => x,
+        Err(e) => return Err(ah::format_err!("Failed to run hdparm: {}", e)),
+    };
+    if !output.status.success() {
+        return Err(ah::format_err!("'hdparm {}' failed: {}",
+                                   args.join(" "),
+                                   String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+impl AtaSecurity {
+    /// Parse the Security section of the output of hdparm -I.
+    /// Returns None, if the output has no Security section.
+    pub fn parse(identify: &str) -> Option<AtaSecurity> {
+        let mut lines = identify.lines().skip_while(|line| !line.starts_with("Security:"));
+        lines.next()?;
+        let mut security = AtaSecurity::default();
+        for line in lines.take_while(|line| line.starts_with(char::is_whitespace)) {
+            let line = line.trim();
+            if line.contains("for SECURITY ERASE UNIT") {
+                for part in line.split('.') {
+                    let time = part.split_whitespace().next().map(|t| t.to_string());
+                    if part.contains("for ENHANCED SECURITY ERASE UNIT") {
+                        security.enhanced_time = time;
+                    } else if part.contains("for SECURITY ERASE UNIT") {
+                        security.erase_time = time;
+                    }
+                }
+                continue;
+            }
+            let (set, key) = match line.strip_prefix("not") {
+                Some(key) => (false, key.trim()),
+                None => (true, line),
+            };
+            match key {
+                "supported" => security.supported = set,
+                "enabled" => security.enabled = set,
+                "locked" => security.locked = set,
+                "frozen" => security.frozen = set,
+                "supported: enhanced erase" => security.enhanced = set,
+                _ => (),
+            }
+        }
+        Some(security)
+    }
+
+    /// Read the ATA security state of the drive with hdparm.
+    pub fn read(device: &str) -> ah::Result<AtaSecurity> {
+        match AtaSecurity::parse(&hdparm(&["-I", device])?) {
+            Some(security) => Ok(security),
+            None => Err(ah::format_err!("{:?} does not report an ATA security feature set. \
+                                         It is probably no ATA drive.", device)),
+        }
+    }
+
+    /// Check that the drive can be erased.
+    pub fn check(&self, enhanced: bool) -> ah::Result<()> {
+        if !self.supported {
+            return Err(ah::format_err!("The drive does not support the ATA security feature set."));
+        }
+        if self.frozen {
+            return Err(ah::format_err!("The ATA security of the drive is frozen. \
+                                        Suspend and resume the computer or replug the drive \
+                                        to unfreeze it."));
+        }
+        if self.locked {
+            return Err(ah::format_err!("The drive is locked with a password."));
+        }
+        if self.enabled {
+            return Err(ah::format_err!("The drive already has a security password. \
+                                        Disable it with hdparm --security-disable first."));
+        }
+        if enhanced && !self.enhanced {
+            return Err(ah::format_err!("The drive does not support the enhanced security erase."));
+        }
+        Ok(())
+    }
+}
+
+/// Erase the drive with ATA SECURITY ERASE UNIT or ENHANCED SECURITY ERASE UNIT.
+/// This sets a temporary user password, which the drive clears during the erase.
+/// This blocks until the drive has finished the erase.
+pub fn security_erase(device: &str, enhanced: bool) -> ah::Result<()> {
+    hdparm(&["--user-master", "u", "--security-set-pass", ERASE_PASSWORD, device])?;
+    let erase = if enhanced { "--security-erase-enhanced" } else { "--security-erase" };
+    hdparm(&["--user-master", "u", erase, ERASE_PASSWORD, device])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDENTIFY: &str = "\
+ATA device, with non-removable media
+	Model Number:       Samsung SSD 860 EVO 500GB
+Security:
+	Master password revision code = 65534
+		supported
+	not	enabled
+	not	locked
+		frozen
+	not	expired: security count
+		supported: enhanced erase
+	2min for SECURITY ERASE UNIT. 4min for ENHANCED SECURITY ERASE UNIT.
+Logical Unit WWN Device Identifier: 5002538e40a1b2c3
+";
+
+    #[test]
+    fn test_parse() {
+        let security = AtaSecurity::parse(IDENTIFY).unwrap();
+        assert_eq!(security, AtaSecurity {
+            supported:  true,
+            enabled:    false,
+            locked:     false,
+            frozen:     true,
+            enhanced:   true,
+            erase_time: Some("2min".to_string()),
+            enhanced_time: Some("4min".to_string()),
+        });
+        assert!(security.check(false).unwrap_err().to_string().contains("frozen"));
+
+        let unfrozen = IDENTIFY.replace("\t\tfrozen", "\tnot\tfrozen");
+        let security = AtaSecurity::parse(&unfrozen).unwrap();
+        assert!(!security.frozen);
+        assert!(security.check(false).is_ok());
+        assert!(security.check(true).is_ok());
+
+        let basic = unfrozen.replace("\t\tsupported: enhanced erase\n", "");
+        assert!(AtaSecurity::parse(&basic).unwrap().check(true).is_err());
+
+        assert!(AtaSecurity::parse("ATA device\n").is_none());
+        assert!(!AtaSecurity::parse("Security:\n").unwrap().supported);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
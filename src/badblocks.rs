@@ -0,0 +1,133 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+use disktest_core::DtErrorRecord;
+use std::fs;
+use std::path::Path;
+
+/// Default block size of the bad blocks list, in bytes.
+pub const DEFAULT_LIST_BLOCK_SIZE: u64 = 4096;
+
+/// Get the sorted numbers of all blocks that are touched by a bad region.
+pub fn bad_blocks(errors: &[DtErrorRecord], block_size: u64) -> Vec<u64> {
+    assert!(block_size > 0);
+    let mut blocks: Vec<u64> = errors.iter()
+        .filter(|e| e.length > 0)
+        .flat_map(|e| (e.offset / block_size)..=((e.offset + e.length - 1) / block_size))
+        .collect();
+    blocks.sort_unstable();
+    blocks.dedup();
+    blocks
+}
+
+/// Write the bad blocks list in the badblocks(8) format,
+/// as accepted by mke2fs -l and e2fsck -l.
+/// Returns the number of bad blocks.
+pub fn write_bad_blocks_list(path: &Path,
+                             errors: &[DtErrorRecord],
+                             block_size: u64) -> ah::Result<usize> {
+    let blocks = bad_blocks(errors, block_size);
+    let text: String = blocks.iter().map(|b| format!("{}\n", b)).collect();
+    if let Err(e) = fs::write(path, text) {
+        return Err(ah::format_err!("Failed to write bad blocks list {:?}: {}", path, e));
+    }
+    Ok(blocks.len())
+}
+
+/// Read a list of known bad blocks in the badblocks(8) format,
+/// as written by write_bad_blocks_list() or by badblocks -o.
+/// Empty lines and lines starting with # are ignored.
+/// Returns the regions (offset, length) of the blocks in bytes.
+pub fn read_skip_list(path: &Path, block_size: u64) -> ah::Result<Vec<(u64, u64)>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => return Err(ah::format_err!("Failed to read skip list {:?}: {}", path, e)),
+    };
+    let mut regions = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let block = match line.parse::<u64>() {
+            Ok(block) => block,
+            Err(e) => return Err(ah::format_err!("Skip list {:?} line {}: Invalid block number {:?}: {}",
+                                                 path, i + 1, line, e)),
+        };
+        match block.checked_mul(block_size) {
+            Some(offset) => regions.push((offset, block_size)),
+            None => return Err(ah::format_err!("Skip list {:?} line {}: Block number {} is too big.",
+                                               path, i + 1, block)),
+        }
+    }
+    Ok(regions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use disktest_core::DtErrorKind;
+    use tempfile::NamedTempFile;
+
+    fn record(offset: u64, length: u64) -> DtErrorRecord {
+        DtErrorRecord {
+            kind:       DtErrorKind::Mismatch,
+            offset,
+            length,
+            message:    "".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_bad_blocks() {
+        let errors = vec![record(5000, 1024), record(512, 512), record(4096, 10)];
+        assert_eq!(bad_blocks(&errors, 1024), vec![0, 4, 5]);
+        assert_eq!(bad_blocks(&errors, 4096), vec![0, 1]);
+        assert_eq!(bad_blocks(&errors, 512), vec![1, 8, 9, 10, 11]);
+        assert_eq!(bad_blocks(&[], 4096), Vec::<u64>::new());
+
+        let tfile = NamedTempFile::new().unwrap();
+        assert_eq!(write_bad_blocks_list(tfile.path(), &errors, 1024).unwrap(), 3);
+        assert_eq!(fs::read_to_string(tfile.path()).unwrap(), "0\n4\n5\n");
+    }
+
+    #[test]
+    fn test_read_skip_list() {
+        let tfile = NamedTempFile::new().unwrap();
+        fs::write(tfile.path(), "# known bad\n0\n\n 4 \n5\n").unwrap();
+        assert_eq!(read_skip_list(tfile.path(), 1024).unwrap(),
+                   vec![(0, 1024), (4096, 1024), (5120, 1024)]);
+        fs::write(tfile.path(), "").unwrap();
+        assert!(read_skip_list(tfile.path(), 4096).unwrap().is_empty());
+        fs::write(tfile.path(), "1\nfoo\n").unwrap();
+        assert!(read_skip_list(tfile.path(), 4096).is_err());
+        fs::write(tfile.path(), format!("{}\n", u64::MAX)).unwrap();
+        assert!(read_skip_list(tfile.path(), 4096).is_err());
+
+        // A written bad blocks list can be read back.
+        let errors = vec![record(5000, 1024), record(512, 512)];
+        write_bad_blocks_list(tfile.path(), &errors, 4096).unwrap();
+        assert_eq!(read_skip_list(tfile.path(), 4096).unwrap(), vec![(0, 4096), (4096, 4096)]);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
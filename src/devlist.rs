@@ -0,0 +1,128 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+
+use anyhow as ah;
+use std::ffi::OsString;
+use std::fs;
+use std::path::Path;
+
+/// One device of the device list file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceEntry {
+    /// Line number in the device list file.
+    pub line:       usize,
+    pub device:     String,
+    /// Options that override the command line for this device.
+    pub overrides:  Vec<String>,
+}
+
+/// Parse the content of a device list file.
+pub fn parse_device_list(text: &str) -> ah::Result<Vec<DeviceEntry>> {
+    let mut entries = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace().map(|x| x.to_string());
+        let device = fields.next().unwrap();
+        if device.starts_with('-') {
+            return Err(ah::format_err!("Line {} of the device list does not start \
+                                       with a device: {}", i + 1, line));
+        }
+        entries.push(DeviceEntry {
+            line:       i + 1,
+            device,
+            overrides:  fields.collect(),
+        });
+    }
+    if entries.is_empty() {
+        return Err(ah::format_err!("The device list is empty."));
+    }
+    Ok(entries)
+}
+
+/// Read the device list file.
+pub fn read_device_list(path: &Path) -> ah::Result<Vec<DeviceEntry>> {
+    match fs::read_to_string(path) {
+        Ok(text) => parse_device_list(&text),
+        Err(e) => Err(ah::format_err!("Failed to read the device list {:?}: {}", path, e)),
+    }
+}
+
+/// Options of the original command line, that are not passed on to the devices.
+/// The seed file is only read once. Its seed is passed on to the devices instead.
+const LIST_OPTIONS: [&str; 2] = ["--devices-from", "--seed-file"];
+
+/// Build the command line for one device of the list
+/// from the original command line.
+pub fn device_args(cmdline: &[OsString], entry: &DeviceEntry) -> Vec<OsString> {
+    let mut args = vec![];
+    let mut skip = false;
+    for arg in cmdline {
+        let text = arg.to_string_lossy();
+        if skip {
+            skip = false;
+        } else if LIST_OPTIONS.contains(&&*text) {
+            skip = true;
+        } else if !LIST_OPTIONS.iter().any(|o| text.starts_with(&format!("{}=", o))) {
+            args.push(arg.clone());
+        }
+    }
+    args.push(entry.device.clone().into());
+    args.extend(entry.overrides.iter().map(OsString::from));
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let list = parse_device_list("# JBOD\n\n/dev/sdb\n  /dev/sdc --bytes 1G  -R 2\n").unwrap();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0], DeviceEntry { line: 3, device: "/dev/sdb".to_string(), overrides: vec![] });
+        assert_eq!(list[1].line, 4);
+        assert_eq!(list[1].device, "/dev/sdc");
+        assert_eq!(list[1].overrides, vec!["--bytes", "1G", "-R", "2"]);
+        assert!(parse_device_list("# nothing\n").is_err());
+        assert!(parse_device_list("--bytes 1G\n").is_err());
+    }
+
+    #[test]
+    fn test_device_args() {
+        let cmdline: Vec<OsString> = ["disktest", "write", "--devices-from", "list", "-Sx",
+                                      "--devices-from=list", "--seed-file", "-", "--seed-file=seed"]
+            .iter().map(OsString::from).collect();
+        let entry = DeviceEntry {
+            line:       1,
+            device:     "/dev/sdc".to_string(),
+            overrides:  vec!["-Sy".to_string()],
+        };
+        let args: Vec<OsString> = ["disktest", "write", "-Sx", "/dev/sdc", "-Sy"]
+            .iter().map(OsString::from).collect();
+        assert_eq!(device_args(&cmdline, &entry), args);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
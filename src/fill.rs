@@ -0,0 +1,80 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use disktest_core::Disktest;
+use std::path::{Path, PathBuf};
+
+/// Size of each test file, in bytes.
+pub const FILL_FILE_SIZE: u64 = 1024 * 1024 * 1024;
+/// Free space that is left for the file system metadata of the next file.
+const FILL_RESERVE: u64 = 16 * 1024 * 1024;
+
+/// Get the path of the numbered test file in the directory.
+pub fn fill_file_path(dir: &Path, index: u64) -> PathBuf {
+    dir.join(format!("disktest-fill-{:05}.dat", index))
+}
+
+/// Get the seed of the numbered test file.
+/// Each file gets its own stream, so that misplaced data of another file is detected.
+pub fn fill_file_seed(seed: &[u8], index: u64) -> Vec<u8> {
+    let mut seed = seed.to_vec();
+    seed.extend_from_slice(format!("-{}", index).as_bytes());
+    seed
+}
+
+/// Get the number of bytes to write to the next test file.
+/// remaining is the number of bytes that are still to be written in total
+/// and free is the free space of the file system, if known.
+/// Returns Disktest::UNLIMITED, if the file shall fill the file system.
+pub fn fill_file_size(remaining: u64, free: Option<u64>) -> u64 {
+    let size = remaining.min(FILL_FILE_SIZE);
+    match free {
+        Some(free) if free >= size.saturating_add(FILL_RESERVE) => size,
+        Some(_) => Disktest::UNLIMITED,
+        None if remaining == Disktest::UNLIMITED => Disktest::UNLIMITED,
+        None => size,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fill_file_path() {
+        assert_eq!(fill_file_path(Path::new("/mnt/sd"), 1),
+                   Path::new("/mnt/sd/disktest-fill-00001.dat"));
+        assert_eq!(fill_file_seed(b"abc", 12), b"abc-12");
+    }
+
+    #[test]
+    fn test_fill_file_size() {
+        let u = Disktest::UNLIMITED;
+        assert_eq!(fill_file_size(u, Some(FILL_FILE_SIZE * 4)), FILL_FILE_SIZE);
+        assert_eq!(fill_file_size(u, Some(FILL_FILE_SIZE)), u);
+        assert_eq!(fill_file_size(1000, Some(FILL_FILE_SIZE)), 1000);
+        assert_eq!(fill_file_size(1000, Some(1000)), u);
+        assert_eq!(fill_file_size(u, None), u);
+        assert_eq!(fill_file_size(FILL_FILE_SIZE * 2, None), FILL_FILE_SIZE);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,116 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+use crate::args::Args;
+use std::process::Command;
+use std::time::Duration;
+
+/// Get the environment variables with the result details for the hook command.
+fn hook_env(args:       &Args,
+            result:     &ah::Result<()>,
+            duration:   Duration) -> Vec<(&'static str, String)> {
+    vec![
+        ("DISKTEST_RESULT",         if result.is_ok() { "success" } else { "failure" }.to_string()),
+        ("DISKTEST_EXIT_STATUS",    crate::exit_status(result).to_string()),
+        ("DISKTEST_MESSAGE",        result.as_ref().err().map(|e| e.to_string()).unwrap_or_default()),
+        ("DISKTEST_DEVICE",         args.device.clone()),
+        ("DISKTEST_DURATION",       duration.as_secs().to_string()),
+    ]
+}
+
+#[cfg(not(target_os = "windows"))]
+fn shell_command(cmd: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(cmd);
+    command
+}
+
+#[cfg(target_os = "windows")]
+fn shell_command(cmd: &str) -> Command {
+    let mut command = Command::new("cmd");
+    command.arg("/C").arg(cmd);
+    command
+}
+
+/// Run the --on-success or --on-failure hook command for the result of the run, if any.
+/// The result details are passed in DISKTEST_* environment variables.
+pub fn run_hook(args:       &Args,
+                result:     &ah::Result<()>,
+                duration:   Duration) -> ah::Result<()> {
+    let cmd = match result {
+        Ok(()) => &args.on_success,
+        Err(_) => &args.on_failure,
+    };
+    let cmd = match cmd {
+        Some(cmd) => cmd,
+        None => return Ok(()),
+    };
+    let status = match shell_command(cmd).envs(hook_env(args, result, duration)).status() {
+        Ok(x) => x,
+        Err(e) => return Err(ah::format_err!("Failed to run the hook command {:?}: {}", cmd, e)),
+    };
+    if !status.success() {
+        return Err(ah::format_err!("The hook command {:?} failed: {}", cmd, status));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::parse_args;
+    use disktest_core::DtError;
+
+    #[test]
+    fn test_hook_env() {
+        let args = parse_args(vec!["disktest", "write", "/dev/foobar"]).unwrap();
+        let env = hook_env(&args, &Ok(()), Duration::from_secs(42));
+        assert_eq!(env, vec![
+            ("DISKTEST_RESULT", "success".to_string()),
+            ("DISKTEST_EXIT_STATUS", "0".to_string()),
+            ("DISKTEST_MESSAGE", "".to_string()),
+            ("DISKTEST_DEVICE", "/dev/foobar".to_string()),
+            ("DISKTEST_DURATION", "42".to_string()),
+        ]);
+        let error = DtError::BadRegions { count: 1, length: 512 };
+        let message = error.to_string();
+        let env = hook_env(&args, &Err(error.into()), Duration::from_secs(1));
+        assert_eq!(env[0].1, "failure");
+        assert_eq!(env[1].1, "2");
+        assert_eq!(env[2].1, message);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_hook() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let cmd = format!("echo \"$DISKTEST_RESULT $DISKTEST_DEVICE\" > {}", out.display());
+        let args = parse_args(vec!["disktest", "write", "--on-success", &cmd,
+                                   "--on-failure", "false", "/dev/foobar"]).unwrap();
+        run_hook(&args, &Ok(()), Duration::from_secs(1)).unwrap();
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "success /dev/foobar\n");
+        assert!(run_hook(&args, &Err(ah::format_err!("foo")), Duration::from_secs(1)).is_err());
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
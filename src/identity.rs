@@ -0,0 +1,298 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+use disktest_core::disk_size::{disk_sector_size, disk_size};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// The identity of a device, as far as the operating system reports it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceIdentity {
+    pub model:      Option<String>,
+    pub serial:     Option<String>,
+    /// The firmware revision.
+    pub firmware:   Option<String>,
+    /// The size of the device, in bytes.
+    pub size:       Option<u64>,
+    pub logical_sector_size: Option<u32>,
+    pub physical_sector_size: Option<u32>,
+    /// True for rotating disks, false for solid state devices.
+    pub rotational: Option<bool>,
+    /// The bus the device is attached to (e.g. ata, usb or nvme).
+    pub bus:        Option<String>,
+}
+
+/// Get the value of a property from the content of a udev database file.
+#[cfg(any(target_os="linux", test))]
+fn udev_property(udev_db: &str, key: &str) -> Option<String> {
+    udev_db.lines()
+        .filter_map(|line| line.strip_prefix("E:"))
+        .filter_map(|line| line.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, value)| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// Get the bus of a block device from its sysfs device path
+/// (e.g. /sys/devices/pci0000:00/0000:00:14.0/usb2/2-1/.../block/sdb).
+#[cfg(any(target_os="linux", test))]
+fn bus_from_sysfs_path(path: &str) -> Option<&'static str> {
+    // The bus that connects the device to the host comes first in the path.
+    const BUSES: [(&str, &str); 6] = [
+        ("/usb", "usb"),
+        ("/nvme/", "nvme"),
+        ("/mmc_host/", "mmc"),
+        ("/virtio", "virtio"),
+        ("/ata", "ata"),
+        ("/host", "scsi"),
+    ];
+    BUSES.iter()
+        .filter_map(|(pattern, bus)| path.find(pattern).map(|pos| (pos, *bus)))
+        .min_by_key(|(pos, _)| *pos)
+        .map(|(_, bus)| bus)
+}
+
+/// Read a sysfs attribute.
+#[cfg(target_os="linux")]
+fn read_attr(path: &Path) -> Option<String> {
+    let value = std::fs::read_to_string(path).ok()?.trim().to_string();
+    if value.is_empty() { None } else { Some(value) }
+}
+
+/// Get the identity of the disk with the sysfs directory from sysfs and from the udev database.
+#[cfg(target_os="linux")]
+fn sysfs_identity(sysfs: &Path, identity: &mut DeviceIdentity) {
+    let udev_db = read_attr(&sysfs.join("dev"))
+        .and_then(|number| std::fs::read_to_string(format!("/run/udev/data/b{}", number)).ok())
+        .unwrap_or_default();
+    identity.model = udev_property(&udev_db, "ID_MODEL")
+        .map(|model| model.replace('_', " "))
+        .or_else(|| read_attr(&sysfs.join("device/model")))
+        .or_else(|| read_attr(&sysfs.join("device/name")));
+    identity.serial = udev_property(&udev_db, "ID_SERIAL_SHORT")
+        .or_else(|| read_attr(&sysfs.join("device/serial")));
+    identity.firmware = udev_property(&udev_db, "ID_REVISION")
+        .or_else(|| read_attr(&sysfs.join("device/firmware_rev")))
+        .or_else(|| read_attr(&sysfs.join("device/rev")))
+        .or_else(|| read_attr(&sysfs.join("device/fwrev")));
+    if identity.logical_sector_size.is_none() {
+        identity.logical_sector_size = read_attr(&sysfs.join("queue/logical_block_size"))
+            .and_then(|size| size.parse().ok());
+    }
+    identity.physical_sector_size = read_attr(&sysfs.join("queue/physical_block_size"))
+        .and_then(|size| size.parse().ok());
+    identity.rotational = read_attr(&sysfs.join("queue/rotational"))
+        .map(|rotational| rotational == "1");
+    identity.bus = udev_property(&udev_db, "ID_BUS")
+        .or_else(|| bus_from_sysfs_path(&sysfs.to_string_lossy()).map(|bus| bus.to_string()));
+}
+
+/// Get the identity of the device from sysfs and from the udev database.
+#[cfg(target_os="linux")]
+fn os_identity(device: &str, identity: &mut DeviceIdentity) {
+    let path = match Path::new(device).canonicalize() {
+        Ok(path) => path,
+        Err(_) => return,
+    };
+    let name = match path.strip_prefix("/dev") {
+        Ok(name) => name.to_string_lossy().replace('/', "!"),
+        Err(_) => return,
+    };
+    let mut sysfs = match Path::new("/sys/class/block").join(name).canonicalize() {
+        Ok(sysfs) => sysfs,
+        Err(_) => return,
+    };
+    // A partition has the identity of its disk.
+    if sysfs.join("partition").exists() {
+        sysfs.pop();
+    }
+    sysfs_identity(&sysfs, identity);
+}
+
+/// Find the device nodes of all disks in the sysfs block class directory
+/// that have the serial number.
+#[cfg(target_os="linux")]
+fn find_serial(class_block: &Path, serial: &str) -> Vec<String> {
+    let entries = match std::fs::read_dir(class_block) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut devices: Vec<String> = entries.flatten()
+        .filter(|entry| !entry.path().join("partition").exists())
+        .filter(|entry| {
+            let mut identity = DeviceIdentity::default();
+            sysfs_identity(&entry.path(), &mut identity);
+            identity.serial.as_deref() == Some(serial)
+        })
+        .map(|entry| format!("/dev/{}", entry.file_name().to_string_lossy().replace('!', "/")))
+        .collect();
+    devices.sort();
+    devices
+}
+
+#[cfg(target_os="linux")]
+fn os_device_by_serial(serial: &str) -> ah::Result<String> {
+    let devices = find_serial(Path::new("/sys/class/block"), serial);
+    match devices.len() {
+        0 => Err(ah::format_err!("No device with the serial number {:?} found.", serial)),
+        1 => Ok(devices[0].clone()),
+        _ => Err(ah::format_err!("The serial number {:?} is ambiguous. It matches the devices {}.",
+                                 serial, devices.join(", "))),
+    }
+}
+
+#[cfg(not(target_os="linux"))]
+fn os_device_by_serial(_serial: &str) -> ah::Result<String> {
+    Err(ah::format_err!("Selecting the device by serial number is not supported on this system."))
+}
+
+/// Find the device node of the disk with the serial number.
+pub fn device_by_serial(serial: &str) -> ah::Result<String> {
+    os_device_by_serial(serial)
+}
+
+/// Resolve a /dev/disk/by-id path to the current device node.
+/// A plain id without a path is looked up in /dev/disk/by-id.
+pub fn device_by_id(id: &str) -> ah::Result<String> {
+    let path = if id.contains('/') {
+        PathBuf::from(id)
+    } else {
+        Path::new("/dev/disk/by-id").join(id)
+    };
+    match path.canonicalize() {
+        Ok(device) => Ok(device.to_string_lossy().to_string()),
+        Err(e) => Err(ah::format_err!("No device with the id {:?} found: {}", path, e)),
+    }
+}
+
+#[cfg(not(target_os="linux"))]
+fn os_identity(_device: &str, _identity: &mut DeviceIdentity) {
+}
+
+impl DeviceIdentity {
+    /// Read the identity of the device.
+    /// Returns None, if the path is a regular file or a directory
+    /// or if it can't be opened.
+    pub fn read(device: &str) -> Option<DeviceIdentity> {
+        let file = File::open(device).ok()?;
+        let meta = file.metadata().ok()?;
+        if meta.is_file() || meta.is_dir() {
+            return None;
+        }
+        let mut identity = DeviceIdentity {
+            size: disk_size(&file).ok().flatten(),
+            logical_sector_size: disk_sector_size(&file).ok().flatten(),
+            ..Default::default()
+        };
+        os_identity(device, &mut identity);
+        Some(identity)
+    }
+}
+
+/// Check whether the answer to the confirmation prompt is the device path,
+/// the device name (e.g. sdb for /dev/sdb) or "yes".
+pub fn confirmation_accepted(answer: &str, device: &str) -> bool {
+    let answer = answer.trim();
+    !answer.is_empty() &&
+    (answer == "yes" ||
+     answer == device ||
+     Path::new(device).file_name().is_some_and(|name| name == answer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_udev_property() {
+        let udev_db = "\
+S:disk/by-id/ata-Samsung_SSD_860_EVO_500GB_S3Z1NB0K123456A
+E:ID_MODEL=Samsung_SSD_860_EVO_500GB
+E:ID_SERIAL=Samsung_SSD_860_EVO_500GB_S3Z1NB0K123456A
+E:ID_SERIAL_SHORT=S3Z1NB0K123456A
+E:ID_EMPTY=
+";
+        assert_eq!(udev_property(udev_db, "ID_MODEL"), Some("Samsung_SSD_860_EVO_500GB".to_string()));
+        assert_eq!(udev_property(udev_db, "ID_SERIAL_SHORT"), Some("S3Z1NB0K123456A".to_string()));
+        assert_eq!(udev_property(udev_db, "ID_SERIAL_"), None);
+        assert_eq!(udev_property(udev_db, "ID_EMPTY"), None);
+        assert_eq!(udev_property("", "ID_MODEL"), None);
+    }
+
+    #[test]
+    fn test_bus_from_sysfs_path() {
+        assert_eq!(bus_from_sysfs_path("/sys/devices/pci0000:00/0000:00:14.0/usb2/2-1/2-1:1.0/\
+                                        host6/target6:0:0/6:0:0:0/block/sdb"), Some("usb"));
+        assert_eq!(bus_from_sysfs_path("/sys/devices/pci0000:00/0000:00:17.0/ata3/host2/\
+                                        target2:0:0/2:0:0:0/block/sda"), Some("ata"));
+        assert_eq!(bus_from_sysfs_path("/sys/devices/pci0000:00/0000:00:1d.0/0000:3d:00.0/\
+                                        nvme/nvme0/nvme0n1"), Some("nvme"));
+        assert_eq!(bus_from_sysfs_path("/sys/devices/pci0000:00/0000:00:03.0/virtio1/block/vda"),
+                   Some("virtio"));
+        assert_eq!(bus_from_sysfs_path("/sys/devices/virtual/block/loop0"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_device_by_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let disk = dir.path().join("sdx");
+        std::fs::write(&disk, b"").unwrap();
+        let link = dir.path().join("usb-Vendor_Stick_1234-0:0");
+        std::os::unix::fs::symlink(&disk, &link).unwrap();
+        assert_eq!(device_by_id(link.to_str().unwrap()).unwrap(),
+                   disk.canonicalize().unwrap().to_str().unwrap());
+        assert!(device_by_id(dir.path().join("foo").to_str().unwrap()).is_err());
+        assert!(device_by_id("no-such-disk-id").is_err());
+    }
+
+    #[cfg(target_os="linux")]
+    #[test]
+    fn test_find_serial() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, serial) in [("sda", "A1"), ("sda1", "A1"), ("sdb", "B2"), ("sdc", "B2"), ("loop0", "")] {
+            let device = dir.path().join(name).join("device");
+            std::fs::create_dir_all(&device).unwrap();
+            std::fs::write(device.join("serial"), serial).unwrap();
+        }
+        // Partitions are not matched.
+        std::fs::write(dir.path().join("sda1/partition"), "1").unwrap();
+        assert_eq!(find_serial(dir.path(), "A1"), vec!["/dev/sda"]);
+        assert_eq!(find_serial(dir.path(), "B2"), vec!["/dev/sdb", "/dev/sdc"]);
+        assert!(find_serial(dir.path(), "C3").is_empty());
+        assert!(find_serial(dir.path(), "").is_empty());
+    }
+
+    #[test]
+    fn test_confirmation_accepted() {
+        assert!(confirmation_accepted("yes\n", "/dev/sdb"));
+        assert!(confirmation_accepted("/dev/sdb\n", "/dev/sdb"));
+        assert!(confirmation_accepted("  sdb \n", "/dev/sdb"));
+        assert!(!confirmation_accepted("y\n", "/dev/sdb"));
+        assert!(!confirmation_accepted("sdc\n", "/dev/sdb"));
+        assert!(!confirmation_accepted("/dev/sd\n", "/dev/sdb"));
+        assert!(!confirmation_accepted("", "/dev/sdb"));
+        assert!(!confirmation_accepted("\n", ""));
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
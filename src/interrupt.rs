@@ -0,0 +1,244 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use crate::args::{Args, Command};
+use crate::resume::Phase;
+use disktest_core::Disktest;
+use disktest_core::util::prettybytes;
+
+/// Options of the write subcommand that the verify subcommand does not accept.
+const WRITE_ONLY_FLAGS: [&str; 4] = ["--verify", "-v", "--loop", "--sync-writes"];
+const WRITE_ONLY_OPTIONS: [&str; 2] = ["--verify-behind", "--sync-every"];
+/// Options that conflict with --bytes.
+const BYTES_OPTIONS: [&str; 1] = ["--end"];
+/// Short forms of the options that are appended to the command lines.
+const SHORT_OPTIONS: [(&str, &str); 3] = [("--seek", "-s"), ("--bytes", "-b"), ("--seed", "-S")];
+
+/// Quote the argument for the shell, if necessary.
+fn shell_quote(arg: &str) -> String {
+    let plain = !arg.is_empty() &&
+                arg.chars().all(|c| c.is_ascii_alphanumeric() || "_-+=/.,:@%".contains(c));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// Build a command line from the original command line.
+/// The subcommand is replaced and the extra arguments are appended.
+/// The extra options replace the same options of the original command line.
+fn build_cmdline(cmdline: &[String], subcommand: &str, extra: &[String]) -> String {
+    let mut dropped: Vec<&str> = extra.iter()
+        .filter(|a| a.starts_with("--"))
+        .map(|a| a.split('=').next().unwrap())
+        .collect();
+    if subcommand == "verify" {
+        dropped.extend_from_slice(&WRITE_ONLY_OPTIONS);
+    }
+    if dropped.contains(&"--bytes") {
+        dropped.extend_from_slice(&BYTES_OPTIONS);
+    }
+    let shorts: Vec<&str> = SHORT_OPTIONS.iter()
+        .filter(|(long, _)| dropped.contains(long))
+        .map(|(_, short)| *short)
+        .collect();
+    let mut args = vec![cmdline[0].clone(), subcommand.to_string()];
+    let mut skip = false;
+    for arg in &cmdline[2..] {
+        if skip {
+            skip = false;
+            continue;
+        }
+        if dropped.contains(&arg.as_str()) || shorts.contains(&arg.as_str()) {
+            skip = true;
+            continue;
+        }
+        if (subcommand == "verify" && WRITE_ONLY_FLAGS.contains(&arg.as_str())) ||
+           dropped.iter().any(|o| arg.starts_with(&format!("{}=", o))) ||
+           shorts.iter().any(|o| arg.starts_with(o)) {
+            continue;
+        }
+        args.push(arg.clone());
+    }
+    args.extend(extra.iter().cloned());
+    args.iter().map(|a| shell_quote(a)).collect::<Vec<_>>().join(" ")
+}
+
+/// Format the summary of a test that was interrupted in the phase at the absolute position.
+/// It contains the command lines that continue the test and verify the written data.
+/// cmdline is the original command line.
+pub fn format_interruption(args: &Args, cmdline: &[String], phase: Phase, position: u64) -> String {
+    let mut text = format!("Interrupted in the {} phase at byte offset {} ({}).",
+                           phase, position, prettybytes(position, true, true));
+    if phase == Phase::Write {
+        text += "\nThe data up to this offset was written and synced to the device.";
+    }
+
+    if args.resume.is_some() {
+        text += &format!("\nResume the run with:\n    {}",
+                         build_cmdline(cmdline, &cmdline[1], &[]));
+        return text;
+    }
+    let subcommand = cmdline.get(1).map(|s| s.as_str());
+    if args.command != Command::Test || args.devices_from.is_some() || !args.ranges.is_empty() ||
+       !matches!(subcommand, Some("write") | Some("verify")) {
+        return text;
+    }
+
+    let mut seed = vec![];
+    if !args.user_seed {
+        seed.push("--seed".to_string());
+        seed.push(String::from_utf8_lossy(&args.seed).to_string());
+    }
+    let range = |seek: u64, bytes: u64| {
+        let mut range = seed.clone();
+        range.push(format!("--seek={}", seek));
+        if bytes != Disktest::UNLIMITED {
+            range.push(format!("--bytes={}", bytes));
+        }
+        range
+    };
+    // The data of a later round is verified with its sub-seed.
+    let seed_round = if args.seed_round > 1 {
+        vec![format!("--seed-round={}", args.seed_round)]
+    } else {
+        vec![]
+    };
+    let done = position.saturating_sub(args.seek);
+    let remaining = if args.max_bytes == Disktest::UNLIMITED {
+        Disktest::UNLIMITED
+    } else {
+        args.max_bytes.saturating_sub(done)
+    };
+    match phase {
+        Phase::Write => {
+            text += &format!("\nContinue writing with:\n    {}\n\
+                              Verify the data written before the interruption with:\n    {}",
+                             build_cmdline(cmdline, &cmdline[1], &range(position, remaining)),
+                             build_cmdline(cmdline, "verify",
+                                           &[range(args.seek, done), seed_round].concat()));
+        },
+        Phase::Verify => {
+            text += &format!("\nContinue verifying with:\n    {}",
+                             build_cmdline(cmdline, "verify",
+                                           &[range(position, remaining), seed_round].concat()));
+        },
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::parse_args;
+
+    fn cmdline(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    /// Parse the command line that follows the line with the title.
+    fn parse_following(text: &str, title: &str) -> Args {
+        let line = text.lines()
+            .skip_while(|l| !l.starts_with(title))
+            .nth(1).unwrap();
+        parse_args(line.split_whitespace()).unwrap()
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("/dev/sdb"), "/dev/sdb");
+        assert_eq!(shell_quote("--bytes=1G"), "--bytes=1G");
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn test_write() {
+        let cmd = cmdline(&["disktest", "write", "--verify", "--verify-behind", "1G",
+                            "--sync-writes", "--sync-every", "1M", "--bytes", "10M", "/dev/foobar"]);
+        let args = parse_args(&cmd).unwrap();
+        let text = format_interruption(&args, &cmd, Phase::Write, 3 * 1024 * 1024);
+        assert!(text.starts_with("Interrupted in the write phase at byte offset 3145728 (3.0 MiB"));
+
+        let cont = parse_following(&text, "Continue writing");
+        assert!(cont.write && cont.verify && cont.sync_writes);
+        assert_eq!(cont.sync_every, Some(1024 * 1024));
+        assert_eq!(cont.seed, args.seed);
+        assert_eq!(cont.seek, 3 * 1024 * 1024);
+        assert_eq!(cont.max_bytes, 7 * 1024 * 1024);
+
+        let verify = parse_following(&text, "Verify the data");
+        assert!(!verify.write && verify.verify);
+        assert_eq!(verify.seed, args.seed);
+        assert_eq!(verify.seek, 0);
+        assert_eq!(verify.max_bytes, 3 * 1024 * 1024);
+        assert_eq!(verify.seed_round, 1);
+
+        // The data of a later round is verified with its sub-seed.
+        let mut args = args;
+        args.seed_round = 3;
+        let text = format_interruption(&args, &cmd, Phase::Write, 3 * 1024 * 1024);
+        assert_eq!(parse_following(&text, "Continue writing").seed_round, 1);
+        assert_eq!(parse_following(&text, "Verify the data").seed_round, 3);
+    }
+
+    #[test]
+    fn test_verify() {
+        let cmd = cmdline(&["disktest", "verify", "-Sx", "--seek", "1M", "/dev/foobar"]);
+        let args = parse_args(&cmd).unwrap();
+        let text = format_interruption(&args, &cmd, Phase::Verify, 5 * 1024 * 1024);
+        assert!(!text.contains("synced"));
+        let cont = parse_following(&text, "Continue verifying");
+        assert_eq!(cont.seed, b"x".to_vec());
+        assert_eq!(cont.seek, 5 * 1024 * 1024);
+        assert_eq!(cont.max_bytes, Disktest::UNLIMITED);
+
+        let cmd = cmdline(&["disktest", "verify", "-Sx", "--seek", "1M", "--end", "9M", "/dev/foobar"]);
+        let args = parse_args(&cmd).unwrap();
+        let text = format_interruption(&args, &cmd, Phase::Verify, 5 * 1024 * 1024);
+        let cont = parse_following(&text, "Continue verifying");
+        assert_eq!(cont.seek, 5 * 1024 * 1024);
+        assert_eq!(cont.max_bytes, 4 * 1024 * 1024);
+
+        // The short forms of the options are replaced, too.
+        let cmd = cmdline(&["disktest", "verify", "-Sx", "-s", "1M", "-b10M", "/dev/foobar"]);
+        let args = parse_args(&cmd).unwrap();
+        let text = format_interruption(&args, &cmd, Phase::Verify, 5 * 1024 * 1024);
+        let cont = parse_following(&text, "Continue verifying");
+        assert_eq!(cont.seed, b"x".to_vec());
+        assert_eq!(cont.seek, 5 * 1024 * 1024);
+        assert_eq!(cont.max_bytes, 6 * 1024 * 1024);
+
+        let cmd = cmdline(&["disktest", "write", "--resume", "state", "-Sx", "/dev/foobar"]);
+        let args = parse_args(&cmd).unwrap();
+        let text = format_interruption(&args, &cmd, Phase::Write, 0);
+        assert!(text.ends_with("Resume the run with:\n    disktest write --resume state -Sx /dev/foobar"));
+
+        let cmd = cmdline(&["disktest", "wipe", "/dev/foobar"]);
+        let args = parse_args(&cmd).unwrap();
+        let text = format_interruption(&args, &cmd, Phase::Write, 0);
+        assert_eq!(text.lines().count(), 2);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,212 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+
+use anyhow as ah;
+use crate::report::{Report, ReportRange};
+use std::fs;
+use std::path::Path;
+
+/// Escape the special characters of XML text and attribute values.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\n' | '\t' => escaped.push(c),
+            c if c.is_control() => (),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// One JUnit test case.
+struct TestCase {
+    name:       String,
+    /// Duration, in seconds.
+    time:       f64,
+    /// Message and details of the failure, if the test case failed.
+    failure:    Option<(String, String)>,
+}
+
+/// List the bad regions of the report that overlap the range, one per line.
+/// All bad regions are listed, if range is None.
+fn bad_regions(report: &Report, range: Option<&ReportRange>) -> String {
+    report.errors.iter()
+        .filter(|e| match range {
+            Some(r) => e.offset < r.offset + r.length && r.offset < e.offset + e.length,
+            None => true,
+        })
+        .map(|e| format!("Round {} {}: {} at offset {}, length {}: {}\n",
+                         e.round, e.phase, e.kind, e.offset, e.length, e.message))
+        .collect()
+}
+
+/// The test cases of the report of one device.
+/// With --range every range is a separate test case, otherwise the device is one test case.
+fn test_cases(report: &Report) -> Vec<TestCase> {
+    if report.parameters.ranges.is_empty() {
+        let failure = if report.success {
+            None
+        } else {
+            let message = report.message.clone().unwrap_or_else(|| "Failed".to_string());
+            Some((message, bad_regions(report, None)))
+        };
+        return vec![TestCase {
+            name:       report.device.clone(),
+            time:       report.duration,
+            failure,
+        }];
+    }
+
+    // The final phase of a range is the verification, if any.
+    let last_phase = if report.parameters.verify { "verify" } else { "write" };
+    report.parameters.ranges.iter()
+        .map(|range| {
+            let phases: Vec<_> = report.phases.iter()
+                .filter(|p| p.offset <= range.offset &&
+                            range.offset + range.length <= p.offset + p.bytes)
+                .collect();
+            let done = phases.iter().any(|p| p.phase == last_phase && p.success);
+            let errors = bad_regions(report, Some(range));
+            let failure = if !errors.is_empty() {
+                Some(("Bad regions found".to_string(), errors))
+            } else if !done && !report.success {
+                let message = report.message.clone().unwrap_or_else(|| "Failed".to_string());
+                Some((format!("The range was not completed: {}", message), String::new()))
+            } else {
+                None
+            };
+            TestCase {
+                name:       format!("{} range {}:{}", report.device, range.offset, range.length),
+                time:       phases.iter().fold(0.0, |time, p| time + p.duration),
+                failure,
+            }
+        })
+        .collect()
+}
+
+/// Format the reports as JUnit XML with one test suite per device.
+pub fn junit_xml(reports: &[Report]) -> String {
+    let suites: Vec<(&Report, Vec<TestCase>)> = reports.iter()
+        .map(|r| (r, test_cases(r)))
+        .collect();
+    let count = |cases: &[TestCase]| cases.len();
+    let failures = |cases: &[TestCase]| cases.iter().filter(|c| c.failure.is_some()).count();
+
+    let mut xml = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n".to_string();
+    xml += &format!("<testsuites name=\"disktest\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">\n",
+                    suites.iter().map(|(_, c)| count(c)).sum::<usize>(),
+                    suites.iter().map(|(_, c)| failures(c)).sum::<usize>(),
+                    reports.iter().map(|r| r.duration).sum::<f64>());
+    for (report, cases) in &suites {
+        xml += &format!("  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">\n",
+                        escape(&report.device), count(cases), failures(cases), report.duration);
+        for case in cases {
+            xml += &format!("    <testcase classname=\"disktest\" name=\"{}\" time=\"{:.3}\"",
+                            escape(&case.name), case.time);
+            match &case.failure {
+                Some((message, text)) => {
+                    xml += &format!(">\n      <failure message=\"{}\">{}</failure>\n    </testcase>\n",
+                                    escape(message), escape(text));
+                },
+                None => xml += "/>\n",
+            }
+        }
+        xml += "  </testsuite>\n";
+    }
+    xml += "</testsuites>\n";
+    xml
+}
+
+/// Write the reports as JUnit XML file. See --report-junit.
+pub fn write_junit(path: &Path, reports: &[Report]) -> ah::Result<()> {
+    if let Err(e) = fs::write(path, junit_xml(reports)) {
+        return Err(ah::format_err!("Failed to write JUnit report file {:?}: {}", path, e));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::parse_args;
+    use crate::resume::Phase;
+    use disktest_core::{DtErrorKind, DtErrorRecord};
+    use std::time::Duration;
+
+    #[test]
+    fn test_junit() {
+        let args = parse_args(vec!["disktest", "write", "-v", "-Sx", "/dev/sd<b>"]).unwrap();
+        let mut ok = Report::new(&args);
+        ok.add_phase(1, Phase::Write, 0, Some(4096), Duration::from_secs(1), &[], None, None);
+        ok.finish(&Ok(()));
+        let mut bad = Report::new(&args);
+        let error = DtErrorRecord {
+            kind:       DtErrorKind::Mismatch,
+            offset:     8192,
+            length:     4096,
+            message:    "Data mismatch".to_string(),
+        };
+        bad.add_phase(1, Phase::Verify, 0, None, Duration::from_secs(1), &[error], None, None);
+        bad.finish(&Err(ah::format_err!("Verify <failed>")));
+        let xml = junit_xml(&[ok, bad]);
+        assert!(xml.contains("<testsuites name=\"disktest\" tests=\"2\" failures=\"1\""));
+        assert!(xml.contains("<testcase classname=\"disktest\" name=\"/dev/sd&lt;b&gt;\" time=\""));
+        assert!(xml.contains("<failure message=\"Verify &lt;failed&gt;\">\
+                              Round 1 verify: data mismatch at offset 8192, length 4096: \
+                              Data mismatch\n</failure>"));
+        assert!(xml.ends_with("</testsuite>\n</testsuites>\n"));
+
+        // One test case per range.
+        let args = parse_args(vec!["disktest", "write", "-v", "-Sx",
+                                   "--range", "0:4k", "--range", "1M:4k", "--range", "2M:4k",
+                                   "disk.img"]).unwrap();
+        let mut report = Report::new(&args);
+        let error = DtErrorRecord {
+            kind:       DtErrorKind::Read,
+            offset:     1024 * 1024,
+            length:     512,
+            message:    "I/O error".to_string(),
+        };
+        for &offset in &[0, 1024 * 1024] {
+            report.add_phase(1, Phase::Write, offset, Some(4096), Duration::from_secs(1),
+                             &[], None, None);
+            report.add_phase(1, Phase::Verify, offset, Some(4096), Duration::from_secs(2),
+                             if offset == 0 { &[] } else { std::slice::from_ref(&error) }, None, None);
+        }
+        report.finish(&Err(ah::format_err!("Aborted")));
+        let cases = test_cases(&report);
+        assert_eq!(cases.len(), 3);
+        assert_eq!(cases[0].name, "disk.img range 0:4096");
+        assert!(cases[0].failure.is_none());
+        assert_eq!(cases[0].time, 3.0);
+        assert_eq!(cases[1].failure.as_ref().unwrap().0, "Bad regions found");
+        assert_eq!(cases[2].failure.as_ref().unwrap().0, "The range was not completed: Aborted");
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
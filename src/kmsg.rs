@@ -0,0 +1,238 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+use disktest_core::{DtErrorRecord, DtPhase, ProgressInfo, ProgressObserver};
+use serde::Serialize;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Keywords of kernel messages that indicate an I/O problem.
+#[cfg(any(target_os = "linux", test))]
+const ERROR_KEYWORDS: [&str; 7] = [
+    "error", "fail", "timeout", "timed out", "reset", "abort", "medium",
+];
+
+/// A kernel log message about the device that was logged during the run.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct KernelMessage {
+    /// Phase that was running when the message was logged, if any.
+    pub phase:      Option<String>,
+    /// Absolute byte position of the test when the message was logged.
+    pub offset:     u64,
+    /// Byte offset reported by the kernel, if the message contains a sector number.
+    pub sector_offset: Option<u64>,
+    pub message:    String,
+}
+
+/// Get the kernel name of the device (e.g. "sdb" for /dev/sdb).
+fn kernel_name(device: &str) -> String {
+    let path = Path::new(device);
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    path.file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| device.to_string())
+}
+
+/// Check if the message mentions the device name.
+/// Partitions of the device (e.g. sdb1 for sdb) match as well.
+#[cfg(any(target_os = "linux", test))]
+fn mentions_device(message: &str, name: &str) -> bool {
+    message.match_indices(name).any(|(i, _)| {
+        let before = message[..i].chars().next_back();
+        let after = message[i + name.len()..].chars().next();
+        !before.is_some_and(|c| c.is_ascii_alphanumeric()) &&
+        !after.is_some_and(|c| c.is_ascii_alphabetic())
+    })
+}
+
+/// Extract the byte offset from the "sector N" part of a kernel message.
+#[cfg(any(target_os = "linux", test))]
+fn parse_sector_offset(message: &str) -> Option<u64> {
+    let i = message.find("sector ")?;
+    let digits: String = message[i + 7..].chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    digits.parse::<u64>().ok()?.checked_mul(512)
+}
+
+/// Parse one /dev/kmsg record ("prio,seq,usec,flags;message").
+/// Returns the message, if it is an I/O problem report about the device.
+#[cfg(any(target_os = "linux", test))]
+fn parse_record(record: &str, name: &str) -> Option<String> {
+    let message = record.lines().next()?.split_once(';')?.1.trim();
+    let lower = message.to_lowercase();
+    if mentions_device(message, name) &&
+       ERROR_KEYWORDS.iter().any(|k| lower.contains(k)) {
+        Some(message.to_string())
+    } else {
+        None
+    }
+}
+
+/// Current phase and position of the test.
+type Position = Arc<Mutex<(Option<DtPhase>, u64)>>;
+
+/// Progress observer that tracks the current position for the kernel log watcher.
+struct PositionTracker {
+    position:   Position,
+}
+
+impl ProgressObserver for PositionTracker {
+    fn begin(&mut self, phase: DtPhase, _path: &Path, seek: u64) {
+        *self.position.lock().unwrap() = (Some(phase), seek);
+    }
+
+    fn progress(&mut self, info: &ProgressInfo) {
+        *self.position.lock().unwrap() = (Some(info.phase), info.offset);
+    }
+
+    fn finish(&mut self, info: &ProgressInfo, _errors: &[DtErrorRecord]) {
+        *self.position.lock().unwrap() = (None, info.offset);
+    }
+}
+
+/// Follows the kernel log during the run and collects the I/O
+/// problem reports about the device.
+pub struct KmsgWatcher {
+    position:   Position,
+    messages:   Arc<Mutex<Vec<KernelMessage>>>,
+    stop:       Arc<AtomicBool>,
+}
+
+impl KmsgWatcher {
+    /// Start following the kernel log.
+    /// Only messages that are logged from now on are collected.
+    pub fn start(device: &str) -> ah::Result<KmsgWatcher> {
+        let watcher = KmsgWatcher {
+            position:   Arc::new(Mutex::new((None, 0))),
+            messages:   Arc::new(Mutex::new(vec![])),
+            stop:       Arc::new(AtomicBool::new(false)),
+        };
+        os_start(&watcher, kernel_name(device))?;
+        Ok(watcher)
+    }
+
+    /// Get an observer that has to be attached to the Disktest instances
+    /// to correlate the messages with the current position.
+    pub fn observer(&self) -> Box<dyn ProgressObserver> {
+        Box::new(PositionTracker {
+            position:   Arc::clone(&self.position),
+        })
+    }
+
+    /// Stop collecting and get all collected messages.
+    pub fn finish(self) -> Vec<KernelMessage> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.messages.lock().unwrap().clone()
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn os_start(watcher: &KmsgWatcher, name: String) -> ah::Result<()> {
+    use std::fs::File;
+    use std::io::{ErrorKind, Read, Seek, SeekFrom};
+
+    let mut file = match File::open("/dev/kmsg") {
+        Ok(f) => f,
+        Err(e) => return Err(ah::format_err!("Failed to open the kernel log /dev/kmsg: {}", e)),
+    };
+    if let Err(e) = file.seek(SeekFrom::End(0)) {
+        return Err(ah::format_err!("Failed to seek the kernel log /dev/kmsg: {}", e));
+    }
+
+    let position = Arc::clone(&watcher.position);
+    let messages = Arc::clone(&watcher.messages);
+    let stop = Arc::clone(&watcher.stop);
+    // The thread blocks in read() while there are no new messages.
+    // It is not joined and terminates with the process.
+    std::thread::spawn(move || {
+        let mut buf = vec![0; 8192];
+        while !stop.load(Ordering::Relaxed) {
+            let count = match file.read(&mut buf) {
+                Ok(0) => break,
+                Ok(count) => count,
+                // Some records have been overwritten before we could read them.
+                Err(e) if e.kind() == ErrorKind::BrokenPipe => continue,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            let record = String::from_utf8_lossy(&buf[..count]);
+            if let Some(message) = parse_record(&record, &name) {
+                if stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                let (phase, offset) = *position.lock().unwrap();
+                messages.lock().unwrap().push(KernelMessage {
+                    phase:          phase.map(|p| p.to_string()),
+                    offset,
+                    sector_offset:  parse_sector_offset(&message),
+                    message,
+                });
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn os_start(_watcher: &KmsgWatcher, _name: String) -> ah::Result<()> {
+    Err(ah::format_err!("Kernel log monitoring is only supported on Linux."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_record() {
+        let rec = "3,1234,5678901,-;blk_update_request: I/O error, dev sdb, sector 2048 op 0x0:(READ)\n \
+                   SUBSYSTEM=block\n DEVICE=b8:16\n";
+        let msg = parse_record(rec, "sdb").unwrap();
+        assert_eq!(msg, "blk_update_request: I/O error, dev sdb, sector 2048 op 0x0:(READ)");
+        assert_eq!(parse_sector_offset(&msg), Some(2048 * 512));
+        assert_eq!(parse_record(rec, "sda"), None);
+        assert_eq!(parse_record(rec, "sd"), None);
+        assert!(parse_record("3,1,2,-;sd 2:0:0:0: [sdb1] tag#0 FAILED Result", "sdb").is_some());
+        assert!(parse_record("6,1,2,-;sd 2:0:0:0: [sdb] 1953525168 512-byte logical blocks", "sdb").is_none());
+        assert!(parse_record("4,1,2,-;nvme0n1: I/O Cmd(0x2) @ LBA 0, 8 blocks, I/O Error", "nvme0n1").is_some());
+        assert!(parse_record("garbage", "sdb").is_none());
+    }
+
+    #[test]
+    fn test_parse_sector_offset() {
+        assert_eq!(parse_sector_offset("critical medium error, dev sdb, sector 10 op"), Some(5120));
+        assert_eq!(parse_sector_offset("sector x"), None);
+        assert_eq!(parse_sector_offset("I/O error"), None);
+    }
+
+    #[test]
+    fn test_mentions_device() {
+        assert!(mentions_device("dev sdb,", "sdb"));
+        assert!(mentions_device("[sdb2]", "sdb"));
+        assert!(mentions_device("sdb", "sdb"));
+        assert!(!mentions_device("dev sdba,", "sdb"));
+        assert!(!mentions_device("dev xsdb,", "sdb"));
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,135 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+
+use crate::resume::Phase;
+use disktest_core::{DtLatency, LATENCY_BUCKETS};
+use std::time::Duration;
+
+/// Width of the longest histogram bar, in characters.
+const BAR_WIDTH: u64 = 40;
+
+/// Latency histograms of all write and verify phases of a run.
+#[derive(Default)]
+pub struct RunLatency {
+    pub write:      DtLatency,
+    pub verify:     DtLatency,
+}
+
+impl RunLatency {
+    /// Add the latencies of a finished phase.
+    pub fn add(&mut self, phase: Phase, latency: &DtLatency) {
+        match phase {
+            Phase::Write => self.write.merge(latency),
+            Phase::Verify => self.verify.merge(latency),
+        }
+    }
+
+    /// Print the histograms of all phases that did any I/O.
+    pub fn print(&self) {
+        for (name, latency) in &[("Write", &self.write), ("Read", &self.verify)] {
+            if latency.count() > 0 {
+                println!("\n{} latency ({} calls):", name, latency.count());
+                for line in format_histogram(latency) {
+                    println!("    {}", line);
+                }
+            }
+        }
+    }
+}
+
+/// Format a latency for display.
+pub fn format_latency(latency: Duration) -> String {
+    let us = latency.as_micros();
+    if us < 1000 {
+        format!("{} us", us)
+    } else if us < 1_000_000 {
+        format!("{:.1} ms", us as f64 / 1000.0)
+    } else {
+        format!("{:.2} s", latency.as_secs_f64())
+    }
+}
+
+/// Format the percentiles and the histogram bars.
+/// Only the range between the first and the last used bucket is shown.
+pub fn format_histogram(latency: &DtLatency) -> Vec<String> {
+    let mut lines = vec![format!("p50: {}, p95: {}, p99.9: {}, max: {}",
+                                 format_latency(latency.percentile(50.0)),
+                                 format_latency(latency.percentile(95.0)),
+                                 format_latency(latency.percentile(99.9)),
+                                 format_latency(latency.max()))];
+    let buckets = latency.buckets();
+    let first = buckets.iter().position(|&c| c > 0);
+    let last = buckets.iter().rposition(|&c| c > 0);
+    let (first, last) = match (first, last) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return lines,
+    };
+    let most = buckets.iter().copied().max().unwrap_or(1).max(1);
+    for (i, &count) in buckets.iter().enumerate().take(last + 1).skip(first) {
+        let label = match DtLatency::bucket_limit(i) {
+            Some(limit) => format!("< {}", format_latency(limit)),
+            None => format!(">= {}",
+                            format_latency(DtLatency::bucket_limit(LATENCY_BUCKETS - 2).unwrap())),
+        };
+        let bar = if count > 0 { (count * BAR_WIDTH / most).max(1) } else { 0 };
+        let line = format!("{:>11} {:>10} {}", label, count, "#".repeat(bar as usize));
+        lines.push(line.trim_end().to_string());
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_latency() {
+        assert_eq!(format_latency(Duration::from_nanos(500)), "0 us");
+        assert_eq!(format_latency(Duration::from_micros(512)), "512 us");
+        assert_eq!(format_latency(Duration::from_micros(16384)), "16.4 ms");
+        assert_eq!(format_latency(Duration::from_millis(2500)), "2.50 s");
+    }
+
+    #[test]
+    fn test_histogram() {
+        let mut latency = DtLatency::new();
+        assert_eq!(format_histogram(&latency), vec!["p50: 0 us, p95: 0 us, p99.9: 0 us, max: 0 us"]);
+        for _ in 0..99 {
+            latency.record(Duration::from_micros(100));
+        }
+        latency.record(Duration::from_micros(600));
+        assert_eq!(format_histogram(&latency),
+                   vec!["p50: 128 us, p95: 128 us, p99.9: 600 us, max: 600 us",
+                        "   < 128 us         99 ########################################",
+                        "   < 256 us          0",
+                        "   < 512 us          0",
+                        "   < 1.0 ms          1 #"]);
+
+        let mut run = RunLatency::default();
+        run.add(Phase::Write, &latency);
+        run.add(Phase::Write, &latency);
+        assert_eq!(run.write.count(), 200);
+        assert_eq!(run.verify.count(), 0);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
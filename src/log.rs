@@ -0,0 +1,167 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+use disktest_core::util::prettybytes;
+use disktest_core::{DtErrorRecord, DtPhase, ProgressInfo, ProgressObserver, format_progress};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Interval of the progress lines in the log file.
+const LOG_PROGRESS_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Format a time as UTC timestamp (e.g. "2020-11-28T13:45:10Z").
+pub fn format_timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (days, rem) = ((secs / 86400) as i64, secs % 86400);
+    // Convert the days since the epoch to the civil date.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            year, month, day, rem / 3600, rem / 60 % 60, rem % 60)
+}
+
+/// Timestamped log file.
+/// The file is opened in append mode and each line is written with a single write,
+/// so existing logs are never truncated.
+#[derive(Clone)]
+pub struct LogFile {
+    path:       PathBuf,
+    file:       Arc<Mutex<File>>,
+}
+
+impl LogFile {
+    /// Open the log file for appending. It is created, if it does not exist.
+    pub fn open(path: &Path) -> ah::Result<LogFile> {
+        let file = match OpenOptions::new().create(true).append(true).open(path) {
+            Ok(x) => x,
+            Err(e) => return Err(ah::format_err!("Failed to open log file {:?}: {}", path, e)),
+        };
+        Ok(LogFile {
+            path:       path.to_path_buf(),
+            file:       Arc::new(Mutex::new(file)),
+        })
+    }
+
+    /// Write a timestamped line to the log.
+    /// Write errors are reported on the console, but they don't abort the run.
+    pub fn log(&self, message: &str) {
+        let line = format!("{} {}\n", format_timestamp(SystemTime::now()), message);
+        let mut file = self.file.lock().unwrap();
+        if let Err(e) = file.write_all(line.as_bytes()).and_then(|_| file.flush()) {
+            eprintln!("WARNING: Failed to write log file {:?}: {}", self.path, e);
+        }
+    }
+
+    /// Get an observer that logs the progress and error messages of a Disktest instance.
+    pub fn observer(&self) -> Box<dyn ProgressObserver> {
+        Box::new(LogProgress {
+            log:        self.clone(),
+            last_time:  Instant::now(),
+        })
+    }
+}
+
+/// Progress observer that writes to the log file.
+struct LogProgress {
+    log:        LogFile,
+    last_time:  Instant,
+}
+
+impl ProgressObserver for LogProgress {
+    fn begin(&mut self, phase: DtPhase, path: &Path, seek: u64) {
+        self.last_time = Instant::now();
+        self.log.log(&format!("Begin {} of {:?} at position {}.",
+                              phase, path, prettybytes(seek, true, true)));
+    }
+
+    fn progress(&mut self, info: &ProgressInfo) {
+        if self.last_time.elapsed() >= LOG_PROGRESS_INTERVAL {
+            self.log.log(&format_progress(info, false));
+            self.last_time = Instant::now();
+        }
+    }
+
+    fn error(&mut self, error: &DtErrorRecord) {
+        self.log.log(&format!("ERROR: {} at offset {}, {} bytes: {}",
+                              error.kind, error.offset, error.length, error.message));
+    }
+
+    fn message(&mut self, message: &str) {
+        for line in message.lines().filter(|l| !l.trim().is_empty()) {
+            self.log.log(line);
+        }
+    }
+
+    fn finish(&mut self, info: &ProgressInfo, errors: &[DtErrorRecord]) {
+        self.log.log(&format_progress(info, true));
+        if !errors.is_empty() {
+            self.log.log(&format!("The {} phase found {} bad regions.", info.phase, errors.len()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_format_timestamp() {
+        let t = |secs| format_timestamp(UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(t(0), "1970-01-01T00:00:00Z");
+        assert_eq!(t(951782400), "2000-02-29T00:00:00Z");
+        assert_eq!(t(1606571110), "2020-11-28T13:45:10Z");
+        assert_eq!(t(4107542399), "2100-02-28T23:59:59Z");
+    }
+
+    #[test]
+    fn test_log_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log");
+        fs::write(&path, "old\n").unwrap();
+        let log = LogFile::open(&path).unwrap();
+        log.log("foo");
+        let mut obs = log.observer();
+        obs.message("bar\n\nbaz");
+
+        let text = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "old");
+        assert!(lines[1].ends_with("Z foo"));
+        assert!(lines[2].ends_with("Z bar"));
+        assert!(lines[3].ends_with("Z baz"));
+        assert!(LogFile::open(dir.path()).is_err());
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
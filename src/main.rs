@@ -20,31 +20,113 @@
 //
 
 mod args;
-mod disktest;
-mod drop_caches;
-mod generator;
-mod kdf;
+mod ata;
+mod badblocks;
+mod devlist;
+mod fill;
+mod hooks;
+mod identity;
+mod interrupt;
+mod junit;
+mod kmsg;
+mod latency;
+mod log;
+mod meta;
+mod notify;
+mod nvme;
+mod priority;
+mod metrics;
+mod partitions;
+mod ptable;
+mod ranges;
+mod rawdisk;
+mod remote;
+mod report;
+mod resume;
 mod seed;
-mod stream;
-mod stream_aggregator;
-mod util;
+mod smart;
+mod stats;
+mod status;
+mod thermal;
+mod throughput;
+mod time_limit;
+mod trim;
+mod tui;
+mod wipe;
+mod zones;
 
 use anyhow as ah;
-use args::{Args, parse_args};
-use crate::seed::print_generated_seed;
-use disktest::{Disktest, DisktestFile};
-use signal_hook;
+use args::{Args, Command, OutputFormat, gen_seed_len, parse_args, parse_device_args, resolve_percent};
+use crate::ata::{AtaSecurity, security_erase};
+use crate::badblocks::{read_skip_list, write_bad_blocks_list};
+use crate::devlist::{device_args, read_device_list};
+use crate::fill::{fill_file_path, fill_file_seed, fill_file_size};
+use crate::hooks::run_hook;
+use crate::identity::{DeviceIdentity, confirmation_accepted, device_by_id, device_by_serial};
+use crate::interrupt::format_interruption;
+use crate::junit::write_junit;
+use crate::kmsg::{KernelMessage, KmsgWatcher};
+use crate::latency::RunLatency;
+use crate::log::LogFile;
+use crate::meta::{RunMeta, stream_seed};
+use crate::notify::notify;
+use crate::nvme::{check_crypto_erased, nvme_erase, read_samples};
+use crate::priority::set_priority;
+use crate::ptable::PtableBackup;
+use crate::metrics::Metrics;
+use crate::partitions::{PartitionTable, read_partitions};
+use crate::ranges::align_ranges;
+use crate::rawdisk::{device_use, preferred_device_path, unmount_disk};
+use crate::remote::{RemoteFailure, run_remote, serve};
+use crate::report::{DeviceListReport, Report, ReportErase, classify_errors};
+use crate::resume::{Phase, ResumeSaver, ResumeState};
+use crate::seed::{gen_seed_string, print_generated_seed, round_seed};
+use crate::smart::{SmartSnapshot, read_health};
+use crate::stats::RunStats;
+use crate::status::StatusRequest;
+use crate::thermal::{ThermalWatch, Throttling};
+use crate::throughput::ThroughputLog;
+use crate::time_limit::TimeLimit;
+use crate::trim::{discard, discard_range, format_trim_result, read_is_deterministic};
+use crate::tui::Tui;
+use crate::wipe::WipePass;
+use crate::zones::RunZones;
+use disktest_core::disk_size::{disk_size, free_space};
+use disktest_core::util::prettybytes;
+use disktest_core::{Disktest, DisktestBuilder, DisktestFile, DisktestHandle, DtError, DtErrorKind, DtErrorRecord,
+                    DtLatency, DtRandomIo, DtStreamType, DtTrail, DtZones, HEADER_SIZE, JsonSink, open_json_sink};
 use std::env::args_os;
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write, stdin, stdout};
+use std::path::{Path, PathBuf};
+use std::slice;
 use std::sync::Arc;
-use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
 /// Install abort signal handlers and return
 /// the abort-flag that is written to true by these handlers.
+/// The run stops cleanly on the first signal and terminates immediately on the second one.
 fn install_abort_handlers() -> ah::Result<Arc<AtomicBool>> {
     let abort = Arc::new(AtomicBool::new(false));
     for sig in &[signal_hook::SIGTERM,
                  signal_hook::SIGINT] {
-        if let Err(e) = signal_hook::flag::register(*sig, Arc::clone(&abort)) {
+        let flag = Arc::clone(&abort);
+        let sig_num = *sig;
+        let handler = move || {
+            // Only async-signal-safe functions may be called here.
+            if flag.swap(true, Ordering::SeqCst) {
+                unsafe { libc::_exit(128 + sig_num) };
+            }
+            #[cfg(unix)]
+            {
+                const MSG: &[u8] = b"\nStopping. Interrupt again to terminate immediately.\n";
+                unsafe { libc::write(libc::STDERR_FILENO, MSG.as_ptr() as *const libc::c_void, MSG.len()) };
+            }
+        };
+        if let Err(e) = unsafe { signal_hook::register(*sig, handler) } {
             return Err(ah::format_err!("Failed to register signal {}: {}", sig, e));
         }
 
@@ -53,50 +135,873 @@ fn install_abort_handlers() -> ah::Result<Arc<AtomicBool>> {
     Ok(abort)
 }
 
+/// Install the pause signal handlers and return
+/// the pause-flag that is controlled by these handlers.
+/// SIGTSTP (Ctrl-Z) toggles the pause instead of stopping the process
+/// and SIGCONT resumes the run.
+fn install_pause_handlers() -> ah::Result<Arc<AtomicBool>> {
+    let pause = Arc::new(AtomicBool::new(false));
+    #[cfg(unix)]
+    {
+        let flag = Arc::clone(&pause);
+        let toggle = move || {
+            // Only async-signal-safe functions may be called here.
+            let msg: &[u8] = if flag.fetch_xor(true, Ordering::SeqCst) {
+                b"\nResuming.\n"
+            } else {
+                b"\nPausing. Press Ctrl-Z again to resume.\n"
+            };
+            unsafe { libc::write(libc::STDERR_FILENO, msg.as_ptr() as *const libc::c_void, msg.len()) };
+        };
+        let flag = Arc::clone(&pause);
+        let resume = move || flag.store(false, Ordering::SeqCst);
+        for (sig, result) in [(libc::SIGTSTP, unsafe { signal_hook::register(libc::SIGTSTP, toggle) }),
+                              (libc::SIGCONT, unsafe { signal_hook::register(libc::SIGCONT, resume) })] {
+            if let Err(e) = result {
+                return Err(ah::format_err!("Failed to register signal {}: {}", sig, e));
+            }
+        }
+    }
+    Ok(pause)
+}
+
+/// Progress outputs and limits of the run that apply to all disktest core instances.
+struct RunContext {
+    json:       Option<JsonSink>,
+    metrics:    Option<Metrics>,
+    log:        Option<LogFile>,
+    throughput: Option<ThroughputLog>,
+    time_limit: Option<TimeLimit>,
+    thermal:    ThermalWatch,
+    status:     StatusRequest,
+    pause:      Arc<AtomicBool>,
+    tui:        Option<Tui>,
+}
+
+impl RunContext {
+    /// Returns an error, if the time limit of the run has been reached.
+    fn check_time_limit(&self) -> ah::Result<()> {
+        match &self.time_limit {
+            Some(time_limit) if time_limit.expired() => Err(DtError::TimeLimit.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Close the terminal user interface, if it is shown.
+    fn close_tui(&self) {
+        if let Some(tui) = &self.tui {
+            tui.close();
+        }
+    }
+}
+
 /// Create a new disktest core instance.
-fn new_disktest(args:  &Args,
-                write: bool,
-                abort: &Arc<AtomicBool>) -> ah::Result<(Disktest, DisktestFile)> {
+fn new_disktest(args:       &Args,
+                write:      bool,
+                abort:      &Arc<AtomicBool>,
+                ctx:        &RunContext) -> ah::Result<(Disktest, DisktestFile)> {
+    new_disktest_rw(args, !write, write, abort, ctx, None)
+}
+
+/// Get the size of the device or of the regular file, if it exists.
+fn device_size(path: &str) -> Option<u64> {
+    let file = File::open(path).ok()?;
+    match disk_size(&file) {
+        Ok(Some(size)) => Some(size),
+        _ => file.metadata().ok().filter(|m| m.is_file()).map(|m| m.len()),
+    }
+}
+
+/// Check whether the command destroys the data on the device.
+fn destroys_data(args: &Args) -> bool {
+    match args.command {
+        Command::Test => args.write && !args.expect_image,
+        Command::Wipe | Command::Bench | Command::Surface |
+        Command::CapacityCheck | Command::TrimCheck | Command::SecureErase |
+        Command::NvmeErase => true,
+        _ => false,
+    }
+}
+
+/// Check whether a report of the run is collected. See --report, --report-junit and --output.
+fn wants_report(args: &Args) -> bool {
+    args.report.is_some() || args.report_junit.is_some() ||
+    args.output == OutputFormat::SummaryJson
+}
+
+/// Print the identity of the device and ask the user to confirm
+/// that all data on it shall be destroyed.
+/// Regular files are overwritten without confirmation.
+fn confirm_destruction(args: &Args) -> ah::Result<()> {
+    if args.yes || !destroys_data(args) {
+        return Ok(());
+    }
+    let identity = match DeviceIdentity::read(&args.device) {
+        Some(identity) => identity,
+        None => return Ok(()),
+    };
+    // Don't ask for a device that can't be written to anyway.
+    device_use(&args.device).check(&args.device, args.force, args.allow_system_disk)?;
+    // stdout only carries the summary.
+    if args.output == OutputFormat::SummaryJson {
+        return Err(ah::format_err!("All data on {:?} will be destroyed. \
+                                    Use --yes to confirm this with --output summary-json.",
+                                   args.device));
+    }
+    println!("All data on {:?} will be destroyed:", args.device);
+    println!("    Model:  {}", identity.model.as_deref().unwrap_or("unknown"));
+    println!("    Serial: {}", identity.serial.as_deref().unwrap_or("unknown"));
+    println!("    Size:   {}", identity.size.map_or("unknown".to_string(),
+                                                   |size| prettybytes(size, true, true)));
+    if let Some(table) = read_partitions(&args.device) {
+        println!("    Partitions ({}):", table.kind);
+        for part in &table.partitions {
+            println!("        {}: {} at byte {}",
+                     part.number, prettybytes(part.length, true, true), part.offset);
+        }
+    }
+    print!("Type {:?} or 'yes' to continue: ", args.device);
+    stdout().flush()?;
+    let mut answer = String::new();
+    if let Err(e) = stdin().read_line(&mut answer) {
+        return Err(ah::format_err!("Failed to read the confirmation: {}", e));
+    }
+    if !confirmation_accepted(&answer, &args.device) {
+        return Err(ah::format_err!("Not confirmed. Nothing has been written to {:?}. \
+                                    Use --yes to skip the confirmation.", args.device));
+    }
+    Ok(())
+}
+
+/// Create a new disktest core instance and open the device for reading and/or writing.
+/// If kmsg is given, then the instance reports its position to the kernel log watcher.
+fn new_disktest_rw(args:        &Args,
+                   read:        bool,
+                   write:       bool,
+                   abort:       &Arc<AtomicBool>,
+                   ctx:         &RunContext,
+                   kmsg:        Option<&KmsgWatcher>) -> ah::Result<(Disktest, DisktestFile)> {
+    if write {
+        device_use(&args.device).check(&args.device, args.force, args.allow_system_disk)?;
+    }
+    if write && unmount_disk(&args.device)? && args.quiet < 2 {
+        println!("Unmounted all volumes of {:?}.", args.device);
+    }
+    let mut builder = DisktestBuilder::new()
+        .algorithm(args.algorithm)
+        .seed(round_seed(&args.seed, args.kdf, args.seed_round))
+        .kdf(args.kdf)
+        .threads(args.threads)
+        // The terminal user interface replaces the console progress output.
+        .quiet_level(if ctx.tui.is_some() { 2 } else { args.quiet })
+        .abort(Arc::clone(abort))
+        .pause(Arc::clone(&ctx.pause))
+        .keep_going(args.keep_going)
+        .verify_stride(args.verify_stride)
+        .read_retries(args.read_retries)
+        .mmap(args.mmap);
+    if let Some(bytes) = args.sync_every {
+        builder = builder.sync_every(bytes);
+    }
+    if let Some(path) = &args.skip_list {
+        builder = builder.skip_regions(read_skip_list(Path::new(path), args.list_block_size)?);
+    }
+    if let Some(pattern) = &args.pattern {
+        builder = builder.pattern(pattern.clone());
+    }
+    if let Some(chunk_size) = args.chunk_size {
+        builder = builder.chunk_size(chunk_size);
+    }
+    if let Some(max_errors) = args.max_errors {
+        builder = builder.max_errors(max_errors);
+    }
+    if let Some(max_rate) = args.max_rate {
+        builder = builder.max_rate(max_rate);
+    }
+    if let Some(zones) = args.zones {
+        builder = builder.zones(zones);
+    }
+    if let Some(cpu_set) = &args.cpu_set {
+        builder = builder.cpu_set(cpu_set.clone());
+    }
+    if args.command == Command::Test && args.header {
+        builder = builder.header(args.seek);
+    }
+    if let Some(json) = &ctx.json {
+        builder = builder.progress_json(Arc::clone(json));
+    }
+    if let Some(metrics) = &ctx.metrics {
+        builder = builder.progress_observer(metrics.observer());
+    }
+    if let Some(log) = &ctx.log {
+        builder = builder.progress_observer(log.observer());
+    }
+    if let Some(throughput) = &ctx.throughput {
+        builder = builder.progress_observer(throughput.observer());
+    }
+    if let Some(kmsg) = kmsg {
+        builder = builder.progress_observer(kmsg.observer());
+    }
+    builder = builder.progress_observer(ctx.thermal.observer());
+    builder = builder.progress_observer(ctx.status.observer());
+    if let Some(tui) = &ctx.tui {
+        builder = builder.progress_observer(tui.observer());
+    }
+    let disktest = builder.build()?;
+    if let Some(time_limit) = &ctx.time_limit {
+        time_limit.register(disktest.handle());
+    }
     Ok((
-        Disktest::new(args.algorithm,
-                      args.seed.as_bytes().to_vec(),
-                      args.threads,
-                      Some(Arc::clone(abort))),
+        disktest,
         DisktestFile::open(&args.device,
-                           !write,
+                           read,
                            write,
+                           args.direct,
+                           write && args.sync_writes,
                            args.quiet)?,
     ))
 }
 
-/// Main program entry point.
-fn main() -> ah::Result<()> {
-    let args = parse_args(args_os())?;
-    let abort = install_abort_handlers()?;
+/// Print the summary of a combined write + verify run.
+fn print_summary(title:             &str,
+                 bytes_written:     Option<u64>,
+                 bytes_verified:    Option<u64>,
+                 result:            &ah::Result<()>) {
+    let fmt = |count: Option<u64>| {
+        match count {
+            Some(count) => prettybytes(count, true, true),
+            None => "-".to_string(),
+        }
+    };
+    println!("\n{}:\n    \
+             Written:  {}\n    \
+             Verified: {}\n    \
+             Result:   {}\n",
+             title,
+             fmt(bytes_written),
+             fmt(bytes_verified),
+             match result {
+                 Ok(_) => "Success".to_string(),
+                 Err(e) => format!("FAILED: {}", e),
+             });
+}
 
-    if !args.user_seed && args.quiet < 2 {
-        print_generated_seed(&args.seed, true);
-    }
+/// Check whether the operation was aborted by SIGINT or SIGTERM.
+fn is_aborted<T>(result: &ah::Result<T>) -> bool {
+    matches!(result.as_ref().err().and_then(|e| e.downcast_ref::<DtError>()),
+             Some(DtError::Aborted))
+}
+
+/// Result of a verification that trails the write phase:
+/// The number of verified bytes, the bad regions, the latencies and the zone throughput.
+type VerifyBehindResult = (ah::Result<u64>, Vec<DtErrorRecord>, DtLatency, Option<DtZones>);
+
+/// Start the verification that trails the write phase at the absolute position pos by lag bytes.
+/// The device is read with direct I/O, so that the data is read from the device.
+/// The write is cancelled through the writer handle, if the verification fails.
+fn start_verify_behind(args:    &Args,
+                       abort:   &Arc<AtomicBool>,
+                       ctx:     &RunContext,
+                       kmsg:    Option<&KmsgWatcher>,
+                       writer:  DisktestHandle,
+                       pos:     u64,
+                       lag:     u64) -> ah::Result<(DtTrail, thread::JoinHandle<VerifyBehindResult>)> {
+    let mut verify_args = args.clone();
+    verify_args.direct = true;
+    // The write phase prints the progress.
+    verify_args.quiet = 2;
+    let (mut disktest, file) = new_disktest_rw(&verify_args, true, false, abort, ctx, kmsg)?;
+    let trail = DtTrail::new();
+    disktest.follow_trail(trail.clone(), lag);
+    let thread = thread::spawn(move || {
+        let res = disktest.verify(file, pos, Disktest::UNLIMITED);
+        if res.is_err() {
+            writer.cancel();
+        }
+        (res,
+         disktest.get_errors().to_vec(),
+         disktest.get_latency().clone(),
+         disktest.get_zones().cloned())
+    });
+    Ok((trail, thread))
+}
+
+/// Run one write and/or verify round.
+/// If start is given, the round is continued in the given phase at the given position.
+/// The result of each phase is added to the report, if any.
+/// All bad regions that were found are appended to bad_regions.
+/// The latencies and the zone throughput of all phases are added to latency and zones.
+/// If kmsg is given, then the kernel log messages are correlated with the position.
+/// The phase and the absolute position that was reached last are stored to reached.
+/// Returns the number of bytes written and verified and the result of the round.
+#[allow(clippy::too_many_arguments)]
+fn run_round(args:      &Args,
+             abort:     &Arc<AtomicBool>,
+             ctx:       &RunContext,
+             kmsg:      Option<&KmsgWatcher>,
+             saver:     Option<&ResumeSaver>,
+             mut report: Option<&mut Report>,
+             bad_regions: &mut Vec<DtErrorRecord>,
+             latency:   &mut RunLatency,
+             zones:     &mut RunZones,
+             reached:   &mut Option<(Phase, u64)>,
+             round:     u64,
+             start:     Option<&ResumeState>) -> (Option<u64>, Option<u64>, ah::Result<()>) {
+    let start_phase = start.map(|s| s.phase);
+    let start_pos = |phase| {
+        match start {
+            Some(s) if s.phase == phase => s.position,
+            _ => args.seek,
+        }
+    };
+    let reduce = |max_bytes: u64, done: u64| {
+        if max_bytes == Disktest::UNLIMITED {
+            max_bytes
+        } else {
+            max_bytes.saturating_sub(done)
+        }
+    };
 
     // Run write-mode, if requested.
     let mut result = Ok(());
-    if args.write {
-        let (mut disktest, file) = new_disktest(&args, true, &abort)?;
-        result = match disktest.write(file, args.seek, args.max_bytes) {
-            Ok(_) => Ok(()),
-            Err(e) => Err(e),
+    let mut bytes_written = start.and_then(|s| s.written);
+    let mut bytes_verified = None;
+    if args.write && start_phase != Some(Phase::Verify) {
+        let pos = start_pos(Phase::Write);
+        let done = pos.saturating_sub(args.seek);
+        let begin = Instant::now();
+        let mut count = None;
+        let mut errors = vec![];
+        let mut lat = DtLatency::new();
+        let mut zn = None;
+        let mut behind = None;
+        result = new_disktest_rw(args, false, true, abort, ctx, kmsg).and_then(|(mut disktest, file)| {
+            if let Some(saver) = saver {
+                saver.begin(round, Phase::Write, pos, None)?;
+                disktest.set_checkpoint(saver.checkpoint());
+            }
+            // The device is opened for verification after it has been created for writing.
+            if let Some(lag) = args.verify_behind {
+                let (trail, thread) = start_verify_behind(args, abort, ctx, kmsg,
+                                                          disktest.handle(), pos, lag)?;
+                disktest.set_trail(trail.clone());
+                behind = Some((trail, thread));
+            }
+            let res = disktest.write(file, pos, reduce(args.max_bytes, done));
+            let position = disktest.get_position();
+            *reached = Some((Phase::Write, position));
+            errors = disktest.get_errors().to_vec();
+            lat = disktest.get_latency().clone();
+            zn = disktest.get_zones().cloned();
+            if is_aborted(&res) {
+                bytes_written = Some(position.saturating_sub(args.seek));
+            }
+            let res = res?;
+            count = Some(res);
+            bytes_written = Some(done + res);
+            Ok(())
+        });
+        if let Some(report) = report.as_mut() {
+            report.add_phase(round, Phase::Write, pos, count, begin.elapsed(), &errors,
+                             if args.latency { Some(&lat) } else { None }, zn.as_ref());
+        }
+        bad_regions.extend(errors);
+        latency.add(Phase::Write, &lat);
+        if let Some(zn) = &zn {
+            zones.add(Phase::Write, zn);
+        }
+
+        // Wait for the verification of the rest of the written data.
+        if let Some((trail, thread)) = behind {
+            trail.finish();
+            let (res, errors, lat, zn) = thread.join().expect("Verify thread panicked");
+            let count = res.as_ref().ok().copied();
+            if let Some(report) = report.as_mut() {
+                report.add_phase(round, Phase::Verify, pos, count, begin.elapsed(), &errors,
+                                 if args.latency { Some(&lat) } else { None }, zn.as_ref());
+            }
+            bad_regions.extend(errors);
+            latency.add(Phase::Verify, &lat);
+            if let Some(zn) = &zn {
+                zones.add(Phase::Verify, zn);
+            }
+            bytes_verified = count;
+            // A verification failure is more important than a failed write.
+            if let Err(e) = res {
+                result = Err(e);
+            }
+        }
+
+        // Write the on-disk header, if the written data covers it.
+        if let (true, Ok(()), Some(bytes)) = (args.header, &result, bytes_written) {
+            if bytes >= HEADER_SIZE as u64 {
+                result = new_disktest(args, true, abort, ctx).and_then(|(mut disktest, file)| {
+                    disktest.write_header(file, bytes)
+                });
+            }
+        }
+
+        // Save the parameters to the metadata file.
+        if let (Some(path), Ok(()), Some(bytes)) = (&args.meta, &result, bytes_written) {
+            result = RunMeta::new(args, bytes).save(Path::new(path));
+        }
+
+        // Do not verify the incomplete data, if the time is up.
+        if result.is_ok() {
+            result = ctx.check_time_limit();
+        }
+    }
+
+    // Run verify-mode, if requested and not already done behind the write.
+    // If we just wrote the data, then verify exactly the written bytes.
+    if args.verify && result.is_ok() && bytes_verified.is_none() {
+        let pos = start_pos(Phase::Verify);
+        let done = pos.saturating_sub(args.seek);
+        let max_bytes = reduce(bytes_written.unwrap_or(args.max_bytes), done);
+        let begin = Instant::now();
+        let mut count = None;
+        let mut errors = vec![];
+        let mut lat = DtLatency::new();
+        let mut zn = None;
+        result = new_disktest_rw(args, true, false, abort, ctx, kmsg).and_then(|(mut disktest, file)| {
+            if let Some(saver) = saver {
+                saver.begin(round, Phase::Verify, pos, bytes_written)?;
+                disktest.set_checkpoint(saver.checkpoint());
+            }
+            let res = disktest.verify(file, pos, max_bytes);
+            let position = disktest.get_position();
+            *reached = Some((Phase::Verify, position));
+            errors = disktest.get_errors().to_vec();
+            lat = disktest.get_latency().clone();
+            zn = disktest.get_zones().cloned();
+            if is_aborted(&res) {
+                bytes_verified = Some(position.saturating_sub(args.seek));
+            }
+            let res = res?;
+            count = Some(res);
+            bytes_verified = Some(done + res);
+            Ok(())
+        });
+        if let Some(report) = report.as_mut() {
+            report.add_phase(round, Phase::Verify, pos, count, begin.elapsed(), &errors,
+                             if args.latency { Some(&lat) } else { None }, zn.as_ref());
+        }
+        bad_regions.extend(errors);
+        latency.add(Phase::Verify, &lat);
+        if let Some(zn) = &zn {
+            zones.add(Phase::Verify, zn);
+        }
+        if result.is_ok() {
+            result = ctx.check_time_limit();
+        }
+    }
+
+    (bytes_written, bytes_verified, result)
+}
+
+/// Read the on-disk header for a verify-only run and apply its parameters.
+/// If there is no header, then the header is disabled.
+fn apply_header(args: &mut Args) -> ah::Result<()> {
+    let file = DisktestFile::open(&args.device, true, false, args.direct, false, args.quiet)?;
+    let header = match Disktest::read_header(file, args.seek)? {
+        Some(header) => header,
+        None => {
+            args.header = false;
+            return Ok(());
+        },
+    };
+    if !header.check_seed(&stream_seed(args)) {
+        return Err(DtError::InvalidParameter(
+            "The --seed does not match the seed that was used to write the device. \
+             After a write with --rounds the device holds the data of the last round \
+             (see --seed-round).".to_string()).into());
+    }
+    args.algorithm = header.algorithm;
+    args.kdf = header.kdf;
+    args.max_bytes = args.max_bytes.min(header.bytes);
+    if args.quiet < 1 {
+        println!("Using the on-disk header: Algorithm {}, KDF {}, {} written.",
+                 header.algorithm,
+                 header.kdf,
+                 prettybytes(header.bytes, true, true));
+    }
+    Ok(())
+}
+
+/// Print the kernel log messages about the device that were logged during the run.
+fn print_kernel_messages(device: &str, messages: &[KernelMessage]) {
+    println!("\nKernel log messages about {:?}:", device);
+    for msg in messages {
+        let phase = msg.phase.as_deref().unwrap_or("idle");
+        let sector = match msg.sector_offset {
+            Some(offset) => format!(", kernel reported offset {}", offset),
+            None => "".to_string(),
+        };
+        println!("    [{} @ {}{}] {}", phase, msg.offset, sector, msg.message);
+    }
+}
+
+/// Maximum number of ranges that are printed per kind of error.
+const MAX_PRINTED_RANGES: usize = 10;
+
+/// Print the number and the ranges of the bad regions of each kind of error.
+fn print_error_classes(bad_regions: &[DtErrorRecord]) {
+    if bad_regions.is_empty() {
+        return;
+    }
+    println!("\nErrors by type:");
+    for class in classify_errors(bad_regions) {
+        println!("    {}: {} regions, {}", class.kind, class.count, prettybytes(class.length, true, true));
+        for range in class.ranges.iter().take(MAX_PRINTED_RANGES) {
+            println!("        {:>15} - {:>15} ({:>10} bytes)",
+                     range.offset, range.offset + range.length, range.length);
+        }
+        if class.count > MAX_PRINTED_RANGES {
+            println!("        ... and {} more regions", class.count - MAX_PRINTED_RANGES);
+        }
+    }
+}
+
+/// Print the partition and the relative offset of each bad region on a partitioned disk.
+fn print_bad_partitions(table: Option<&PartitionTable>, bad_regions: &[DtErrorRecord]) {
+    let table = match table {
+        Some(table) if !bad_regions.is_empty() => table,
+        _ => return,
+    };
+    println!("\nBad regions by {} partition:", table.kind);
+    for region in bad_regions {
+        match table.locate(region.offset) {
+            Some((part, offset)) => {
+                println!("    {:>15} ({:>10} bytes): partition {} + {}",
+                         region.offset, region.length, part.number, offset);
+            },
+            None => {
+                println!("    {:>15} ({:>10} bytes): outside of all partitions",
+                         region.offset, region.length);
+            },
+        }
+    }
+}
+
+/// Print the phases with periodic throughput dips and the drive temperature, if known.
+fn print_throttling(throttling:     &[Throttling],
+                    smart_before:   Option<&SmartSnapshot>,
+                    smart_after:    Option<&SmartSnapshot>) {
+    println!("\nWARNING: Periodic throughput dips are consistent with thermal throttling:");
+    for t in throttling {
+        println!("    {}", t.describe());
+    }
+    let temp = |s: Option<&SmartSnapshot>| s.and_then(|s| s.temperature);
+    if let (Some(before), Some(after)) = (temp(smart_before), temp(smart_after)) {
+        println!("    Drive temperature: {} degrees Celsius before and {} after the run.", before, after);
+    } else {
+        println!("    Use --smart to also show the drive temperature.");
+    }
+}
+
+/// Read the S.M.A.R.T. attributes of the device.
+/// Returns None and prints a warning, if they can't be read.
+fn read_smart(args: &Args) -> Option<SmartSnapshot> {
+    match SmartSnapshot::read(&args.device) {
+        Ok(snapshot) => Some(snapshot),
+        Err(e) => {
+            eprintln!("WARNING: Failed to read the S.M.A.R.T. attributes: {}", e);
+            None
+        },
+    }
+}
+
+/// Back up the partition table of the device and limit the test
+/// to the part of the device between the partition table areas.
+fn preserve_ptable(args: &mut Args) -> ah::Result<PtableBackup> {
+    let ptable = PtableBackup::read(&args.device)?;
+    let chunk_size = args.chunk_size.unwrap_or_else(|| args.algorithm.default_chunk_size());
+    let (seek, max_bytes) = ptable.limit_range(args.seek, args.max_bytes, chunk_size as u64);
+    args.seek = seek;
+    args.max_bytes = max_bytes;
+    if args.quiet < 2 {
+        println!("Preserving the partition table. Testing {} starting at position {} bytes.",
+                 prettybytes(max_bytes, true, true), seek);
+    }
+    Ok(ptable)
+}
+
+/// Load the resume state, if any, and start the resume state saver.
+fn start_resume(args: &mut Args) -> ah::Result<(Option<ResumeSaver>, Option<ResumeState>)> {
+    let path = match &args.resume {
+        Some(path) => Path::new(path).to_path_buf(),
+        None => return Ok((None, None)),
+    };
+    let start = ResumeState::load(&path)?;
+    if let Some(state) = &start {
+        state.check_args(args)?;
+        args.seed = state.seed.clone();
+        if args.quiet < 2 {
+            println!("Resuming round {} at position {}.",
+                     state.round,
+                     prettybytes(state.position, true, true));
+        }
+    } else if !args.user_seed && !args.write {
+        return Err(ah::format_err!("The resume state file {:?} does not exist. \
+                                   Verify-only mode requires --seed.", path));
+    }
+    let state = start.clone().unwrap_or_else(|| ResumeState::new(args));
+    Ok((Some(ResumeSaver::new(&path, state)), start))
+}
+
+/// Run the write and/or verify test.
+/// If reports is given, then the report is added to it instead of being written.
+fn run_test(args:       &Args,
+            abort:      &Arc<AtomicBool>,
+            ctx:        &RunContext,
+            reports:    Option<&mut Vec<Report>>) -> ah::Result<()> {
+    let mut args = args.clone();
+    let (saver, mut start) = start_resume(&mut args)?;
+    let ptable = if args.preserve_ptable { Some(preserve_ptable(&mut args)?) } else { None };
+    if let (Some(path), false) = (args.meta.clone(), args.write) {
+        RunMeta::load(Path::new(&path))?.apply(&mut args)?;
+        if args.quiet < 1 {
+            println!("Using the parameters from the metadata file {:?}.", path);
+        }
+    }
+    if args.header && args.verify && !args.write {
+        apply_header(&mut args)?;
+    }
+    let args = &args;
+    let mut report = if wants_report(args) { Some(Report::new(args)) } else { None };
+    let mut bad_regions = vec![];
+    let mut latency = RunLatency::default();
+    let mut zones = RunZones::default();
+    let smart_before = if args.smart { read_smart(args) } else { None };
+    let identity = DeviceIdentity::read(&args.device);
+    let begin = Instant::now();
+    let kmsg = if args.kernel_log { Some(KmsgWatcher::start(&args.device)?) } else { None };
+    // The partition table is read before it is overwritten.
+    let partitions = if args.quiet < 2 { read_partitions(&args.device) } else { None };
+
+    if !args.user_seed && start.is_none() && args.quiet < 2 {
+        print_generated_seed(&args.seed, true);
+    }
+    if args.mmap && args.verify && args.quiet < 2 &&
+       !std::fs::metadata(&args.device).map(|m| m.is_file()).unwrap_or(true) {
+        println!("WARNING: {:?} is not a regular file. Verifying with regular reads instead of --mmap.",
+                 args.device);
+    }
+
+    let add = |total: Option<u64>, count: Option<u64>| {
+        match (total, count) {
+            (Some(total), Some(count)) => Some(total + count),
+            (None, count) => count,
+            (total, None) => total,
+        }
+    };
+
+    let mut round = start.as_ref().map(|s| s.round - 1).unwrap_or(0);
+    let mut completed = round;
+    let mut total_written = None;
+    let mut total_verified = None;
+    let mut reached = None;
+    let result = loop {
+        round += 1;
+        if args.rounds != 1 && args.quiet < 2 {
+            if args.rounds == 0 {
+                println!("Round {} ...", round);
+            } else {
+                println!("Round {} of {} ...", round, args.rounds);
+            }
+        }
+
+        // All --range windows are processed in each round.
+        // They start at a chunk boundary, so that they are covered completely.
+        let windows = if args.ranges.is_empty() {
+            vec![(args.seek, args.max_bytes)]
+        } else {
+            let chunk_size = args.chunk_size.unwrap_or_else(|| args.algorithm.default_chunk_size());
+            align_ranges(&args.ranges, chunk_size as u64)
         };
+        let mut window_args = args.clone();
+        // Every round writes different data. A verify-only run verifies the same data in all rounds.
+        if args.write {
+            window_args.seed_round = round;
+        }
+        if let Some(report) = report.as_mut() {
+            report.add_round_seed(round, window_args.seed_round, &stream_seed(&window_args));
+        }
+        let mut bytes_written = None;
+        let mut bytes_verified = None;
+        let mut result = Ok(());
+        for (i, &(seek, max_bytes)) in windows.iter().enumerate() {
+            if windows.len() > 1 && args.quiet < 2 {
+                println!("Range {} of {}: {} at byte offset {} ...",
+                         i + 1, windows.len(), prettybytes(max_bytes, true, true), seek);
+            }
+            window_args.seek = seek;
+            window_args.max_bytes = max_bytes;
+            let (written, verified, res) =
+                run_round(&window_args, abort, ctx, kmsg.as_ref(), saver.as_ref(), report.as_mut(),
+                          &mut bad_regions, &mut latency, &mut zones, &mut reached, round,
+                          start.take().as_ref());
+            bytes_written = add(bytes_written, written);
+            bytes_verified = add(bytes_verified, verified);
+            result = res;
+            if result.is_err() {
+                break;
+            }
+        }
+        total_written = add(total_written, bytes_written);
+        total_verified = add(total_verified, bytes_verified);
+        // The summary is printed to the regular screen.
+        if result.is_err() || round == args.rounds {
+            ctx.close_tui();
+        }
+
+        if args.rounds != 1 && args.quiet < 2 {
+            print_summary(&format!("Round {} summary", round),
+                          bytes_written, bytes_verified, &result);
+        }
+        if result.is_ok() {
+            completed = round;
+            if args.loop_mode && args.quiet < 2 {
+                println!("Completed {} cycles: {} written and {} verified in total.\n",
+                         completed,
+                         prettybytes(total_written.unwrap_or(0), true, true),
+                         prettybytes(total_verified.unwrap_or(0), true, true));
+            }
+        }
+
+        if result.is_err() || round == args.rounds {
+            break result;
+        }
+    };
+
+    // The endurance loop regularly ends at the time limit, if a cycle has been completed.
+    let result = match result {
+        Err(e) if args.loop_mode && completed > 0 &&
+                  matches!(e.downcast_ref::<DtError>(), Some(DtError::TimeLimit)) => {
+            if args.quiet < 2 {
+                println!("The time limit has been reached after {} complete cycles.", completed);
+            }
+            Ok(())
+        },
+        result => result,
+    };
+
+    // The partition table is restored, even if the test failed.
+    let result = match (&ptable, args.write) {
+        (Some(ptable), true) => match (result, ptable.restore(&args.device)) {
+            (result, Ok(())) => {
+                if args.quiet < 2 {
+                    println!("Restored the partition table of {:?}.", args.device);
+                }
+                result
+            },
+            (Ok(()), Err(e)) => Err(e),
+            (Err(result), Err(e)) => {
+                eprintln!("ERROR: {}", e);
+                Err(result)
+            },
+        },
+        _ => result,
+    };
+
+    if args.latency && args.quiet < 2 {
+        latency.print();
+    }
+    if args.quiet < 2 {
+        zones.print();
+        print_bad_partitions(partitions.as_ref(), &bad_regions);
+        print_error_classes(&bad_regions);
+    }
+
+    let kernel_messages = kmsg.map(|k| k.finish()).unwrap_or_default();
+    if !kernel_messages.is_empty() && args.quiet < 2 {
+        print_kernel_messages(&args.device, &kernel_messages);
     }
 
-    // Run verify-mode, if requested.
-    if args.verify && result.is_ok() {
-        let (mut disktest, file) = new_disktest(&args, false, &abort)?;
-        result = match disktest.verify(file, args.seek, args.max_bytes) {
-            Ok(_) => Ok(()),
-            Err(e) => Err(e),
+    let throttling = ctx.thermal.analyze();
+    let smart_after = if smart_before.is_some() { read_smart(args) } else { None };
+    let smart_delta = match (&smart_before, &smart_after) {
+        (Some(before), Some(after)) => after.delta(before),
+        _ => vec![],
+    };
+    let mut stats = RunStats::new(identity.as_ref(), total_written, total_verified, begin.elapsed(),
+                                  ctx.thermal.busy_time(), ctx.thermal.rate_range(), &bad_regions);
+    stats.rounds = completed;
+    if args.quiet < 2 {
+        let title = if is_aborted(&result) {
+            "Partial summary".to_string()
+        } else if args.loop_mode {
+            format!("Total summary of {} complete cycles", completed)
+        } else if args.rounds != 1 {
+            format!("Total summary of {} rounds", round)
+        } else {
+            "Summary".to_string()
         };
+        print!("{}", stats.format(&title, &args.device, &result));
     }
 
+    if let Some(mut report) = report {
+        report.kernel_messages = kernel_messages;
+        report.throttling = throttling.clone();
+        report.smart = smart_delta.clone();
+        report.statistics = Some(stats.clone());
+        report.finish(&result);
+        match reports {
+            Some(reports) => reports.push(report),
+            None => {
+                if let Some(path) = &args.report {
+                    report.write(Path::new(path))?;
+                    if args.quiet < 2 {
+                        println!("Wrote the report to {:?}.", path);
+                    }
+                }
+                if let Some(path) = &args.report_junit {
+                    write_junit(Path::new(path), slice::from_ref(&report))?;
+                    if args.quiet < 2 {
+                        println!("Wrote the JUnit report to {:?}.", path);
+                    }
+                }
+            },
+        }
+    }
+
+    if let Some(path) = &args.bad_blocks {
+        let count = write_bad_blocks_list(Path::new(path), &bad_regions, args.list_block_size)?;
+        if args.quiet < 2 {
+            println!("Wrote {} bad blocks of {} bytes to {:?}.",
+                     count, args.list_block_size, path);
+        }
+    }
+
+    let aborted = is_aborted(&result);
+    if let Some(saver) = saver {
+        let success = result.is_ok();
+        saver.finish(success)?;
+        if !success && !aborted && args.quiet < 2 {
+            println!("The run can be resumed with the same --resume state file.");
+        }
+    }
+    if let (true, Some((phase, position))) = (aborted, reached) {
+        let cmdline: Vec<String> = args_os().map(|a| a.to_string_lossy().to_string()).collect();
+        let mut last_args = args.clone();
+        if args.write {
+            last_args.seed_round = round;
+        }
+        // On stderr, so that it does not mix with the JSON of --output summary-json.
+        eprintln!("\n{}", format_interruption(&last_args, &cmdline, phase, position));
+    }
+
+    if !throttling.is_empty() && args.quiet < 2 {
+        print_throttling(&throttling, smart_before.as_ref(), smart_after.as_ref());
+    }
+    if let (Some(before), Some(after)) = (&smart_before, &smart_after) {
+        if args.quiet < 2 {
+            println!("S.M.A.R.T. attributes of {:?}:\n{}", args.device, after.format_delta(before));
+        }
+        if after.increased(before) {
+            eprintln!("WARNING: S.M.A.R.T. error counters increased during the run.");
+        }
+    }
     if !args.user_seed && args.quiet < 2 {
         print_generated_seed(&args.seed, false);
     }
@@ -107,4 +1012,886 @@ fn main() -> ah::Result<()> {
     result
 }
 
+/// Test all devices of the --devices-from list one after another.
+/// All devices are tested, even if the test of a device fails.
+fn run_device_list(args:    &Args,
+                   abort:   &Arc<AtomicBool>,
+                   ctx:     &RunContext) -> ah::Result<()> {
+    let path = args.devices_from.as_ref().unwrap();
+    let entries = read_device_list(Path::new(path))?;
+
+    // Check the options of all devices before testing the first one.
+    // A seed given on the command line is used for all devices.
+    let cmdline: Vec<OsString> = args_os().collect();
+    let seed = args.user_seed.then_some(args.seed.as_slice());
+    let mut devices = Vec::with_capacity(entries.len());
+    for entry in &entries {
+        let mut dev_args = match parse_device_args(device_args(&cmdline, entry), seed) {
+            Ok(x) => x,
+            Err(e) => return Err(ah::format_err!("Line {} of the device list {:?}: {}",
+                                                 entry.line, path, e)),
+        };
+        dev_args.device = preferred_device_path(&dev_args.device);
+        if let Err(e) = resolve_percent(&mut dev_args, device_size) {
+            return Err(ah::format_err!("Line {} of the device list {:?}: {}", entry.line, path, e));
+        }
+        devices.push(dev_args);
+    }
+    for dev_args in &devices {
+        confirm_destruction(dev_args)?;
+    }
+
+    let begin = Instant::now();
+    let mut reports = vec![];
+    let mut results = vec![];
+    for (i, dev_args) in devices.iter().enumerate() {
+        if args.quiet < 2 {
+            println!("\n=== Device {} of {}: {:?} ===", i + 1, devices.len(), dev_args.device);
+        }
+        ctx.thermal.clear();
+        let count = reports.len();
+        let result = run_test(dev_args, abort, ctx, Some(&mut reports));
+        if wants_report(dev_args) && reports.len() == count {
+            // The test failed before it started.
+            let mut report = Report::new(dev_args);
+            report.finish(&result);
+            reports.push(report);
+        }
+        let stop = abort.load(Ordering::Relaxed) ||
+                   ctx.check_time_limit().is_err();
+        results.push(result);
+        if stop {
+            break;
+        }
+    }
+
+    if args.quiet < 2 {
+        println!("\nDevice list summary:");
+        for (i, dev_args) in devices.iter().enumerate() {
+            let status = match results.get(i) {
+                Some(Ok(())) => "Ok".to_string(),
+                Some(Err(e)) => format!("FAILED: {}", e),
+                None => "Not tested".to_string(),
+            };
+            println!("    {}: {}", dev_args.device, status);
+        }
+    }
+    let result = results.into_iter().find(|r| r.is_err()).unwrap_or(Ok(()));
+
+    if let Some(path) = &args.report_junit {
+        write_junit(Path::new(path), &reports)?;
+        if args.quiet < 2 {
+            println!("Wrote the JUnit report to {:?}.", path);
+        }
+    }
+    if wants_report(args) {
+        let report = DeviceListReport::new(reports, begin.elapsed(), &result);
+        if let Some(path) = &args.report {
+            report.write(Path::new(path))?;
+            if args.quiet < 2 {
+                println!("Wrote the report to {:?}.", path);
+            }
+        }
+        if args.output == OutputFormat::SummaryJson {
+            println!("{}", report.to_json()?);
+        }
+    }
+    result
+}
+
+/// Run the test and print its report as the only output to stdout.
+/// See --output summary-json.
+fn run_summary_json(args:   &Args,
+                    abort:  &Arc<AtomicBool>,
+                    ctx:    &RunContext) -> ah::Result<()> {
+    let mut reports = vec![];
+    let result = run_test(args, abort, ctx, Some(&mut reports));
+    let report = match reports.pop() {
+        Some(report) => report,
+        None => {
+            // The test failed before it started.
+            let mut report = Report::new(args);
+            report.finish(&result);
+            report
+        },
+    };
+    if let Some(path) = &args.report {
+        report.write(Path::new(path))?;
+    }
+    if let Some(path) = &args.report_junit {
+        write_junit(Path::new(path), slice::from_ref(&report))?;
+    }
+    println!("{}", report.to_json()?);
+    result
+}
+
+/// Overwrite the device with all passes of the wipe scheme.
+fn run_wipe(args:       &Args,
+            abort:      &Arc<AtomicBool>,
+            ctx:        &RunContext) -> ah::Result<()> {
+    let count = args.wipe_passes.len();
+    let mut last_pass = None;
+    for (i, pass) in args.wipe_passes.iter().enumerate() {
+        let mut pass_args = args.clone();
+        match pass {
+            WipePass::Random => {
+                // Only the last pass can be verified with the --seed.
+                if i + 1 < count {
+                    pass_args.seed = gen_seed_string(gen_seed_len(args.kdf)).into_bytes();
+                }
+            },
+            WipePass::Pattern(pattern) => {
+                pass_args.algorithm = DtStreamType::PATTERN;
+                pass_args.pattern = Some(pattern.clone());
+            },
+        }
+        if count > 1 && args.quiet < 2 {
+            println!("Pass {} of {}: {}", i + 1, count, pass);
+        }
+        let (mut disktest, file) = new_disktest(&pass_args, true, abort, ctx)?;
+        let bytes = disktest.write(file, args.seek, args.max_bytes)?;
+        ctx.check_time_limit()?;
+        last_pass = Some((pass_args, bytes));
+    }
+
+    if let (true, Some((pass_args, bytes))) = (args.verify, last_pass) {
+        if args.quiet < 2 {
+            println!("Verifying the last pass ...");
+        }
+        let (mut disktest, file) = new_disktest(&pass_args, false, abort, ctx)?;
+        disktest.verify(file, args.seek, bytes)?;
+    }
+
+    if args.quiet < 1 {
+        println!("Wipe done.");
+    }
+    Ok(())
+}
+
+/// Duration of each random access phase of the benchmark.
+const RANDOM_BENCH_TIME: Duration = Duration::from_secs(10);
+
+/// Measure the sequential and random write and read speed of the device.
+fn run_bench(args:      &Args,
+             abort:     &Arc<AtomicBool>,
+             ctx:       &RunContext) -> ah::Result<()> {
+    fn rate(count: u64, begin: Instant) -> String {
+        let secs = begin.elapsed().as_secs_f64();
+        let rate = if secs > 0.0 { (count as f64 / secs) as u64 } else { 0 };
+        format!("{}/s", prettybytes(rate, true, false))
+    }
+    fn random(r: &DtRandomIo) -> String {
+        format!("{:.0} IOPS ({}/s)", r.iops(), prettybytes(r.rate(), true, false))
+    }
+
+    let (mut disktest, file) = new_disktest(args, true, abort, ctx)?;
+    let begin = Instant::now();
+    let bytes_written = disktest.write(file, args.seek, args.max_bytes)?;
+    let write_rate = rate(bytes_written, begin);
+
+    let (mut disktest, file) = new_disktest(args, false, abort, ctx)?;
+    let begin = Instant::now();
+    let bytes_read = disktest.verify(file, args.seek, bytes_written)?;
+    let read_rate = rate(bytes_read, begin);
+
+    if args.quiet < 1 {
+        println!("Random 4K reads for {} seconds ...", RANDOM_BENCH_TIME.as_secs());
+    }
+    let (mut disktest, file) = new_disktest(args, false, abort, ctx)?;
+    let random_read = disktest.random_io(file, args.seek, bytes_written,
+                                         false, RANDOM_BENCH_TIME)?;
+
+    if args.quiet < 1 {
+        println!("Random 4K writes for {} seconds ...", RANDOM_BENCH_TIME.as_secs());
+    }
+    let (mut disktest, file) = new_disktest(args, true, abort, ctx)?;
+    let random_write = disktest.random_io(file, args.seek, bytes_written,
+                                          true, RANDOM_BENCH_TIME)?;
+
+    println!("\nBenchmark of {:?} ({}):\n    \
+             Sequential write: {}\n    \
+             Sequential read:  {}\n    \
+             Random 4K read:   {}\n    \
+             Random 4K write:  {}",
+             args.device,
+             prettybytes(bytes_written, true, true),
+             write_rate,
+             read_rate,
+             random(&random_read),
+             random(&random_write));
+    Ok(())
+}
+
+/// Read the whole device without modifying it.
+fn run_scan(args:       &Args,
+            abort:      &Arc<AtomicBool>,
+            ctx:        &RunContext) -> ah::Result<()> {
+    let (mut disktest, file) = new_disktest(args, false, abort, ctx)?;
+    let begin = Instant::now();
+    let result = disktest.scan(file, args.seek, args.max_bytes);
+    let secs = begin.elapsed().as_secs_f64();
+
+    if args.quiet < 2 {
+        let errors = disktest.get_errors();
+        // The number of read bytes is not known, if the scan failed.
+        let (read, rate) = match &result {
+            Ok(count) => {
+                let rate = if secs > 0.0 { (*count as f64 / secs) as u64 } else { 0 };
+                (prettybytes(*count, true, true),
+                 format!("{}/s", prettybytes(rate, true, false)))
+            },
+            Err(_) => ("-".to_string(), "-".to_string()),
+        };
+        println!("\nScan of {:?}:\n    \
+                 Read:       {}\n    \
+                 Unreadable: {} regions, {}\n    \
+                 Read rate:  {}",
+                 args.device,
+                 read,
+                 errors.len(),
+                 prettybytes(errors.iter().map(|e| e.length).sum(), true, true),
+                 rate);
+        print_bad_partitions(read_partitions(&args.device).as_ref(), errors);
+    }
+    result.map(|_| ())
+}
+
+/// Compare the device to the other device.
+/// With --expect-image the whole compared part of the image must be on the device.
+fn run_compare(args:    &Args,
+               abort:   &Arc<AtomicBool>,
+               ctx:     &RunContext) -> ah::Result<()> {
+    let (mut disktest, file) = new_disktest(args, false, abort, ctx)?;
+    let other = DisktestFile::open(&args.other, true, false, args.direct, false, args.quiet)?;
+    if let (Some(size), Some(other_size)) = (file.get_disk_size(), other.get_disk_size()) {
+        if size != other_size && !args.expect_image && args.quiet < 2 {
+            println!("The sizes differ: {:?} has {}, {:?} has {}. \
+                     Only the common part is compared.",
+                     args.device, prettybytes(size, true, true),
+                     args.other, prettybytes(other_size, true, true));
+        }
+    }
+    let image_size = match other.get_disk_size() {
+        Some(size) => size,
+        None => std::fs::metadata(&args.other)?.len(),
+    };
+    let begin = Instant::now();
+    let result = disktest.compare(file, other, args.seek, args.other_seek, args.max_bytes);
+    let secs = begin.elapsed().as_secs_f64();
+
+    if args.quiet < 2 {
+        let errors = disktest.get_errors();
+        let differing = |kind| {
+            let errors: Vec<_> = errors.iter().filter(|e| e.kind == kind).collect();
+            format!("{} regions, {}", errors.len(),
+                    prettybytes(errors.iter().map(|e| e.length).sum(), true, true))
+        };
+        // The number of compared bytes is not known, if the comparison failed.
+        let (compared, rate) = match &result {
+            Ok(count) => {
+                let rate = if secs > 0.0 { (*count as f64 / secs) as u64 } else { 0 };
+                (prettybytes(*count, true, true),
+                 format!("{}/s", prettybytes(rate, true, false)))
+            },
+            Err(_) => ("-".to_string(), "-".to_string()),
+        };
+        println!("\nComparison of {:?} and {:?}:\n    \
+                 Compared:   {}\n    \
+                 Different:  {}\n    \
+                 Unreadable: {}\n    \
+                 Read rate:  {}",
+                 args.device,
+                 args.other,
+                 compared,
+                 differing(DtErrorKind::Mismatch),
+                 differing(DtErrorKind::Read),
+                 rate);
+    }
+    let expected = image_size.saturating_sub(args.other_seek).min(args.max_bytes);
+    match result {
+        Ok(count) if args.expect_image && count < expected => {
+            Err(ah::format_err!("The device is too short. Only {} of the {} of the image \
+                                have been compared.",
+                                prettybytes(count, true, true),
+                                prettybytes(expected, true, true)))
+        },
+        result => result.map(|_| ()),
+    }
+}
+
+/// Test the device segment by segment and restore the original data.
+fn run_nondestructive(args:     &Args,
+                      abort:    &Arc<AtomicBool>,
+                      ctx:      &RunContext) -> ah::Result<()> {
+    let partitions = if args.quiet < 2 { read_partitions(&args.device) } else { None };
+    let (mut disktest, file) = new_disktest_rw(args, true, true, abort, ctx, None)?;
+    let result = disktest.nondestructive(file, args.seek, args.max_bytes);
+    print_bad_partitions(partitions.as_ref(), disktest.get_errors());
+    result?;
+    if args.quiet < 1 {
+        println!("The original data has been restored.");
+    }
+    Ok(())
+}
+
+/// Write, read back and compare the device segment by segment.
+/// The data is read back with direct I/O, so that it is read from the device.
+fn run_surface(args:    &Args,
+               abort:   &Arc<AtomicBool>,
+               ctx:     &RunContext) -> ah::Result<()> {
+    let mut args = args.clone();
+    args.direct = true;
+    let (mut disktest, file) = new_disktest_rw(&args, true, true, abort, ctx, None)?;
+    disktest.surface(file, args.seek, args.max_bytes)?;
+    Ok(())
+}
+
+/// Check the real usable capacity of the device.
+fn run_capacity_check(args:     &Args,
+                      abort:    &Arc<AtomicBool>,
+                      ctx:      &RunContext) -> ah::Result<()> {
+    let (mut disktest, file) = new_disktest_rw(args, true, true, abort, ctx, None)?;
+    let cap = disktest.capacity_check(file, args.seek, args.max_bytes)?;
+
+    if args.quiet < 2 {
+        println!("\nCapacity check of {:?}:\n    \
+                 Claimed capacity: {}\n    \
+                 Usable capacity:  {}\n    \
+                 Markers:          {} written, {} bad, every {}",
+                 args.device,
+                 prettybytes(cap.claimed, true, true),
+                 prettybytes(cap.usable, true, true),
+                 cap.markers,
+                 cap.bad,
+                 prettybytes(cap.resolution, true, true));
+    }
+    if cap.is_fake() {
+        return Err(DtError::FakeCapacity {
+            claimed:    cap.claimed,
+            usable:     cap.usable,
+        }.into());
+    }
+    if args.quiet < 1 {
+        println!("The device has the claimed capacity.");
+    }
+    Ok(())
+}
+
+/// Discard the device and check that it reads back zeros (RZAT)
+/// and that the data read after the discard is deterministic (DRAT).
+fn run_trim_check(args:     &Args,
+                  abort:    &Arc<AtomicBool>,
+                  ctx:      &RunContext) -> ah::Result<()> {
+    let mut args = args.clone();
+    args.algorithm = DtStreamType::PATTERN;
+    args.pattern = Some(vec![0]);
+    let args = &args;
+    device_use(&args.device).check(&args.device, args.force, args.allow_system_disk)?;
+
+    let mut file = match OpenOptions::new().read(true).write(true).open(&args.device) {
+        Ok(f) => f,
+        Err(e) => return Err(ah::format_err!("Failed to open file {:?}: {}", args.device, e)),
+    };
+    let size = match disk_size(&file)? {
+        Some(size) => size,
+        None => file.seek(SeekFrom::End(0))?,
+    };
+    // The verification starts at a chunk boundary.
+    // Only whole chunks within the range are discarded.
+    let chunk_size = args.chunk_size.unwrap_or_else(|| args.algorithm.default_chunk_size()) as u64;
+    let (seek, len) = discard_range(args.seek, args.max_bytes, size, chunk_size);
+    if len == 0 {
+        return Err(ah::format_err!("There is nothing to discard at position {} of {:?}.",
+                                   seek, args.device));
+    }
+
+    if args.quiet < 2 {
+        println!("Discarding {} of {:?}, starting at position {} bytes...",
+                 prettybytes(len, true, true), args.device, seek);
+    }
+    discard(&file, seek, len)?;
+    file.sync_all()?;
+    let deterministic = read_is_deterministic(&mut file, seek, len)?;
+    drop(file);
+
+    let (mut disktest, file) = new_disktest_rw(args, true, false, abort, ctx, None)?;
+    let result = disktest.verify(file, seek, len).map(|_| ());
+    let zeros = match &result {
+        Ok(()) => Some(true),
+        Err(e) if e.downcast_ref::<DtError>().is_some_and(|e| e.is_corruption()) => Some(false),
+        Err(_) => None,
+    };
+    if args.quiet < 2 {
+        println!("\nTRIM check of {:?}:\n{}",
+                 args.device, format_trim_result(deterministic, zeros));
+    }
+    result
+}
+
+/// Erase the drive with the ATA security erase and verify that it reads back zeros.
+/// The report contains the result of the erase and of the verification.
+fn run_secure_erase(args:   &Args,
+                    abort:  &Arc<AtomicBool>,
+                    ctx:    &RunContext) -> ah::Result<()> {
+    device_use(&args.device).check(&args.device, args.force, args.allow_system_disk)?;
+    let security = AtaSecurity::read(&args.device)?;
+    security.check(args.enhanced_erase)?;
+
+    let (method, estimate) = if args.enhanced_erase {
+        ("ENHANCED SECURITY ERASE UNIT", &security.enhanced_time)
+    } else {
+        ("SECURITY ERASE UNIT", &security.erase_time)
+    };
+    if args.quiet < 2 {
+        println!("Erasing {:?} with ATA {} (estimated duration: {}). \
+                  Do not interrupt the erase or power off the drive.",
+                 args.device, method, estimate.as_deref().unwrap_or("unknown"));
+    }
+    let begin = Instant::now();
+    let erase_result = security_erase(&args.device, args.enhanced_erase);
+    let erase = ReportErase {
+        method:     method.to_string(),
+        duration:   begin.elapsed().as_secs_f64(),
+        success:    erase_result.is_ok(),
+        message:    erase_result.as_ref().err().map(|e| e.to_string()),
+    };
+    verify_erase(args, erase, erase_result, true, abort, ctx)
+}
+
+/// Verify the drive after a successful erase and write the report.
+/// If zeros is true, every byte of the drive must read back as zero.
+fn verify_erase(args:           &Args,
+                erase:          ReportErase,
+                erase_result:   ah::Result<()>,
+                zeros:          bool,
+                abort:          &Arc<AtomicBool>,
+                ctx:            &RunContext) -> ah::Result<()> {
+    let mut verify_args = args.clone();
+    verify_args.command = Command::Test;
+    verify_args.write = false;
+    verify_args.verify = true;
+    verify_args.algorithm = DtStreamType::PATTERN;
+    verify_args.pattern = Some(vec![0x00]);
+    verify_args.user_seed = true;
+    verify_args.header = false;
+    verify_args.keep_going = true;
+    let mut reports = vec![];
+    let result = erase_result.and_then(|_| {
+        if args.quiet < 2 {
+            println!("Erase done after {:.0} seconds.", erase.duration);
+        }
+        if !zeros {
+            return Ok(());
+        }
+        if args.quiet < 2 {
+            println!("Verifying that the drive reads zeros ...");
+        }
+        run_test(&verify_args, abort, ctx, Some(&mut reports))
+    });
+
+    if let Some(path) = &args.report {
+        let mut report = match reports.pop() {
+            Some(report) => report,
+            None => {
+                let mut report = Report::new(&verify_args);
+                report.finish(&result);
+                report
+            },
+        };
+        report.erase = Some(erase);
+        report.write(Path::new(path))?;
+        if args.quiet < 2 {
+            println!("Wrote the report to {:?}.", path);
+        }
+    }
+    result
+}
+
+/// Erase the drive with NVMe Format NVM or Sanitize and verify the result.
+/// A cryptographic erase is verified by comparing samples of the data before and after.
+fn run_nvme_erase(args:     &Args,
+                  abort:    &Arc<AtomicBool>,
+                  ctx:      &RunContext) -> ah::Result<()> {
+    device_use(&args.device).check(&args.device, args.force, args.allow_system_disk)?;
+    let erase = args.nvme_erase;
+    let samples = if erase.is_crypto() {
+        Some(read_samples(&args.device)?)
+    } else {
+        None
+    };
+
+    if args.quiet < 2 {
+        println!("Erasing {:?} with NVMe {}. \
+                  Do not interrupt the erase or power off the drive.",
+                 args.device, erase.command_name());
+    }
+    let begin = Instant::now();
+    let quiet = args.quiet;
+    let mut progress = |done: f64| {
+        if quiet < 1 {
+            println!("Sanitize {:.1}% done ...", done * 100.0);
+        }
+    };
+    let erase_result = nvme_erase(&args.device, erase, abort, &mut progress)
+        .and_then(|_| match &samples {
+            Some(before) => check_crypto_erased(before, &read_samples(&args.device)?),
+            None => Ok(()),
+        });
+    let report_erase = ReportErase {
+        method:     erase.command_name().to_string(),
+        duration:   begin.elapsed().as_secs_f64(),
+        success:    erase_result.is_ok(),
+        message:    erase_result.as_ref().err().map(|e| e.to_string()),
+    };
+    verify_erase(args, report_erase, erase_result, !erase.is_crypto(), abort, ctx)
+}
+
+/// Write the test files until the file system is full.
+/// The paths and the number of written bytes are appended to files.
+fn fill_write(args:     &Args,
+              dir:      &Path,
+              files:    &mut Vec<(PathBuf, u64)>,
+              abort:    &Arc<AtomicBool>,
+              ctx:      &RunContext) -> ah::Result<()> {
+    let mut remaining = args.max_bytes;
+    let mut index = 0;
+    while remaining > 0 {
+        index += 1;
+        let path = fill_file_path(dir, index);
+        if path.exists() {
+            return Err(ah::format_err!("The file {:?} already exists. \
+                                       Please remove it before running fill.", path));
+        }
+        let size = fill_file_size(remaining, free_space(dir)?);
+
+        let mut file_args = args.clone();
+        file_args.device = path.to_string_lossy().to_string();
+        file_args.seed = fill_file_seed(&args.seed, index);
+        files.push((path, 0));
+        let (mut disktest, file) = new_disktest(&file_args, true, abort, ctx)?;
+        let written = disktest.write(file, 0, size)?;
+        if let Some(last) = files.last_mut() {
+            last.1 = written;
+        }
+        if remaining != Disktest::UNLIMITED {
+            remaining -= written;
+        }
+        ctx.check_time_limit()?;
+        if size == Disktest::UNLIMITED || written < size {
+            break; // The file system is full.
+        }
+    }
+    Ok(())
+}
+
+/// Verify all test files.
+/// All files are verified, even if one of them is corrupt.
+/// Returns the number of verified bytes, the number of corrupt files and the first error.
+fn fill_verify(args:        &Args,
+               files:       &[(PathBuf, u64)],
+               abort:       &Arc<AtomicBool>,
+               ctx:         &RunContext) -> (u64, usize, ah::Result<()>) {
+    let mut verified = 0;
+    let mut corrupt = 0;
+    let mut result = Ok(());
+    for (index, (path, written)) in files.iter().enumerate() {
+        let mut file_args = args.clone();
+        file_args.device = path.to_string_lossy().to_string();
+        file_args.seed = fill_file_seed(&args.seed, index as u64 + 1);
+        let file_result = new_disktest(&file_args, false, abort, ctx)
+            .and_then(|(mut disktest, file)| disktest.verify(file, 0, *written));
+        match file_result {
+            Ok(count) => verified += count,
+            Err(e) => {
+                let aborted = matches!(e.downcast_ref::<DtError>(), Some(DtError::Aborted));
+                corrupt += 1;
+                if result.is_ok() {
+                    result = Err(e);
+                }
+                if aborted {
+                    break;
+                }
+            },
+        }
+    }
+    (verified, corrupt, result)
+}
+
+/// Fill the free space of a file system with test files, verify and delete them.
+fn run_fill(args:       &Args,
+            abort:      &Arc<AtomicBool>,
+            ctx:        &RunContext) -> ah::Result<()> {
+    let dir = Path::new(&args.device);
+    if !dir.is_dir() {
+        return Err(ah::format_err!("{:?} is not a directory.", args.device));
+    }
+
+    let mut files = vec![];
+    let write_result = fill_write(args, dir, &mut files, abort, ctx);
+    let (verified, corrupt, result) = match write_result {
+        Ok(()) => fill_verify(args, &files, abort, ctx),
+        Err(e) => (0, 0, Err(e)),
+    };
+
+    // Always delete the test files.
+    for (path, _) in &files {
+        if let Err(e) = std::fs::remove_file(path) {
+            eprintln!("WARNING: Failed to remove the test file {:?}: {}", path, e);
+        }
+    }
+
+    if args.quiet < 2 {
+        println!("\nFill of {:?}:\n    \
+                 Files:    {} ({} corrupt)\n    \
+                 Written:  {}\n    \
+                 Verified: {}",
+                 args.device,
+                 files.len(),
+                 corrupt,
+                 prettybytes(files.iter().map(|f| f.1).sum(), true, true),
+                 prettybytes(verified, true, true));
+    }
+    if result.is_ok() && args.quiet < 1 {
+        println!("Success! The test files have been deleted.");
+    }
+    result
+}
+
+/// Print information about the device.
+fn run_info(args: &Args) -> ah::Result<()> {
+    fn show(value: &Option<String>) -> &str {
+        value.as_deref().unwrap_or("unknown")
+    }
+
+    let mut file = match File::open(&args.device) {
+        Ok(f) => f,
+        Err(e) => return Err(ah::format_err!("Failed to open file {:?}: {}", args.device, e)),
+    };
+    let meta = file.metadata()?;
+    let kind = if meta.is_file() {
+        "Regular file"
+    } else if meta.is_dir() {
+        "Directory"
+    } else {
+        "Device"
+    };
+    println!("Device:   {:?}\nType:     {}", args.device, kind);
+    if meta.is_dir() {
+        return Ok(());
+    }
+    let identity = match DeviceIdentity::read(&args.device) {
+        Some(identity) => identity,
+        None => {
+            println!("Size:     {}", prettybytes(file.seek(SeekFrom::End(0))?, true, true));
+            return Ok(());
+        },
+    };
+    println!("Model:    {}", show(&identity.model));
+    println!("Serial:   {}", show(&identity.serial));
+    println!("Firmware: {}", show(&identity.firmware));
+    println!("Bus:      {}", show(&identity.bus));
+    println!("Medium:   {}", match identity.rotational {
+        Some(true) => "Rotating disk (HDD)",
+        Some(false) => "Solid state (SSD/flash)",
+        None => "unknown",
+    });
+    let size = match identity.size {
+        Some(size) => size,
+        None => file.seek(SeekFrom::End(0))?,
+    };
+    println!("Size:     {}", prettybytes(size, true, true));
+    let sector = |size: Option<u32>| size.map_or("unknown".to_string(), |s| format!("{} bytes", s));
+    println!("Sector:   {} logical, {} physical",
+             sector(identity.logical_sector_size), sector(identity.physical_sector_size));
+    match read_health(&args.device) {
+        Ok(true) => println!("S.M.A.R.T. health: PASSED"),
+        Ok(false) => println!("S.M.A.R.T. health: FAILED"),
+        Err(e) => println!("S.M.A.R.T. health: unavailable ({})", e),
+    }
+    Ok(())
+}
+
+/// Run the agent. The controllers may only override the safety checks,
+/// that the agent has been started to override.
+fn run_serve(args:  &Args,
+             abort: &Arc<AtomicBool>) -> ah::Result<()> {
+    let overrides: Vec<&str> = [("--force", args.force),
+                                ("--allow-system-disk", args.allow_system_disk),
+                                ("--yes", args.yes)]
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| *name)
+        .collect();
+    serve(&args.agent, &args.token, &overrides, abort, args.quiet)
+}
+
+/// Start the run on the remote agent and write its report.
+fn run_on_agent(args:   &Args,
+                abort:  &Arc<AtomicBool>,
+                ctx:    &RunContext) -> ah::Result<()> {
+    let (status, report) = run_remote(&args.agent, &args.token, &args.remote_args,
+                                      ctx.json.as_ref(), abort)?;
+    if let (Some(path), Some(report)) = (&args.report, report) {
+        let mut json = serde_json::to_string_pretty(&report)?;
+        json.push('\n');
+        if let Err(e) = std::fs::write(path, json) {
+            return Err(ah::format_err!("Failed to write report file {:?}: {}", path, e));
+        }
+        if args.quiet < 2 {
+            println!("Wrote the report to {:?}.", path);
+        }
+    }
+    if status != EXIT_SUCCESS {
+        return Err(RemoteFailure {
+            agent:          args.agent.clone(),
+            exit_status:    status,
+        }.into());
+    }
+    Ok(())
+}
+
+/// Exit status: Success.
+const EXIT_SUCCESS: i32     = 0;
+/// Exit status: General failure.
+const EXIT_FAILURE: i32     = 1;
+/// Exit status: Data corruption has been detected.
+const EXIT_CORRUPTION: i32  = 2;
+/// Exit status: An I/O error occurred.
+const EXIT_IO_ERROR: i32    = 3;
+/// Exit status: The run has been stopped at the time limit.
+const EXIT_INCOMPLETE: i32  = 4;
+
+/// Get the process exit status for the result of a run.
+pub fn exit_status(result: &ah::Result<()>) -> i32 {
+    match result {
+        Ok(()) => EXIT_SUCCESS,
+        Err(e) if e.is::<RemoteFailure>() => e.downcast_ref::<RemoteFailure>().unwrap().exit_status,
+        Err(e) => match e.downcast_ref::<DtError>() {
+            Some(e) if e.is_corruption() => EXIT_CORRUPTION,
+            Some(DtError::Io { .. }) => EXIT_IO_ERROR,
+            Some(DtError::TimeLimit) => EXIT_INCOMPLETE,
+            _ => EXIT_FAILURE,
+        },
+    }
+}
+
+/// Run the command and the hook command for its result.
+fn run() -> ah::Result<()> {
+    let mut args = parse_args(args_os())?;
+    if args.flat_cmdline {
+        eprintln!("WARNING: Running disktest without a subcommand is deprecated. \
+                   Please use 'disktest write' or 'disktest verify' instead.");
+    }
+    let selected = match (&args.serial, &args.by_id) {
+        (Some(serial), _) => Some((device_by_serial(serial)?, format!("serial number {:?}", serial))),
+        (None, Some(id)) => Some((device_by_id(id)?, format!("id {:?}", id))),
+        (None, None) => None,
+    };
+    if let Some((device, what)) = selected {
+        if args.quiet < 2 {
+            println!("Using {:?} with the {}.", device, what);
+        }
+        args.device = device;
+    }
+    let device = preferred_device_path(&args.device);
+    if device != args.device {
+        if args.quiet < 2 {
+            println!("Using the raw disk {:?} instead of {:?}.", device, args.device);
+        }
+        args.device = device;
+    }
+    resolve_percent(&mut args, device_size)?;
+    let log = match &args.log {
+        Some(path) => Some(LogFile::open(Path::new(path))?),
+        None => None,
+    };
+    if let Some(log) = &log {
+        let cmdline: Vec<String> = args_os().map(|a| a.to_string_lossy().to_string()).collect();
+        log.log(&format!("disktest {} started: {}", env!("CARGO_PKG_VERSION"), cmdline.join(" ")));
+    }
+
+    let begin = Instant::now();
+    let result = run_command(&args, log.clone());
+    if let Some(log) = &log {
+        match &result {
+            Ok(()) => log.log("Finished successfully."),
+            Err(e) => log.log(&format!("Finished with error: {}", e)),
+        }
+    }
+    if let Err(e) = run_hook(&args, &result, begin.elapsed()) {
+        eprintln!("WARNING: {}", e);
+    }
+    if let Err(e) = notify(&args, &result, begin.elapsed()) {
+        eprintln!("WARNING: {}", e);
+    }
+    result
+}
+
+/// Run the command.
+/// If log is given, then all progress and error messages are logged to it.
+fn run_command(args: &Args, log: Option<LogFile>) -> ah::Result<()> {
+    set_priority(args.nice, args.io_priority)?;
+    let abort = install_abort_handlers()?;
+    let mut ctx = RunContext {
+        json: match &args.progress_json {
+            Some(target) => Some(open_json_sink(target)?),
+            None => None,
+        },
+        metrics: match &args.metrics_listen {
+            Some(addr) => Some(Metrics::start(addr)?),
+            None => None,
+        },
+        log,
+        throughput: match &args.throughput_log {
+            Some(path) => Some(ThroughputLog::create(Path::new(path))?),
+            None => None,
+        },
+        time_limit: args.max_time.map(TimeLimit::start),
+        thermal: ThermalWatch::default(),
+        status: StatusRequest::install()?,
+        pause: install_pause_handlers()?,
+        tui: None,
+    };
+    confirm_destruction(args)?;
+    if args.tui {
+        ctx.tui = Some(Tui::start(&args.device, Arc::clone(&ctx.pause), Arc::clone(&abort))?);
+    }
+    let ctx = &ctx;
+
+    match args.command {
+        Command::Test if args.expect_image => {
+            run_compare(args, &abort, ctx).and_then(|_| ctx.check_time_limit())
+        },
+        Command::Test if args.devices_from.is_some() => run_device_list(args, &abort, ctx),
+        Command::Test if args.output == OutputFormat::SummaryJson => run_summary_json(args, &abort, ctx),
+        Command::Test => run_test(args, &abort, ctx, None),
+        Command::Wipe => run_wipe(args, &abort, ctx),
+        Command::Bench => run_bench(args, &abort, ctx).and_then(|_| ctx.check_time_limit()),
+        Command::Scan => run_scan(args, &abort, ctx).and_then(|_| ctx.check_time_limit()),
+        Command::Compare => run_compare(args, &abort, ctx).and_then(|_| ctx.check_time_limit()),
+        Command::Nondestructive => {
+            run_nondestructive(args, &abort, ctx).and_then(|_| ctx.check_time_limit())
+        },
+        Command::Surface => run_surface(args, &abort, ctx).and_then(|_| ctx.check_time_limit()),
+        // The capacity check always runs to completion.
+        Command::CapacityCheck => run_capacity_check(args, &abort, ctx),
+        Command::TrimCheck => run_trim_check(args, &abort, ctx).and_then(|_| ctx.check_time_limit()),
+        Command::SecureErase => run_secure_erase(args, &abort, ctx),
+        Command::NvmeErase => run_nvme_erase(args, &abort, ctx),
+        Command::Fill => run_fill(args, &abort, ctx),
+        Command::Info => run_info(args),
+        Command::Serve => run_serve(args, &abort),
+        Command::Remote => run_on_agent(args, &abort, ctx),
+    }
+}
+
+/// Main program entry point.
+fn main() {
+    let result = run();
+    if let Err(e) = &result {
+        eprintln!("Error: {:?}", e);
+    }
+    std::process::exit(exit_status(&result));
+}
+
 // vim: ts=4 sw=4 expandtab
@@ -0,0 +1,231 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+use crate::args::{Args, parse_pattern};
+use crate::seed::round_seed;
+use disktest_core::{DtKdf, DtStreamType, seed_fingerprint};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// Parameters of a write run, saved to a sidecar file.
+/// A later verify run reads them back, so that it uses consistent settings.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RunMeta {
+    pub version:            String,
+    pub device:             String,
+    pub algorithm:          String,
+    /// The key derivation function. See --kdf.
+    pub kdf:                Option<String>,
+    /// The fixed byte pattern in hexadecimal, if any.
+    pub pattern:            Option<String>,
+    pub chunk_size:         usize,
+    pub seek:               u64,
+    /// Number of bytes written.
+    pub bytes:              u64,
+    /// Whether the on-disk header has been written.
+    pub header:             bool,
+    /// The fingerprint of the seed.
+    pub seed_fingerprint:   String,
+    /// The generated seed. A seed given by the user is not saved.
+    pub seed:               Option<String>,
+    /// The round whose sub-seed has been written. See --seed-round.
+    pub seed_round:         Option<u64>,
+}
+
+/// Get the seed of the pseudo random stream, as passed to the Disktest core.
+/// This is the sub-seed of the round. See --seed-round.
+pub fn stream_seed(args: &Args) -> Vec<u8> {
+    match &args.pattern {
+        Some(pattern) => pattern.clone(),
+        None => round_seed(&args.seed, args.kdf, args.seed_round),
+    }
+}
+
+impl RunMeta {
+    /// Create the metadata of a write run that wrote the given number of bytes.
+    pub fn new(args: &Args, bytes: u64) -> RunMeta {
+        RunMeta {
+            version:            env!("CARGO_PKG_VERSION").to_string(),
+            device:             args.device.clone(),
+            algorithm:          args.algorithm.to_string(),
+            kdf:                Some(args.kdf.to_string()),
+            pattern:            args.pattern.as_ref()
+                                    .map(|p| p.iter().map(|b| format!("{:02x}", b)).collect()),
+            chunk_size:         args.chunk_size.unwrap_or_else(|| args.algorithm.default_chunk_size()),
+            seek:               args.seek,
+            bytes,
+            header:             args.header,
+            seed_fingerprint:   seed_fingerprint(&stream_seed(args)),
+            seed:               if args.user_seed {
+                                    None
+                                } else {
+                                    Some(String::from_utf8_lossy(&args.seed).to_string())
+                                },
+            seed_round:         Some(args.seed_round),
+        }
+    }
+
+    /// Load the metadata from a file.
+    pub fn load(path: &Path) -> ah::Result<RunMeta> {
+        let text = match fs::read_to_string(path) {
+            Ok(x) => x,
+            Err(e) => return Err(ah::format_err!("Failed to read metadata file {:?}: {}", path, e)),
+        };
+        match serde_json::from_str(&text) {
+            Ok(x) => Ok(x),
+            Err(e) => Err(ah::format_err!("Invalid metadata file {:?}: {}", path, e)),
+        }
+    }
+
+    /// Save the metadata as JSON to a file.
+    pub fn save(&self, path: &Path) -> ah::Result<()> {
+        let mut json = serde_json::to_string_pretty(self)?;
+        json.push('\n');
+        if let Err(e) = fs::write(path, json) {
+            return Err(ah::format_err!("Failed to write metadata file {:?}: {}", path, e));
+        }
+        Ok(())
+    }
+
+    /// Apply the saved parameters to the arguments of a verify run.
+    /// The seed must match the seed of the write run.
+    pub fn apply(&self, args: &mut Args) -> ah::Result<()> {
+        let algorithm: DtStreamType = self.algorithm.parse()?;
+        let kdf: DtKdf = match &self.kdf {
+            Some(kdf) => kdf.parse()?,
+            None => DtKdf::default(),
+        };
+        match &self.pattern {
+            Some(pattern) => {
+                let pattern = parse_pattern(pattern)?;
+                args.seed = pattern.iter().map(|b| format!("{:02x}", b)).collect::<String>().into_bytes();
+                args.user_seed = true;
+                args.pattern = Some(pattern);
+            },
+            None if !args.user_seed => {
+                match &self.seed {
+                    Some(seed) => {
+                        args.seed = seed.as_bytes().to_vec();
+                        args.user_seed = true;
+                    },
+                    None => return Err(ah::format_err!("Verify-only mode requires --seed.")),
+                }
+                args.pattern = None;
+            },
+            None => args.pattern = None,
+        }
+        if let Some(seed_round) = self.seed_round {
+            args.seed_round = seed_round;
+        }
+        if seed_fingerprint(&stream_seed(args)) != self.seed_fingerprint {
+            return Err(ah::format_err!("The --seed does not match the seed \
+                                       that was used to write the device."));
+        }
+        args.algorithm = algorithm;
+        args.kdf = kdf;
+        args.chunk_size = Some(self.chunk_size);
+        args.seek = self.seek;
+        args.max_bytes = args.max_bytes.min(self.bytes);
+        args.header &= self.header;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::parse_args;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_meta() {
+        let args = parse_args(vec!["disktest", "write", "-Sx", "-ACRC", "-s", "4k",
+                                   "--no-header", "/dev/foobar"]).unwrap();
+        let meta = RunMeta::new(&args, 10000);
+        let tdir = tempdir().unwrap();
+        let path = tdir.path().join("meta.json");
+        meta.save(&path).unwrap();
+        assert_eq!(RunMeta::load(&path).unwrap(), meta);
+
+        let mut args = parse_args(vec!["disktest", "verify", "-Sx", "/dev/sdz"]).unwrap();
+        meta.apply(&mut args).unwrap();
+        assert_eq!(args.algorithm, DtStreamType::CRC);
+        assert_eq!(args.kdf, DtKdf::default());
+        assert_eq!(args.seek, 4096);
+        assert_eq!(args.max_bytes, 10000);
+        assert!(!args.header);
+
+        let mut args = parse_args(vec!["disktest", "verify", "-Sy", "/dev/foobar"]).unwrap();
+        assert!(meta.apply(&mut args).is_err());
+
+        let args = parse_args(vec!["disktest", "write", "-Sx", "--kdf", "scrypt:10:8:1",
+                                   "/dev/foobar"]).unwrap();
+        let meta = RunMeta::new(&args, 10000);
+        assert_eq!(meta.kdf, Some("scrypt:10:8:1".to_string()));
+        let mut args = parse_args(vec!["disktest", "verify", "-Sx", "/dev/foobar"]).unwrap();
+        meta.apply(&mut args).unwrap();
+        assert_eq!(args.kdf, DtKdf::Scrypt { log_n: 10, r: 8, p: 1 });
+        assert!(RunMeta::load(&tdir.path().join("nonexistent")).is_err());
+    }
+
+    #[test]
+    fn test_meta_pattern() {
+        let args = parse_args(vec!["disktest", "write", "--pattern", "AA55", "/dev/foobar"]).unwrap();
+        let meta = RunMeta::new(&args, 10000);
+        assert_eq!(meta.pattern, Some("aa55".to_string()));
+
+        let mut args = parse_args(vec!["disktest", "verify", "--meta", "m.json",
+                                       "/dev/foobar"]).unwrap();
+        meta.apply(&mut args).unwrap();
+        assert_eq!(args.algorithm, DtStreamType::PATTERN);
+        assert_eq!(args.pattern, Some(vec![0xAA, 0x55]));
+    }
+
+    #[test]
+    fn test_meta_generated_seed() {
+        let args = parse_args(vec!["disktest", "write", "--seed", "random", "/dev/foobar"]).unwrap();
+        let meta = RunMeta::new(&args, 10000);
+        assert_eq!(meta.seed.as_ref().map(|s| s.as_bytes()), Some(&args.seed[..]));
+        let mut verify_args = parse_args(vec!["disktest", "verify", "--meta", "m.json",
+                                              "/dev/foobar"]).unwrap();
+        meta.apply(&mut verify_args).unwrap();
+        assert_eq!(verify_args.seed, args.seed);
+        assert!(verify_args.user_seed);
+
+        // The sub-seed of a later round is restored.
+        let mut args = args;
+        args.seed_round = 3;
+        let meta = RunMeta::new(&args, 10000);
+        assert_eq!(meta.seed_round, Some(3));
+        let mut verify_args = parse_args(vec!["disktest", "verify", "--meta", "m.json",
+                                              "/dev/foobar"]).unwrap();
+        meta.apply(&mut verify_args).unwrap();
+        assert_eq!(verify_args.seed_round, 3);
+
+        // A seed of the user is not saved.
+        let args = parse_args(vec!["disktest", "write", "-Sx", "/dev/foobar"]).unwrap();
+        assert_eq!(RunMeta::new(&args, 10000).seed, None);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
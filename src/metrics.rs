@@ -0,0 +1,246 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+use disktest_core::{DtErrorRecord, DtPhase, ProgressInfo, ProgressObserver};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// All phases in the order of the metric output.
+const PHASES: [DtPhase; 6] = [
+    DtPhase::Write,
+    DtPhase::Verify,
+    DtPhase::Scan,
+    DtPhase::Nondestructive,
+    DtPhase::Compare,
+    DtPhase::Surface,
+];
+
+/// Timeout for reading a request from a client.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Accumulated metrics of the run.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct MetricsState {
+    /// Processed bytes of all finished phases, per phase.
+    done:       [u64; PHASES.len()],
+    /// Bad regions of all finished phases.
+    errors:     u64,
+    /// Progress of the running phase, if any.
+    current:    Option<ProgressInfo>,
+}
+
+fn phase_index(phase: DtPhase) -> usize {
+    PHASES.iter().position(|p| *p == phase).unwrap()
+}
+
+/// Format the metrics in the Prometheus text exposition format.
+fn format_metrics(state: &MetricsState) -> String {
+    let mut text = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, u64)]| {
+        text.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind));
+        for (labels, value) in samples {
+            text.push_str(&format!("{}{} {}\n", name, labels, value));
+        }
+    };
+    let cur = state.current.as_ref();
+
+    let bytes: Vec<(String, u64)> = PHASES.iter().enumerate().map(|(i, phase)| {
+        let running = cur.filter(|c| c.phase == *phase).map(|c| c.bytes).unwrap_or(0);
+        (format!("{{phase=\"{}\"}}", phase), state.done[i] + running)
+    }).collect();
+    metric("disktest_bytes_total", "counter",
+           "Number of processed bytes.", &bytes);
+    metric("disktest_errors_total", "counter",
+           "Number of bad regions found.",
+           &[("".to_string(), state.errors + cur.map(|c| c.errors as u64).unwrap_or(0))]);
+    let running: Vec<(String, u64)> = PHASES.iter().map(|phase| {
+        (format!("{{phase=\"{}\"}}", phase), cur.filter(|c| c.phase == *phase).is_some() as u64)
+    }).collect();
+    metric("disktest_phase_running", "gauge",
+           "Whether the phase is currently running.", &running);
+
+    if let Some(cur) = cur {
+        metric("disktest_position_bytes", "gauge",
+               "Current absolute byte position on the device.", &[("".to_string(), cur.offset)]);
+        metric("disktest_rate_bytes_per_second", "gauge",
+               "Current throughput of the running phase.", &[("".to_string(), cur.rate)]);
+        metric("disktest_avg_rate_bytes_per_second", "gauge",
+               "Average throughput of the running phase.", &[("".to_string(), cur.avg_rate)]);
+        if let Some(total) = cur.total {
+            metric("disktest_phase_total_bytes", "gauge",
+                   "Total number of bytes of the running phase.", &[("".to_string(), total)]);
+            if cur.bytes > 0 && total >= cur.bytes {
+                let eta = cur.elapsed.as_secs_f64() *
+                          (total - cur.bytes) as f64 / cur.bytes as f64;
+                metric("disktest_eta_seconds", "gauge",
+                       "Estimated remaining time of the running phase.",
+                       &[("".to_string(), eta as u64)]);
+            }
+        }
+    }
+    text
+}
+
+/// Answer one HTTP request.
+fn handle_client(mut stream: TcpStream, state: &Mutex<MetricsState>) {
+    let _ = stream.set_read_timeout(Some(CLIENT_TIMEOUT));
+    let mut request = vec![];
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < 16 * 1024 {
+        match stream.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(count) => request.extend_from_slice(&buf[..count]),
+        }
+    }
+    let request = String::from_utf8_lossy(&request);
+    let path = request.split_whitespace().nth(1).unwrap_or("");
+    let (status, body) = match path {
+        "/" | "/metrics" => ("200 OK", format_metrics(&state.lock().unwrap())),
+        _ => ("404 Not Found", "Not found\n".to_string()),
+    };
+    let _ = write!(stream,
+                   "HTTP/1.0 {}\r\n\
+                   Content-Type: text/plain; version=0.0.4\r\n\
+                   Content-Length: {}\r\n\
+                   Connection: close\r\n\r\n{}",
+                   status, body.len(), body);
+}
+
+/// Prometheus metrics of the run, served over HTTP.
+#[derive(Clone)]
+pub struct Metrics {
+    state:      Arc<Mutex<MetricsState>>,
+}
+
+impl Metrics {
+    /// Start serving the metrics on the given address (e.g. "0.0.0.0:9100").
+    /// The server runs until the process terminates.
+    pub fn start(addr: &str) -> ah::Result<Metrics> {
+        let listener = match TcpListener::bind(addr) {
+            Ok(x) => x,
+            Err(e) => return Err(ah::format_err!("Failed to listen on {:?} for metrics: {}", addr, e)),
+        };
+        let metrics = Metrics {
+            state:      Arc::new(Mutex::new(MetricsState::default())),
+        };
+        let state = Arc::clone(&metrics.state);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                handle_client(stream, &state);
+            }
+        });
+        Ok(metrics)
+    }
+
+    /// Get an observer that has to be attached to the Disktest instances.
+    pub fn observer(&self) -> Box<dyn ProgressObserver> {
+        Box::new(MetricsObserver {
+            state:      Arc::clone(&self.state),
+        })
+    }
+}
+
+/// Progress observer that updates the metrics.
+struct MetricsObserver {
+    state:      Arc<Mutex<MetricsState>>,
+}
+
+impl ProgressObserver for MetricsObserver {
+    fn progress(&mut self, info: &ProgressInfo) {
+        self.state.lock().unwrap().current = Some(info.clone());
+    }
+
+    fn finish(&mut self, info: &ProgressInfo, errors: &[DtErrorRecord]) {
+        let mut state = self.state.lock().unwrap();
+        state.done[phase_index(info.phase)] += info.bytes;
+        state.errors += errors.len() as u64;
+        state.current = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(phase: DtPhase, bytes: u64) -> ProgressInfo {
+        ProgressInfo {
+            phase,
+            offset:     1000 + bytes,
+            bytes,
+            total:      Some(4000),
+            elapsed:    Duration::from_secs(10),
+            rate:       200,
+            avg_rate:   100,
+            errors:     1,
+        }
+    }
+
+    #[test]
+    fn test_format_metrics() {
+        let state = Mutex::new(MetricsState::default());
+        let mut obs = MetricsObserver { state: Arc::new(state) };
+        obs.progress(&info(DtPhase::Write, 500));
+        obs.finish(&info(DtPhase::Write, 4000), &[]);
+        obs.progress(&info(DtPhase::Verify, 1000));
+
+        let text = format_metrics(&obs.state.lock().unwrap());
+        assert!(text.contains("# TYPE disktest_bytes_total counter\n"));
+        assert!(text.contains("disktest_bytes_total{phase=\"write\"} 4000\n"));
+        assert!(text.contains("disktest_bytes_total{phase=\"verify\"} 1000\n"));
+        assert!(text.contains("disktest_bytes_total{phase=\"scan\"} 0\n"));
+        assert!(text.contains("disktest_errors_total 1\n"));
+        assert!(text.contains("disktest_phase_running{phase=\"write\"} 0\n"));
+        assert!(text.contains("disktest_phase_running{phase=\"verify\"} 1\n"));
+        assert!(text.contains("disktest_position_bytes 2000\n"));
+        assert!(text.contains("disktest_rate_bytes_per_second 200\n"));
+        assert!(text.contains("disktest_avg_rate_bytes_per_second 100\n"));
+        assert!(text.contains("disktest_eta_seconds 30\n"));
+
+        obs.finish(&info(DtPhase::Verify, 4000), &[]);
+        let text = format_metrics(&obs.state.lock().unwrap());
+        assert!(text.contains("disktest_bytes_total{phase=\"verify\"} 4000\n"));
+        assert!(!text.contains("disktest_eta_seconds"));
+    }
+
+    #[test]
+    fn test_server() {
+        assert!(Metrics::start("127.0.0.1:0").is_ok());
+        assert!(Metrics::start("256.0.0.1:0").is_err());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Mutex::new(MetricsState::default());
+        for (path, status) in &[("/metrics", "200 OK"), ("/foo", "404 Not Found")] {
+            let mut client = TcpStream::connect(addr).unwrap();
+            write!(client, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+            handle_client(listener.accept().unwrap().0, &state);
+            let mut response = String::new();
+            client.read_to_string(&mut response).unwrap();
+            assert!(response.starts_with(&format!("HTTP/1.0 {}\r\n", status)));
+        }
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
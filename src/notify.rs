@@ -0,0 +1,128 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+
+use anyhow as ah;
+use crate::args::Args;
+use std::process::Command;
+use std::time::Duration;
+
+/// Get the title and the body text of the notification for the result of the run.
+fn notification(args:       &Args,
+                result:     &ah::Result<()>,
+                duration:   Duration) -> (String, String) {
+    let secs = duration.as_secs();
+    let elapsed = format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60);
+    match result {
+        Ok(()) => ("disktest: Success".to_string(),
+                   format!("{}: finished successfully after {}.", args.device, elapsed)),
+        Err(e) => ("disktest: FAILED".to_string(),
+                   format!("{}: failed after {}: {}", args.device, elapsed, e)),
+    }
+}
+
+/// freedesktop.org notification via notify-send.
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+fn notify_command(title: &str, body: &str, failed: bool) -> Command {
+    let mut command = Command::new("notify-send");
+    command.arg("--app-name=disktest")
+           .arg(if failed { "--urgency=critical" } else { "--urgency=normal" })
+           .arg(title)
+           .arg(body);
+    command
+}
+
+/// Notification Center message via AppleScript.
+/// The texts are passed in environment variables, so they don't need to be quoted.
+#[cfg(target_os = "macos")]
+fn notify_command(title: &str, body: &str, _failed: bool) -> Command {
+    let mut command = Command::new("osascript");
+    command.arg("-e")
+           .arg("display notification (system attribute \"DISKTEST_NOTIFY_BODY\") \
+                 with title (system attribute \"DISKTEST_NOTIFY_TITLE\")")
+           .env("DISKTEST_NOTIFY_TITLE", title)
+           .env("DISKTEST_NOTIFY_BODY", body);
+    command
+}
+
+/// Toast notification via PowerShell.
+/// The texts are passed in environment variables, so they don't need to be quoted.
+#[cfg(target_os = "windows")]
+fn notify_command(title: &str, body: &str, _failed: bool) -> Command {
+    const SCRIPT: &str = "\
+        [Windows.UI.Notifications.ToastNotificationManager, Windows.UI.Notifications, \
+         ContentType = WindowsRuntime] | Out-Null; \
+        $t = [Windows.UI.Notifications.ToastNotificationManager]::GetTemplateContent(\
+             [Windows.UI.Notifications.ToastTemplateType]::ToastText02); \
+        $x = $t.GetElementsByTagName('text'); \
+        $x.Item(0).AppendChild($t.CreateTextNode($env:DISKTEST_NOTIFY_TITLE)) | Out-Null; \
+        $x.Item(1).AppendChild($t.CreateTextNode($env:DISKTEST_NOTIFY_BODY)) | Out-Null; \
+        [Windows.UI.Notifications.ToastNotificationManager]::CreateToastNotifier('disktest').Show(\
+             [Windows.UI.Notifications.ToastNotification]::new($t))";
+    let mut command = Command::new("powershell");
+    command.args(["-NoProfile", "-NonInteractive", "-Command", SCRIPT])
+           .env("DISKTEST_NOTIFY_TITLE", title)
+           .env("DISKTEST_NOTIFY_BODY", body);
+    command
+}
+
+/// Show a desktop notification with the result of the run, if --notify is given.
+pub fn notify(args:     &Args,
+              result:   &ah::Result<()>,
+              duration: Duration) -> ah::Result<()> {
+    if !args.notify {
+        return Ok(());
+    }
+    let (title, body) = notification(args, result, duration);
+    let mut command = notify_command(&title, &body, result.is_err());
+    let output = match command.output() {
+        Ok(x) => x,
+        Err(e) => return Err(ah::format_err!("Failed to show the desktop notification with {:?}: {}",
+                                             command.get_program(), e)),
+    };
+    if !output.status.success() {
+        return Err(ah::format_err!("Failed to show the desktop notification: {}: {}",
+                                   output.status,
+                                   String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::parse_args;
+
+    #[test]
+    fn test_notification() {
+        let args = parse_args(vec!["disktest", "write", "--notify", "/dev/foobar"]).unwrap();
+        assert!(args.notify);
+        let (title, body) = notification(&args, &Ok(()), Duration::from_secs(3725));
+        assert_eq!(title, "disktest: Success");
+        assert_eq!(body, "/dev/foobar: finished successfully after 01:02:05.");
+        let (title, body) = notification(&args, &Err(ah::format_err!("Read error")),
+                                         Duration::from_secs(59));
+        assert_eq!(title, "disktest: FAILED");
+        assert_eq!(body, "/dev/foobar: failed after 00:00:59: Read error");
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,437 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+use crate::trim::{DRAT_SAMPLE_SIZE, drop_cache, read_sample};
+use disktest_core::disk_size::disk_size;
+use std::fmt;
+use std::fs::File;
+use std::io::{Seek, SeekFrom};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Admin command opcodes.
+const OPC_GET_LOG_PAGE: u8 = 0x02;
+const OPC_IDENTIFY: u8 = 0x06;
+const OPC_FORMAT_NVM: u8 = 0x80;
+const OPC_SANITIZE: u8 = 0x84;
+
+/// Log page identifier of the sanitize status log.
+const LID_SANITIZE_STATUS: u32 = 0x81;
+/// Size of the identify data structures.
+const IDENTIFY_SIZE: usize = 4096;
+/// Size of the sanitize status log page.
+const SANITIZE_LOG_SIZE: usize = 512;
+
+/// Timeout of the format command, which only completes when the format is done.
+const FORMAT_TIMEOUT_MS: u32 = 4 * 60 * 60 * 1000;
+/// Interval of polling the sanitize status.
+const SANITIZE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The erase operation of an NVMe namespace or controller.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NvmeErase {
+    /// Format NVM with user data erase.
+    Format,
+    /// Format NVM with cryptographic erase.
+    FormatCrypto,
+    /// Sanitize with block erase.
+    SanitizeBlock,
+    /// Sanitize with cryptographic erase.
+    SanitizeCrypto,
+}
+
+impl NvmeErase {
+    /// Check whether the erase only destroys the encryption key.
+    /// The data read afterwards is indeterminate.
+    pub fn is_crypto(&self) -> bool {
+        matches!(self, NvmeErase::FormatCrypto | NvmeErase::SanitizeCrypto)
+    }
+
+    /// The name of the NVMe command.
+    pub fn command_name(&self) -> &'static str {
+        match self {
+            NvmeErase::Format => "Format NVM (user data erase)",
+            NvmeErase::FormatCrypto => "Format NVM (cryptographic erase)",
+            NvmeErase::SanitizeBlock => "Sanitize (block erase)",
+            NvmeErase::SanitizeCrypto => "Sanitize (crypto erase)",
+        }
+    }
+}
+
+impl FromStr for NvmeErase {
+    type Err = ah::Error;
+
+    fn from_str(s: &str) -> Result<NvmeErase, ah::Error> {
+        match s.to_lowercase().as_str() {
+            "format" => Ok(NvmeErase::Format),
+            "format-crypto" => Ok(NvmeErase::FormatCrypto),
+            "sanitize-block" => Ok(NvmeErase::SanitizeBlock),
+            "sanitize-crypto" => Ok(NvmeErase::SanitizeCrypto),
+            _ => Err(ah::format_err!("Invalid NVMe erase method: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for NvmeErase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            NvmeErase::Format => "format",
+            NvmeErase::FormatCrypto => "format-crypto",
+            NvmeErase::SanitizeBlock => "sanitize-block",
+            NvmeErase::SanitizeCrypto => "sanitize-crypto",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+fn le32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+}
+
+/// Check whether the controller supports the erase.
+/// id_ctrl is the Identify Controller data structure.
+fn check_support(id_ctrl: &[u8], erase: NvmeErase) -> ah::Result<()> {
+    // FNA bit 2: Cryptographic erase is supported as part of the format.
+    let fna = id_ctrl[524];
+    // SANICAP bit 0: Crypto erase, bit 1: Block erase.
+    let sanicap = le32(id_ctrl, 328);
+    let supported = match erase {
+        NvmeErase::Format => true,
+        NvmeErase::FormatCrypto => fna & 0x04 != 0,
+        NvmeErase::SanitizeBlock => sanicap & 0x02 != 0,
+        NvmeErase::SanitizeCrypto => sanicap & 0x01 != 0,
+    };
+    if supported {
+        Ok(())
+    } else {
+        Err(ah::format_err!("The NVMe controller does not support {}.", erase.command_name()))
+    }
+}
+
+/// Build the command dword 10 of Format NVM.
+/// The current LBA format, metadata and protection information settings of the namespace
+/// are kept. id_ns is the Identify Namespace data structure.
+fn format_cdw10(id_ns: &[u8], crypto: bool) -> u32 {
+    let flbas = id_ns[26] as u32;
+    let dps = id_ns[29] as u32;
+    let lbaf = flbas & 0x0F;
+    let lbafu = (flbas >> 5) & 0x03;
+    let mset = (flbas >> 4) & 0x01;
+    let pi = dps & 0x07;
+    let pil = (dps >> 3) & 0x01;
+    let ses = if crypto { 2 } else { 1 };
+    lbaf | (mset << 4) | (pi << 5) | (pil << 8) | (ses << 9) | (lbafu << 12)
+}
+
+/// Build the command dword 10 of Sanitize.
+fn sanitize_cdw10(crypto: bool) -> u32 {
+    // SANACT: 2 = block erase, 4 = crypto erase.
+    if crypto { 4 } else { 2 }
+}
+
+/// The state of a sanitize operation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SanitizeState {
+    InProgress(f64),
+    Done,
+    Failed,
+}
+
+/// Parse the sanitize status log page.
+fn parse_sanitize_log(log: &[u8]) -> SanitizeState {
+    let progress = u16::from_le_bytes([log[0], log[1]]) as f64 / 65536.0;
+    match log[2] & 0x07 {
+        2 => SanitizeState::InProgress(progress),
+        3 => SanitizeState::Failed,
+        _ => SanitizeState::Done,
+    }
+}
+
+/// The NVMe admin command, as struct nvme_admin_cmd of linux/nvme_ioctl.h.
+#[cfg(target_os="linux")]
+#[repr(C)]
+#[derive(Default)]
+struct NvmeAdminCmd {
+    opcode:         u8,
+    flags:          u8,
+    rsvd1:          u16,
+    nsid:           u32,
+    cdw2:           u32,
+    cdw3:           u32,
+    metadata:       u64,
+    addr:           u64,
+    metadata_len:   u32,
+    data_len:       u32,
+    cdw10:          u32,
+    cdw11:          u32,
+    cdw12:          u32,
+    cdw13:          u32,
+    cdw14:          u32,
+    cdw15:          u32,
+    timeout_ms:     u32,
+    result:         u32,
+}
+
+/// An opened NVMe namespace block device.
+#[cfg(target_os="linux")]
+struct NvmeDevice {
+    file:   File,
+    nsid:   u32,
+}
+
+#[cfg(target_os="linux")]
+impl NvmeDevice {
+    fn open(device: &str) -> ah::Result<NvmeDevice> {
+        use std::os::unix::io::AsRawFd;
+
+        // NVME_IOCTL_ID = _IO('N', 0x40)
+        const NVME_IOCTL_ID: libc::c_ulong = ((b'N' as libc::c_ulong) << 8) | 0x40;
+
+        let file = match File::open(device) {
+            Ok(f) => f,
+            Err(e) => return Err(ah::format_err!("Failed to open file {:?}: {}", device, e)),
+        };
+        let nsid = unsafe { libc::ioctl(file.as_raw_fd(), NVME_IOCTL_ID as _) };
+        if nsid <= 0 {
+            return Err(ah::format_err!("{:?} is no NVMe namespace: {}",
+                                       device, std::io::Error::last_os_error()));
+        }
+        Ok(NvmeDevice {
+            file,
+            nsid: nsid as u32,
+        })
+    }
+
+    fn admin(&self, cmd: &mut NvmeAdminCmd, data: Option<&mut [u8]>) -> ah::Result<u32> {
+        use std::mem::size_of;
+        use std::os::unix::io::AsRawFd;
+
+        // NVME_IOCTL_ADMIN_CMD = _IOWR('N', 0x41, struct nvme_admin_cmd)
+        const IOC_READ_WRITE: libc::c_ulong = 3;
+        const NVME_IOCTL_ADMIN_CMD: libc::c_ulong = (IOC_READ_WRITE << 30) |
+                                                    ((size_of::<NvmeAdminCmd>() as libc::c_ulong) << 16) |
+                                                    ((b'N' as libc::c_ulong) << 8) |
+                                                    0x41;
+
+        if let Some(data) = data {
+            cmd.addr = data.as_mut_ptr() as u64;
+            cmd.data_len = data.len() as u32;
+        }
+        let ret = unsafe { libc::ioctl(self.file.as_raw_fd(),
+                                       NVME_IOCTL_ADMIN_CMD as _,
+                                       cmd as *mut NvmeAdminCmd) };
+        if ret < 0 {
+            Err(ah::format_err!("NVMe admin command 0x{:02X} failed: {}",
+                                cmd.opcode, std::io::Error::last_os_error()))
+        } else if ret > 0 {
+            Err(ah::format_err!("NVMe admin command 0x{:02X} failed with status 0x{:X}.",
+                                cmd.opcode, ret))
+        } else {
+            Ok(cmd.result)
+        }
+    }
+
+    fn identify(&self, cns: u32, nsid: u32) -> ah::Result<Vec<u8>> {
+        let mut data = vec![0; IDENTIFY_SIZE];
+        let mut cmd = NvmeAdminCmd {
+            opcode: OPC_IDENTIFY,
+            nsid,
+            cdw10:  cns,
+            ..Default::default()
+        };
+        self.admin(&mut cmd, Some(&mut data))?;
+        Ok(data)
+    }
+
+    fn sanitize_log(&self) -> ah::Result<Vec<u8>> {
+        let mut data = vec![0; SANITIZE_LOG_SIZE];
+        let dwords = (SANITIZE_LOG_SIZE / 4 - 1) as u32;
+        let mut cmd = NvmeAdminCmd {
+            opcode: OPC_GET_LOG_PAGE,
+            nsid:   0xFFFF_FFFF,
+            cdw10:  LID_SANITIZE_STATUS | (dwords << 16),
+            ..Default::default()
+        };
+        self.admin(&mut cmd, Some(&mut data))?;
+        Ok(data)
+    }
+}
+
+#[cfg(target_os="linux")]
+fn os_nvme_erase(device:    &str,
+                 erase:     NvmeErase,
+                 abort:     &Arc<AtomicBool>,
+                 progress:  &mut dyn FnMut(f64)) -> ah::Result<()> {
+    let dev = NvmeDevice::open(device)?;
+    check_support(&dev.identify(1, 0)?, erase)?;
+
+    match erase {
+        NvmeErase::Format | NvmeErase::FormatCrypto => {
+            let id_ns = dev.identify(0, dev.nsid)?;
+            let mut cmd = NvmeAdminCmd {
+                opcode:     OPC_FORMAT_NVM,
+                nsid:       dev.nsid,
+                cdw10:      format_cdw10(&id_ns, erase.is_crypto()),
+                timeout_ms: FORMAT_TIMEOUT_MS,
+                ..Default::default()
+            };
+            dev.admin(&mut cmd, None)?;
+        },
+        NvmeErase::SanitizeBlock | NvmeErase::SanitizeCrypto => {
+            let mut cmd = NvmeAdminCmd {
+                opcode: OPC_SANITIZE,
+                cdw10:  sanitize_cdw10(erase.is_crypto()),
+                ..Default::default()
+            };
+            dev.admin(&mut cmd, None)?;
+            // The sanitize runs in the background. Wait for its completion.
+            loop {
+                match parse_sanitize_log(&dev.sanitize_log()?) {
+                    SanitizeState::InProgress(done) => progress(done),
+                    SanitizeState::Done => break,
+                    SanitizeState::Failed => {
+                        return Err(ah::format_err!("The sanitize operation failed."));
+                    },
+                }
+                if abort.load(Ordering::Relaxed) {
+                    return Err(ah::format_err!("Stopped waiting for the sanitize operation. \
+                                                It continues in the background."));
+                }
+                std::thread::sleep(SANITIZE_POLL_INTERVAL);
+            }
+        },
+    }
+    // The page cache still holds the data from before the erase.
+    drop_cache(&dev.file, 0, 0);
+    Ok(())
+}
+
+#[cfg(not(target_os="linux"))]
+fn os_nvme_erase(_device:   &str,
+                 _erase:    NvmeErase,
+                 _abort:    &Arc<AtomicBool>,
+                 _progress: &mut dyn FnMut(f64)) -> ah::Result<()> {
+    Err(ah::format_err!("NVMe erase is not supported on this system."))
+}
+
+/// Erase the NVMe namespace with Format NVM or the whole controller with Sanitize
+/// and wait for the completion.
+/// progress is called with the completed fraction while a sanitize is running.
+/// Waiting for a sanitize is stopped, if abort is set.
+pub fn nvme_erase(device:   &str,
+                  erase:    NvmeErase,
+                  abort:    &Arc<AtomicBool>,
+                  progress: &mut dyn FnMut(f64)) -> ah::Result<()> {
+    os_nvme_erase(device, erase, abort, progress)
+}
+
+/// Read the samples at the start, in the middle and at the end of the device.
+/// They are compared before and after a cryptographic erase.
+pub fn read_samples(device: &str) -> ah::Result<Vec<Vec<u8>>> {
+    let mut file = match File::open(device) {
+        Ok(f) => f,
+        Err(e) => return Err(ah::format_err!("Failed to open file {:?}: {}", device, e)),
+    };
+    let size = match disk_size(&file)? {
+        Some(size) => size,
+        None => file.seek(SeekFrom::End(0))?,
+    };
+    let len = size.min(DRAT_SAMPLE_SIZE);
+    drop_cache(&file, 0, 0);
+    let mut samples = vec![];
+    for offset in [0, (size / 2).saturating_sub(len / 2), size - len] {
+        samples.push(read_sample(&mut file, offset, len)?);
+    }
+    Ok(samples)
+}
+
+/// Check that the samples after a cryptographic erase differ from the samples before.
+/// Samples that read back as zeros are erased as well.
+pub fn check_crypto_erased(before: &[Vec<u8>], after: &[Vec<u8>]) -> ah::Result<()> {
+    for (before, after) in before.iter().zip(after) {
+        if before == after && after.iter().any(|&b| b != 0) {
+            return Err(ah::format_err!("The data did not change during the cryptographic erase."));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("format".parse::<NvmeErase>().unwrap(), NvmeErase::Format);
+        assert_eq!("Sanitize-Crypto".parse::<NvmeErase>().unwrap(), NvmeErase::SanitizeCrypto);
+        assert!("sanitize".parse::<NvmeErase>().is_err());
+        for erase in [NvmeErase::Format, NvmeErase::FormatCrypto,
+                      NvmeErase::SanitizeBlock, NvmeErase::SanitizeCrypto] {
+            assert_eq!(erase.to_string().parse::<NvmeErase>().unwrap(), erase);
+        }
+    }
+
+    #[test]
+    fn test_commands() {
+        let mut id_ctrl = vec![0; IDENTIFY_SIZE];
+        assert!(check_support(&id_ctrl, NvmeErase::Format).is_ok());
+        assert!(check_support(&id_ctrl, NvmeErase::FormatCrypto).is_err());
+        assert!(check_support(&id_ctrl, NvmeErase::SanitizeBlock).is_err());
+        id_ctrl[524] = 0x04;
+        id_ctrl[328] = 0x02;
+        assert!(check_support(&id_ctrl, NvmeErase::FormatCrypto).is_ok());
+        assert!(check_support(&id_ctrl, NvmeErase::SanitizeBlock).is_ok());
+        assert!(check_support(&id_ctrl, NvmeErase::SanitizeCrypto).is_err());
+
+        let mut id_ns = vec![0; IDENTIFY_SIZE];
+        assert_eq!(format_cdw10(&id_ns, false), 1 << 9);
+        // LBA format 17 with metadata at the end of the LBA and PI type 1 first.
+        id_ns[26] = 0x01 | 0x10 | (0x01 << 5);
+        id_ns[29] = 0x01 | 0x08;
+        assert_eq!(format_cdw10(&id_ns, true), 0x01 | (1 << 4) | (1 << 5) | (1 << 8) | (2 << 9) | (1 << 12));
+
+        assert_eq!(sanitize_cdw10(false), 2);
+        assert_eq!(sanitize_cdw10(true), 4);
+
+        let mut log = vec![0; SANITIZE_LOG_SIZE];
+        log[0..2].copy_from_slice(&0x8000u16.to_le_bytes());
+        log[2] = 2;
+        assert_eq!(parse_sanitize_log(&log), SanitizeState::InProgress(0.5));
+        log[2] = 1;
+        assert_eq!(parse_sanitize_log(&log), SanitizeState::Done);
+        log[2] = 3;
+        assert_eq!(parse_sanitize_log(&log), SanitizeState::Failed);
+    }
+
+    #[test]
+    fn test_check_crypto_erased() {
+        let before = vec![vec![0xAA; 16], vec![0; 16]];
+        assert!(check_crypto_erased(&before, &[vec![0x55; 16], vec![0; 16]]).is_ok());
+        assert!(check_crypto_erased(&before, &[vec![0; 16], vec![0x12; 16]]).is_ok());
+        assert!(check_crypto_erased(&before, &[vec![0x55; 16], vec![0xAA; 16]]).is_ok());
+        assert!(check_crypto_erased(&before, &[vec![0xAA; 16], vec![0; 16]]).is_err());
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
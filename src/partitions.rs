@@ -0,0 +1,306 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Size of the MBR and of the extended boot records.
+const MBR_SIZE: usize = 512;
+/// Offset of the first partition entry in the MBR.
+const MBR_ENTRIES: usize = 446;
+/// Size of the sectors that MBR addresses are counted in.
+const MBR_SECTOR_SIZE: u64 = 512;
+/// MBR partition type of a GPT protective partition.
+const MBR_TYPE_GPT: u8 = 0xEE;
+/// MBR partition types of extended partitions.
+const MBR_TYPES_EXTENDED: [u8; 3] = [0x05, 0x0F, 0x85];
+/// Maximum number of logical partitions in an extended partition.
+const MAX_LOGICAL: u32 = 128;
+/// Maximum number of GPT partition entries.
+const MAX_GPT_ENTRIES: u32 = 1024;
+
+/// A partition of a partitioned disk.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Partition {
+    /// The number of the partition, as used by the operating system
+    /// (e.g. 2 for /dev/sda2).
+    pub number:     u32,
+    /// The absolute byte offset of the partition on the disk.
+    pub offset:     u64,
+    /// The length of the partition, in bytes.
+    pub length:     u64,
+}
+
+/// The partition table of a disk.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PartitionTable {
+    /// The type of the partition table ("GPT" or "MBR").
+    pub kind:       &'static str,
+    pub partitions: Vec<Partition>,
+}
+
+impl PartitionTable {
+    /// Find the partition that contains the absolute byte offset.
+    /// Returns the partition and the offset relative to its start.
+    pub fn locate(&self, offset: u64) -> Option<(&Partition, u64)> {
+        self.partitions.iter()
+            .find(|p| offset >= p.offset && offset - p.offset < p.length)
+            .map(|p| (p, offset - p.offset))
+    }
+}
+
+/// Read len bytes at the absolute offset.
+fn read_at<R: Read + Seek>(disk: &mut R, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0; len];
+    disk.seek(SeekFrom::Start(offset))?;
+    disk.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn le32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset+4].try_into().unwrap())
+}
+
+fn le64(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset+8].try_into().unwrap())
+}
+
+/// Get the (type, start sector, number of sectors) of the four entries of an MBR or EBR.
+/// Returns None, if the record has no boot signature.
+fn mbr_entries(record: &[u8]) -> Option<Vec<(u8, u64, u64)>> {
+    if record[510..512] != [0x55, 0xAA] {
+        return None;
+    }
+    Some((0..4).map(|i| {
+        let entry = &record[MBR_ENTRIES + i * 16..MBR_ENTRIES + (i + 1) * 16];
+        (entry[4], le32(entry, 8) as u64, le32(entry, 12) as u64)
+    }).collect())
+}
+
+/// Parse the GPT with the logical sector size.
+fn parse_gpt<R: Read + Seek>(disk: &mut R, sector_size: u64) -> io::Result<Option<PartitionTable>> {
+    let header = read_at(disk, sector_size, 92)?;
+    if &header[0..8] != b"EFI PART" {
+        return Ok(None);
+    }
+    let entries_lba = le64(&header, 72);
+    let count = le32(&header, 80);
+    let entry_size = le32(&header, 84) as usize;
+    if count > MAX_GPT_ENTRIES || !(128..=4096).contains(&entry_size) {
+        return Ok(None);
+    }
+    let entries = read_at(disk, entries_lba * sector_size, count as usize * entry_size)?;
+    let partitions = entries.chunks(entry_size).enumerate()
+        .filter(|(_, entry)| entry[0..16].iter().any(|&b| b != 0))
+        .map(|(i, entry)| {
+            let first = le64(entry, 32);
+            let last = le64(entry, 40);
+            Partition {
+                number: i as u32 + 1,
+                offset: first * sector_size,
+                length: (last + 1).saturating_sub(first) * sector_size,
+            }
+        })
+        .collect();
+    Ok(Some(PartitionTable {
+        kind: "GPT",
+        partitions,
+    }))
+}
+
+/// Parse the MBR including the logical partitions of an extended partition.
+fn parse_mbr<R: Read + Seek>(disk: &mut R) -> io::Result<Option<PartitionTable>> {
+    let mbr = read_at(disk, 0, MBR_SIZE)?;
+    let entries = match mbr_entries(&mbr) {
+        Some(entries) => entries,
+        None => return Ok(None),
+    };
+    if entries.iter().any(|&(kind, _, _)| kind == MBR_TYPE_GPT) {
+        return Ok(None);
+    }
+    let mut partitions = vec![];
+    let mut extended = None;
+    for (i, &(kind, start, count)) in entries.iter().enumerate() {
+        if kind == 0 || count == 0 {
+            continue;
+        }
+        if MBR_TYPES_EXTENDED.contains(&kind) {
+            // The extended partition only contains the logical partitions.
+            extended = Some(start);
+            continue;
+        }
+        partitions.push(Partition {
+            number: i as u32 + 1,
+            offset: start * MBR_SECTOR_SIZE,
+            length: count * MBR_SECTOR_SIZE,
+        });
+    }
+
+    // The logical partitions are a chain of extended boot records.
+    // Their entries are relative to the EBR and to the extended partition respectively.
+    if let Some(ext_start) = extended {
+        let mut ebr_start = ext_start;
+        for number in 5..5 + MAX_LOGICAL {
+            let ebr = read_at(disk, ebr_start * MBR_SECTOR_SIZE, MBR_SIZE)?;
+            let entries = match mbr_entries(&ebr) {
+                Some(entries) => entries,
+                None => break,
+            };
+            let (kind, start, count) = entries[0];
+            if kind != 0 && count != 0 {
+                partitions.push(Partition {
+                    number,
+                    offset: (ebr_start + start) * MBR_SECTOR_SIZE,
+                    length: count * MBR_SECTOR_SIZE,
+                });
+            }
+            let (kind, next, _) = entries[1];
+            if kind == 0 || next == 0 {
+                break;
+            }
+            ebr_start = ext_start + next;
+        }
+    }
+
+    Ok(Some(PartitionTable {
+        kind: "MBR",
+        partitions,
+    }))
+}
+
+/// Parse the GPT or MBR partition table of a disk.
+/// Returns None, if the disk has no partition table.
+pub fn parse_partitions<R: Read + Seek>(disk: &mut R) -> io::Result<Option<PartitionTable>> {
+    for sector_size in [512, 4096] {
+        match parse_gpt(disk, sector_size) {
+            Ok(Some(table)) => return Ok(Some(table)),
+            Ok(None) => (),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => (),
+            Err(e) => return Err(e),
+        }
+    }
+    parse_mbr(disk)
+}
+
+/// Check whether the device is a whole disk rather than a partition.
+#[cfg(target_os="linux")]
+fn is_whole_disk(device: &str) -> bool {
+    use std::path::Path;
+
+    let path = match Path::new(device).canonicalize() {
+        Ok(path) => path,
+        Err(_) => return false,
+    };
+    let name = match path.strip_prefix("/dev") {
+        Ok(name) => name.to_string_lossy().replace('/', "!"),
+        Err(_) => return false,
+    };
+    let sysfs = Path::new("/sys/class/block").join(name);
+    sysfs.exists() && !sysfs.join("partition").exists()
+}
+
+/// Check whether the device is a whole disk rather than a partition.
+#[cfg(not(target_os="linux"))]
+fn is_whole_disk(_device: &str) -> bool {
+    false
+}
+
+/// Read the partition table of the device, if it is a whole disk.
+/// Returns None, if the device is no whole disk, has no partition table,
+/// or if the partition table can't be read.
+pub fn read_partitions(device: &str) -> Option<PartitionTable> {
+    if !is_whole_disk(device) {
+        return None;
+    }
+    let mut file = File::open(device).ok()?;
+    parse_partitions(&mut file).ok().flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn mbr_entry(record: &mut [u8], index: usize, kind: u8, start: u32, count: u32) {
+        let entry = &mut record[MBR_ENTRIES + index * 16..MBR_ENTRIES + (index + 1) * 16];
+        entry[4] = kind;
+        entry[8..12].copy_from_slice(&start.to_le_bytes());
+        entry[12..16].copy_from_slice(&count.to_le_bytes());
+        record[510] = 0x55;
+        record[511] = 0xAA;
+    }
+
+    #[test]
+    fn test_mbr() {
+        let mut disk = vec![0; 64 * 1024];
+        mbr_entry(&mut disk[0..512], 0, 0x83, 2, 10);
+        mbr_entry(&mut disk[0..512], 1, 0x05, 20, 80);
+        // Two logical partitions.
+        mbr_entry(&mut disk[20 * 512..21 * 512], 0, 0x83, 1, 9);
+        mbr_entry(&mut disk[20 * 512..21 * 512], 1, 0x05, 30, 50);
+        mbr_entry(&mut disk[50 * 512..51 * 512], 0, 0x07, 2, 40);
+        let table = parse_partitions(&mut Cursor::new(disk)).unwrap().unwrap();
+        assert_eq!(table.kind, "MBR");
+        let parts: Vec<(u32, u64, u64)> = table.partitions.iter()
+            .map(|p| (p.number, p.offset, p.length)).collect();
+        assert_eq!(parts, vec![(1, 1024, 5120), (5, 10752, 4608), (6, 26624, 20480)]);
+
+        let (part, offset) = table.locate(1024 + 100).unwrap();
+        assert_eq!((part.number, offset), (1, 100));
+        let (part, offset) = table.locate(26624).unwrap();
+        assert_eq!((part.number, offset), (6, 0));
+        assert!(table.locate(0).is_none());
+        assert!(table.locate(6144).is_none());
+        assert!(table.locate(10240).is_none());
+
+        // No partition table.
+        assert!(parse_partitions(&mut Cursor::new(vec![0; 4096])).unwrap().is_none());
+        assert!(parse_partitions(&mut Cursor::new(vec![0; 100])).is_err());
+    }
+
+    #[test]
+    fn test_gpt() {
+        let mut disk = vec![0; 64 * 1024];
+        mbr_entry(&mut disk[0..512], 0, MBR_TYPE_GPT, 1, 127);
+        let header = &mut disk[512..1024];
+        header[0..8].copy_from_slice(b"EFI PART");
+        header[72..80].copy_from_slice(&2u64.to_le_bytes());
+        header[80..84].copy_from_slice(&4u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+        for (i, first, last) in [(0, 34u64, 40u64), (2, 50, 99)] {
+            let entry = &mut disk[1024 + i * 128..1024 + (i + 1) * 128];
+            entry[0] = 0xAF;
+            entry[32..40].copy_from_slice(&first.to_le_bytes());
+            entry[40..48].copy_from_slice(&last.to_le_bytes());
+        }
+        let table = parse_partitions(&mut Cursor::new(disk)).unwrap().unwrap();
+        assert_eq!(table.kind, "GPT");
+        assert_eq!(table.partitions, vec![
+            Partition { number: 1, offset: 34 * 512, length: 7 * 512 },
+            Partition { number: 3, offset: 50 * 512, length: 50 * 512 },
+        ]);
+        let (part, offset) = table.locate(60 * 512 + 7).unwrap();
+        assert_eq!((part.number, offset), (3, 10 * 512 + 7));
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
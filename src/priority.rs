@@ -0,0 +1,165 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+
+/// I/O scheduling priority of the process.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IoPriority {
+    /// Only do I/O, if no other process needs the disk.
+    Idle,
+    /// Lowest best-effort priority.
+    Low,
+    /// Default best-effort priority.
+    Normal,
+}
+
+impl IoPriority {
+    /// Parse an I/O priority name.
+    pub fn parse(s: &str) -> ah::Result<IoPriority> {
+        match s.trim().to_lowercase().as_str() {
+            "idle" => Ok(IoPriority::Idle),
+            "low" => Ok(IoPriority::Low),
+            "normal" => Ok(IoPriority::Normal),
+            _ => Err(ah::format_err!("Unknown I/O priority '{}'. Expected idle, low or normal.", s)),
+        }
+    }
+}
+
+/// Minimum and maximum niceness.
+pub const NICE_MIN: i32 = -20;
+pub const NICE_MAX: i32 = 19;
+
+/// Linux ioprio value (class << 13 | data) for the I/O priority.
+#[cfg(any(target_os="linux", test))]
+fn linux_ioprio(prio: IoPriority) -> libc::c_int {
+    const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+    const IOPRIO_CLASS_BE: libc::c_int = 2;
+    const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+    match prio {
+        IoPriority::Idle => IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+        IoPriority::Low => (IOPRIO_CLASS_BE << IOPRIO_CLASS_SHIFT) | 7,
+        IoPriority::Normal => (IOPRIO_CLASS_BE << IOPRIO_CLASS_SHIFT) | 4,
+    }
+}
+
+#[cfg(target_os="linux")]
+fn os_set_io_priority(prio: IoPriority) -> ah::Result<()> {
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+    let res = unsafe {
+        libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, linux_ioprio(prio))
+    };
+    if res < 0 {
+        return Err(ah::format_err!("Failed to set the I/O priority: {}",
+                                   std::io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+#[cfg(all(unix, not(target_os="linux")))]
+fn os_set_io_priority(prio: IoPriority) -> ah::Result<()> {
+    match prio {
+        IoPriority::Normal => Ok(()),
+        _ => Err(ah::format_err!("The I/O priority is not supported on this operating system.")),
+    }
+}
+
+#[cfg(unix)]
+fn os_set_nice(nice: i32) -> ah::Result<()> {
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS as _, 0, nice) } < 0 {
+        return Err(ah::format_err!("Failed to set the niceness to {}: {}",
+                                   nice, std::io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+#[cfg(target_os="windows")]
+fn os_set_priority_class(class: winapi::shared::minwindef::DWORD) -> ah::Result<()> {
+    use winapi::um::processthreadsapi::{GetCurrentProcess, SetPriorityClass};
+
+    if unsafe { SetPriorityClass(GetCurrentProcess(), class) } == 0 {
+        return Err(ah::format_err!("Failed to set the process priority: {}",
+                                   std::io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+/// On Windows idle and low both select the background processing mode,
+/// which lowers the I/O priority of the process.
+#[cfg(target_os="windows")]
+fn os_set_io_priority(prio: IoPriority) -> ah::Result<()> {
+    use winapi::um::winbase::PROCESS_MODE_BACKGROUND_BEGIN;
+
+    match prio {
+        IoPriority::Normal => Ok(()),
+        _ => os_set_priority_class(PROCESS_MODE_BACKGROUND_BEGIN),
+    }
+}
+
+/// On Windows the niceness is mapped to the nearest priority class.
+#[cfg(target_os="windows")]
+fn os_set_nice(nice: i32) -> ah::Result<()> {
+    use winapi::um::winbase::{ABOVE_NORMAL_PRIORITY_CLASS, BELOW_NORMAL_PRIORITY_CLASS,
+                              HIGH_PRIORITY_CLASS, IDLE_PRIORITY_CLASS, NORMAL_PRIORITY_CLASS};
+
+    os_set_priority_class(match nice {
+        15..=i32::MAX => IDLE_PRIORITY_CLASS,
+        5..=14 => BELOW_NORMAL_PRIORITY_CLASS,
+        -4..=4 => NORMAL_PRIORITY_CLASS,
+        -14..=-5 => ABOVE_NORMAL_PRIORITY_CLASS,
+        _ => HIGH_PRIORITY_CLASS,
+    })
+}
+
+/// Set the CPU niceness and the I/O priority of the process.
+/// This must be called before any threads are started,
+/// because on Linux the priorities are inherited by new threads only.
+pub fn set_priority(nice: Option<i32>, io_priority: Option<IoPriority>) -> ah::Result<()> {
+    if let Some(nice) = nice {
+        os_set_nice(nice)?;
+    }
+    if let Some(io_priority) = io_priority {
+        os_set_io_priority(io_priority)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(IoPriority::parse("idle").unwrap(), IoPriority::Idle);
+        assert_eq!(IoPriority::parse("LOW").unwrap(), IoPriority::Low);
+        assert_eq!(IoPriority::parse("normal").unwrap(), IoPriority::Normal);
+        assert!(IoPriority::parse("high").is_err());
+    }
+
+    #[test]
+    fn test_linux_ioprio() {
+        assert_eq!(linux_ioprio(IoPriority::Idle), 0x6000);
+        assert_eq!(linux_ioprio(IoPriority::Low), 0x4007);
+        assert_eq!(linux_ioprio(IoPriority::Normal), 0x4004);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
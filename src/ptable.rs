@@ -0,0 +1,144 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+use disktest_core::disk_size::disk_size;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// Size of the areas at the start and at the end of the device
+/// that are preserved. They hold the MBR, the primary GPT and the backup GPT.
+pub const PTABLE_AREA_SIZE: u64 = 1024 * 1024;
+
+/// Backup of the partition table areas at the start and at the end of a device.
+pub struct PtableBackup {
+    head:       Vec<u8>,
+    /// The offset of the area at the end of the device.
+    tail_offset: u64,
+    tail:       Vec<u8>,
+}
+
+impl PtableBackup {
+    /// Back up the partition table areas of a device with the size.
+    fn read_from<F: Read + Seek>(file: &mut F, size: u64) -> ah::Result<PtableBackup> {
+        if size < PTABLE_AREA_SIZE * 3 {
+            return Err(ah::format_err!("The device is too small to preserve the partition table. \
+                                        It must be at least {} bytes.", PTABLE_AREA_SIZE * 3));
+        }
+        let mut head = vec![0; PTABLE_AREA_SIZE as usize];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut head)?;
+        let tail_offset = size - PTABLE_AREA_SIZE;
+        let mut tail = vec![0; PTABLE_AREA_SIZE as usize];
+        file.seek(SeekFrom::Start(tail_offset))?;
+        file.read_exact(&mut tail)?;
+        Ok(PtableBackup {
+            head,
+            tail_offset,
+            tail,
+        })
+    }
+
+    /// Write the backup back to the device.
+    fn write_to<F: Write + Seek>(&self, file: &mut F) -> ah::Result<()> {
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&self.head)?;
+        file.seek(SeekFrom::Start(self.tail_offset))?;
+        file.write_all(&self.tail)?;
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Back up the partition table areas of the device.
+    pub fn read(device: &str) -> ah::Result<PtableBackup> {
+        let mut file = match File::open(device) {
+            Ok(f) => f,
+            Err(e) => return Err(ah::format_err!("Failed to open file {:?}: {}", device, e)),
+        };
+        let size = match disk_size(&file)? {
+            Some(size) => size,
+            None => file.seek(SeekFrom::End(0))?,
+        };
+        PtableBackup::read_from(&mut file, size)
+            .map_err(|e| ah::format_err!("Failed to back up the partition table of {:?}: {}", device, e))
+    }
+
+    /// Restore the partition table areas of the device.
+    pub fn restore(&self, device: &str) -> ah::Result<()> {
+        let result = OpenOptions::new().write(true).open(device)
+            .map_err(ah::Error::from)
+            .and_then(|mut file| {
+                self.write_to(&mut file)?;
+                file.sync_all()?;
+                Ok(())
+            });
+        result.map_err(|e| ah::format_err!("Failed to restore the partition table of {:?}: {}", device, e))
+    }
+
+    /// Limit the range from seek with max_bytes to the part of the device
+    /// between the partition table areas.
+    /// The start is rounded up to a multiple of the chunk size,
+    /// because the stream can only start at a chunk boundary.
+    /// Returns the limited (seek, max_bytes).
+    pub fn limit_range(&self, seek: u64, max_bytes: u64, chunk_size: u64) -> (u64, u64) {
+        let seek = seek.max(PTABLE_AREA_SIZE).div_ceil(chunk_size) * chunk_size;
+        let seek = seek.min(self.tail_offset);
+        (seek, max_bytes.min(self.tail_offset - seek))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_backup_restore() {
+        const SIZE: u64 = PTABLE_AREA_SIZE * 4;
+        let orig: Vec<u8> = (0..SIZE).map(|i| (i % 251) as u8).collect();
+        let mut disk = Cursor::new(orig.clone());
+        let backup = PtableBackup::read_from(&mut disk, SIZE).unwrap();
+        assert_eq!(backup.tail_offset, SIZE - PTABLE_AREA_SIZE);
+
+        // Overwrite the whole device and restore the partition table areas.
+        disk.get_mut().iter_mut().for_each(|b| *b = 0xFF);
+        backup.write_to(&mut disk).unwrap();
+        let disk = disk.into_inner();
+        let area = PTABLE_AREA_SIZE as usize;
+        assert_eq!(disk[..area], orig[..area]);
+        assert!(disk[area..SIZE as usize - area].iter().all(|&b| b == 0xFF));
+        assert_eq!(disk[SIZE as usize - area..], orig[SIZE as usize - area..]);
+
+        const MIB: u64 = PTABLE_AREA_SIZE;
+        assert_eq!(backup.limit_range(0, u64::MAX, 4096), (MIB, MIB * 2));
+        assert_eq!(backup.limit_range(MIB * 2, 100, 4096), (MIB * 2, 100));
+        assert_eq!(backup.limit_range(MIB * 2, u64::MAX, 4096), (MIB * 2, MIB));
+        assert_eq!(backup.limit_range(SIZE, 100, 4096), (SIZE - MIB, 0));
+        // The start is rounded up to the chunk size.
+        assert_eq!(backup.limit_range(0, u64::MAX, MIB * 3 / 2), (MIB * 3 / 2, MIB * 3 / 2));
+        assert_eq!(backup.limit_range(0, u64::MAX, MIB * 4), (SIZE - MIB, 0));
+
+        let mut small = Cursor::new(vec![0; PTABLE_AREA_SIZE as usize * 2]);
+        assert!(PtableBackup::read_from(&mut small, PTABLE_AREA_SIZE * 2).is_err());
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
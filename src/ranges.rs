@@ -0,0 +1,137 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+use disktest_core::util::parsebytes;
+use std::fs;
+use std::path::Path;
+
+/// Parse a byte range START:LEN (e.g. 1G:100M).
+/// Returns the absolute offset and the length.
+pub fn parse_range(s: &str) -> ah::Result<(u64, u64)> {
+    let (start, len) = match s.split_once(':') {
+        Some(x) => x,
+        None => return Err(ah::format_err!("The range {:?} is not given as START:LEN.", s)),
+    };
+    let start = match parsebytes(start) {
+        Ok(x) => x,
+        Err(e) => return Err(ah::format_err!("Invalid start of the range {:?}: {}", s, e)),
+    };
+    let len = match parsebytes(len) {
+        Ok(x) if x > 0 => x,
+        Ok(_) => return Err(ah::format_err!("The range {:?} is empty.", s)),
+        Err(e) => return Err(ah::format_err!("Invalid length of the range {:?}: {}", s, e)),
+    };
+    if start.checked_add(len).is_none() {
+        return Err(ah::format_err!("The range {:?} is too big.", s));
+    }
+    Ok((start, len))
+}
+
+/// Parse the content of a ranges file with one START:LEN range per line.
+/// Empty lines and lines starting with # are ignored.
+pub fn parse_ranges(text: &str) -> ah::Result<Vec<(u64, u64)>> {
+    let mut ranges = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse_range(line) {
+            Ok(range) => ranges.push(range),
+            Err(e) => return Err(ah::format_err!("Line {} of the ranges file: {}", i + 1, e)),
+        }
+    }
+    if ranges.is_empty() {
+        return Err(ah::format_err!("The ranges file is empty."));
+    }
+    Ok(ranges)
+}
+
+/// Read the ranges file.
+pub fn read_ranges(path: &Path) -> ah::Result<Vec<(u64, u64)>> {
+    match fs::read_to_string(path) {
+        Ok(text) => parse_ranges(&text),
+        Err(e) => Err(ah::format_err!("Failed to read the ranges file {:?}: {}", path, e)),
+    }
+}
+
+/// Sort the ranges by offset and merge the overlapping and adjacent ones.
+pub fn merge_ranges(ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    let mut ranges = ranges;
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (offset, length) in ranges {
+        match merged.last_mut() {
+            Some(last) if offset <= last.0 + last.1 => {
+                last.1 = last.1.max(offset + length - last.0);
+            },
+            _ => merged.push((offset, length)),
+        }
+    }
+    merged
+}
+
+/// Align the ranges outwards to multiples of align and merge the overlapping ones.
+pub fn align_ranges(ranges: &[(u64, u64)], align: u64) -> Vec<(u64, u64)> {
+    merge_ranges(ranges.iter()
+        .map(|&(offset, length)| {
+            let begin = offset - offset % align;
+            (begin, offset + length - begin)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("1G:100M").unwrap(), (1024 * 1024 * 1024, 100 * 1024 * 1024));
+        assert_eq!(parse_range(" 0 : 4096 ").unwrap(), (0, 4096));
+        assert!(parse_range("1G").is_err());
+        assert!(parse_range("1G:0").is_err());
+        assert!(parse_range("x:1M").is_err());
+        assert!(parse_range("1M:").is_err());
+        assert!(parse_range("15E:2E").is_err());
+
+        let text = "# Bad regions\n\n4096:512\n  0:1k\n";
+        assert_eq!(parse_ranges(text).unwrap(), vec![(4096, 512), (0, 1024)]);
+        assert!(parse_ranges("# Nothing\n").is_err());
+        let e = parse_ranges("0:1k\nfoo\n").unwrap_err();
+        assert!(e.to_string().starts_with("Line 2 of the ranges file"));
+    }
+
+    #[test]
+    fn test_merge_ranges() {
+        assert_eq!(merge_ranges(vec![(5000, 1000), (0, 100), (5500, 1000), (100, 50), (9000, 1)]),
+                   vec![(0, 150), (5000, 1500), (9000, 1)]);
+        assert_eq!(merge_ranges(vec![(0, 1000), (10, 20)]), vec![(0, 1000)]);
+        assert_eq!(merge_ranges(vec![]), vec![]);
+
+        assert_eq!(align_ranges(&[(0, 100), (5000, 1000), (8200, 10)], 4096),
+                   vec![(0, 100), (4096, 1904), (8192, 18)]);
+        assert_eq!(align_ranges(&[(4000, 200), (4100, 10)], 4096), vec![(0, 4200)]);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,303 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+
+/// Translate a macOS buffered disk path (/dev/diskN) into
+/// the corresponding raw disk path (/dev/rdiskN).
+/// Returns None, if the path is not a buffered disk path.
+#[cfg(any(target_os="macos", test))]
+fn macos_raw_disk_path(path: &str) -> Option<String> {
+    let name = path.strip_prefix("/dev/disk")?;
+    if name.is_empty() || !name.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    Some(format!("/dev/rdisk{}", name))
+}
+
+#[cfg(target_os="macos")]
+fn os_preferred_device_path(path: &str) -> String {
+    macos_raw_disk_path(path).unwrap_or_else(|| path.to_string())
+}
+
+#[cfg(not(target_os="macos"))]
+fn os_preferred_device_path(path: &str) -> String {
+    path.to_string()
+}
+
+#[cfg(target_os="macos")]
+fn os_unmount_disk(path: &str) -> ah::Result<bool> {
+    use std::process::Command;
+
+    if !path.starts_with("/dev/disk") && !path.starts_with("/dev/rdisk") {
+        return Ok(false);
+    }
+
+    match Command::new("diskutil").arg("unmountDisk").arg(path).output() {
+        Ok(output) => {
+            if output.status.success() {
+                Ok(true)
+            } else {
+                Err(ah::format_err!("'diskutil unmountDisk {}' failed: {}",
+                                    path,
+                                    String::from_utf8_lossy(&output.stderr).trim()))
+            }
+        },
+        Err(e) => Err(ah::format_err!("Failed to run diskutil: {}", e)),
+    }
+}
+
+#[cfg(not(target_os="macos"))]
+fn os_unmount_disk(_path: &str) -> ah::Result<bool> {
+    Ok(false)
+}
+
+/// Get the device path that is preferred for accessing the device.
+/// On macOS this is the unbuffered raw disk /dev/rdiskN instead of /dev/diskN.
+pub fn preferred_device_path(path: &str) -> String {
+    os_preferred_device_path(path)
+}
+
+/// Unmount all volumes of the disk before writing to it, if required by the
+/// operating system. On macOS all volumes of a mounted disk are unmounted via diskutil.
+/// Returns true, if the disk has been unmounted.
+pub fn unmount_disk(path: &str) -> ah::Result<bool> {
+    os_unmount_disk(path)
+}
+
+/// Get the (device number, mount point) of all mounts in /proc/self/mountinfo.
+/// The device number is in the "major:minor" format.
+#[cfg(any(target_os="linux", test))]
+fn parse_mountinfo(mountinfo: &str) -> Vec<(String, String)> {
+    mountinfo.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace().skip(2);
+            let dev = fields.next()?;
+            let mount_point = fields.nth(1)?;
+            // Spaces and other special characters are escaped as octal numbers.
+            Some((dev.to_string(), mount_point.replace("\\040", " ")))
+        })
+        .collect()
+}
+
+/// Get the file names of all swap areas in /proc/swaps.
+#[cfg(any(target_os="linux", test))]
+fn parse_swaps(swaps: &str) -> Vec<String> {
+    swaps.lines()
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().next())
+        .map(|name| name.replace("\\040", " "))
+        .collect()
+}
+
+/// Mount points of filesystems that the running system boots from.
+const SYSTEM_MOUNT_POINTS: [&str; 4] = ["/", "/boot", "/boot/efi", "/efi"];
+
+/// The users of a block device.
+#[derive(Clone, Debug, Default)]
+pub struct DeviceUse {
+    /// Descriptions of the mounts, swap areas and holders of the device
+    /// and of its partitions.
+    pub users:      Vec<String>,
+    /// Descriptions of the system filesystems and swap areas
+    /// that are stored on the device, directly or via a holder.
+    pub system:     Vec<String>,
+}
+
+impl DeviceUse {
+    /// Check that the device may be written to.
+    /// A system disk is only written to with allow_system
+    /// and a device that is in use is only written to with force.
+    pub fn check(&self, path: &str, force: bool, allow_system: bool) -> ah::Result<()> {
+        if !self.system.is_empty() && !allow_system {
+            return Err(ah::format_err!("Refusing to write to {:?}, because it is a system disk:\n  {}\n\
+                                        Use --allow-system-disk to write to it anyway. \
+                                        This destroys the running system.",
+                                       path, self.system.join("\n  ")));
+        }
+        if !self.users.is_empty() && !force {
+            return Err(ah::format_err!("Refusing to write to {:?}, because it is in use:\n  {}\n\
+                                        Use --force to write to it anyway.",
+                                       path, self.users.join("\n  ")));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os="linux")]
+fn os_device_use(path: &str) -> DeviceUse {
+    use std::fs;
+    use std::path::Path;
+
+    let mut usage = DeviceUse::default();
+    let dev_path = match Path::new(path).canonicalize() {
+        Ok(dev_path) => dev_path,
+        Err(_) => return usage,
+    };
+    let name = match dev_path.strip_prefix("/dev") {
+        Ok(name) => name.to_string_lossy().replace('/', "!"),
+        Err(_) => return usage,
+    };
+    let sysfs = Path::new("/sys/class/block").join(&name);
+    if !sysfs.exists() {
+        // Not a block device.
+        return usage;
+    }
+
+    // The device itself and all of its partitions.
+    let mut devices = vec![name];
+    if let Ok(entries) = fs::read_dir(&sysfs) {
+        for entry in entries.flatten() {
+            if entry.path().join("partition").exists() {
+                devices.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+    }
+    devices.sort();
+    let direct = devices.len();
+
+    let mounts = parse_mountinfo(&fs::read_to_string("/proc/self/mountinfo").unwrap_or_default());
+    let swaps: Vec<_> = parse_swaps(&fs::read_to_string("/proc/swaps").unwrap_or_default())
+        .iter()
+        .filter_map(|swap| Path::new(swap).canonicalize().ok())
+        .collect();
+
+    // Walk the devices and, recursively, their holders (e.g. the LVM volume
+    // on top of a partition), because the system may be stored on a holder.
+    let mut index = 0;
+    while index < devices.len() {
+        let device = devices[index].clone();
+        let is_holder = index >= direct;
+        index += 1;
+        let dev_name = format!("/dev/{}", device.replace('!', "/"));
+        let sysfs = Path::new("/sys/class/block").join(&device);
+        if let Ok(number) = fs::read_to_string(sysfs.join("dev")) {
+            let number = number.trim();
+            for (_, mount_point) in mounts.iter().filter(|(dev, _)| dev == number) {
+                if !is_holder {
+                    usage.users.push(format!("{} is mounted on {}", dev_name, mount_point));
+                }
+                if SYSTEM_MOUNT_POINTS.contains(&mount_point.as_str()) {
+                    usage.system.push(format!("{} is the system filesystem {}", dev_name, mount_point));
+                }
+            }
+        }
+        if swaps.iter().any(|swap| swap == Path::new(&dev_name)) {
+            if !is_holder {
+                usage.users.push(format!("{} is used as swap space", dev_name));
+            }
+            usage.system.push(format!("{} is active swap space", dev_name));
+        }
+        if let Ok(holders) = fs::read_dir(sysfs.join("holders")) {
+            for holder in holders.flatten() {
+                let holder = holder.file_name().to_string_lossy().to_string();
+                if !is_holder {
+                    usage.users.push(format!("{} is held by {}", dev_name, holder));
+                }
+                if !devices.contains(&holder) {
+                    devices.push(holder);
+                }
+            }
+        }
+    }
+    usage
+}
+
+#[cfg(not(target_os="linux"))]
+fn os_device_use(_path: &str) -> DeviceUse {
+    DeviceUse::default()
+}
+
+/// Find the users of the device before writing to it.
+/// On Linux the device is in use, if the device or one of its partitions is mounted,
+/// used as swap space or held by another device (e.g. device-mapper, LVM or RAID).
+/// It is a system disk, if it stores the root, boot or EFI system filesystem
+/// or active swap space, directly or via a holder.
+pub fn device_use(path: &str) -> DeviceUse {
+    os_device_use(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_macos_raw_disk_path() {
+        assert_eq!(macos_raw_disk_path("/dev/disk2"), Some("/dev/rdisk2".to_string()));
+        assert_eq!(macos_raw_disk_path("/dev/disk2s1"), Some("/dev/rdisk2s1".to_string()));
+        assert_eq!(macos_raw_disk_path("/dev/rdisk2"), None);
+        assert_eq!(macos_raw_disk_path("/dev/disk"), None);
+        assert_eq!(macos_raw_disk_path("/dev/sdc"), None);
+    }
+
+    #[test]
+    fn test_parse_mountinfo() {
+        let mountinfo = "\
+22 1 8:2 / / rw,relatime shared:1 - ext4 /dev/sda2 rw
+23 22 0:21 / /proc rw,nosuid shared:12 - proc proc rw
+24 22 8:17 /data /mnt/my\\040disk rw shared:2 - vfat /dev/sdb1 rw
+";
+        assert_eq!(parse_mountinfo(mountinfo), vec![
+            ("8:2".to_string(), "/".to_string()),
+            ("0:21".to_string(), "/proc".to_string()),
+            ("8:17".to_string(), "/mnt/my disk".to_string()),
+        ]);
+        assert!(parse_mountinfo("").is_empty());
+    }
+
+    #[test]
+    fn test_parse_swaps() {
+        let swaps = "\
+Filename\t\t\t\tType\t\tSize\t\tUsed\t\tPriority
+/dev/sda3                               partition\t8388604\t\t0\t\t-2
+/swap\\040file                           file\t\t1048572\t\t0\t\t-3
+";
+        assert_eq!(parse_swaps(swaps), vec!["/dev/sda3".to_string(), "/swap file".to_string()]);
+        assert!(parse_swaps("").is_empty());
+    }
+
+    #[test]
+    fn test_device_use_check() {
+        let usage = DeviceUse::default();
+        assert!(usage.check("/dev/foo", false, false).is_ok());
+
+        let usage = DeviceUse {
+            users:  vec!["/dev/foo1 is mounted on /mnt".to_string()],
+            system: vec![],
+        };
+        let e = usage.check("/dev/foo", false, false).unwrap_err().to_string();
+        assert!(e.contains("in use:\n  /dev/foo1 is mounted on /mnt\n"));
+        assert!(e.contains("--force"));
+        assert!(usage.check("/dev/foo", true, false).is_ok());
+
+        let usage = DeviceUse {
+            users:  vec!["/dev/foo2 is held by dm-0".to_string()],
+            system: vec!["/dev/dm-0 is the system filesystem /".to_string()],
+        };
+        let e = usage.check("/dev/foo", true, false).unwrap_err().to_string();
+        assert!(e.contains("system disk:\n  /dev/dm-0 is the system filesystem /\n"));
+        assert!(e.contains("--allow-system-disk"));
+        assert!(usage.check("/dev/foo", false, true).is_err());
+        assert!(usage.check("/dev/foo", true, true).is_ok());
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
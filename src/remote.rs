@@ -0,0 +1,654 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+use crate::args::parse_args;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha2::Sha256;
+use crypto::util::fixed_time_eq;
+use disktest_core::{DtError, JsonSink};
+use rand::Rng;
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// TCP port of the agent, if the address does not contain a port.
+pub const DEFAULT_PORT: u16 = 9127;
+
+/// Timeout for the authentication and the run request of a controller.
+const AUTH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Maximum length of a message from a controller to the agent.
+const MAX_REQUEST_SIZE: usize = 64 * 1024;
+
+/// Interval for checking the abort flag and the state of the run.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The subcommands that can be run by a controller.
+const REMOTE_COMMANDS: [&str; 2] = ["write", "verify"];
+
+/// Option of the command line: The long name, the short name and whether it takes a value.
+type CmdOption = (&'static str, Option<char>, bool);
+
+/// The options that are accepted from a controller.
+/// Only options of the data path are accepted. Options that access files,
+/// run commands or open listeners on the agent are rejected.
+const REMOTE_OPTIONS: [CmdOption; 30] = [
+    ("--seek",          Some('s'),  true),
+    ("--bytes",         Some('b'),  true),
+    ("--end",           None,       true),
+    ("--range",         None,       true),
+    ("--algorithm",     Some('A'),  true),
+    ("--seed",          Some('S'),  true),
+    ("--kdf",           None,       true),
+    ("--raw-seed",      None,       false),
+    ("--pattern",       None,       true),
+    ("--seed-round",    None,       true),
+    ("--verify-zero",   None,       false),
+    ("--verify-value",  None,       true),
+    ("--threads",       Some('j'),  true),
+    ("--chunk-size",    None,       true),
+    ("--quiet",         Some('q'),  true),
+    ("--direct",        Some('D'),  false),
+    ("--mmap",          None,       false),
+    ("--max-errors",    None,       true),
+    ("--read-retries",  None,       true),
+    ("--max-rate",      None,       true),
+    ("--max-time",      None,       true),
+    ("--rounds",        Some('R'),  true),
+    ("--keep-going",    Some('k'),  false),
+    ("--verify",        Some('v'),  false),
+    ("--verify-behind", None,       true),
+    ("--verify-stride", None,       true),
+    ("--loop",          None,       false),
+    ("--sync-writes",   None,       false),
+    ("--sync-every",    None,       true),
+    ("--no-header",     None,       false),
+];
+
+/// The options that override the safety checks.
+/// They are only accepted from a controller, if the agent has been started with them.
+const OVERRIDE_OPTIONS: [CmdOption; 3] = [
+    ("--force",             None,       false),
+    ("--allow-system-disk", None,       false),
+    ("--yes",               Some('y'),  false),
+];
+
+/// Message of the remote protocol.
+/// Each message is sent as one line of JSON.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Message {
+    /// Agent to controller: Greeting with the authentication challenge.
+    Hello {
+        version:    String,
+        nonce:      String,
+    },
+    /// Controller to agent: Response to the challenge.
+    Auth {
+        mac:        String,
+    },
+    /// Agent to controller: The authentication succeeded.
+    Authenticated,
+    /// Controller to agent: Start a run with the disktest command line (without program name).
+    Run {
+        args:       Vec<String>,
+    },
+    /// Agent to controller: Line of the console output of the run.
+    Output {
+        line:       String,
+    },
+    /// Agent to controller: Event of the JSON progress stream of the run.
+    Progress {
+        event:      serde_json::Value,
+    },
+    /// Agent to controller: The run finished.
+    Result {
+        exit_status: i32,
+        report:     Option<serde_json::Value>,
+    },
+    /// Agent to controller: The request has been rejected.
+    Error {
+        message:    String,
+    },
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Calculate the response to the challenge nonce with the shared token.
+fn auth_mac(token: &[u8], nonce: &str) -> String {
+    let mut mac = Hmac::new(Sha256::new(), token);
+    mac.input(b"disktest-remote:");
+    mac.input(nonce.as_bytes());
+    hex(mac.result().code())
+}
+
+/// Read the shared secret token from a file.
+/// Trailing white space (e.g. the line break) is removed.
+pub fn read_token(path: &str) -> ah::Result<Vec<u8>> {
+    let mut token = match fs::read(path) {
+        Ok(x) => x,
+        Err(e) => return Err(ah::format_err!("Failed to read the token file {:?}: {}", path, e)),
+    };
+    while token.last().map(|b| b.is_ascii_whitespace()).unwrap_or(false) {
+        token.pop();
+    }
+    if token.is_empty() {
+        return Err(ah::format_err!("The token is empty."));
+    }
+    Ok(token)
+}
+
+/// Append the default port to the address, if it has none.
+pub fn agent_address(addr: &str) -> String {
+    let has_port = match addr.rsplit_once(':') {
+        // An IPv6 address without port is enclosed in brackets or contains several colons.
+        Some((host, port)) => !port.is_empty() &&
+                              port.chars().all(|c| c.is_ascii_digit()) &&
+                              (!host.contains(':') || host.ends_with(']')),
+        None => false,
+    };
+    if has_port {
+        addr.to_string()
+    } else {
+        format!("{}:{}", addr, DEFAULT_PORT)
+    }
+}
+
+fn send(stream: &mut impl Write, msg: &Message) -> ah::Result<()> {
+    let mut line = serde_json::to_vec(msg)?;
+    line.push(b'\n');
+    stream.write_all(&line)?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Receive one message.
+/// Returns None, if the connection has been closed.
+/// A read timeout is returned as io::Error, and the partial line is kept in buf.
+fn receive(reader: &mut impl BufRead,
+           buf: &mut Vec<u8>,
+           limit: usize) -> ah::Result<Option<Message>> {
+    let res = reader.by_ref().take((limit + 1 - buf.len()) as u64).read_until(b'\n', buf);
+    match res {
+        Ok(0) if buf.is_empty() => return Ok(None),
+        Ok(_) if buf.last() != Some(&b'\n') => {
+            if buf.len() > limit {
+                return Err(ah::format_err!("The message is too long."));
+            }
+            return Err(ah::format_err!("The connection has been closed unexpectedly."));
+        },
+        Ok(_) => (),
+        Err(e) => return Err(e.into()),
+    }
+    let msg = serde_json::from_slice(buf);
+    buf.clear();
+    match msg {
+        Ok(msg) => Ok(Some(msg)),
+        Err(e) => Err(ah::format_err!("Invalid message: {}", e)),
+    }
+}
+
+/// Check whether the error is a read timeout.
+fn is_timeout(e: &ah::Error) -> bool {
+    matches!(e.downcast_ref::<std::io::Error>().map(|e| e.kind()),
+             Some(ErrorKind::WouldBlock) | Some(ErrorKind::TimedOut))
+}
+
+/// Find the option of the command line, that a controller may ask for.
+fn find_option(f: impl Fn(&CmdOption) -> bool) -> Option<&'static CmdOption> {
+    REMOTE_OPTIONS.iter().chain(OVERRIDE_OPTIONS.iter()).find(|o| f(o))
+}
+
+/// Check whether a controller may use the option.
+/// overrides are the OVERRIDE_OPTIONS the agent has been started with.
+fn check_option(option: Option<&CmdOption>, name: &str, overrides: &[&str]) -> ah::Result<()> {
+    match option {
+        Some(o) if REMOTE_OPTIONS.contains(o) || overrides.contains(&o.0) => Ok(()),
+        Some(o) => Err(ah::format_err!("The option {} can only be used remotely, \
+                                       if the agent has been started with it.", o.0)),
+        None => Err(ah::format_err!("The option {} can't be used remotely.", name)),
+    }
+}
+
+/// Check the command line requested by a controller.
+fn check_remote_args(args: &[String], overrides: &[&str]) -> ah::Result<()> {
+    match args.first() {
+        Some(cmd) if REMOTE_COMMANDS.contains(&cmd.as_str()) => (),
+        _ => return Err(ah::format_err!("Only the subcommands {} can be run remotely.",
+                                        REMOTE_COMMANDS.join(" and "))),
+    }
+    // Every argument that starts with - is checked as an option,
+    // even if it is meant to be the value of the previous option.
+    for arg in &args[1..] {
+        if arg.starts_with("--") {
+            let name = arg.split('=').next().unwrap();
+            check_option(find_option(|o| o.0 == name), name, overrides)?;
+        } else if arg.starts_with('-') {
+            // Short options can be combined. The rest after an option with a value is the value.
+            for c in arg.chars().skip(1) {
+                let option = find_option(|o| o.1 == Some(c));
+                check_option(option, &format!("-{}", c), overrides)?;
+                if option.unwrap().2 {
+                    break;
+                }
+            }
+        }
+    }
+    let mut cmdline = vec!["disktest".to_string()];
+    cmdline.extend_from_slice(args);
+    parse_args(cmdline)?;
+    Ok(())
+}
+
+/// Authenticate the controller and receive its run request.
+fn accept_request(stream:    &mut TcpStream,
+                  reader:    &mut impl BufRead,
+                  token:     &[u8],
+                  overrides: &[&str]) -> ah::Result<Vec<String>> {
+    let nonce = hex(&OsRng.gen::<[u8; 32]>());
+    send(stream, &Message::Hello {
+        version:    env!("CARGO_PKG_VERSION").to_string(),
+        nonce:      nonce.clone(),
+    })?;
+    let mut buf = vec![];
+    match receive(reader, &mut buf, MAX_REQUEST_SIZE)? {
+        Some(Message::Auth { mac }) if fixed_time_eq(mac.as_bytes(),
+                                                     auth_mac(token, &nonce).as_bytes()) => (),
+        _ => return Err(ah::format_err!("Authentication failed.")),
+    }
+    send(stream, &Message::Authenticated)?;
+    let args = match receive(reader, &mut buf, MAX_REQUEST_SIZE)? {
+        Some(Message::Run { args }) => args,
+        _ => return Err(ah::format_err!("Expected a run request.")),
+    };
+    check_remote_args(&args, overrides)?;
+    Ok(args)
+}
+
+/// Ask the run to stop.
+#[cfg(unix)]
+fn terminate(child: &mut Child) {
+    unsafe {
+        libc::kill(child.id() as libc::pid_t, libc::SIGTERM);
+    }
+}
+
+#[cfg(not(unix))]
+fn terminate(child: &mut Child) {
+    let _ = child.kill();
+}
+
+/// Forward the lines of the child output to the controller.
+/// If json is true, then JSON objects are forwarded as progress events.
+/// Lines equal to hide are not forwarded.
+fn forward(output:  impl Read + Send + 'static,
+           stream:  Arc<Mutex<TcpStream>>,
+           json:    bool,
+           hide:    String) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        for line in BufReader::new(output).lines() {
+            let line = match line {
+                Ok(x) if x == hide => continue,
+                Ok(x) => x,
+                Err(_) => break,
+            };
+            let msg = match serde_json::from_str::<serde_json::Value>(&line) {
+                Ok(event) if json && event.is_object() => Message::Progress { event },
+                _ => Message::Output { line },
+            };
+            // A closed connection is detected by the agent.
+            let _ = send(&mut *stream.lock().unwrap(), &msg);
+        }
+    })
+}
+
+/// Run disktest with the requested arguments and forward its output to the controller.
+/// The run is stopped, if the controller disconnects or if the agent is aborted.
+fn run_remote_args(exe:     &Path,
+                   args:    &[String],
+                   stream:  &TcpStream,
+                   abort:   &Arc<AtomicBool>) -> ah::Result<Message> {
+    static RUN_COUNT: AtomicU64 = AtomicU64::new(0);
+    let report_path: PathBuf = std::env::temp_dir().join(
+        format!("disktest-remote-{}-{}.json",
+                std::process::id(),
+                RUN_COUNT.fetch_add(1, Ordering::Relaxed)));
+
+    let mut child = match Command::new(exe)
+            .args(args)
+            .arg("--progress-json").arg("stderr")
+            .arg("--report").arg(&report_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn() {
+        Ok(x) => x,
+        Err(e) => return Err(ah::format_err!("Failed to start {:?}: {}", exe, e)),
+    };
+    // The temporary report file is an internal detail of the agent.
+    let hide = format!("Wrote the report to {:?}.", report_path);
+    let writer = Arc::new(Mutex::new(stream.try_clone()?));
+    let outputs = [
+        forward(child.stdout.take().unwrap(), Arc::clone(&writer), false, hide.clone()),
+        forward(child.stderr.take().unwrap(), Arc::clone(&writer), true, hide),
+    ];
+
+    // The controller does not send anything during the run.
+    // End of stream means that it disconnected.
+    let disconnected = Arc::new(AtomicBool::new(false));
+    {
+        let mut stream = stream.try_clone()?;
+        let disconnected = Arc::clone(&disconnected);
+        thread::spawn(move || {
+            let _ = stream.read(&mut [0; 1]);
+            disconnected.store(true, Ordering::Relaxed);
+        });
+    }
+
+    let mut stopping = false;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if !stopping && (disconnected.load(Ordering::Relaxed) || abort.load(Ordering::Relaxed)) {
+            terminate(&mut child);
+            stopping = true;
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+    for output in outputs {
+        let _ = output.join();
+    }
+
+    let report = fs::read(&report_path).ok()
+        .and_then(|data| serde_json::from_slice(&data).ok());
+    let _ = fs::remove_file(&report_path);
+    Ok(Message::Result {
+        exit_status: status.code().unwrap_or(crate::EXIT_FAILURE),
+        report,
+    })
+}
+
+/// Handle one controller connection.
+fn handle_controller(mut stream:    TcpStream,
+                     exe:           &Path,
+                     token:         &[u8],
+                     overrides:     &[&str],
+                     abort:         &Arc<AtomicBool>,
+                     quiet:         u8) -> ah::Result<()> {
+    let peer = stream.peer_addr()?;
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(AUTH_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let args = match accept_request(&mut stream, &mut reader, token, overrides) {
+        Ok(x) => x,
+        Err(e) => {
+            let _ = send(&mut stream, &Message::Error { message: e.to_string() });
+            return Err(ah::format_err!("Rejected the controller {}: {}", peer, e));
+        },
+    };
+    stream.set_read_timeout(None)?;
+
+    if quiet < 2 {
+        println!("Starting the run of {}: disktest {}", peer, args.join(" "));
+    }
+    let result = match run_remote_args(exe, &args, &stream, abort) {
+        Ok(x) => x,
+        Err(e) => Message::Error { message: e.to_string() },
+    };
+    if quiet < 2 {
+        match &result {
+            Message::Result { exit_status, .. } => {
+                println!("The run of {} finished with exit status {}.", peer, exit_status);
+            },
+            Message::Error { message } => println!("The run of {} failed: {}", peer, message),
+            _ => (),
+        }
+    }
+    let _ = send(&mut stream, &result);
+    let _ = stream.shutdown(Shutdown::Both);
+    Ok(())
+}
+
+/// Run the agent that accepts runs from controllers on the address.
+/// Each connection runs in its own thread, until the agent is aborted.
+/// overrides are the OVERRIDE_OPTIONS the controllers may use.
+pub fn serve(addr:      &str,
+             token:     &[u8],
+             overrides: &[&'static str],
+             abort:     &Arc<AtomicBool>,
+             quiet:     u8) -> ah::Result<()> {
+    let addr = agent_address(addr);
+    let listener = match TcpListener::bind(&addr) {
+        Ok(x) => x,
+        Err(e) => return Err(ah::format_err!("Failed to listen on {:?}: {}", addr, e)),
+    };
+    let exe = std::env::current_exe()?;
+    // Poll for new connections, so that the agent can be aborted.
+    listener.set_nonblocking(true)?;
+    if quiet < 2 {
+        println!("Waiting for controllers on {} ...", listener.local_addr()?);
+    }
+    while !abort.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let (exe, token, abort) = (exe.clone(), token.to_vec(), Arc::clone(abort));
+                let overrides = overrides.to_vec();
+                thread::spawn(move || {
+                    if let Err(e) = handle_controller(stream, &exe, &token, &overrides,
+                                                      &abort, quiet) {
+                        eprintln!("WARNING: {}", e);
+                    }
+                });
+            },
+            Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+            Err(e) => eprintln!("WARNING: Failed to accept a connection: {}", e),
+        }
+    }
+    Err(DtError::Aborted.into())
+}
+
+/// The remote run finished with a failure.
+#[derive(Debug)]
+pub struct RemoteFailure {
+    pub agent:          String,
+    pub exit_status:    i32,
+}
+
+impl std::fmt::Display for RemoteFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "The run on {} failed with exit status {}.", self.agent, self.exit_status)
+    }
+}
+
+impl std::error::Error for RemoteFailure {}
+
+/// Start a run on the agent and print its output, until it finished.
+/// The progress events are written to json, if given.
+/// Returns the exit status and the report of the run, if any.
+pub fn run_remote(addr:     &str,
+                  token:    &[u8],
+                  args:     &[String],
+                  json:     Option<&JsonSink>,
+                  abort:    &Arc<AtomicBool>) -> ah::Result<(i32, Option<serde_json::Value>)> {
+    let addr = agent_address(addr);
+    let mut stream = match TcpStream::connect(&addr) {
+        Ok(x) => x,
+        Err(e) => return Err(ah::format_err!("Failed to connect to the agent {:?}: {}", addr, e)),
+    };
+    // Wake up periodically to check the abort flag.
+    // Closing the connection stops the run on the agent.
+    stream.set_read_timeout(Some(POLL_INTERVAL))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut buf = vec![];
+    loop {
+        let msg = match receive(&mut reader, &mut buf, usize::MAX - 1) {
+            Ok(Some(x)) => x,
+            Ok(None) => return Err(ah::format_err!("The agent {} closed the connection.", addr)),
+            Err(e) if is_timeout(&e) => {
+                if abort.load(Ordering::Relaxed) {
+                    return Err(DtError::Aborted.into());
+                }
+                continue;
+            },
+            Err(e) => return Err(e),
+        };
+        match msg {
+            Message::Hello { nonce, .. } => {
+                send(&mut stream, &Message::Auth { mac: auth_mac(token, &nonce) })?;
+            },
+            Message::Authenticated => {
+                send(&mut stream, &Message::Run { args: args.to_vec() })?;
+            },
+            Message::Output { line } => println!("{}", line),
+            Message::Progress { event } => {
+                if let Some(json) = json {
+                    let _ = writeln!(json.lock().unwrap(), "{}", event);
+                }
+            },
+            Message::Result { exit_status, report } => return Ok((exit_status, report)),
+            Message::Error { message } => {
+                return Err(ah::format_err!("The agent {} rejected the run: {}", addr, message));
+            },
+            _ => return Err(ah::format_err!("Unexpected message from the agent {}.", addr)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(a: &[&str]) -> Vec<String> {
+        a.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn test_message() {
+        let msg = Message::Run { args: args(&["write", "/dev/sdb"]) };
+        let mut line = vec![];
+        send(&mut line, &msg).unwrap();
+        assert_eq!(line, b"{\"run\":{\"args\":[\"write\",\"/dev/sdb\"]}}\n");
+        let mut buf = vec![];
+        let mut reader = &line[..];
+        assert_eq!(receive(&mut reader, &mut buf, 1024).unwrap(), Some(msg));
+        assert_eq!(receive(&mut reader, &mut buf, 1024).unwrap(), None);
+        assert!(receive(&mut &line[..], &mut buf, 10).is_err());
+        assert!(receive(&mut &b"{\"foo\":1}\n"[..], &mut buf, 1024).is_err());
+        buf.clear();
+        assert!(receive(&mut &b"{\"run\""[..], &mut buf, 1024).is_err());
+    }
+
+    #[test]
+    fn test_auth_mac() {
+        let mac = auth_mac(b"secret", "1234");
+        assert_eq!(mac.len(), 64);
+        assert_eq!(mac, auth_mac(b"secret", "1234"));
+        assert_ne!(mac, auth_mac(b"secret", "1235"));
+        assert_ne!(mac, auth_mac(b"secreT", "1234"));
+    }
+
+    #[test]
+    fn test_read_token() {
+        let tdir = tempfile::tempdir().unwrap();
+        let path = tdir.path().join("token");
+        fs::write(&path, b"secret \n").unwrap();
+        assert_eq!(read_token(path.to_str().unwrap()).unwrap(), b"secret");
+        fs::write(&path, b"\n").unwrap();
+        assert!(read_token(path.to_str().unwrap()).is_err());
+        assert!(read_token(tdir.path().join("none").to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_agent_address() {
+        assert_eq!(agent_address("station1"), format!("station1:{}", DEFAULT_PORT));
+        assert_eq!(agent_address("station1:1234"), "station1:1234");
+        assert_eq!(agent_address("10.0.0.1:1234"), "10.0.0.1:1234");
+        assert_eq!(agent_address("[::1]:1234"), "[::1]:1234");
+        assert_eq!(agent_address("[::1]"), format!("[::1]:{}", DEFAULT_PORT));
+        assert_eq!(agent_address("fe80::1"), format!("fe80::1:{}", DEFAULT_PORT));
+    }
+
+    #[test]
+    fn test_check_remote_args() {
+        assert!(check_remote_args(&args(&["write", "--verify", "/dev/sdb"]), &[]).is_ok());
+        assert!(check_remote_args(&args(&["verify", "-Sx", "/dev/sdb"]), &[]).is_ok());
+        assert!(check_remote_args(&args(&["write", "-vkSx", "-j", "4", "--seek=1M", "-q2",
+                                          "--chunk-size", "1M", "/dev/sdb"]), &[]).is_ok());
+        assert!(check_remote_args(&args(&["wipe", "/dev/sdb"]), &[]).is_err());
+        assert!(check_remote_args(&args(&[]), &[]).is_err());
+        assert!(check_remote_args(&args(&["write", "--on-success", "reboot", "/dev/sdb"]), &[]).is_err());
+        assert!(check_remote_args(&args(&["write", "--on-failure=reboot", "/dev/sdb"]), &[]).is_err());
+        assert!(check_remote_args(&args(&["write", "--foo", "/dev/sdb"]), &[]).is_err());
+        for opt in &["--log", "--meta", "--bad-blocks", "--throughput-log", "--report-junit",
+                     "--resume", "--devices-from", "--metrics-listen", "--seed-file"] {
+            assert!(check_remote_args(&args(&["write", opt, "/tmp/x", "/dev/sdb"]), &[]).is_err());
+            let opt = format!("{}=/tmp/x", opt);
+            assert!(check_remote_args(&args(&["write", &opt, "/dev/sdb"]), &[]).is_err());
+        }
+        assert!(check_remote_args(&args(&["write", "--seed", "--log", "/dev/sdb"]), &[]).is_err());
+        assert!(check_remote_args(&args(&["write", "--", "/dev/sdb"]), &[]).is_err());
+
+        let err = check_remote_args(&args(&["write", "--yes", "/dev/sdb"]), &[]).unwrap_err();
+        assert!(err.to_string().contains("started with it"));
+        assert!(check_remote_args(&args(&["write", "-vy", "/dev/sdb"]), &[]).is_err());
+        assert!(check_remote_args(&args(&["write", "--force", "/dev/sdb"]), &["--yes"]).is_err());
+        assert!(check_remote_args(&args(&["write", "--yes", "/dev/sdb"]), &["--yes"]).is_ok());
+        assert!(check_remote_args(&args(&["write", "-vy", "/dev/sdb"]), &["--yes"]).is_ok());
+        assert!(check_remote_args(&args(&["write", "--force", "--allow-system-disk", "/dev/sdb"]),
+                                  &["--force", "--allow-system-disk"]).is_ok());
+    }
+
+    #[test]
+    fn test_rejected() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let abort = Arc::new(AtomicBool::new(false));
+        let agent = thread::spawn(move || {
+            for _ in 0..2 {
+                let stream = listener.accept().unwrap().0;
+                assert!(handle_controller(stream, Path::new("/nonexistent"), b"secret", &[],
+                                          &Arc::new(AtomicBool::new(false)), 2).is_err());
+            }
+        });
+        let err = run_remote(&addr, b"wrong", &args(&["write", "/dev/sdb"]), None, &abort)
+            .unwrap_err();
+        assert!(err.to_string().contains("Authentication failed"));
+        let err = run_remote(&addr, b"secret", &args(&["wipe", "/dev/sdb"]), None, &abort)
+            .unwrap_err();
+        assert!(err.to_string().contains("Only the subcommands"));
+        agent.join().unwrap();
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,534 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+use crate::args::Args;
+use crate::kmsg::KernelMessage;
+use crate::resume::Phase;
+use crate::smart::SmartChange;
+use crate::stats::RunStats;
+use crate::thermal::Throttling;
+use disktest_core::{Disktest, DtErrorKind, DtErrorRecord, DtLatency, DtZones, LATENCY_BUCKETS,
+                    seed_fingerprint};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Parameters of the run.
+#[derive(Serialize)]
+pub struct ReportParameters {
+    pub write:      bool,
+    pub verify:     bool,
+    pub algorithm:  String,
+    pub kdf:        String,
+    pub threads:    usize,
+    pub chunk_size: usize,
+    pub seek:       u64,
+    /// None, if the whole device is processed.
+    pub max_bytes:  Option<u64>,
+    /// The processed ranges of --range, instead of seek and max_bytes.
+    pub ranges:     Vec<ReportRange>,
+    pub rounds:     u64,
+    pub direct:     bool,
+    pub sync_writes: bool,
+    pub user_seed:  bool,
+    /// The generated seed. A seed given by the user is not reported.
+    pub seed:       Option<String>,
+}
+
+/// One bucket of a latency histogram.
+#[derive(Serialize)]
+pub struct ReportLatencyBucket {
+    /// Exclusive upper bound of the bucket, in seconds.
+    /// None for the last bucket, which is unbounded.
+    pub below:      Option<f64>,
+    pub count:      u64,
+}
+
+/// Latencies of the read or write calls of one phase.
+/// All latencies are in seconds.
+#[derive(Serialize)]
+pub struct ReportLatency {
+    pub count:      u64,
+    pub p50:        f64,
+    pub p95:        f64,
+    pub p99_9:      f64,
+    pub max:        f64,
+    /// The used buckets of the histogram.
+    pub histogram:  Vec<ReportLatencyBucket>,
+}
+
+impl ReportLatency {
+    fn new(latency: &DtLatency) -> ReportLatency {
+        ReportLatency {
+            count:      latency.count(),
+            p50:        latency.percentile(50.0).as_secs_f64(),
+            p95:        latency.percentile(95.0).as_secs_f64(),
+            p99_9:      latency.percentile(99.9).as_secs_f64(),
+            max:        latency.max().as_secs_f64(),
+            histogram:  (0..LATENCY_BUCKETS)
+                .filter(|&i| latency.buckets()[i] > 0)
+                .map(|i| ReportLatencyBucket {
+                    below:  DtLatency::bucket_limit(i).map(|l| l.as_secs_f64()),
+                    count:  latency.buckets()[i],
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Throughput of one zone of a phase.
+#[derive(Serialize)]
+pub struct ReportZone {
+    /// Absolute byte offset of the zone on the device.
+    pub offset:     u64,
+    /// Length of the zone, in bytes.
+    pub length:     u64,
+    /// Number of bytes processed in the zone.
+    pub bytes:      u64,
+    /// Duration of the read or write calls in the zone, in seconds.
+    pub duration:   f64,
+    /// Average throughput, in bytes per second.
+    pub rate:       u64,
+}
+
+/// Result of the ATA security erase or the NVMe erase.
+#[derive(Clone, Serialize)]
+pub struct ReportErase {
+    /// The ATA or NVMe command (e.g. SECURITY ERASE UNIT or Sanitize (block erase)).
+    pub method:     String,
+    /// Duration of the erase, in seconds.
+    pub duration:   f64,
+    pub success:    bool,
+    /// Error message, if the erase failed.
+    pub message:    Option<String>,
+}
+
+/// Result of one write or verify phase.
+#[derive(Serialize)]
+pub struct ReportPhase {
+    pub round:      u64,
+    pub phase:      String,
+    /// Absolute byte offset where the phase started.
+    pub offset:     u64,
+    /// Number of bytes processed by the phase.
+    pub bytes:      u64,
+    /// Duration of the phase, in seconds.
+    pub duration:   f64,
+    /// Average throughput, in bytes per second.
+    pub rate:       f64,
+    pub success:    bool,
+    /// Latencies of the phase, if --latency is active.
+    pub latency:    Option<ReportLatency>,
+    /// Throughput per zone, if --zones is active.
+    pub zones:      Option<Vec<ReportZone>>,
+}
+
+/// The seed of one round. Every round after the first one writes with its own sub-seed.
+#[derive(Serialize)]
+pub struct ReportRoundSeed {
+    pub round:      u64,
+    /// The round whose sub-seed is used. See --seed-round.
+    pub seed_round: u64,
+    pub seed_fingerprint: String,
+}
+
+/// A bad region that was found during the run.
+#[derive(Serialize)]
+pub struct ReportError {
+    pub round:      u64,
+    pub phase:      String,
+    pub kind:       String,
+    /// Absolute byte offset of the bad region on the device.
+    pub offset:     u64,
+    /// Length of the bad region, in bytes.
+    pub length:     u64,
+    pub message:    String,
+}
+
+/// A byte range on the device.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ReportRange {
+    /// Absolute byte offset of the range on the device.
+    pub offset:     u64,
+    /// Length of the range, in bytes.
+    pub length:     u64,
+}
+
+/// All bad regions of one kind of error.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ReportErrorClass {
+    pub kind:       String,
+    /// Number of bad regions.
+    pub count:      usize,
+    /// Total length of the bad regions, in bytes.
+    pub length:     u64,
+    pub ranges:     Vec<ReportRange>,
+}
+
+/// Group the bad regions by the kind of error.
+/// Kinds without bad regions are left out.
+pub fn classify_errors(errors: &[DtErrorRecord]) -> Vec<ReportErrorClass> {
+    DtErrorKind::ALL.iter()
+        .map(|&kind| {
+            let ranges: Vec<ReportRange> = errors.iter()
+                .filter(|e| e.kind == kind)
+                .map(|e| ReportRange { offset: e.offset, length: e.length })
+                .collect();
+            ReportErrorClass {
+                kind:   kind.to_string(),
+                count:  ranges.len(),
+                length: ranges.iter().map(|r| r.length).sum(),
+                ranges,
+            }
+        })
+        .filter(|class| class.count > 0)
+        .collect()
+}
+
+/// Structured end-of-run report.
+#[derive(Serialize)]
+pub struct Report {
+    pub version:        String,
+    pub device:         String,
+    pub parameters:     ReportParameters,
+    /// The seeds of all rounds.
+    pub round_seeds:    Vec<ReportRoundSeed>,
+    pub phases:         Vec<ReportPhase>,
+    pub errors:         Vec<ReportError>,
+    /// The bad regions of the whole run grouped by the kind of error.
+    pub error_classes:  Vec<ReportErrorClass>,
+    /// Kernel log messages about the device, if --kernel-log is active.
+    pub kernel_messages: Vec<KernelMessage>,
+    /// Phases with periodic throughput dips, which indicate thermal throttling.
+    pub throttling:     Vec<Throttling>,
+    /// The changes of the key S.M.A.R.T. attributes during the run, if --smart is active.
+    pub smart:          Vec<SmartChange>,
+    /// The erase before the verification. See secure-erase and nvme-erase.
+    pub erase:          Option<ReportErase>,
+    pub bytes_written:  u64,
+    pub bytes_verified: u64,
+    /// The statistics of the final summary.
+    pub statistics:     Option<RunStats>,
+    /// Duration of the whole run, in seconds.
+    pub duration:       f64,
+    pub success:        bool,
+    /// Error message, if the run failed.
+    pub message:        Option<String>,
+    pub exit_status:    i32,
+    #[serde(skip)]
+    begin:              Instant,
+    /// The bad regions of all phases.
+    #[serde(skip)]
+    records:            Vec<DtErrorRecord>,
+}
+
+impl Report {
+    /// Create a new report for a run with the given arguments.
+    pub fn new(args: &Args) -> Report {
+        Report {
+            version:        env!("CARGO_PKG_VERSION").to_string(),
+            device:         args.device.clone(),
+            parameters:     ReportParameters {
+                write:      args.write,
+                verify:     args.verify,
+                algorithm:  args.algorithm.to_string(),
+                kdf:        args.kdf.to_string(),
+                threads:    args.threads,
+                chunk_size: args.chunk_size.unwrap_or_else(|| args.algorithm.default_chunk_size()),
+                seek:       args.seek,
+                max_bytes:  if args.max_bytes == Disktest::UNLIMITED {
+                                None
+                            } else {
+                                Some(args.max_bytes)
+                            },
+                ranges:     args.ranges.iter()
+                                .map(|&(offset, length)| ReportRange { offset, length })
+                                .collect(),
+                rounds:     args.rounds,
+                direct:     args.direct,
+                sync_writes: args.sync_writes,
+                user_seed:  args.user_seed,
+                seed:       if args.user_seed {
+                                None
+                            } else {
+                                Some(String::from_utf8_lossy(&args.seed).to_string())
+                            },
+            },
+            round_seeds:    vec![],
+            phases:         vec![],
+            errors:         vec![],
+            error_classes:  vec![],
+            kernel_messages: vec![],
+            throttling:     vec![],
+            smart:          vec![],
+            erase:          None,
+            bytes_written:  0,
+            bytes_verified: 0,
+            statistics:     None,
+            duration:       0.0,
+            success:        false,
+            message:        None,
+            exit_status:    0,
+            begin:          Instant::now(),
+            records:        vec![],
+        }
+    }
+
+    /// Add the seed of a round. stream_seed is the seed as passed to the Disktest core.
+    pub fn add_round_seed(&mut self, round: u64, seed_round: u64, stream_seed: &[u8]) {
+        self.round_seeds.push(ReportRoundSeed {
+            round,
+            seed_round,
+            seed_fingerprint: seed_fingerprint(stream_seed),
+        });
+    }
+
+    /// Add the result of a finished write or verify phase.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_phase(&mut self,
+                     round:     u64,
+                     phase:     Phase,
+                     offset:    u64,
+                     bytes:     Option<u64>,
+                     duration:  Duration,
+                     errors:    &[DtErrorRecord],
+                     latency:   Option<&DtLatency>,
+                     zones:     Option<&DtZones>) {
+        let secs = duration.as_secs_f64();
+        let count = bytes.unwrap_or(0);
+        match phase {
+            Phase::Write => self.bytes_written += count,
+            Phase::Verify => self.bytes_verified += count,
+        }
+        self.phases.push(ReportPhase {
+            round,
+            phase:      phase.to_string(),
+            offset,
+            bytes:      count,
+            duration:   secs,
+            rate:       if secs > 0.0 { count as f64 / secs } else { 0.0 },
+            success:    bytes.is_some(),
+            latency:    latency.map(ReportLatency::new),
+            zones:      zones.map(|zones| zones.zones().iter().map(|z| ReportZone {
+                offset:     z.offset,
+                length:     z.length,
+                bytes:      z.bytes,
+                duration:   z.time.as_secs_f64(),
+                rate:       z.rate(),
+            }).collect()),
+        });
+        for error in errors {
+            self.errors.push(ReportError {
+                round,
+                phase:      phase.to_string(),
+                kind:       error.kind.to_string(),
+                offset:     error.offset,
+                length:     error.length,
+                message:    error.message.clone(),
+            });
+        }
+        self.records.extend_from_slice(errors);
+    }
+
+    /// Finish the report with the final result of the run.
+    pub fn finish(&mut self, result: &ah::Result<()>) {
+        self.duration = self.begin.elapsed().as_secs_f64();
+        self.error_classes = classify_errors(&self.records);
+        self.success = result.is_ok();
+        self.message = result.as_ref().err().map(|e| e.to_string());
+        self.exit_status = crate::exit_status(result);
+    }
+
+    /// Serialize the report to JSON.
+    pub fn to_json(&self) -> ah::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Write the report as JSON to a file.
+    pub fn write(&self, path: &Path) -> ah::Result<()> {
+        let mut json = self.to_json()?;
+        json.push('\n');
+        if let Err(e) = fs::write(path, json) {
+            return Err(ah::format_err!("Failed to write report file {:?}: {}", path, e));
+        }
+        Ok(())
+    }
+}
+
+/// Report of a run over a list of devices. See --devices-from.
+#[derive(Serialize)]
+pub struct DeviceListReport {
+    pub version:        String,
+    /// The report of every device that has been tested.
+    pub devices:        Vec<Report>,
+    /// Duration of the whole run, in seconds.
+    pub duration:       f64,
+    pub success:        bool,
+    pub exit_status:    i32,
+}
+
+impl DeviceListReport {
+    /// Create the report from the reports of the devices.
+    pub fn new(devices: Vec<Report>, duration: Duration, result: &ah::Result<()>) -> DeviceListReport {
+        DeviceListReport {
+            version:        env!("CARGO_PKG_VERSION").to_string(),
+            devices,
+            duration:       duration.as_secs_f64(),
+            success:        result.is_ok(),
+            exit_status:    crate::exit_status(result),
+        }
+    }
+
+    /// Serialize the report to JSON.
+    pub fn to_json(&self) -> ah::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Write the report as JSON to a file.
+    pub fn write(&self, path: &Path) -> ah::Result<()> {
+        let mut json = self.to_json()?;
+        json.push('\n');
+        if let Err(e) = fs::write(path, json) {
+            return Err(ah::format_err!("Failed to write report file {:?}: {}", path, e));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::parse_args;
+    use disktest_core::{DtError, DtErrorKind};
+
+    #[test]
+    fn test_report() {
+        let args = parse_args(vec!["disktest", "write", "-v", "-Sx", "-ACRC", "/dev/foobar"]).unwrap();
+        let mut report = Report::new(&args);
+        let mut latency = DtLatency::new();
+        latency.record(Duration::from_micros(100));
+        latency.record(Duration::from_micros(3000));
+        report.add_phase(1, Phase::Write, 0, Some(1000), Duration::from_secs(2), &[], Some(&latency), None);
+        let errors = vec![DtErrorRecord {
+            kind:       DtErrorKind::Read,
+            offset:     42,
+            length:     512,
+            message:    "foo".to_string(),
+        }];
+        let mut zones = DtZones::new(0, 1000, 2);
+        zones.record(0, 1000, Duration::from_secs(2));
+        report.add_phase(1, Phase::Verify, 0, None, Duration::from_secs(1), &errors, None, Some(&zones));
+        report.finish(&Err(ah::format_err!("foo")));
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(json["device"], "/dev/foobar");
+        assert_eq!(json["parameters"]["algorithm"], "CRC");
+        assert_eq!(json["parameters"]["kdf"], "pbkdf2:50000");
+        assert_eq!(json["parameters"]["chunk_size"], 3 * 1024 * 1024);
+        assert_eq!(json["parameters"]["max_bytes"], serde_json::Value::Null);
+        assert_eq!(json["parameters"]["seed"], serde_json::Value::Null);
+        assert_eq!(json["parameters"]["ranges"], serde_json::json!([]));
+        assert_eq!(json["phases"][0]["phase"], "write");
+        assert_eq!(json["phases"][0]["bytes"], 1000);
+        assert_eq!(json["phases"][0]["rate"], 500.0);
+        assert_eq!(json["phases"][0]["latency"]["count"], 2);
+        assert_eq!(json["phases"][0]["latency"]["p50"], 0.000128);
+        assert_eq!(json["phases"][0]["latency"]["max"], 0.003);
+        assert_eq!(json["phases"][0]["latency"]["histogram"][1]["below"], 0.004096);
+        assert_eq!(json["phases"][0]["latency"]["histogram"][1]["count"], 1);
+        assert_eq!(json["phases"][1]["success"], false);
+        assert_eq!(json["phases"][1]["latency"], serde_json::Value::Null);
+        assert_eq!(json["phases"][0]["zones"], serde_json::Value::Null);
+        assert_eq!(json["phases"][1]["zones"][1]["offset"], 500);
+        assert_eq!(json["phases"][1]["zones"][1]["duration"], 1.0);
+        assert_eq!(json["phases"][1]["zones"][1]["rate"], 500);
+        assert_eq!(json["errors"][0]["kind"], "read error");
+        assert_eq!(json["errors"][0]["offset"], 42);
+        assert_eq!(json["errors"][0]["length"], 512);
+        assert_eq!(json["errors"][0]["phase"], "verify");
+        assert_eq!(json["error_classes"], serde_json::json!([{
+            "kind": "read error", "count": 1, "length": 512,
+            "ranges": [{"offset": 42, "length": 512}],
+        }]));
+        assert_eq!(json["kernel_messages"], serde_json::json!([]));
+        assert_eq!(json["throttling"], serde_json::json!([]));
+        assert_eq!(json["smart"], serde_json::json!([]));
+        assert_eq!(json["bytes_written"], 1000);
+        assert_eq!(json["bytes_verified"], 0);
+        assert_eq!(json["success"], false);
+        assert_eq!(json["message"], "foo");
+        assert_eq!(json["exit_status"], 1);
+
+        report.finish(&Err(DtError::BadRegions { count: 1, length: 512 }.into()));
+        assert_eq!(report.exit_status, 2);
+        report.finish(&Err(DtError::TimeLimit.into()));
+        assert_eq!(report.exit_status, 4);
+        report.finish(&Ok(()));
+        assert_eq!(report.exit_status, 0);
+    }
+
+    #[test]
+    fn test_classify_errors() {
+        let record = |kind, offset, length| {
+            DtErrorRecord { kind, offset, length, message: "".to_string() }
+        };
+        let errors = vec![record(DtErrorKind::Read, 0, 512),
+                          record(DtErrorKind::Mismatch, 4096, 1024),
+                          record(DtErrorKind::Read, 8192, 512),
+                          record(DtErrorKind::ShortRead, 10000, 100)];
+        let classes = classify_errors(&errors);
+        let summary: Vec<(&str, usize, u64)> = classes.iter()
+            .map(|c| (c.kind.as_str(), c.count, c.length)).collect();
+        assert_eq!(summary, vec![("data mismatch", 1, 1024),
+                                 ("read error", 2, 1024),
+                                 ("short read", 1, 100)]);
+        assert_eq!(classes[1].ranges, vec![ReportRange { offset: 0, length: 512 },
+                                           ReportRange { offset: 8192, length: 512 }]);
+        assert!(classify_errors(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_device_list_report() {
+        let mut devices = vec![];
+        for device in &["/dev/sdb", "/dev/sdc"] {
+            let args = parse_args(vec!["disktest", "write", "-Sx", device]).unwrap();
+            let mut report = Report::new(&args);
+            report.finish(&Ok(()));
+            devices.push(report);
+        }
+        let report = DeviceListReport::new(devices, Duration::from_secs(3),
+                                           &Err(DtError::TimeLimit.into()));
+        let tdir = tempfile::tempdir().unwrap();
+        let path = tdir.path().join("report.json");
+        report.write(&path).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(json["devices"][0]["device"], "/dev/sdb");
+        assert_eq!(json["devices"][1]["device"], "/dev/sdc");
+        assert_eq!(json["devices"][1]["success"], true);
+        assert_eq!(json["duration"], 3.0);
+        assert_eq!(json["success"], false);
+        assert_eq!(json["exit_status"], 4);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
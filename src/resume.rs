@@ -0,0 +1,407 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+use crate::args::Args;
+use disktest_core::{DtKdf, DtStreamType};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Interval for storing the state file.
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The phase of a round that has been interrupted.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Phase {
+    Write,
+    Verify,
+}
+
+impl std::fmt::Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Phase::Write => write!(f, "write"),
+            Phase::Verify => write!(f, "verify"),
+        }
+    }
+}
+
+/// Persistent state of a run that can be resumed later.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResumeState {
+    pub device:     String,
+    pub write:      bool,
+    pub verify:     bool,
+    pub algorithm:  DtStreamType,
+    pub kdf:        DtKdf,
+    pub threads:    usize,
+    pub seed:       Vec<u8>,
+    pub seek:       u64,
+    pub max_bytes:  u64,
+    pub round:      u64,
+    pub phase:      Phase,
+    pub position:   u64,
+    pub written:    Option<u64>,
+}
+
+fn hex_encode(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hex_decode(s: &str) -> ah::Result<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return Err(ah::format_err!("Invalid hex string length."));
+    }
+    (0..s.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i+2], 16)
+             .map_err(|e| ah::format_err!("Invalid hex string: {}", e)))
+        .collect()
+}
+
+impl ResumeState {
+    /// Create a new state from the command line arguments.
+    pub fn new(args: &Args) -> ResumeState {
+        ResumeState {
+            device:     args.device.clone(),
+            write:      args.write,
+            verify:     args.verify,
+            algorithm:  args.algorithm,
+            kdf:        args.kdf,
+            threads:    args.threads,
+            seed:       args.seed.clone(),
+            seek:       args.seek,
+            max_bytes:  args.max_bytes,
+            round:      1,
+            phase:      if args.write { Phase::Write } else { Phase::Verify },
+            position:   args.seek,
+            written:    None,
+        }
+    }
+
+    /// Check whether the command line arguments match the stored state.
+    pub fn check_args(&self, args: &Args) -> ah::Result<()> {
+        let mismatch = |name: &str| {
+            Err(ah::format_err!("The resume state does not match the \
+                                command line: Different {}.", name))
+        };
+        if self.device != args.device {
+            return mismatch("device");
+        }
+        if self.write != args.write || self.verify != args.verify {
+            return mismatch("write/verify mode");
+        }
+        if self.algorithm != args.algorithm {
+            return mismatch("--algorithm");
+        }
+        if self.kdf != args.kdf {
+            return mismatch("--kdf");
+        }
+        if args.user_seed && self.seed != args.seed {
+            return mismatch("--seed");
+        }
+        if self.seek != args.seek {
+            return mismatch("--seek");
+        }
+        if self.max_bytes != args.max_bytes {
+            return mismatch("--bytes");
+        }
+        Ok(())
+    }
+
+    /// Serialize the state to a string.
+    fn to_text(&self) -> String {
+        let mut text = String::new();
+        text.push_str("# disktest resume state\n");
+        text.push_str(&format!("device={}\n", self.device));
+        text.push_str(&format!("write={}\n", self.write));
+        text.push_str(&format!("verify={}\n", self.verify));
+        text.push_str(&format!("algorithm={}\n", self.algorithm));
+        text.push_str(&format!("kdf={}\n", self.kdf));
+        text.push_str(&format!("threads={}\n", self.threads));
+        text.push_str(&format!("seed={}\n", hex_encode(&self.seed)));
+        text.push_str(&format!("seek={}\n", self.seek));
+        text.push_str(&format!("max_bytes={}\n", self.max_bytes));
+        text.push_str(&format!("round={}\n", self.round));
+        text.push_str(&format!("phase={}\n", self.phase));
+        text.push_str(&format!("position={}\n", self.position));
+        if let Some(written) = self.written {
+            text.push_str(&format!("written={}\n", written));
+        }
+        text
+    }
+
+    /// Deserialize the state from a string.
+    fn from_text(text: &str) -> ah::Result<ResumeState> {
+        fn parse<T: std::str::FromStr>(key: &str, value: &str) -> ah::Result<T> {
+            value.parse().map_err(|_| ah::format_err!("Invalid value for '{}'.", key))
+        }
+
+        let mut device = None;
+        let mut write = None;
+        let mut verify = None;
+        let mut algorithm = None;
+        let mut kdf = None;
+        let mut threads = None;
+        let mut seed = None;
+        let mut seek = None;
+        let mut max_bytes = None;
+        let mut round = None;
+        let mut phase = None;
+        let mut position = None;
+        let mut written = None;
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = match line.find('=') {
+                Some(i) => (&line[..i], &line[i+1..]),
+                None => return Err(ah::format_err!("Invalid line: {}", line)),
+            };
+            match key {
+                "device" => device = Some(value.to_string()),
+                "write" => write = Some(parse(key, value)?),
+                "verify" => verify = Some(parse(key, value)?),
+                "algorithm" => algorithm = Some(parse(key, value)?),
+                "kdf" => kdf = Some(parse(key, value)?),
+                "threads" => threads = Some(parse(key, value)?),
+                "seed" => seed = Some(hex_decode(value)?),
+                "seek" => seek = Some(parse(key, value)?),
+                "max_bytes" => max_bytes = Some(parse(key, value)?),
+                "round" => round = Some(parse(key, value)?),
+                "phase" => phase = Some(match value {
+                    "write" => Phase::Write,
+                    "verify" => Phase::Verify,
+                    _ => return Err(ah::format_err!("Invalid value for 'phase'.")),
+                }),
+                "position" => position = Some(parse(key, value)?),
+                "written" => written = Some(parse(key, value)?),
+                _ => return Err(ah::format_err!("Unknown key: {}", key)),
+            }
+        }
+
+        let missing = |key: &str| ah::format_err!("Missing key '{}'.", key);
+        Ok(ResumeState {
+            device:     device.ok_or_else(|| missing("device"))?,
+            write:      write.ok_or_else(|| missing("write"))?,
+            verify:     verify.ok_or_else(|| missing("verify"))?,
+            algorithm:  algorithm.ok_or_else(|| missing("algorithm"))?,
+            // State files of older versions don't have a KDF.
+            kdf:        kdf.unwrap_or_default(),
+            threads:    threads.ok_or_else(|| missing("threads"))?,
+            seed:       seed.ok_or_else(|| missing("seed"))?,
+            seek:       seek.ok_or_else(|| missing("seek"))?,
+            max_bytes:  max_bytes.ok_or_else(|| missing("max_bytes"))?,
+            round:      round.ok_or_else(|| missing("round"))?,
+            phase:      phase.ok_or_else(|| missing("phase"))?,
+            position:   position.ok_or_else(|| missing("position"))?,
+            written,
+        })
+    }
+
+    /// Load the state from a file.
+    /// Returns None, if the file does not exist.
+    pub fn load(path: &Path) -> ah::Result<Option<ResumeState>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                match ResumeState::from_text(&text) {
+                    Ok(state) => Ok(Some(state)),
+                    Err(e) => Err(ah::format_err!("Invalid resume state file {:?}: {}", path, e)),
+                }
+            },
+            Err(e) => Err(ah::format_err!("Failed to read resume state file {:?}: {}", path, e)),
+        }
+    }
+
+    /// Store the state to a file.
+    /// The file is replaced atomically.
+    pub fn store(&self, path: &Path) -> ah::Result<()> {
+        let mut tmp_path = path.as_os_str().to_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        if let Err(e) = fs::write(&tmp_path, self.to_text()) {
+            return Err(ah::format_err!("Failed to write resume state file {:?}: {}", tmp_path, e));
+        }
+        if let Err(e) = fs::rename(&tmp_path, path) {
+            return Err(ah::format_err!("Failed to write resume state file {:?}: {}", path, e));
+        }
+        Ok(())
+    }
+}
+
+/// Background thread that periodically stores the resume state
+/// with the current checkpoint position.
+pub struct ResumeSaver {
+    path:           PathBuf,
+    state:          Arc<Mutex<ResumeState>>,
+    checkpoint:     Arc<AtomicU64>,
+    stop:           Arc<AtomicBool>,
+    thread_join:    Option<thread::JoinHandle<()>>,
+}
+
+impl ResumeSaver {
+    /// Start saving the state to the file at path.
+    pub fn new(path: &Path, state: ResumeState) -> ResumeSaver {
+        let checkpoint = Arc::new(AtomicU64::new(state.position));
+        let state = Arc::new(Mutex::new(state));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread_path = path.to_path_buf();
+        let thread_state = Arc::clone(&state);
+        let thread_checkpoint = Arc::clone(&checkpoint);
+        let thread_stop = Arc::clone(&stop);
+        let thread_join = thread::spawn(move || {
+            while !thread_stop.load(Ordering::Relaxed) {
+                thread::park_timeout(SAVE_INTERVAL);
+                let mut state = thread_state.lock().unwrap();
+                let position = thread_checkpoint.load(Ordering::Relaxed);
+                if state.position != position {
+                    state.position = position;
+                    if let Err(e) = state.store(&thread_path) {
+                        eprintln!("WARNING: {}", e);
+                    }
+                }
+            }
+        });
+
+        ResumeSaver {
+            path: path.to_path_buf(),
+            state,
+            checkpoint,
+            stop,
+            thread_join: Some(thread_join),
+        }
+    }
+
+    /// Get the checkpoint that is to be updated by the Disktest core.
+    pub fn checkpoint(&self) -> Arc<AtomicU64> {
+        Arc::clone(&self.checkpoint)
+    }
+
+    /// Begin a new phase and store the state immediately.
+    pub fn begin(&self,
+                 round: u64,
+                 phase: Phase,
+                 position: u64,
+                 written: Option<u64>) -> ah::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.round = round;
+        state.phase = phase;
+        state.position = position;
+        state.written = written;
+        self.checkpoint.store(position, Ordering::Relaxed);
+        state.store(&self.path)
+    }
+
+    /// Stop the saver thread.
+    /// On success the state file is removed.
+    /// Otherwise the final state is stored.
+    pub fn finish(mut self, success: bool) -> ah::Result<()> {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread_join) = self.thread_join.take() {
+            thread_join.thread().unpark();
+            thread_join.join().unwrap();
+        }
+        if success {
+            if !self.path.exists() {
+                return Ok(());
+            }
+            if let Err(e) = fs::remove_file(&self.path) {
+                return Err(ah::format_err!("Failed to remove resume state file {:?}: {}",
+                                           self.path, e));
+            }
+            Ok(())
+        } else {
+            let mut state = self.state.lock().unwrap();
+            state.position = self.checkpoint.load(Ordering::Relaxed);
+            state.store(&self.path)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn mk_state() -> ResumeState {
+        ResumeState {
+            device:     "/dev/foobar".to_string(),
+            write:      true,
+            verify:     true,
+            algorithm:  DtStreamType::CRC,
+            kdf:        DtKdf::Argon2id { memory: 1024, iterations: 2 },
+            threads:    4,
+            seed:       b"my=secret\nseed\x00\xFF".to_vec(),
+            seek:       0,
+            max_bytes:  u64::MAX,
+            round:      3,
+            phase:      Phase::Verify,
+            position:   123456,
+            written:    Some(1000000),
+        }
+    }
+
+    #[test]
+    fn test_text() {
+        let state = mk_state();
+        assert_eq!(ResumeState::from_text(&state.to_text()).unwrap(), state);
+        let mut state = mk_state();
+        state.written = None;
+        state.phase = Phase::Write;
+        assert_eq!(ResumeState::from_text(&state.to_text()).unwrap(), state);
+        assert!(ResumeState::from_text("device=/dev/foobar\n").is_err());
+        assert!(ResumeState::from_text("foo=bar\n").is_err());
+        let text = mk_state().to_text().replace("kdf=argon2id:1024:2\n", "");
+        assert_eq!(ResumeState::from_text(&text).unwrap().kdf, DtKdf::default());
+    }
+
+    #[test]
+    fn test_saver() {
+        let tdir = tempdir().unwrap();
+        let path = tdir.path().join("state");
+        assert_eq!(ResumeState::load(&path).unwrap(), None);
+
+        let saver = ResumeSaver::new(&path, mk_state());
+        saver.begin(4, Phase::Write, 10, None).unwrap();
+        let state = ResumeState::load(&path).unwrap().unwrap();
+        assert_eq!(state.round, 4);
+        assert_eq!(state.phase, Phase::Write);
+        assert_eq!(state.position, 10);
+        saver.checkpoint().store(20, Ordering::Relaxed);
+        saver.finish(false).unwrap();
+        assert_eq!(ResumeState::load(&path).unwrap().unwrap().position, 20);
+
+        let saver = ResumeSaver::new(&path, mk_state());
+        saver.finish(true).unwrap();
+        assert!(!path.exists());
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
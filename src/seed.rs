@@ -19,18 +19,64 @@
 // 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
 //
 
+use anyhow as ah;
+use disktest_core::DtKdf;
 use rand::distributions::Alphanumeric;
-use rand::{thread_rng, Rng};
+use rand::Rng;
+use rand::rngs::OsRng;
+use std::fs;
+use std::io::{Read, stdin};
 
-/// Generate a new alphanumeric truly random seed.
+/// Value of --seed that selects a generated seed.
+pub const RANDOM_SEED: &str = "random";
+
+/// Generate a new alphanumeric truly random seed from the operating system's RNG.
 /// length: The number of ASCII characters to return.
 pub fn gen_seed_string(length: usize) -> String {
-    let rng = thread_rng();
-    rng.sample_iter(Alphanumeric).take(length).collect()
+    OsRng.sample_iter(Alphanumeric).take(length).collect()
+}
+
+/// Get the sub-seed of the round of a multi-round run, so that each round writes different data.
+/// Round 1 uses the seed itself. The other rounds append "-roundN" to the seed.
+/// A raw seed must keep its length, so the round number is mixed into its first bytes instead.
+pub fn round_seed(seed: &[u8], kdf: DtKdf, round: u64) -> Vec<u8> {
+    let mut seed = seed.to_vec();
+    if round > 1 {
+        if kdf == DtKdf::Raw {
+            for (s, r) in seed.iter_mut().zip(round.to_le_bytes().iter()) {
+                *s ^= r;
+            }
+        } else {
+            seed.extend_from_slice(format!("-round{}", round).as_bytes());
+        }
+    }
+    seed
+}
+
+/// Read a seed from a file or from stdin, if the path is "-".
+/// The content is used as is.
+pub fn read_seed_file(path: &str) -> ah::Result<Vec<u8>> {
+    let seed = if path == "-" {
+        let mut seed = vec![];
+        if let Err(e) = stdin().read_to_end(&mut seed) {
+            return Err(ah::format_err!("Failed to read the seed from stdin: {}", e));
+        }
+        seed
+    } else {
+        match fs::read(path) {
+            Ok(x) => x,
+            Err(e) => return Err(ah::format_err!("Failed to read the seed file {:?}: {}", path, e)),
+        }
+    };
+    if seed.is_empty() {
+        return Err(ah::format_err!("The seed is empty."));
+    }
+    Ok(seed)
 }
 
 /// Print the generated seed to the console.
-pub fn print_generated_seed(seed: &str, verbose: bool) {
+pub fn print_generated_seed(seed: &[u8], verbose: bool) {
+    let seed = String::from_utf8_lossy(seed);
     if verbose {
         println!("\nThe generated --seed is:\n    {}\n\
                  Use this seed for subsequent --verify.\n",
@@ -50,13 +96,41 @@ mod tests {
         let seed = gen_seed_string(42);
         assert_eq!(seed.len(), 42);
         assert_eq!(seed.chars().count(), 42);
+        assert!(seed.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_ne!(gen_seed_string(42), seed);
+    }
+
+    #[test]
+    fn test_round_seed() {
+        let kdf = DtKdf::default();
+        assert_eq!(round_seed(b"abc", kdf, 0), b"abc");
+        assert_eq!(round_seed(b"abc", kdf, 1), b"abc");
+        assert_eq!(round_seed(b"abc", kdf, 2), b"abc-round2");
+        assert_eq!(round_seed(b"abc", kdf, 12), b"abc-round12");
+        let raw = [0x10; 32];
+        assert_eq!(round_seed(&raw, DtKdf::Raw, 1), raw);
+        let seed = round_seed(&raw, DtKdf::Raw, 0x0102);
+        assert_eq!(seed.len(), 32);
+        assert_eq!(seed[..3], [0x12, 0x11, 0x10]);
+        assert_eq!(seed[3..], raw[3..]);
+    }
+
+    #[test]
+    fn test_read_seed_file() {
+        let tdir = tempfile::tempdir().unwrap();
+        let path = tdir.path().join("seed");
+        fs::write(&path, b"\x00\xFFsecret\n").unwrap();
+        assert_eq!(read_seed_file(path.to_str().unwrap()).unwrap(), b"\x00\xFFsecret\n");
+        fs::write(&path, b"").unwrap();
+        assert!(read_seed_file(path.to_str().unwrap()).is_err());
+        assert!(read_seed_file(tdir.path().join("none").to_str().unwrap()).is_err());
     }
 
     #[test]
     fn test_print() {
         // Just check if it doesn't panic.
-        print_generated_seed(&"foo", false);
-        print_generated_seed(&"bar", true);
+        print_generated_seed(b"foo", false);
+        print_generated_seed(b"bar", true);
     }
 }
 
@@ -0,0 +1,241 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+use serde::Serialize;
+use serde_json::Value;
+use std::process::Command;
+
+/// Key ATA attributes: (ID, name).
+const ATA_ATTRIBUTES: [(u64, &str); 5] = [
+    (5,     "Reallocated sectors"),
+    (196,   "Reallocation events"),
+    (197,   "Pending sectors"),
+    (198,   "Offline uncorrectable"),
+    (199,   "CRC errors"),
+];
+
+/// Key NVMe health log entries: (JSON key, name).
+const NVME_ATTRIBUTES: [(&str, &str); 3] = [
+    ("media_errors",        "Media errors"),
+    ("num_err_log_entries", "Error log entries"),
+    ("critical_warning",    "Critical warning"),
+];
+
+/// Change of a key S.M.A.R.T. attribute during the run.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SmartChange {
+    pub name:       String,
+    /// Raw value before the run, if it was reported.
+    pub before:     Option<u64>,
+    /// Raw value after the run.
+    pub after:      u64,
+    pub increased:  bool,
+}
+
+/// Raw values of the key S.M.A.R.T. attributes of a device.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SmartSnapshot {
+    pub attributes: Vec<(&'static str, u64)>,
+    /// Current drive temperature in degrees Celsius, if reported.
+    pub temperature: Option<u64>,
+}
+
+impl SmartSnapshot {
+    /// Parse the JSON output of smartctl.
+    pub fn from_json(text: &str) -> ah::Result<SmartSnapshot> {
+        let json: Value = serde_json::from_str(text)
+            .map_err(|e| ah::format_err!("Invalid smartctl output: {}", e))?;
+        let mut attributes = vec![];
+
+        if let Some(table) = json["ata_smart_attributes"]["table"].as_array() {
+            for (id, name) in ATA_ATTRIBUTES.iter() {
+                let value = table.iter()
+                    .find(|a| a["id"].as_u64() == Some(*id))
+                    .and_then(|a| a["raw"]["value"].as_u64());
+                if let Some(value) = value {
+                    attributes.push((*name, value));
+                }
+            }
+        }
+        let nvme = &json["nvme_smart_health_information_log"];
+        for (key, name) in NVME_ATTRIBUTES.iter() {
+            if let Some(value) = nvme[key].as_u64() {
+                attributes.push((*name, value));
+            }
+        }
+
+        if attributes.is_empty() {
+            let message = json["smartctl"]["messages"][0]["string"].as_str()
+                .unwrap_or("No S.M.A.R.T. attributes found.");
+            return Err(ah::format_err!("{}", message));
+        }
+        Ok(SmartSnapshot {
+            attributes,
+            temperature: json["temperature"]["current"].as_u64(),
+        })
+    }
+
+    /// Read the key S.M.A.R.T. attributes of the device with smartctl.
+    pub fn read(device: &str) -> ah::Result<SmartSnapshot> {
+        let output = match Command::new("smartctl").args(["-j", "-A", device]).output() {
+            Ok(x) => x,
+            Err(e) => return Err(ah::format_err!("Failed to run smartctl: {}", e)),
+        };
+        SmartSnapshot::from_json(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    /// Get the changes of all attributes from the before snapshot to this snapshot.
+    pub fn delta(&self, before: &SmartSnapshot) -> Vec<SmartChange> {
+        self.attributes.iter().map(|(name, value)| {
+            let old = before.attributes.iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| *v);
+            SmartChange {
+                name:       name.to_string(),
+                before:     old,
+                after:      *value,
+                increased:  old.is_some_and(|old| *value > old),
+            }
+        }).collect()
+    }
+
+    /// Format the changes of all attributes from the before snapshot to this snapshot.
+    pub fn format_delta(&self, before: &SmartSnapshot) -> String {
+        let mut text = String::new();
+        for change in self.delta(before) {
+            let name = format!("{}:", change.name);
+            let line = match change.before {
+                Some(old) if change.increased => {
+                    format!("    {:<22} {} -> {} (+{})  <-- INCREASED\n",
+                            name, old, change.after, change.after - old)
+                },
+                Some(old) => format!("    {:<22} {} -> {}\n", name, old, change.after),
+                None => format!("    {:<22} - -> {}\n", name, change.after),
+            };
+            text.push_str(&line);
+        }
+        text
+    }
+
+    /// Check if any attribute increased from the before snapshot to this snapshot.
+    pub fn increased(&self, before: &SmartSnapshot) -> bool {
+        self.attributes.iter().any(|(name, value)| {
+            before.attributes.iter().any(|(n, v)| n == name && value > v)
+        })
+    }
+}
+
+/// Parse the overall S.M.A.R.T. health self-assessment from the JSON output of smartctl.
+/// Returns true, if the device passed the self-assessment.
+pub fn health_from_json(text: &str) -> ah::Result<bool> {
+    let json: Value = serde_json::from_str(text)
+        .map_err(|e| ah::format_err!("Invalid smartctl output: {}", e))?;
+    match json["smart_status"]["passed"].as_bool() {
+        Some(passed) => Ok(passed),
+        None => {
+            let message = json["smartctl"]["messages"][0]["string"].as_str()
+                .unwrap_or("No S.M.A.R.T. health status found.");
+            Err(ah::format_err!("{}", message))
+        },
+    }
+}
+
+/// Read the overall S.M.A.R.T. health self-assessment of the device with smartctl.
+pub fn read_health(device: &str) -> ah::Result<bool> {
+    let output = match Command::new("smartctl").args(["-j", "-H", device]).output() {
+        Ok(x) => x,
+        Err(e) => return Err(ah::format_err!("Failed to run smartctl: {}", e)),
+    };
+    health_from_json(&String::from_utf8_lossy(&output.stdout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ATA: &str = r#"{
+        "ata_smart_attributes": {"table": [
+            {"id": 1, "name": "Raw_Read_Error_Rate", "raw": {"value": 1234}},
+            {"id": 5, "name": "Reallocated_Sector_Ct", "raw": {"value": 8}},
+            {"id": 197, "name": "Current_Pending_Sector", "raw": {"value": 0}},
+            {"id": 199, "name": "UDMA_CRC_Error_Count", "raw": {"value": 2}}
+        ]}
+    }"#;
+
+    const NVME: &str = r#"{
+        "temperature": {"current": 41},
+        "nvme_smart_health_information_log": {
+            "critical_warning": 0,
+            "media_errors": 0,
+            "num_err_log_entries": 17
+        }
+    }"#;
+
+    #[test]
+    fn test_from_json() {
+        assert_eq!(SmartSnapshot::from_json(ATA).unwrap().attributes,
+                   vec![("Reallocated sectors", 8), ("Pending sectors", 0), ("CRC errors", 2)]);
+        assert_eq!(SmartSnapshot::from_json(NVME).unwrap().attributes,
+                   vec![("Media errors", 0), ("Error log entries", 17), ("Critical warning", 0)]);
+        assert_eq!(SmartSnapshot::from_json(NVME).unwrap().temperature, Some(41));
+        assert_eq!(SmartSnapshot::from_json(ATA).unwrap().temperature, None);
+        let e = SmartSnapshot::from_json(r#"{"smartctl": {"messages": [{"string": "foo"}]}}"#);
+        assert_eq!(e.unwrap_err().to_string(), "foo");
+        assert!(SmartSnapshot::from_json("").is_err());
+    }
+
+    #[test]
+    fn test_health_from_json() {
+        assert!(health_from_json(r#"{"smart_status": {"passed": true}}"#).unwrap());
+        assert!(!health_from_json(r#"{"smart_status": {"passed": false}}"#).unwrap());
+        let e = health_from_json(r#"{"smartctl": {"messages": [{"string": "foo"}]}}"#);
+        assert_eq!(e.unwrap_err().to_string(), "foo");
+        assert!(health_from_json("{}").is_err());
+        assert!(health_from_json("").is_err());
+    }
+
+    #[test]
+    fn test_delta() {
+        let before = SmartSnapshot::from_json(ATA).unwrap();
+        let after = SmartSnapshot {
+            attributes: vec![("Reallocated sectors", 10), ("Pending sectors", 0), ("CRC errors", 2)],
+            temperature: None,
+        };
+        assert!(after.increased(&before));
+        assert!(!before.increased(&before));
+        let delta = after.delta(&before);
+        assert_eq!(delta.len(), 3);
+        assert_eq!(delta[0], SmartChange {
+            name:       "Reallocated sectors".to_string(),
+            before:     Some(8),
+            after:      10,
+            increased:  true,
+        });
+        assert!(!delta[1].increased && !delta[2].increased);
+        assert_eq!(after.format_delta(&before),
+                   "    Reallocated sectors:   8 -> 10 (+2)  <-- INCREASED\n\
+                   \x20   Pending sectors:       0 -> 0\n\
+                   \x20   CRC errors:            2 -> 2\n");
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,198 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+use crate::identity::DeviceIdentity;
+use crate::report::classify_errors;
+use disktest_core::DtErrorRecord;
+use disktest_core::util::prettybytes;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Statistics of a whole run for the final summary and the report.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct RunStats {
+    pub model:          Option<String>,
+    pub serial:         Option<String>,
+    /// Size of the device, in bytes.
+    pub size:           Option<u64>,
+    pub bytes_written:  Option<u64>,
+    pub bytes_verified: Option<u64>,
+    /// Number of completed write and verify rounds.
+    pub rounds:         u64,
+    /// Duration of the run, in seconds.
+    pub elapsed:        f64,
+    /// Average throughput of the write and verify phases, in bytes per second.
+    pub avg_rate:       u64,
+    /// Lowest throughput of a progress interval, in bytes per second.
+    pub min_rate:       Option<u64>,
+    /// Highest throughput of a progress interval, in bytes per second.
+    pub max_rate:       Option<u64>,
+    pub bad_regions:    usize,
+    /// Total length of the bad regions, in bytes.
+    pub bad_bytes:      u64,
+    /// Number of bad regions by kind of error.
+    pub error_counts:   BTreeMap<String, usize>,
+}
+
+impl RunStats {
+    /// Collect the statistics of a run.
+    /// busy is the time spent in the write and verify phases.
+    /// rates are the lowest and the highest throughput of the progress intervals.
+    pub fn new(identity:        Option<&DeviceIdentity>,
+               bytes_written:   Option<u64>,
+               bytes_verified:  Option<u64>,
+               elapsed:         Duration,
+               busy:            Duration,
+               rates:           Option<(u64, u64)>,
+               bad_regions:     &[DtErrorRecord]) -> RunStats {
+        let secs = elapsed.as_secs_f64();
+        let busy = busy.as_secs_f64();
+        let total = bytes_written.unwrap_or(0) + bytes_verified.unwrap_or(0);
+        RunStats {
+            model:          identity.and_then(|i| i.model.clone()),
+            serial:         identity.and_then(|i| i.serial.clone()),
+            size:           identity.and_then(|i| i.size),
+            bytes_written,
+            bytes_verified,
+            rounds:         0,
+            elapsed:        secs,
+            avg_rate:       if busy > 0.0 { (total as f64 / busy) as u64 } else { 0 },
+            min_rate:       rates.map(|r| r.0),
+            max_rate:       rates.map(|r| r.1),
+            bad_regions:    bad_regions.len(),
+            bad_bytes:      bad_regions.iter().map(|r| r.length).sum(),
+            error_counts:   classify_errors(bad_regions).into_iter()
+                                .map(|c| (c.kind, c.count))
+                                .collect(),
+        }
+    }
+
+    /// Format the summary of the run on the device.
+    pub fn format(&self, title: &str, device: &str, result: &ah::Result<()>) -> String {
+        let bytes = |count: Option<u64>| {
+            match count {
+                Some(count) => prettybytes(count, true, true),
+                None => "-".to_string(),
+            }
+        };
+        let rate = |rate: Option<u64>| {
+            match rate {
+                Some(rate) => format!("{}/s", prettybytes(rate, true, false)),
+                None => "-".to_string(),
+            }
+        };
+        let mut identity = vec![];
+        if let Some(model) = &self.model {
+            identity.push(model.clone());
+        }
+        if let Some(serial) = &self.serial {
+            identity.push(format!("serial {}", serial));
+        }
+        if let Some(size) = self.size {
+            identity.push(prettybytes(size, true, true));
+        }
+        let mut text = format!("\n{}:\n", title);
+        if identity.is_empty() {
+            text += &format!("    Device:      {:?}\n", device);
+        } else {
+            text += &format!("    Device:      {:?} ({})\n", device, identity.join(", "));
+        }
+        let secs = self.elapsed as u64;
+        text += &format!("    Written:     {}\n\
+                          \x20   Verified:    {}\n\
+                          \x20   Elapsed:     {:02}:{:02}:{:02}\n\
+                          \x20   Throughput:  avg {}, min {}, max {}\n",
+                         bytes(self.bytes_written),
+                         bytes(self.bytes_verified),
+                         secs / 3600, secs / 60 % 60, secs % 60,
+                         rate(Some(self.avg_rate)), rate(self.min_rate), rate(self.max_rate));
+        if self.bad_regions == 0 {
+            text += "    Bad regions: none\n";
+        } else {
+            let counts: Vec<String> = self.error_counts.iter()
+                .map(|(kind, count)| format!("{} {}", count, kind))
+                .collect();
+            text += &format!("    Bad regions: {} ({}): {}\n",
+                             self.bad_regions, prettybytes(self.bad_bytes, true, true),
+                             counts.join(", "));
+        }
+        text += &format!("    Result:      {}\n",
+                         match result {
+                             Ok(_) => "Success".to_string(),
+                             Err(e) => format!("FAILED: {}", e),
+                         });
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use disktest_core::DtErrorKind;
+
+    #[test]
+    fn test_stats() {
+        let mib = 1024 * 1024;
+        let identity = DeviceIdentity {
+            model:  Some("ACME Disk".to_string()),
+            serial: Some("1234".to_string()),
+            size:   Some(100 * mib),
+            ..Default::default()
+        };
+        let error = |kind, offset| DtErrorRecord {
+            kind,
+            offset,
+            length:     4096,
+            message:    "".to_string(),
+        };
+        let errors = vec![error(DtErrorKind::Mismatch, 0),
+                          error(DtErrorKind::Mismatch, 8192),
+                          error(DtErrorKind::Read, 16384)];
+        let stats = RunStats::new(Some(&identity), Some(100 * mib), Some(100 * mib),
+                                  Duration::from_secs(3661), Duration::from_secs(20),
+                                  Some((mib, 10 * mib)), &errors);
+        assert_eq!(stats.avg_rate, 10 * mib);
+        assert_eq!(stats.bad_regions, 3);
+        assert_eq!(stats.bad_bytes, 3 * 4096);
+        assert_eq!(stats.error_counts.len(), 2);
+
+        let text = stats.format("Summary", "/dev/sdb", &Ok(()));
+        assert!(text.contains("    Device:      \"/dev/sdb\" (ACME Disk, serial 1234, 100.0 MiB"));
+        assert!(text.contains("    Written:     100.0 MiB"));
+        assert!(text.contains("    Elapsed:     01:01:01\n"));
+        assert!(text.contains(", min 1.0 MiB/s, max 10.0 MiB/s\n"));
+        assert!(text.contains("    Bad regions: 3 (12.0 kiB (12.3 kB)): "));
+        assert!(text.ends_with("    Result:      Success\n"));
+
+        let stats = RunStats::new(None, Some(mib), None, Duration::from_secs(1), Duration::from_secs(0),
+                                  None, &[]);
+        assert_eq!(stats.avg_rate, 0);
+        let text = stats.format("Partial summary", "disk.img", &Ok(()));
+        assert!(text.starts_with("\nPartial summary:\n    Device:      \"disk.img\"\n"));
+        assert!(text.contains("    Verified:    -\n"));
+        assert!(text.contains("avg 0 bytes/s, min -, max -"));
+        assert!(text.contains("    Bad regions: none\n"));
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,122 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+use disktest_core::{DtPhase, ProgressInfo, ProgressObserver, format_progress};
+use std::io::{IsTerminal, stdout};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Format the status line of the device at path.
+fn format_status(path: &Path, info: &ProgressInfo) -> String {
+    format!("Status of {:?}: {}, position {}",
+            path, format_progress(info, false).trim_end_matches(" ..."), info.offset)
+}
+
+/// Prints the status with the next progress update, if it was requested.
+struct StatusPrinter {
+    requested:  Arc<AtomicBool>,
+    path:       PathBuf,
+}
+
+impl ProgressObserver for StatusPrinter {
+    fn begin(&mut self, _phase: DtPhase, path: &Path, _seek: u64) {
+        self.path = path.to_path_buf();
+    }
+
+    fn progress(&mut self, info: &ProgressInfo) {
+        if self.requested.swap(false, Ordering::Relaxed) {
+            // Do not append to a live progress line.
+            let newline = if stdout().is_terminal() { "\n" } else { "" };
+            eprintln!("{}{}", newline, format_status(&self.path, info));
+        }
+    }
+}
+
+/// Status output on request, like dd does.
+/// The status is requested with SIGUSR1 or, on BSD and macOS, with SIGINFO (Ctrl-T).
+/// It is printed independently of the quiet level.
+#[derive(Default)]
+pub struct StatusRequest {
+    requested:  Arc<AtomicBool>,
+}
+
+impl StatusRequest {
+    /// Install the signal handlers.
+    pub fn install() -> ah::Result<StatusRequest> {
+        let status = StatusRequest::default();
+        #[cfg(any(target_os="macos", target_os="freebsd", target_os="openbsd",
+                  target_os="netbsd", target_os="dragonfly"))]
+        let signals = [signal_hook::SIGUSR1, libc::SIGINFO];
+        #[cfg(all(unix, not(any(target_os="macos", target_os="freebsd", target_os="openbsd",
+                                target_os="netbsd", target_os="dragonfly"))))]
+        let signals = [signal_hook::SIGUSR1];
+        #[cfg(not(unix))]
+        let signals: [libc::c_int; 0] = [];
+        for sig in &signals {
+            if let Err(e) = signal_hook::flag::register(*sig, Arc::clone(&status.requested)) {
+                return Err(ah::format_err!("Failed to register signal {}: {}", sig, e));
+            }
+        }
+        Ok(status)
+    }
+
+    /// Get an observer that has to be attached to the Disktest instances.
+    pub fn observer(&self) -> Box<dyn ProgressObserver> {
+        Box::new(StatusPrinter {
+            requested:  Arc::clone(&self.requested),
+            path:       PathBuf::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_status() {
+        let info = ProgressInfo {
+            phase:      DtPhase::Write,
+            offset:     3 * 1024 * 1024,
+            bytes:      2 * 1024 * 1024,
+            total:      Some(4 * 1024 * 1024),
+            elapsed:    Duration::from_secs(2),
+            rate:       1024 * 1024,
+            avg_rate:   1024 * 1024,
+            errors:     0,
+        };
+        let text = format_status(Path::new("/dev/sdb"), &info);
+        assert!(text.starts_with("Status of \"/dev/sdb\": Wrote 2.0 MiB"));
+        assert!(text.contains("(50.0%)"));
+        assert!(text.contains(", ETA 00:00:02, position 3145728"));
+
+        let status = StatusRequest::default();
+        let mut observer = status.observer();
+        status.requested.store(true, Ordering::Relaxed);
+        observer.progress(&info);
+        assert!(!status.requested.load(Ordering::Relaxed));
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,277 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+
+use disktest_core::util::prettybytes;
+use disktest_core::{DtErrorRecord, DtPhase, ProgressInfo, ProgressObserver};
+use serde::Serialize;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Minimum duration of a phase for the analysis.
+const MIN_PHASE_TIME: Duration = Duration::from_secs(30);
+/// A rate sample is part of a dip, if it is below this fraction of the baseline rate.
+const DIP_FRACTION: f64 = 0.5;
+/// A dip ends, if the rate is back above this fraction of the baseline rate.
+const RECOVER_FRACTION: f64 = 0.75;
+/// Minimum duration of one dip.
+const MIN_DIP_TIME: Duration = Duration::from_secs(3);
+/// Minimum number of dips that are flagged as throttling.
+/// A single drop is typically the end of the SLC write cache and not throttling.
+const MIN_DIPS: usize = 2;
+
+/// Sustained periodic throughput dips of one phase.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Throttling {
+    pub phase:          String,
+    /// Number of dips.
+    pub dips:           usize,
+    /// Total duration of all dips, in seconds.
+    pub throttled:      f64,
+    /// Average time from the start of one dip to the start of the next one, in seconds.
+    pub period:         f64,
+    /// Normal rate outside of the dips, in bytes per second.
+    pub baseline_rate:  u64,
+    /// Lowest rate during the dips, in bytes per second.
+    pub min_rate:       u64,
+}
+
+impl Throttling {
+    /// Format a one line description for the summary.
+    pub fn describe(&self) -> String {
+        format!("{} dips below {}/s in the {} phase every {:.0} s, \
+                throttled for {:.0} s, lowest rate {}/s",
+                self.dips,
+                prettybytes((self.baseline_rate as f64 * DIP_FRACTION) as u64, true, false),
+                self.phase,
+                self.period,
+                self.throttled,
+                prettybytes(self.min_rate, true, false))
+    }
+}
+
+/// Search the rate samples of one phase for sustained periodic dips.
+/// Each sample is the elapsed time since the start of the phase
+/// and the rate since the previous sample.
+pub fn detect_throttling(phase: &str, samples: &[(Duration, u64)]) -> Option<Throttling> {
+    match samples.last() {
+        Some((elapsed, _)) if *elapsed >= MIN_PHASE_TIME => (),
+        _ => return None,
+    }
+
+    // The baseline is the 90th percentile of the rate.
+    let mut rates: Vec<u64> = samples.iter().map(|(_, r)| *r).collect();
+    rates.sort_unstable();
+    let baseline = rates[rates.len() * 9 / 10];
+    if baseline == 0 {
+        return None;
+    }
+    let dip_rate = baseline as f64 * DIP_FRACTION;
+    let recover_rate = baseline as f64 * RECOVER_FRACTION;
+
+    // Collect all dips as (start, duration, lowest rate).
+    let mut dips: Vec<(Duration, Duration, u64)> = vec![];
+    let mut current: Option<(Duration, Duration, u64)> = None;
+    let mut prev = Duration::from_secs(0);
+    for &(elapsed, rate) in samples {
+        let interval = elapsed.saturating_sub(prev);
+        prev = elapsed;
+        if (rate as f64) < dip_rate {
+            let dip = current.get_or_insert((elapsed - interval, Duration::from_secs(0), rate));
+            dip.1 += interval;
+            dip.2 = dip.2.min(rate);
+        } else if (rate as f64) < recover_rate {
+            if let Some(dip) = current.as_mut() {
+                dip.1 += interval;
+            }
+        } else if let Some(dip) = current.take() {
+            dips.push(dip);
+        }
+    }
+    dips.extend(current);
+    dips.retain(|(_, duration, _)| *duration >= MIN_DIP_TIME);
+
+    if dips.len() < MIN_DIPS {
+        return None;
+    }
+    let first = dips.first().unwrap().0;
+    let last = dips.last().unwrap().0;
+    Some(Throttling {
+        phase:          phase.to_string(),
+        dips:           dips.len(),
+        throttled:      dips.iter().map(|(_, d, _)| d.as_secs_f64()).sum(),
+        period:         (last - first).as_secs_f64() / (dips.len() - 1) as f64,
+        baseline_rate:  baseline,
+        min_rate:       dips.iter().map(|(_, _, r)| *r).min().unwrap_or(0),
+    })
+}
+
+/// Rate samples of all phases: (phase, samples).
+type Series = Arc<Mutex<Vec<(DtPhase, Vec<(Duration, u64)>)>>>;
+
+/// Progress observer that records the rate samples.
+struct RateRecorder {
+    series:     Series,
+    busy:       Arc<Mutex<Duration>>,
+}
+
+impl ProgressObserver for RateRecorder {
+    fn begin(&mut self, phase: DtPhase, _path: &Path, _seek: u64) {
+        self.series.lock().unwrap().push((phase, vec![]));
+    }
+
+    fn progress(&mut self, info: &ProgressInfo) {
+        if let Some((_, samples)) = self.series.lock().unwrap().last_mut() {
+            samples.push((info.elapsed, info.rate));
+        }
+    }
+
+    fn finish(&mut self, info: &ProgressInfo, _errors: &[DtErrorRecord]) {
+        *self.busy.lock().unwrap() += info.elapsed;
+    }
+}
+
+/// Records the throughput during the run to detect thermal throttling.
+#[derive(Default)]
+pub struct ThermalWatch {
+    series:     Series,
+    /// Total duration of the finished phases.
+    busy:       Arc<Mutex<Duration>>,
+}
+
+impl ThermalWatch {
+    /// Get an observer that has to be attached to the Disktest instances.
+    pub fn observer(&self) -> Box<dyn ProgressObserver> {
+        Box::new(RateRecorder {
+            series:     Arc::clone(&self.series),
+            busy:       Arc::clone(&self.busy),
+        })
+    }
+
+    /// Forget all phases that have been recorded so far.
+    pub fn clear(&self) {
+        self.series.lock().unwrap().clear();
+        *self.busy.lock().unwrap() = Duration::from_secs(0);
+    }
+
+    /// Get the total duration of the phases that have been finished so far.
+    pub fn busy_time(&self) -> Duration {
+        *self.busy.lock().unwrap()
+    }
+
+    /// Get the lowest and the highest rate of all phases that have been recorded so far.
+    pub fn rate_range(&self) -> Option<(u64, u64)> {
+        let series = self.series.lock().unwrap();
+        let rates = series.iter().flat_map(|(_, samples)| samples.iter().map(|(_, r)| *r));
+        let min = rates.clone().min()?;
+        Some((min, rates.max()?))
+    }
+
+    /// Analyze all phases that have been recorded so far.
+    pub fn analyze(&self) -> Vec<Throttling> {
+        self.series.lock().unwrap().iter()
+            .filter_map(|(phase, samples)| detect_throttling(&phase.to_string(), samples))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB: u64 = 1024 * 1024;
+
+    fn series(rates: &[u64]) -> Vec<(Duration, u64)> {
+        rates.iter().enumerate()
+            .map(|(i, r)| (Duration::from_secs(i as u64 + 1), *r * MIB))
+            .collect()
+    }
+
+    #[test]
+    fn test_detect() {
+        // Steady rate.
+        assert_eq!(detect_throttling("write", &series(&[500; 60])), None);
+        // Too short.
+        assert_eq!(detect_throttling("write", &series(&[500; 10])), None);
+        // A single permanent drop after the write cache is full.
+        let mut rates = vec![1000; 20];
+        rates.extend(vec![300; 40]);
+        assert_eq!(detect_throttling("write", &series(&rates)), None);
+
+        // Periodic dips.
+        let mut rates = vec![];
+        for _ in 0..4 {
+            rates.extend(vec![500; 15]);
+            rates.extend(vec![100, 120, 300, 110, 100]);
+        }
+        rates.extend(vec![500; 10]);
+        let t = detect_throttling("write", &series(&rates)).unwrap();
+        assert_eq!(t.phase, "write");
+        assert_eq!(t.dips, 4);
+        assert_eq!(t.throttled, 20.0);
+        assert_eq!(t.period, 20.0);
+        assert_eq!(t.baseline_rate, 500 * MIB);
+        assert_eq!(t.min_rate, 100 * MIB);
+        assert_eq!(t.describe(),
+                   "4 dips below 250.0 MiB/s in the write phase every 20 s, \
+                   throttled for 20 s, lowest rate 100.0 MiB/s");
+
+        // Short drops are ignored.
+        let mut rates = vec![];
+        for _ in 0..4 {
+            rates.extend(vec![500; 15]);
+            rates.extend(vec![100, 100]);
+        }
+        assert_eq!(detect_throttling("write", &series(&rates)), None);
+    }
+
+    #[test]
+    fn test_watch() {
+        let watch = ThermalWatch::default();
+        let mut observer = watch.observer();
+        observer.begin(DtPhase::Write, Path::new("/dev/foo"), 0);
+        let mut rates = vec![];
+        for _ in 0..3 {
+            rates.extend(vec![500; 10]);
+            rates.extend(vec![100; 5]);
+        }
+        for (elapsed, rate) in series(&rates) {
+            observer.progress(&ProgressInfo {
+                phase:      DtPhase::Write,
+                offset:     0,
+                bytes:      0,
+                total:      None,
+                elapsed,
+                rate,
+                avg_rate:   0,
+                errors:     0,
+            });
+        }
+        observer.begin(DtPhase::Verify, Path::new("/dev/foo"), 0);
+        let result = watch.analyze();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].phase, "write");
+        assert_eq!(result[0].dips, 3);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
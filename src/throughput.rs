@@ -0,0 +1,150 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+use crate::log::format_timestamp;
+use disktest_core::{DtPhase, ProgressInfo, ProgressObserver};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// The first line of the CSV file.
+const CSV_HEADER: &str = "timestamp,device,phase,elapsed,offset,rate\n";
+
+/// Quote a CSV field, if necessary.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Format one CSV line of the progress update.
+fn format_line(time: SystemTime, device: &Path, info: &ProgressInfo) -> String {
+    format!("{},{},{},{:.3},{},{}\n",
+            format_timestamp(time),
+            csv_field(&device.to_string_lossy()),
+            info.phase,
+            info.elapsed.as_secs_f64(),
+            info.offset,
+            info.rate)
+}
+
+/// CSV log of the throughput over time. See --throughput-log.
+/// Each progress update adds a line with the absolute offset and the rate in bytes per second.
+#[derive(Clone)]
+pub struct ThroughputLog {
+    path:       PathBuf,
+    file:       Arc<Mutex<File>>,
+}
+
+impl ThroughputLog {
+    /// Create the CSV file and write the header line.
+    pub fn create(path: &Path) -> ah::Result<ThroughputLog> {
+        let mut file = match File::create(path) {
+            Ok(x) => x,
+            Err(e) => return Err(ah::format_err!("Failed to create throughput log {:?}: {}", path, e)),
+        };
+        if let Err(e) = file.write_all(CSV_HEADER.as_bytes()) {
+            return Err(ah::format_err!("Failed to write throughput log {:?}: {}", path, e));
+        }
+        Ok(ThroughputLog {
+            path:       path.to_path_buf(),
+            file:       Arc::new(Mutex::new(file)),
+        })
+    }
+
+    /// Write errors are reported on the console, but they don't abort the run.
+    fn write(&self, line: &str) {
+        let mut file = self.file.lock().unwrap();
+        if let Err(e) = file.write_all(line.as_bytes()).and_then(|_| file.flush()) {
+            eprintln!("WARNING: Failed to write throughput log {:?}: {}", self.path, e);
+        }
+    }
+
+    /// Get an observer that has to be attached to the Disktest instances.
+    pub fn observer(&self) -> Box<dyn ProgressObserver> {
+        Box::new(ThroughputRecorder {
+            log:        self.clone(),
+            device:     PathBuf::new(),
+        })
+    }
+}
+
+/// Progress observer that writes to the throughput log.
+struct ThroughputRecorder {
+    log:        ThroughputLog,
+    device:     PathBuf,
+}
+
+impl ProgressObserver for ThroughputRecorder {
+    fn begin(&mut self, _phase: DtPhase, path: &Path, _seek: u64) {
+        self.device = path.to_path_buf();
+    }
+
+    fn progress(&mut self, info: &ProgressInfo) {
+        self.log.write(&format_line(SystemTime::now(), &self.device, info));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_throughput_log() {
+        let info = ProgressInfo {
+            phase:      DtPhase::Verify,
+            offset:     3 * 1024 * 1024,
+            bytes:      2 * 1024 * 1024,
+            total:      None,
+            elapsed:    Duration::from_millis(2500),
+            rate:       1024 * 1024,
+            avg_rate:   1024 * 1024,
+            errors:     0,
+        };
+        let time = UNIX_EPOCH + Duration::from_secs(1606571110);
+        assert_eq!(format_line(time, Path::new("/dev/sdb"), &info),
+                   "2020-11-28T13:45:10Z,/dev/sdb,verify,2.500,3145728,1048576\n");
+        assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rate.csv");
+        let log = ThroughputLog::create(&path).unwrap();
+        let mut observer = log.observer();
+        observer.begin(DtPhase::Verify, Path::new("disk.img"), 0);
+        observer.progress(&info);
+        observer.progress(&info);
+        let text = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], CSV_HEADER.trim_end());
+        assert!(lines[2].ends_with("Z,disk.img,verify,2.500,3145728,1048576"));
+        assert!(ThroughputLog::create(dir.path()).is_err());
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,96 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use disktest_core::DisktestHandle;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Wall-clock time limit of the run.
+/// When the time is up, all registered Disktest instances are cancelled.
+pub struct TimeLimit {
+    expired:    Arc<AtomicBool>,
+    handles:    Arc<Mutex<Vec<DisktestHandle>>>,
+}
+
+impl TimeLimit {
+    /// Start the timer for the time limit.
+    pub fn start(limit: Duration) -> TimeLimit {
+        let time_limit = TimeLimit {
+            expired:    Arc::new(AtomicBool::new(false)),
+            handles:    Arc::new(Mutex::new(vec![])),
+        };
+        let expired = Arc::clone(&time_limit.expired);
+        let handles = Arc::clone(&time_limit.handles);
+        thread::spawn(move || {
+            thread::sleep(limit);
+            let handles = handles.lock().unwrap();
+            expired.store(true, Ordering::Relaxed);
+            for handle in handles.iter() {
+                handle.cancel();
+            }
+        });
+        time_limit
+    }
+
+    /// Register a Disktest instance that is cancelled when the time is up.
+    /// If the time is already up, then it is cancelled immediately.
+    pub fn register(&self, handle: DisktestHandle) {
+        let mut handles = self.handles.lock().unwrap();
+        if self.expired() {
+            handle.cancel();
+        } else {
+            handles.push(handle);
+        }
+    }
+
+    /// Check whether the time is up.
+    pub fn expired(&self) -> bool {
+        self.expired.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use disktest_core::{Disktest, DtStreamType};
+
+    #[test]
+    fn test_time_limit() {
+        // The key derivation is slow. Create the instances before the time runs.
+        let new_disktest = || Disktest::new(DtStreamType::CRC, vec![1, 2, 3], 1, None);
+        let before = new_disktest();
+        let after = new_disktest();
+        let time_limit = TimeLimit::start(Duration::from_millis(100));
+        time_limit.register(before.handle());
+        assert!(!time_limit.expired());
+        assert!(!before.handle().is_cancelled());
+
+        thread::sleep(Duration::from_millis(500));
+        assert!(time_limit.expired());
+        assert!(before.handle().is_cancelled());
+        time_limit.register(after.handle());
+        assert!(after.handle().is_cancelled());
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
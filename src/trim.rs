@@ -0,0 +1,188 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+use std::cmp::min;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Size of the sample that is read twice to check
+/// whether the data read after the discard is deterministic.
+pub const DRAT_SAMPLE_SIZE: u64 = 1024 * 1024;
+
+#[cfg(target_os="linux")]
+fn os_discard(file: &File, offset: u64, len: u64) -> ah::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::io::AsRawFd;
+
+    let ret = if file.metadata()?.file_type().is_block_device() {
+        // BLKDISCARD = _IO(0x12, 119)
+        const BLKDISCARD: libc::c_ulong = (0x12 << 8) | 119;
+        let range: [u64; 2] = [offset, len];
+        unsafe { libc::ioctl(file.as_raw_fd(), BLKDISCARD as _, &range) }
+    } else {
+        // Regular files are discarded by punching a hole.
+        unsafe { libc::fallocate(file.as_raw_fd(),
+                                 libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                                 offset as libc::off_t,
+                                 len as libc::off_t) }
+    };
+    if ret < 0 {
+        Err(ah::format_err!("Discard failed: {}", std::io::Error::last_os_error()))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os="linux"))]
+fn os_discard(_file: &File, _offset: u64, _len: u64) -> ah::Result<()> {
+    Err(ah::format_err!("Discard (TRIM) is not supported on this system."))
+}
+
+/// Discard (TRIM) len bytes of the device starting at offset.
+/// The file must be opened for writing.
+pub fn discard(file: &File, offset: u64, len: u64) -> ah::Result<()> {
+    os_discard(file, offset, len)
+}
+
+/// Get the range (offset, length) to discard for max_bytes at seek of a device of size bytes.
+/// The start is rounded up and the end, if it is not the end of the device,
+/// is rounded down to a multiple of align, so that no data outside of the range is destroyed.
+pub fn discard_range(seek: u64, max_bytes: u64, size: u64, align: u64) -> (u64, u64) {
+    let start = seek.div_ceil(align).saturating_mul(align);
+    let end = seek.saturating_add(max_bytes).min(size);
+    let end = if end < size { end - end % align } else { end };
+    (start, end.saturating_sub(start))
+}
+
+/// Drop the page cache of len bytes at offset. A len of 0 drops the cache up to the end.
+#[cfg(target_os="linux")]
+pub fn drop_cache(file: &File, offset: u64, len: u64) {
+    use std::os::unix::io::AsRawFd;
+
+    unsafe {
+        libc::posix_fadvise(file.as_raw_fd(),
+                            offset as libc::off_t,
+                            len as libc::off_t,
+                            libc::POSIX_FADV_DONTNEED);
+    }
+}
+
+#[cfg(not(target_os="linux"))]
+pub fn drop_cache(_file: &File, _offset: u64, _len: u64) {
+}
+
+/// Read the sample at offset.
+pub fn read_sample<F: Read + Seek>(file: &mut F, offset: u64, len: u64) -> std::io::Result<Vec<u8>> {
+    let mut buf = vec![0; len as usize];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Check whether the data read after the discard is deterministic (DRAT).
+/// The sample at the start of the discarded range is read twice
+/// and the page cache is dropped before each read.
+pub fn read_is_deterministic(file: &mut File, offset: u64, len: u64) -> ah::Result<bool> {
+    let len = min(len, DRAT_SAMPLE_SIZE);
+    drop_cache(file, offset, len);
+    let first = read_sample(file, offset, len)?;
+    drop_cache(file, offset, len);
+    let second = read_sample(file, offset, len)?;
+    Ok(first == second)
+}
+
+/// Format the result of the TRIM check.
+/// zeros is None, if the zero verification did not complete.
+pub fn format_trim_result(deterministic: bool, zeros: Option<bool>) -> String {
+    let yes_no = |x: bool| if x { "yes" } else { "no" };
+    let verdict = match (deterministic, zeros) {
+        (_, Some(true)) => "The device returns zeros after TRIM.",
+        (true, Some(false)) => "The device returns deterministic data after TRIM, but not zeros.",
+        (false, Some(false)) => "The device returns non-deterministic data after TRIM.",
+        (_, None) => "The zero verification did not complete.",
+    };
+    format!("    Deterministic read after TRIM (DRAT): {}\n    \
+            Read zeros after TRIM (RZAT):         {}\n{}",
+            yes_no(deterministic),
+            zeros.map_or("unknown", yes_no),
+            verdict)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_trim_result() {
+        let text = format_trim_result(true, Some(true));
+        assert!(text.contains("(DRAT): yes\n"));
+        assert!(text.contains("(RZAT):         yes\n"));
+        assert!(text.ends_with("returns zeros after TRIM."));
+        assert!(format_trim_result(true, Some(false)).ends_with("deterministic data after TRIM, but not zeros."));
+        assert!(format_trim_result(false, Some(false)).ends_with("non-deterministic data after TRIM."));
+        assert!(format_trim_result(true, None).contains("(RZAT):         unknown\n"));
+    }
+
+    #[cfg(target_os="linux")]
+    #[test]
+    fn test_discard_file() {
+        use std::io::Write;
+
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&[0xAA; 64 * 1024]).unwrap();
+        file.sync_all().unwrap();
+        discard(&file, 16 * 1024, 32 * 1024).unwrap();
+        let data = read_sample(&mut file, 0, 64 * 1024).unwrap();
+        assert!(data[..16 * 1024].iter().all(|&b| b == 0xAA));
+        assert!(data[16 * 1024..48 * 1024].iter().all(|&b| b == 0));
+        assert!(data[48 * 1024..].iter().all(|&b| b == 0xAA));
+        assert!(read_is_deterministic(&mut file, 16 * 1024, 32 * 1024).unwrap());
+    }
+
+    #[test]
+    fn test_discard_range() {
+        assert_eq!(discard_range(0, u64::MAX, 10000, 4096), (0, 10000));
+        assert_eq!(discard_range(4096, 4096, 16384, 4096), (4096, 4096));
+        assert_eq!(discard_range(100, 8192, 16384, 4096), (4096, 4096));
+        assert_eq!(discard_range(100, 4000, 16384, 4096), (4096, 0));
+        assert_eq!(discard_range(100, u64::MAX, 10000, 4096), (4096, 10000 - 4096));
+        assert_eq!(discard_range(9000, u64::MAX, 10000, 4096), (12288, 0));
+    }
+
+    #[cfg(target_os="linux")]
+    #[test]
+    fn test_discard_unaligned_seek() {
+        use std::io::Write;
+
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&[0xAA; 64 * 1024]).unwrap();
+        file.sync_all().unwrap();
+        let (offset, len) = discard_range(1000, 40 * 1024, 64 * 1024, 16 * 1024);
+        discard(&file, offset, len).unwrap();
+        let data = read_sample(&mut file, 0, 64 * 1024).unwrap();
+        assert!(data[..16 * 1024].iter().all(|&b| b == 0xAA));
+        assert!(data[16 * 1024..32 * 1024].iter().all(|&b| b == 0));
+        assert!(data[32 * 1024..].iter().all(|&b| b == 0xAA));
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
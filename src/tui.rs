@@ -0,0 +1,423 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+use crate::identity::DeviceIdentity;
+use disktest_core::util::prettybytes;
+use disktest_core::{DtErrorRecord, DtPhase, ProgressInfo, ProgressObserver, format_progress};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+/// Number of throughput samples that are kept for the graph.
+const HISTORY_LEN: usize = 512;
+/// Number of status messages that are shown.
+const MESSAGES_LEN: usize = 3;
+/// Number of bad regions that are shown.
+const ERRORS_LEN: usize = 6;
+/// Number of lines of the throughput graph.
+const GRAPH_HEIGHT: usize = 4;
+/// Interval of redrawing the screen, in milliseconds.
+#[cfg(unix)]
+const REFRESH_MS: i32 = 250;
+/// The blocks of the throughput graph, from low to high.
+const GRAPH_BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// The state of the run as shown on the screen.
+#[derive(Default)]
+struct TuiState {
+    phase:      Option<DtPhase>,
+    path:       PathBuf,
+    info:       Option<ProgressInfo>,
+    done:       bool,
+    /// The throughput of the progress updates of the phase, in bytes per second.
+    rates:      VecDeque<u64>,
+    errors:     Vec<DtErrorRecord>,
+    messages:   VecDeque<String>,
+}
+
+/// Records the progress of the Disktest instances for the screen.
+struct TuiObserver {
+    state:      Arc<Mutex<TuiState>>,
+}
+
+impl ProgressObserver for TuiObserver {
+    fn begin(&mut self, phase: DtPhase, path: &Path, _seek: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.phase = Some(phase);
+        state.path = path.to_path_buf();
+        state.info = None;
+        state.done = false;
+        state.rates.clear();
+    }
+
+    fn progress(&mut self, info: &ProgressInfo) {
+        let mut state = self.state.lock().unwrap();
+        if state.rates.len() >= HISTORY_LEN {
+            state.rates.pop_front();
+        }
+        state.rates.push_back(info.rate);
+        state.info = Some(info.clone());
+    }
+
+    fn error(&mut self, error: &DtErrorRecord) {
+        self.state.lock().unwrap().errors.push(error.clone());
+    }
+
+    fn message(&mut self, message: &str) {
+        let mut state = self.state.lock().unwrap();
+        if state.messages.len() >= MESSAGES_LEN {
+            state.messages.pop_front();
+        }
+        state.messages.push_back(message.to_string());
+    }
+
+    fn finish(&mut self, info: &ProgressInfo, _errors: &[DtErrorRecord]) {
+        let mut state = self.state.lock().unwrap();
+        state.info = Some(info.clone());
+        state.done = true;
+    }
+}
+
+/// Format the progress bar with the width for the fraction.
+fn progress_bar(fraction: f64, width: usize) -> String {
+    let inner = width.saturating_sub(2);
+    let filled = ((fraction.clamp(0.0, 1.0) * inner as f64) as usize).min(inner);
+    format!("[{}{}]", "#".repeat(filled), ".".repeat(inner - filled))
+}
+
+/// Draw the graph of the latest rates with the width and the height.
+/// Returns the lines from top to bottom.
+fn graph(rates: &VecDeque<u64>, width: usize, height: usize) -> Vec<String> {
+    let rates: Vec<u64> = rates.iter().skip(rates.len().saturating_sub(width)).copied().collect();
+    let max = rates.iter().copied().max().unwrap_or(0).max(1);
+    let steps = GRAPH_BLOCKS.len();
+    let levels: Vec<usize> = rates.iter()
+        .map(|&r| ((r as f64 / max as f64) * (height * steps) as f64).round() as usize)
+        .collect();
+    (0..height).rev()
+        .map(|row| {
+            levels.iter()
+                .map(|&level| {
+                    if level >= (row + 1) * steps {
+                        GRAPH_BLOCKS[steps - 1]
+                    } else if level > row * steps {
+                        GRAPH_BLOCKS[level - row * steps - 1]
+                    } else {
+                        ' '
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Render the screen with the width and the height.
+/// Returns the lines from top to bottom.
+fn render(state: &TuiState, device_info: &str, paused: bool, aborting: bool,
+          width: usize, height: usize) -> Vec<String> {
+    let mut lines = vec![];
+    let phase = match state.phase {
+        Some(phase) => format!("{} {:?}", phase, state.path),
+        None => "Starting ...".to_string(),
+    };
+    lines.push(format!("disktest {} - {}", env!("CARGO_PKG_VERSION"), phase));
+    lines.push(device_info.to_string());
+    lines.push("".to_string());
+
+    match &state.info {
+        Some(info) => {
+            if let Some(total) = info.total.filter(|&t| t > 0) {
+                let fraction = info.bytes as f64 / total as f64;
+                lines.push(format!("{} {:5.1}%", progress_bar(fraction, width.saturating_sub(7)),
+                                   fraction * 100.0));
+            }
+            lines.push(format_progress(info, state.done));
+            lines.push(format!("Position: {} ({})", info.offset, prettybytes(info.offset, true, true)));
+        },
+        None => lines.push("Waiting for the first progress update ...".to_string()),
+    }
+    lines.push("".to_string());
+
+    let max = state.rates.iter().copied().max().unwrap_or(0);
+    lines.push(format!("Throughput (max {}/s):", prettybytes(max, true, false)));
+    lines.extend(graph(&state.rates, width, GRAPH_HEIGHT));
+    lines.push("".to_string());
+
+    let total: u64 = state.errors.iter().map(|e| e.length).sum();
+    lines.push(format!("Bad regions: {} ({})", state.errors.len(), prettybytes(total, true, true)));
+    let skipped = state.errors.len().saturating_sub(ERRORS_LEN);
+    if skipped > 0 {
+        lines.push(format!("    ... {} more", skipped));
+    }
+    for error in &state.errors[skipped..] {
+        lines.push(format!("    {:>15} - {:>15}: {}", error.offset, error.offset + error.length, error.kind));
+    }
+    lines.push("".to_string());
+    lines.extend(state.messages.iter().cloned());
+
+    // The key bindings are always shown in the last line.
+    lines.truncate(height.saturating_sub(1));
+    while lines.len() < height.saturating_sub(1) {
+        lines.push("".to_string());
+    }
+    let status = if aborting {
+        "  ABORTING"
+    } else if paused {
+        "  PAUSED"
+    } else {
+        ""
+    };
+    lines.push(format!("[p] Pause/resume  [q] Abort{}", status));
+    lines.iter().map(|l| l.chars().take(width).collect()).collect()
+}
+
+/// Describe the device in one line.
+fn device_info(device: &str) -> String {
+    match DeviceIdentity::read(device) {
+        Some(id) => format!("Device: {} (serial {}), {}, bus {}",
+                            id.model.as_deref().unwrap_or("unknown model"),
+                            id.serial.as_deref().unwrap_or("unknown"),
+                            id.size.map_or("unknown size".to_string(), |s| prettybytes(s, true, true)),
+                            id.bus.as_deref().unwrap_or("unknown")),
+        None => format!("Device: {:?}", device),
+    }
+}
+
+/// The terminal in non-canonical mode without echo on the alternate screen.
+#[cfg(unix)]
+struct Screen {
+    orig:       libc::termios,
+}
+
+#[cfg(unix)]
+impl Screen {
+    fn enter() -> ah::Result<Screen> {
+        use std::io::{IsTerminal, Write, stdin, stdout};
+
+        if !stdout().is_terminal() || !stdin().is_terminal() {
+            return Err(ah::format_err!("--tui requires a terminal."));
+        }
+        let mut orig: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut orig) } != 0 {
+            return Err(ah::format_err!("Failed to get the terminal mode: {}",
+                                       std::io::Error::last_os_error()));
+        }
+        let mut raw = orig;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        raw.c_cc[libc::VMIN] = 0;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+            return Err(ah::format_err!("Failed to set the terminal mode: {}",
+                                       std::io::Error::last_os_error()));
+        }
+        // Switch to the alternate screen and hide the cursor.
+        print!("\x1b[?1049h\x1b[?25l");
+        let _ = stdout().flush();
+        Ok(Screen { orig })
+    }
+
+    /// Get the size of the terminal (width, height).
+    fn size(&self) -> (usize, usize) {
+        let mut ws: libc::winsize = unsafe { std::mem::zeroed() };
+        if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut ws) } == 0 &&
+           ws.ws_col > 0 && ws.ws_row > 0 {
+            (ws.ws_col as usize, ws.ws_row as usize)
+        } else {
+            (80, 24)
+        }
+    }
+
+    fn draw(&self, lines: &[String]) {
+        use std::io::{Write, stdout};
+
+        let mut out = String::from("\x1b[H");
+        out += &lines.join("\x1b[K\r\n");
+        out += "\x1b[K\x1b[J";
+        let mut stdout = stdout();
+        let _ = stdout.write_all(out.as_bytes());
+        let _ = stdout.flush();
+    }
+
+    /// Wait up to REFRESH_MS for a key press.
+    fn read_key(&self) -> Option<u8> {
+        let mut fds = libc::pollfd {
+            fd:         libc::STDIN_FILENO,
+            events:     libc::POLLIN,
+            revents:    0,
+        };
+        if unsafe { libc::poll(&mut fds, 1, REFRESH_MS) } <= 0 {
+            return None;
+        }
+        let mut key = 0u8;
+        match unsafe { libc::read(libc::STDIN_FILENO, &mut key as *mut u8 as *mut libc::c_void, 1) } {
+            1 => Some(key),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Screen {
+    fn drop(&mut self) {
+        use std::io::{Write, stdout};
+
+        // Show the cursor and leave the alternate screen.
+        print!("\x1b[?25h\x1b[?1049l");
+        let _ = stdout().flush();
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.orig) };
+    }
+}
+
+/// Full-screen terminal user interface of a run.
+pub struct Tui {
+    state:      Arc<Mutex<TuiState>>,
+    stop:       Arc<AtomicBool>,
+    thread:     Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Tui {
+    /// Show the user interface for the device.
+    /// The keys toggle the pause flag and set the abort flag.
+    #[cfg(unix)]
+    pub fn start(device: &str, pause: Arc<AtomicBool>, abort: Arc<AtomicBool>) -> ah::Result<Tui> {
+        let screen = Screen::enter()?;
+        let state = Arc::new(Mutex::new(TuiState::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let info = device_info(device);
+        let thread = {
+            let state = Arc::clone(&state);
+            let stop = Arc::clone(&stop);
+            std::thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let (width, height) = screen.size();
+                    let lines = render(&state.lock().unwrap(), &info,
+                                       pause.load(Ordering::Relaxed), abort.load(Ordering::Relaxed),
+                                       width, height);
+                    screen.draw(&lines);
+                    match screen.read_key() {
+                        Some(b'p') | Some(b'P') => { pause.fetch_xor(true, Ordering::SeqCst); },
+                        Some(b'q') | Some(b'Q') => abort.store(true, Ordering::SeqCst),
+                        _ => (),
+                    }
+                }
+            })
+        };
+        Ok(Tui {
+            state,
+            stop,
+            thread: Mutex::new(Some(thread)),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn start(_device: &str, _pause: Arc<AtomicBool>, _abort: Arc<AtomicBool>) -> ah::Result<Tui> {
+        Err(ah::format_err!("--tui is not supported on this system."))
+    }
+
+    /// Get an observer that has to be attached to the Disktest instances.
+    pub fn observer(&self) -> Box<dyn ProgressObserver> {
+        Box::new(TuiObserver {
+            state:  Arc::clone(&self.state),
+        })
+    }
+
+    /// Close the user interface and restore the terminal.
+    pub fn close(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.lock().unwrap().take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use disktest_core::DtErrorKind;
+    use std::time::Duration;
+
+    #[test]
+    fn test_widgets() {
+        assert_eq!(progress_bar(0.5, 12), "[#####.....]");
+        assert_eq!(progress_bar(2.0, 6), "[####]");
+        assert_eq!(progress_bar(0.0, 1), "[]");
+
+        let rates: VecDeque<u64> = vec![0, 50, 100, 25].into_iter().collect();
+        assert_eq!(graph(&rates, 10, 2), vec!["  █ ".to_string(), " ██▄".to_string()]);
+        // Only the latest rates that fit are shown.
+        assert_eq!(graph(&rates, 2, 1), vec!["█▂".to_string()]);
+        assert_eq!(graph(&VecDeque::new(), 10, 1), vec!["".to_string()]);
+    }
+
+    #[test]
+    fn test_render() {
+        let mut observer = TuiObserver { state: Arc::new(Mutex::new(TuiState::default())) };
+        observer.begin(DtPhase::Write, Path::new("/dev/sdb"), 0);
+        observer.progress(&ProgressInfo {
+            phase:      DtPhase::Write,
+            offset:     1024 * 1024,
+            bytes:      1024 * 1024,
+            total:      Some(4 * 1024 * 1024),
+            elapsed:    Duration::from_secs(1),
+            rate:       1024 * 1024,
+            avg_rate:   1024 * 1024,
+            errors:     0,
+        });
+        for i in 0..10 {
+            observer.error(&DtErrorRecord {
+                kind:       DtErrorKind::Mismatch,
+                offset:     i * 4096,
+                length:     4096,
+                message:    "".to_string(),
+            });
+        }
+        observer.message("Hello");
+
+        let state = observer.state.lock().unwrap();
+        let lines = render(&state, "Device: foo", true, false, 60, 40);
+        assert_eq!(lines.len(), 40);
+        assert!(lines[0].ends_with("- write \"/dev/sdb\""));
+        assert_eq!(lines[1], "Device: foo");
+        assert!(lines[3].starts_with("[############"));
+        assert!(lines[3].ends_with(" 25.0%"));
+        assert!(lines.iter().any(|l| l == "Bad regions: 10 (40.0 kiB (41.0 kB))"));
+        assert!(lines.iter().any(|l| l == "    ... 4 more"));
+        assert!(lines.iter().any(|l| l == "Hello"));
+        assert_eq!(lines[39], "[p] Pause/resume  [q] Abort  PAUSED");
+        assert!(lines.iter().all(|l| l.chars().count() <= 60));
+
+        // Small screens keep the key bindings.
+        let lines = render(&state, "Device: foo", false, true, 20, 5);
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[4], "[p] Pause/resume  [q");
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
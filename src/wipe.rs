@@ -0,0 +1,134 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+use crate::args::parse_pattern;
+
+/// One overwrite pass of a wipe scheme.
+#[derive(Clone, Debug, PartialEq)]
+pub enum WipePass {
+    /// Pseudo random data.
+    Random,
+    /// Fixed byte pattern.
+    Pattern(Vec<u8>),
+}
+
+impl std::fmt::Display for WipePass {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            WipePass::Random => write!(f, "random"),
+            WipePass::Pattern(p) if p == &[0x00] => write!(f, "zeros"),
+            WipePass::Pattern(p) if p == &[0xFF] => write!(f, "ones"),
+            WipePass::Pattern(p) => {
+                write!(f, "pattern 0x")?;
+                for b in p {
+                    write!(f, "{:02X}", b)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+/// The passes of the Gutmann method.
+/// Passes 5 to 31 are written in their original order.
+fn gutmann() -> Vec<WipePass> {
+    let patterns: [&[u8]; 27] = [
+        &[0x55], &[0xAA],
+        &[0x92, 0x49, 0x24], &[0x49, 0x24, 0x92], &[0x24, 0x92, 0x49],
+        &[0x00], &[0x11], &[0x22], &[0x33], &[0x44], &[0x55], &[0x66], &[0x77],
+        &[0x88], &[0x99], &[0xAA], &[0xBB], &[0xCC], &[0xDD], &[0xEE], &[0xFF],
+        &[0x92, 0x49, 0x24], &[0x49, 0x24, 0x92], &[0x24, 0x92, 0x49],
+        &[0x6D, 0xB6, 0xDB], &[0xB6, 0xDB, 0x6D], &[0xDB, 0x6D, 0xB6],
+    ];
+    let mut passes = vec![WipePass::Random; 4];
+    passes.extend(patterns.iter().map(|p| WipePass::Pattern(p.to_vec())));
+    passes.extend(vec![WipePass::Random; 4]);
+    passes
+}
+
+/// Parse a wipe scheme.
+/// This is either the name of a well-known scheme
+/// or a comma separated list of passes.
+pub fn parse_wipe_scheme(s: &str) -> ah::Result<Vec<WipePass>> {
+    let zeros = || WipePass::Pattern(vec![0x00]);
+    let ones = || WipePass::Pattern(vec![0xFF]);
+
+    match s.trim().to_lowercase().as_str() {
+        "random" => Ok(vec![WipePass::Random]),
+        "dod" => Ok(vec![zeros(), ones(), WipePass::Random]),
+        "schneier" => {
+            let mut passes = vec![ones(), zeros()];
+            passes.extend(vec![WipePass::Random; 5]);
+            Ok(passes)
+        },
+        "gutmann" => Ok(gutmann()),
+        list => {
+            list.split(',')
+                .map(|pass| match pass.trim() {
+                    "random" => Ok(WipePass::Random),
+                    "zero" | "zeros" => Ok(zeros()),
+                    "one" | "ones" => Ok(ones()),
+                    x => match parse_pattern(x) {
+                        Ok(p) => Ok(WipePass::Pattern(p)),
+                        Err(e) => Err(ah::format_err!("Unknown wipe pass '{}': {}", x, e)),
+                    },
+                })
+                .collect()
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_wipe_scheme() {
+        assert_eq!(parse_wipe_scheme("random").unwrap(), vec![WipePass::Random]);
+        assert_eq!(parse_wipe_scheme("DoD").unwrap(),
+                   vec![WipePass::Pattern(vec![0]), WipePass::Pattern(vec![0xFF]), WipePass::Random]);
+        let schneier = parse_wipe_scheme("schneier").unwrap();
+        assert_eq!(schneier.len(), 7);
+        assert_eq!(schneier[0], WipePass::Pattern(vec![0xFF]));
+        assert_eq!(schneier[6], WipePass::Random);
+        let gutmann = parse_wipe_scheme("gutmann").unwrap();
+        assert_eq!(gutmann.len(), 35);
+        assert_eq!(gutmann[4], WipePass::Pattern(vec![0x55]));
+        assert_eq!(gutmann[30], WipePass::Pattern(vec![0xDB, 0x6D, 0xB6]));
+        assert_eq!(gutmann[34], WipePass::Random);
+        assert_eq!(parse_wipe_scheme("zero, ones,random,0xAA55").unwrap(),
+                   vec![WipePass::Pattern(vec![0]), WipePass::Pattern(vec![0xFF]),
+                        WipePass::Random, WipePass::Pattern(vec![0xAA, 0x55])]);
+        assert!(parse_wipe_scheme("zero,foo").is_err());
+        assert!(parse_wipe_scheme("").is_err());
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(WipePass::Random.to_string(), "random");
+        assert_eq!(WipePass::Pattern(vec![0]).to_string(), "zeros");
+        assert_eq!(WipePass::Pattern(vec![0xFF]).to_string(), "ones");
+        assert_eq!(WipePass::Pattern(vec![0xAA, 0x55]).to_string(), "pattern 0xAA55");
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
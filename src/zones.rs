@@ -0,0 +1,133 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+
+use crate::resume::Phase;
+use disktest_core::DtZones;
+use disktest_core::util::prettybytes;
+
+/// Width of the fastest zone's bar, in characters.
+const BAR_WIDTH: u64 = 40;
+/// A zone is marked as slow, if its rate is below this fraction of the median rate.
+const SLOW_FRACTION: f64 = 0.5;
+
+/// Zone throughput of all write and verify phases of a run.
+#[derive(Default)]
+pub struct RunZones {
+    pub write:      Option<DtZones>,
+    pub verify:     Option<DtZones>,
+}
+
+impl RunZones {
+    /// Add the zones of a finished phase.
+    /// Zones of a different layout replace the previous ones.
+    pub fn add(&mut self, phase: Phase, zones: &DtZones) {
+        let total = match phase {
+            Phase::Write => &mut self.write,
+            Phase::Verify => &mut self.verify,
+        };
+        let merged = match total.as_mut() {
+            Some(total) => total.merge(zones),
+            None => false,
+        };
+        if !merged {
+            *total = Some(zones.clone());
+        }
+    }
+
+    /// Print the zone table of all measured phases.
+    pub fn print(&self) {
+        for (name, zones) in &[("Write", &self.write), ("Read", &self.verify)] {
+            if let Some(zones) = zones {
+                println!("\n{} throughput per zone:", name);
+                for line in format_zones(zones) {
+                    println!("    {}", line);
+                }
+            }
+        }
+    }
+}
+
+/// Get the median rate of all zones that have been measured.
+fn median_rate(zones: &DtZones) -> u64 {
+    let mut rates: Vec<u64> = zones.zones().iter()
+        .filter(|z| z.bytes > 0)
+        .map(|z| z.rate())
+        .collect();
+    rates.sort_unstable();
+    rates.get(rates.len() / 2).copied().unwrap_or(0)
+}
+
+/// Format the zone table with one line per zone.
+pub fn format_zones(zones: &DtZones) -> Vec<String> {
+    let median = median_rate(zones);
+    let fastest = zones.zones().iter().map(|z| z.rate()).max().unwrap_or(0).max(1);
+    let mut lines = vec![format!("{:>4}  {:<12} {:>14}", "Zone", "Offset", "Rate")];
+    for (i, zone) in zones.zones().iter().enumerate() {
+        let rate = zone.rate();
+        let (rate_text, bar) = if zone.bytes > 0 {
+            (format!("{}/s", prettybytes(rate, true, false)),
+             "#".repeat((rate * BAR_WIDTH / fastest) as usize))
+        } else {
+            ("-".to_string(), "".to_string())
+        };
+        let slow = zone.bytes > 0 && (rate as f64) < median as f64 * SLOW_FRACTION;
+        let line = format!("{:>4}  {:<12} {:>14} {:<width$}{}",
+                           i + 1,
+                           prettybytes(zone.offset, true, false),
+                           rate_text,
+                           bar,
+                           if slow { " SLOW" } else { "" },
+                           width = BAR_WIDTH as usize);
+        lines.push(line.trim_end().to_string());
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_zones() {
+        let mut zones = DtZones::new(0, 4 * 1024 * 1024, 4);
+        zones.record(0, 1024 * 1024, Duration::from_secs(1));
+        zones.record(1024 * 1024, 1024 * 1024, Duration::from_secs(1));
+        zones.record(2 * 1024 * 1024, 1024 * 1024, Duration::from_secs(4));
+        assert_eq!(format_zones(&zones),
+                   vec!["Zone  Offset                 Rate",
+                        "   1  0 bytes           1.0 MiB/s ########################################",
+                        "   2  1.0 MiB           1.0 MiB/s ########################################",
+                        "   3  2.0 MiB         256.0 kiB/s ##########                               SLOW",
+                        "   4  3.0 MiB                   -"]);
+
+        let mut run = RunZones::default();
+        run.add(Phase::Verify, &zones);
+        run.add(Phase::Verify, &zones);
+        assert_eq!(run.verify.as_ref().unwrap().zones()[0].bytes, 2 * 1024 * 1024);
+        run.add(Phase::Verify, &DtZones::new(0, 100, 2));
+        assert_eq!(run.verify.as_ref().unwrap().zones().len(), 2);
+        assert!(run.write.is_none());
+    }
+}
+
+// vim: ts=4 sw=4 expandtab